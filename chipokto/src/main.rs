@@ -3,14 +3,17 @@ extern crate okto;
 extern crate sdl2;
 
 use std::collections::HashMap;
+use std::fs;
 use std::io;
-use std::sync::{Arc, RwLock};
+use std::path::Path;
 
 use clap::{App, Arg};
 
-use okto::keyboard::WaitKeyResult;
-use okto::machine::Machine;
-use okto::timer::{CountdownTimer, Timer};
+use okto::machine::{HaltReason, Machine};
+use okto::quirks::Quirks;
+use okto::random::XorShiftRandom;
+use okto::sound::SampleGenerator;
+use okto::{chipper, octo};
 
 use sdl2::audio::{AudioCallback, AudioSpecDesired};
 use sdl2::event::Event;
@@ -18,10 +21,6 @@ use sdl2::gfx::primitives::DrawRenderer;
 use sdl2::keyboard::Keycode;
 use sdl2::pixels::Color;
 
-/// Number of milliseconds per Chip8 timer tick ~(1000 * 1 / 60).
-const MILLISECONDS_PER_TICK: u32 = 17;
-/// Number of milliseconds per Chip8 CPU instruction ~(1000 * 1/500).
-const MILLISECONDS_PER_INSTRUCTION: u32 = 2;
 /// Background color used for drawing the absence of a pixel.
 const BACKGROUND_COLOR: Color = Color {
     r: 0,
@@ -36,73 +35,69 @@ const FOREGROUND_COLOR: Color = Color {
     g: 180,
     a: 255,
 };
+/// Color used for pixels set only in the second XO-CHIP bitplane.
+const SECOND_PLANE_COLOR: Color = Color {
+    r: 255,
+    b: 0,
+    g: 120,
+    a: 255,
+};
+/// Color used for pixels set in both XO-CHIP bitplanes.
+const BOTH_PLANES_COLOR: Color = Color {
+    r: 85,
+    b: 85,
+    g: 85,
+    a: 255,
+};
+/// Palette indexed by the color index of each display pixel.
+const PALETTE: [Color; okto::display::NUM_COLORS] = [
+    BACKGROUND_COLOR,
+    FOREGROUND_COLOR,
+    SECOND_PLANE_COLOR,
+    BOTH_PLANES_COLOR,
+];
 
-/// Data structure that will help us in generating a square sound wave whenever
-/// the audio delay timer is non-zero.
-struct SoundWave {
-    phase_inc: f32,
-    phase: f32,
-    volume: f32,
-    sound_timer: Arc<RwLock<Timer>>,
-}
+/// Wrapper allowing the okto sample generator to drive SDL audio playback.
+struct SoundWave(SampleGenerator);
 
 /// Implementation of SDL audio callback for our `SoundWave` type.
 impl AudioCallback for SoundWave {
     /// Channel content type
     type Channel = f32;
 
-    /// Generate a square audio wave whenever the sound timer is non-zero.
+    /// Fill the audio buffer with the output of the machine's sound system.
     fn callback(&mut self, out: &mut [f32]) {
-        for x in out.iter_mut() {
-            let timer = self.sound_timer.read().unwrap();
-            if *timer > 0 {
-                *x = if self.phase <= 0.5 {
-                    self.volume
-                } else {
-                    -self.volume
-                };
-                self.phase = (self.phase + self.phase_inc) % 1.0;
-            } else {
-                *x = 0.0;
-            }
-        }
+        self.0.fill(out);
     }
 }
 
 /// Generalized data type store the emulator application itself.
-struct EmulatorApp<F>
-where
-    F: FnMut() -> WaitKeyResult<u8>,
-{
-    machine: Machine<F>,
-    delta_last_inst_milliseconds: u32,
-    delta_last_tick_milliseconds: u32,
+struct EmulatorApp {
+    machine: Machine,
+    cycles_per_frame: u32,
+    frames_run: u64,
+    display_changed: bool,
 }
 
-impl<F> EmulatorApp<F>
-where
-    F: FnMut() -> WaitKeyResult<u8>,
-{
+impl EmulatorApp {
     /// Default constructor.
-    fn new(wait_key_callback: F) -> Self {
+    fn new(quirks: Quirks, cycles_per_frame: u32) -> Self {
         Self {
-            machine: Machine::new(Box::new(wait_key_callback)),
-            delta_last_inst_milliseconds: 0,
-            delta_last_tick_milliseconds: 0,
+            machine: Machine::with_quirks(quirks),
+            cycles_per_frame,
+            frames_run: 0,
+            display_changed: true,
         }
     }
 
-    /// Execute the next instruction on the emulator.
-    fn step(&mut self, delta_time_milliseconds: u32) {
-        self.delta_last_inst_milliseconds += delta_time_milliseconds;
-        if self.delta_last_inst_milliseconds >= MILLISECONDS_PER_INSTRUCTION {
-            self.machine.step().unwrap();
-            self.delta_last_inst_milliseconds = 0;
+    /// Draw the contents of the framebuffer to the given canvas if it has
+    /// changed since it was last drawn.
+    fn draw(&mut self, canvas: &mut sdl2::render::Canvas<sdl2::video::Window>) {
+        if !self.display_changed {
+            return;
         }
-    }
+        self.display_changed = false;
 
-    /// Draw the contents of the framebuffer to the given canvas.
-    fn draw(&mut self, canvas: &mut sdl2::render::Canvas<sdl2::video::Window>) {
         canvas.set_draw_color(BACKGROUND_COLOR);
         canvas.clear();
 
@@ -116,17 +111,17 @@ where
             (window_width / display_width, window_height / display_height);
 
         // Draw an appropriately sized rectangle per pixel that is on in the frame buffer.
-        canvas.set_draw_color(FOREGROUND_COLOR);
         for height in 0..display_height {
             for width in 0..display_width {
-                if self.machine.display.data[height as usize][width as usize] == 1 {
+                let color_index = self.machine.display.pixel(width as usize, height as usize);
+                if color_index != 0 {
                     canvas
                         .box_(
                             (width * rect_width) as i16,
                             (height * rect_height) as i16,
                             ((width * rect_width) + rect_width) as i16,
                             ((height * rect_height) + rect_height) as i16,
-                            FOREGROUND_COLOR,
+                            PALETTE[color_index as usize],
                         )
                         .unwrap();
                 }
@@ -136,28 +131,52 @@ where
         canvas.present();
     }
 
-    /// Update all time-dependent components of the machine.
-    fn update(&mut self, delta_time_milliseconds: u32) {
-        self.delta_last_tick_milliseconds += delta_time_milliseconds;
+    /// Run every 60 Hz frame that is due after the given number of
+    /// milliseconds since emulation started. Frames are counted from the start
+    /// rather than from the last update so that timing does not drift.
+    fn update(&mut self, elapsed_milliseconds: u32) -> Result<(), okto::OktoError> {
+        let frames_due = elapsed_milliseconds as u64 * okto::timer::TIMER_TICK_HZ as u64 / 1000;
 
-        if self.delta_last_tick_milliseconds >= MILLISECONDS_PER_TICK {
-            self.machine.sound.tick();
-            self.machine.delay_timer.tick();
-            self.delta_last_tick_milliseconds = 0;
+        while self.frames_run < frames_due {
+            let summary = self.machine.run_frame(self.cycles_per_frame);
+            self.frames_run += 1;
+            self.display_changed |= summary.display_changed;
+
+            if let Some(HaltReason::Error(error)) = summary.halt {
+                return Err(error);
+            }
         }
+
+        Ok(())
     }
 
     /// Update state to indiate that a key was pressed.
     fn key_pressed(&mut self, key: u8) {
-        self.machine.keyboard.keys[key as usize] = okto::keyboard::KeyState::Pressed;
+        self.machine.keyboard.press_key(key);
     }
 
     /// Update state to indicate that a key was released.
     fn key_released(&mut self, key: u8) {
-        self.machine.keyboard.keys[key as usize] = okto::keyboard::KeyState::Released;
+        self.machine.keyboard.release_key(key);
     }
 }
 
+/// Read the ROM to run, assembling it first if the path names Octo (`.8o`)
+/// or Chipper (`.chp`) source.
+fn load_rom(rom_path: &str) -> io::Result<Vec<u8>> {
+    let program = match Path::new(rom_path).extension().and_then(|e| e.to_str()) {
+        Some("8o") => octo::assemble(rom_path, &fs::read_to_string(rom_path)?),
+        Some("chp") => chipper::assemble(rom_path, &fs::read_to_string(rom_path)?, |path| {
+            fs::read_to_string(path)
+        }),
+        _ => return okto::read_rom_file(rom_path),
+    };
+
+    program
+        .map(|program| program.rom)
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error.to_string()))
+}
+
 fn main() -> io::Result<()> {
     // Build key map
     let mut keymap: HashMap<sdl2::keyboard::Keycode, u8> = HashMap::new();
@@ -185,15 +204,54 @@ fn main() -> io::Result<()> {
         .about("Run Chip8 game in a virtual machine")
         .arg(
             Arg::with_name("ROMFILE")
-                .help("Path to Chip8 ROM file.")
+                .help("Path to Chip8 ROM file, or Octo (.8o) or Chipper (.chp) source.")
                 .required(true)
                 .index(1),
         )
+        .arg(
+            Arg::with_name("quirks")
+                .short("q")
+                .long("quirks")
+                .value_name("PROFILE")
+                .help("interpreter behaviour to emulate for ambiguous opcodes")
+                .possible_values(&okto::quirks::PROFILE_NAMES)
+                .default_value("okto")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("seed")
+                .short("s")
+                .long("seed")
+                .value_name("SEED")
+                .help("seed for the random number generator, for reproducible runs")
+                .validator(|value| {
+                    value
+                        .parse::<u32>()
+                        .map(|_| ())
+                        .map_err(|_| String::from("seed must be an unsigned 32-bit integer"))
+                })
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("cycles")
+                .short("c")
+                .long("cycles-per-frame")
+                .value_name("CYCLES")
+                .help("number of instructions executed per 60 Hz frame")
+                .validator(|value| {
+                    value
+                        .parse::<u32>()
+                        .map(|_| ())
+                        .map_err(|_| String::from("cycles must be an unsigned 32-bit integer"))
+                })
+                .takes_value(true),
+        )
         .get_matches();
 
     // Load ROM file
     let rom_path = matches.value_of("ROMFILE").unwrap();
-    let rom_data = okto::read_rom_file(rom_path)?;
+    let rom_data = load_rom(rom_path)?;
+    let quirks = Quirks::from_profile_name(matches.value_of("quirks").unwrap()).unwrap();
 
     // Initialize SDL2 for rendering, input, and audio.
     let sdl_context = sdl2::init().unwrap();
@@ -207,26 +265,13 @@ fn main() -> io::Result<()> {
     let mut canvas = window.into_canvas().build().unwrap();
 
     // Initialize the Okto emulator
-    let wait_key_callback = || -> WaitKeyResult<u8> {
-        loop {
-            for event in sdl_context.event_pump().unwrap().poll_iter() {
-                match event {
-                    Event::KeyDown {
-                        keycode: Some(keycode),
-                        ..
-                    } => {
-                        if keymap.contains_key(&keycode) {
-                            return Ok(keymap[&keycode]);
-                        }
-                        return Ok(0x00);
-                    }
-                    _ => {}
-                }
-            }
-        }
-    };
-
-    let mut emulator_app = EmulatorApp::new(wait_key_callback);
+    let cycles_per_frame = matches
+        .value_of("cycles")
+        .map_or(okto::machine::DEFAULT_CYCLES_PER_FRAME, |value| value.parse().unwrap());
+    let mut emulator_app = EmulatorApp::new(quirks, cycles_per_frame);
+    if let Some(seed) = matches.value_of("seed") {
+        emulator_app.machine.rng = Box::new(XorShiftRandom::new(seed.parse().unwrap()));
+    }
     emulator_app
         .machine
         .memory
@@ -234,7 +279,7 @@ fn main() -> io::Result<()> {
         .unwrap();
 
     let mut timer = sdl_context.timer().unwrap();
-    let mut last_update_time = timer.ticks();
+    let start_time = timer.ticks();
 
     // Audio system
     let audio_subsystem = sdl_context.audio().unwrap();
@@ -246,11 +291,8 @@ fn main() -> io::Result<()> {
     };
 
     let device = audio_subsystem
-        .open_playback(None, &desired_spec, |spec| SoundWave {
-            phase_inc: 440.0 / spec.freq as f32,
-            phase: 0.0,
-            volume: 0.25,
-            sound_timer: emulator_app.machine.sound.timer.clone(),
+        .open_playback(None, &desired_spec, |spec| {
+            SoundWave(emulator_app.machine.sound.sample_generator(spec.freq as u32))
         })
         .unwrap();
 
@@ -268,29 +310,25 @@ fn main() -> io::Result<()> {
                 Event::KeyDown {
                     keycode: Some(keycode),
                     ..
-                } => {
-                    if keymap.contains_key(&keycode) {
-                        emulator_app.key_pressed(keymap[&keycode]);
-                    }
+                } if keymap.contains_key(&keycode) => {
+                    emulator_app.key_pressed(keymap[&keycode]);
                 }
                 Event::KeyUp {
                     keycode: Some(keycode),
                     ..
-                } => {
-                    if keymap.contains_key(&keycode) {
-                        emulator_app.key_released(keymap[&keycode]);
-                    }
+                } if keymap.contains_key(&keycode) => {
+                    emulator_app.key_released(keymap[&keycode]);
                 }
+                Event::Window { .. } => emulator_app.display_changed = true,
                 _ => {}
             }
         }
 
         // Update machine subsystems that are time-dependent.
-        let current_time = timer.ticks();
-        let delta_time = current_time - last_update_time;
-        emulator_app.step(delta_time);
-        emulator_app.update(delta_time);
-        last_update_time = timer.ticks();
+        if let Err(error) = emulator_app.update(timer.ticks() - start_time) {
+            eprintln!("{}", error);
+            break 'running;
+        }
 
         // Draw the contents of the framebuffer to the screen.
         emulator_app.draw(&mut canvas);
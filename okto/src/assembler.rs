@@ -0,0 +1,303 @@
+//! Building ROM images from assembly language source.
+//!
+//! Each supported syntax has its own front end, such as `octo`, which reads
+//! the source and writes bytes and operations into an `Image`. Operations can
+//! refer to labels that are only defined further on in the source; the image
+//! records each reference and fills in the address once the whole program has
+//! been read. Errors point at the file, line and column they were found at.
+use super::cpu::{Address, Operation, DEFAULT_PC_ADDRESS};
+use super::isa::OperandKind;
+use super::memory::MEMORY_SIZE_BYTES;
+use super::{OktoError, OktoErrorKind, OktoResult};
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::rc::Rc;
+
+/// Position of a token in the source.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Location {
+    /// Name of the source file
+    pub file: Rc<str>,
+    /// Line number, starting at 1
+    pub line: usize,
+    /// Column number in characters, starting at 1
+    pub column: usize,
+}
+
+impl Location {
+    /// Returns an error with the given message at this location.
+    pub fn error<S: Into<String>>(&self, message: S) -> OktoError {
+        OktoError::new(OktoErrorKind::InvalidSource(SourceError {
+            file: self.file.to_string(),
+            line: self.line,
+            column: self.column,
+            message: message.into(),
+        }))
+    }
+}
+
+/// An error in assembly language source.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SourceError {
+    /// Name of the source file
+    pub file: String,
+    /// Line number, starting at 1
+    pub line: usize,
+    /// Column number in characters, starting at 1
+    pub column: usize,
+    /// Description of the problem
+    pub message: String,
+}
+
+impl fmt::Display for SourceError {
+    /// Display the error as `file:line:column: message`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}: {}",
+            self.file, self.line, self.column, self.message
+        )
+    }
+}
+
+/// Size of an address written into an instruction.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Field {
+    /// Low twelve bits of the instruction word
+    Address,
+    /// Whole sixteen bit word
+    LongAddress,
+}
+
+impl Field {
+    /// Returns the largest address that fits in the field.
+    pub fn max(self) -> u32 {
+        match self {
+            Field::Address => 0xFFF,
+            Field::LongAddress => 0xFFFF,
+        }
+    }
+}
+
+/// Check a value fits in an operand, returning it as an integer. Bytes can
+/// be negative.
+pub fn check_operand(value: i64, kind: OperandKind, location: &Location) -> OktoResult<u16> {
+    let (min, max, what) = match kind {
+        OperandKind::Register | OperandKind::Nibble => (0, 0xF, "a nibble"),
+        OperandKind::Byte => (-128, 0xFF, "a byte"),
+        OperandKind::Address => (0, 0xFFF, "an address"),
+        OperandKind::LongAddress => (0, 0xFFFF, "a long address"),
+    };
+    if value < min || value > max {
+        return Err(location.error(format!("{} does not fit in {}", value, what)));
+    }
+    Ok(if value < 0 {
+        (value & 0xFF) as u16
+    } else {
+        value as u16
+    })
+}
+
+/// Use of a label before it was defined.
+#[derive(Clone, Debug)]
+struct Reference {
+    /// Address of the word holding the label's address
+    address: Address,
+    /// Size of the address in the word
+    field: Field,
+    /// Name of the label
+    name: String,
+    /// Where the label was used
+    location: Location,
+}
+
+/// An assembled ROM along with its labels.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Program {
+    /// Bytes to load at `cpu::DEFAULT_PC_ADDRESS`
+    pub rom: Vec<u8>,
+    /// Address of every label
+    pub labels: BTreeMap<String, Address>,
+}
+
+/// Memory image being assembled, starting at `cpu::DEFAULT_PC_ADDRESS`.
+///
+/// # Examples
+///
+/// ```
+/// # extern crate okto;
+/// # use okto::assembler::{Field, Image, Location};
+/// # use okto::cpu::Operation;
+/// let location = Location {
+///     file: "example.8o".into(),
+///     line: 1,
+///     column: 1,
+/// };
+/// let mut image = Image::new();
+/// image.emit_operation(&Operation::Jump(0), &location).unwrap();
+/// image.refer(0x200, Field::Address, "start", &location);
+/// image.define("start", &location).unwrap();
+/// image.emit(&[0x00, 0xFD], &location).unwrap();
+///
+/// assert_eq!(vec![0x12, 0x02, 0x00, 0xFD], image.finish().unwrap().rom);
+/// ```
+#[derive(Clone, Debug)]
+pub struct Image {
+    /// Contents of memory
+    data: Vec<u8>,
+    /// Address the next byte is written to
+    here: u32,
+    /// Address after the last byte written
+    end: u32,
+    /// Address of every label defined so far
+    labels: BTreeMap<String, Address>,
+    /// Labels used before their definition
+    references: Vec<Reference>,
+}
+
+impl Default for Image {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Image {
+    /// Create an empty image.
+    pub fn new() -> Self {
+        Self {
+            data: vec![0; MEMORY_SIZE_BYTES],
+            here: DEFAULT_PC_ADDRESS as u32,
+            end: DEFAULT_PC_ADDRESS as u32,
+            labels: BTreeMap::new(),
+            references: Vec::new(),
+        }
+    }
+
+    /// Returns the address the next byte is written to.
+    pub fn here(&self) -> u32 {
+        self.here
+    }
+
+    /// Move to the given address, which must be inside the ROM.
+    pub fn set_here(&mut self, address: u32, location: &Location) -> OktoResult<()> {
+        if address < DEFAULT_PC_ADDRESS as u32 || address >= MEMORY_SIZE_BYTES as u32 {
+            return Err(location.error(format!("address 0x{:X} is outside of the ROM", address)));
+        }
+        self.here = address;
+        Ok(())
+    }
+
+    /// Returns the byte at the given address.
+    pub fn read(&self, address: u32) -> u8 {
+        self.data.get(address as usize).cloned().unwrap_or(0)
+    }
+
+    /// Write bytes at the current address and move past them.
+    pub fn emit(&mut self, bytes: &[u8], location: &Location) -> OktoResult<()> {
+        let start = self.here as usize;
+        if start + bytes.len() > MEMORY_SIZE_BYTES {
+            return Err(location.error("program is too large to fit in memory"));
+        }
+        self.data[start..start + bytes.len()].copy_from_slice(bytes);
+        self.here += bytes.len() as u32;
+        self.end = self.end.max(self.here);
+        Ok(())
+    }
+
+    /// Write the instruction words of an operation.
+    pub fn emit_operation(&mut self, operation: &Operation, location: &Location) -> OktoResult<()> {
+        match operation.to_bytes() {
+            Some(bytes) => self.emit(&bytes, location),
+            None => Err(location.error(format!("cannot encode {:?}", operation))),
+        }
+    }
+
+    /// Define a label at the current address.
+    pub fn define(&mut self, name: &str, location: &Location) -> OktoResult<()> {
+        let address = self.here as Address;
+        self.define_at(name, address, location)
+    }
+
+    /// Define a label at the given address.
+    pub fn define_at(
+        &mut self,
+        name: &str,
+        address: Address,
+        location: &Location,
+    ) -> OktoResult<()> {
+        if self.labels.contains_key(name) {
+            return Err(location.error(format!("the label '{}' is already defined", name)));
+        }
+        self.labels.insert(String::from(name), address);
+        Ok(())
+    }
+
+    /// Returns the address of a label, if it has been defined.
+    pub fn label(&self, name: &str) -> Option<Address> {
+        self.labels.get(name).cloned()
+    }
+
+    /// Fill in the address of a label in the word at the given address once
+    /// the label is defined.
+    pub fn refer(&mut self, address: Address, field: Field, name: &str, location: &Location) {
+        self.references.push(Reference {
+            address,
+            field,
+            name: String::from(name),
+            location: location.clone(),
+        });
+    }
+
+    /// Write an address into the word at the given address, keeping the
+    /// opcode bits outside of the field.
+    pub fn patch(
+        &mut self,
+        address: Address,
+        field: Field,
+        value: u32,
+        location: &Location,
+    ) -> OktoResult<()> {
+        if value > field.max() {
+            return Err(location.error(format!(
+                "address 0x{:X} is out of range for this instruction",
+                value
+            )));
+        }
+        let index = address as usize;
+        let word = (self.data[index] as u32) << 8 | self.data[index + 1] as u32;
+        let word = match field {
+            Field::Address => (word & 0xF000) | value,
+            Field::LongAddress => value,
+        };
+        self.data[index] = (word >> 8) as u8;
+        self.data[index + 1] = word as u8;
+        Ok(())
+    }
+
+    /// Resolve every reference to a label and return the assembled program.
+    pub fn finish(mut self) -> OktoResult<Program> {
+        for reference in std::mem::take(&mut self.references) {
+            let value = match self.labels.get(&reference.name) {
+                Some(&value) => value as u32,
+                None => {
+                    return Err(reference
+                        .location
+                        .error(format!("undefined label '{}'", reference.name)))
+                }
+            };
+            self.patch(
+                reference.address,
+                reference.field,
+                value,
+                &reference.location,
+            )?;
+        }
+
+        let start = DEFAULT_PC_ADDRESS as usize;
+        Ok(Program {
+            rom: self.data[start..self.end as usize].to_vec(),
+            labels: self.labels,
+        })
+    }
+}
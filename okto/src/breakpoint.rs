@@ -0,0 +1,481 @@
+//! Breakpoints and watchpoints checked by `Machine::step`.
+//!
+//! Program counter and operation breakpoints stop the machine before the
+//! instruction executes, while memory and register watchpoints stop it right
+//! after the instruction that touched the watched location. Memory watchpoints
+//! see the implicit accesses of instructions such as `Draw`, `MemStoreBcd`,
+//! `MemStoreRegs` and `MemLoadRegs` as reported by `Machine::memory_accesses`.
+//!
+//! Any breakpoint can carry a condition written in the expression language of
+//! the `expression` module and a hit condition, and can log a message instead
+//! of stopping, as described on `BreakpointEntry`.
+use super::cpu::{Address, Cpu, Operation, Register, NUM_REGISTERS};
+use super::expression::{Context, Expression, LogMessage};
+use super::isa::OperationInfo;
+use super::memory::{AccessKind, MemoryAccess};
+
+/// Identifier handed out when a breakpoint is added.
+pub type BreakpointId = u32;
+
+/// Kinds of memory access a watchpoint reacts to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WatchAccess {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl WatchAccess {
+    /// Returns true if an access of the given kind should trigger the
+    /// watchpoint.
+    pub fn matches(self, kind: AccessKind) -> bool {
+        match self {
+            WatchAccess::Read => kind == AccessKind::Read,
+            WatchAccess::Write => kind == AccessKind::Write,
+            WatchAccess::ReadWrite => true,
+        }
+    }
+}
+
+/// CPU registers that can be watched for changes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WatchedRegister {
+    /// General purpose register VX
+    V(Register),
+    /// Index register
+    I,
+    /// Stack pointer
+    Sp,
+}
+
+impl WatchedRegister {
+    /// Returns the current value of the register.
+    pub fn value(self, cpu: &Cpu) -> u16 {
+        match self {
+            WatchedRegister::V(vx) => cpu.v[vx as usize % NUM_REGISTERS] as u16,
+            WatchedRegister::I => cpu.i,
+            WatchedRegister::Sp => cpu.sp as u16,
+        }
+    }
+}
+
+/// A condition under which the machine stops.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Breakpoint {
+    /// Stop before executing the instruction at the address.
+    Pc(Address),
+    /// Stop after an instruction accesses memory between `start` and `end`
+    /// inclusive.
+    Memory {
+        start: Address,
+        end: Address,
+        access: WatchAccess,
+    },
+    /// Stop after an instruction changes the register.
+    Register(WatchedRegister),
+    /// Stop before executing any operation of the kind described by the
+    /// instruction set entry, for example `isa::find("Draw")`.
+    Operation(&'static OperationInfo),
+}
+
+impl Breakpoint {
+    /// Returns true if the breakpoint is checked before the instruction
+    /// executes rather than after.
+    pub fn is_before_execution(&self) -> bool {
+        match *self {
+            Breakpoint::Pc(_) | Breakpoint::Operation(_) => true,
+            Breakpoint::Memory { .. } | Breakpoint::Register(_) => false,
+        }
+    }
+
+    /// Returns why the breakpoint triggers before the given operation at the
+    /// given address executes, if it does.
+    fn reason_before(&self, pc: Address, operation: &Operation) -> Option<HitReason> {
+        match *self {
+            Breakpoint::Pc(address) if address == pc => Some(HitReason::Pc(pc)),
+            Breakpoint::Operation(info) if info.name == operation.name() => {
+                Some(HitReason::Operation(*operation))
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns why the watchpoint triggers given the memory accessed by an
+    /// instruction and the registers before and after it executed, if it
+    /// does.
+    fn reason_after(
+        &self,
+        accesses: &[MemoryAccess],
+        before: &Cpu,
+        after: &Cpu,
+    ) -> Option<HitReason> {
+        match *self {
+            Breakpoint::Memory { start, end, access } => {
+                accesses.iter().find_map(|memory_access| {
+                    if !access.matches(memory_access.kind) {
+                        return None;
+                    }
+                    first_address_in_range(memory_access, start, end).map(|address| {
+                        match memory_access.kind {
+                            AccessKind::Read => HitReason::MemoryRead(address),
+                            AccessKind::Write => HitReason::MemoryWrite(address),
+                        }
+                    })
+                })
+            }
+            Breakpoint::Register(register) => {
+                let (old, new) = (register.value(before), register.value(after));
+                if old == new {
+                    return None;
+                }
+                Some(HitReason::RegisterChanged { register, old, new })
+            }
+            Breakpoint::Pc(_) | Breakpoint::Operation(_) => None,
+        }
+    }
+}
+
+/// What triggered a breakpoint.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HitReason {
+    /// The program counter reached the address.
+    Pc(Address),
+    /// An instruction read the address.
+    MemoryRead(Address),
+    /// An instruction wrote the address.
+    MemoryWrite(Address),
+    /// An instruction changed the register.
+    RegisterChanged {
+        register: WatchedRegister,
+        old: u16,
+        new: u16,
+    },
+    /// The operation is about to execute.
+    Operation(Operation),
+}
+
+/// Report of a breakpoint that stopped the machine.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BreakpointHit {
+    /// The breakpoint that triggered
+    pub id: BreakpointId,
+    /// What triggered it
+    pub reason: HitReason,
+}
+
+/// Condition on the number of times a breakpoint has been reached, checked
+/// after the count is incremented.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HitCondition {
+    /// Stop every time
+    Always,
+    /// Stop only when reached for exactly the given time
+    Equal(u64),
+    /// Stop every time once reached at least the given number of times
+    AtLeast(u64),
+    /// Stop every given number of times
+    Multiple(u64),
+}
+
+impl HitCondition {
+    /// Returns true if a breakpoint reached the given number of times should
+    /// trigger.
+    pub fn matches(self, hits: u64) -> bool {
+        match self {
+            HitCondition::Always => true,
+            HitCondition::Equal(count) => hits == count,
+            HitCondition::AtLeast(count) => hits >= count,
+            HitCondition::Multiple(count) => count != 0 && hits.is_multiple_of(count),
+        }
+    }
+}
+
+/// A breakpoint together with the conditions under which it triggers and
+/// what it does when it does.
+///
+/// A breakpoint is reached when its location matches and its condition, if
+/// any, evaluates to a non-zero value. Each time it is reached its hit count
+/// is incremented, and it triggers if the hit condition matches the new
+/// count. A tracepoint, which has a log message, then appends the rendered
+/// message to the machine's log instead of stopping.
+///
+/// # Examples
+///
+/// ```
+/// # extern crate okto;
+/// # use okto::breakpoint::{Breakpoint, BreakpointEntry, HitCondition};
+/// # use okto::expression::{Expression, LogMessage};
+/// # use okto::machine::Machine;
+/// let mut machine = Machine::new();
+/// // V0 += 1 and loop forever
+/// machine.memory.data[0x200..0x204].copy_from_slice(&[0x70, 0x01, 0x12, 0x00]);
+///
+/// let stop = BreakpointEntry::new(Breakpoint::Pc(0x202))
+///     .when(Expression::parse("v0 >= 2").unwrap())
+///     .hit_condition(HitCondition::Multiple(2));
+/// let trace = BreakpointEntry::new(Breakpoint::Pc(0x200))
+///     .log(LogMessage::parse("v0 = {v0}").unwrap());
+/// let stop_id = machine.add_breakpoint(stop);
+/// machine.add_breakpoint(trace);
+///
+/// while machine.breakpoint_hit().is_none() {
+///     machine.step().unwrap();
+/// }
+/// assert_eq!(3, machine.cpu.v[0x0]);
+/// assert_eq!(Some(2), machine.breakpoints().get(stop_id).map(|entry| entry.hits()));
+/// assert_eq!(vec!["v0 = 0", "v0 = 1", "v0 = 2"], machine.take_log_messages());
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct BreakpointEntry {
+    /// Where the breakpoint applies
+    pub breakpoint: Breakpoint,
+    /// Expression that must be non-zero for the breakpoint to be reached
+    pub condition: Option<Expression>,
+    /// Condition on the hit count for the breakpoint to trigger
+    pub hit_condition: HitCondition,
+    /// Message logged instead of stopping, making this a tracepoint
+    pub log_message: Option<LogMessage>,
+    /// Number of times the breakpoint has been reached
+    hits: u64,
+}
+
+impl BreakpointEntry {
+    /// Create an unconditional breakpoint that stops every time it is
+    /// reached.
+    pub fn new(breakpoint: Breakpoint) -> Self {
+        Self {
+            breakpoint,
+            condition: None,
+            hit_condition: HitCondition::Always,
+            log_message: None,
+            hits: 0,
+        }
+    }
+
+    /// Only reach the breakpoint when the condition is non-zero.
+    pub fn when(mut self, condition: Expression) -> Self {
+        self.condition = Some(condition);
+        self
+    }
+
+    /// Only trigger the breakpoint when the hit count matches.
+    pub fn hit_condition(mut self, hit_condition: HitCondition) -> Self {
+        self.hit_condition = hit_condition;
+        self
+    }
+
+    /// Log the message instead of stopping when the breakpoint triggers.
+    pub fn log(mut self, message: LogMessage) -> Self {
+        self.log_message = Some(message);
+        self
+    }
+
+    /// Returns the number of times the breakpoint has been reached.
+    pub fn hits(&self) -> u64 {
+        self.hits
+    }
+
+    /// Returns true if the breakpoint logs a message instead of stopping.
+    pub fn is_tracepoint(&self) -> bool {
+        self.log_message.is_some()
+    }
+
+    /// Count a match of the breakpoint's location, returning true if it
+    /// should stop the machine. Log messages are appended to `log`.
+    fn reach(&mut self, context: &Context, log: &mut Vec<String>) -> bool {
+        if let Some(ref condition) = self.condition {
+            if !condition.is_true(context) {
+                return false;
+            }
+        }
+
+        self.hits += 1;
+        if !self.hit_condition.matches(self.hits) {
+            return false;
+        }
+
+        match self.log_message {
+            Some(ref message) => {
+                log.push(message.render(context));
+                false
+            }
+            None => true,
+        }
+    }
+}
+
+impl From<Breakpoint> for BreakpointEntry {
+    fn from(breakpoint: Breakpoint) -> Self {
+        Self::new(breakpoint)
+    }
+}
+
+/// Set of breakpoints, each with its own identifier.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Breakpoints {
+    entries: Vec<(BreakpointId, BreakpointEntry)>,
+    next_id: BreakpointId,
+}
+
+impl Breakpoints {
+    /// Create an empty set of breakpoints.
+    pub fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+            next_id: 1,
+        }
+    }
+
+    /// Add a breakpoint and return its identifier.
+    pub fn add<B: Into<BreakpointEntry>>(&mut self, breakpoint: B) -> BreakpointId {
+        let id = self.next_id.max(1);
+        self.next_id = id + 1;
+        self.entries.push((id, breakpoint.into()));
+        id
+    }
+
+    /// Remove the breakpoint with the given identifier, returning it if it
+    /// existed.
+    pub fn remove(&mut self, id: BreakpointId) -> Option<BreakpointEntry> {
+        let index = self
+            .entries
+            .iter()
+            .position(|&(entry_id, _)| entry_id == id)?;
+        Some(self.entries.remove(index).1)
+    }
+
+    /// Remove every breakpoint.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Returns the breakpoint with the given identifier.
+    pub fn get(&self, id: BreakpointId) -> Option<&BreakpointEntry> {
+        self.entries
+            .iter()
+            .find(|&&(entry_id, _)| entry_id == id)
+            .map(|(_, entry)| entry)
+    }
+
+    /// Returns the breakpoint with the given identifier for modification.
+    pub fn get_mut(&mut self, id: BreakpointId) -> Option<&mut BreakpointEntry> {
+        self.entries
+            .iter_mut()
+            .find(|&&mut (entry_id, _)| entry_id == id)
+            .map(|(_, entry)| entry)
+    }
+
+    /// Returns the breakpoints in the order they were added.
+    pub fn iter(&self) -> impl Iterator<Item = &(BreakpointId, BreakpointEntry)> {
+        self.entries.iter()
+    }
+
+    /// Returns the number of breakpoints.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns true if there are no breakpoints.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns true if any breakpoint is checked after execution, meaning the
+    /// machine must capture the registers before each instruction.
+    pub fn has_watchpoints(&self) -> bool {
+        self.entries
+            .iter()
+            .any(|(_, entry)| !entry.breakpoint.is_before_execution())
+    }
+
+    /// Check the breakpoints that apply before the given operation at the
+    /// given address executes. Every matching breakpoint is counted and every
+    /// triggered tracepoint logged, and the first one that stops is returned.
+    pub fn check_before(
+        &mut self,
+        pc: Address,
+        operation: &Operation,
+        context: &Context,
+        log: &mut Vec<String>,
+    ) -> Option<BreakpointHit> {
+        let mut hit = None;
+
+        for &mut (id, ref mut entry) in self.entries.iter_mut() {
+            let reason = match entry.breakpoint.reason_before(pc, operation) {
+                Some(reason) => reason,
+                None => continue,
+            };
+            if entry.reach(context, log) && hit.is_none() {
+                hit = Some(BreakpointHit { id, reason });
+            }
+        }
+
+        hit
+    }
+
+    /// Check the watchpoints against the memory accessed by an instruction and
+    /// the registers before and after it executed. Conditions are evaluated
+    /// against the state after execution.
+    pub fn check_after(
+        &mut self,
+        accesses: &[MemoryAccess],
+        before: &Cpu,
+        context: &Context,
+        log: &mut Vec<String>,
+    ) -> Option<BreakpointHit> {
+        let mut hit = None;
+
+        for &mut (id, ref mut entry) in self.entries.iter_mut() {
+            let reason = match entry.breakpoint.reason_after(accesses, before, context.cpu) {
+                Some(reason) => reason,
+                None => continue,
+            };
+            if entry.reach(context, log) && hit.is_none() {
+                hit = Some(BreakpointHit { id, reason });
+            }
+        }
+
+        hit
+    }
+
+    /// Check the breakpoints after an instruction has been undone, given the
+    /// memory it accessed and the registers before it executed, which are
+    /// those in `context`, and after. Breakpoints that would have stopped the
+    /// machine before or after the instruction executed trigger when their
+    /// condition holds in `context`. Hit counts are left alone and tracepoints
+    /// are ignored, so that running backwards does not disturb them.
+    pub fn check_reverse(
+        &self,
+        pc: Address,
+        operation: &Operation,
+        accesses: &[MemoryAccess],
+        after: &Cpu,
+        context: &Context,
+    ) -> Option<BreakpointHit> {
+        self.entries.iter().find_map(|&(id, ref entry)| {
+            if entry.is_tracepoint() {
+                return None;
+            }
+            let reason = entry
+                .breakpoint
+                .reason_before(pc, operation)
+                .or_else(|| entry.breakpoint.reason_after(accesses, context.cpu, after))?;
+            let condition_holds = entry
+                .condition
+                .as_ref()
+                .is_none_or(|condition| condition.is_true(context));
+            if !condition_holds {
+                return None;
+            }
+            Some(BreakpointHit { id, reason })
+        })
+    }
+}
+
+/// Returns the first address touched by the access that lies between `start`
+/// and `end` inclusive.
+fn first_address_in_range(access: &MemoryAccess, start: Address, end: Address) -> Option<Address> {
+    (0..access.length)
+        .map(|offset| access.address.wrapping_add(offset as Address))
+        .find(|&address| address >= start && address <= end)
+}
@@ -0,0 +1,815 @@
+//! Assembler for Chipper, the syntax of the original SuperChip assembler.
+//!
+//! Each line holds an optional label, an instruction or directive and an
+//! optional comment starting with `;`. Labels end with a colon, or start in
+//! the first column of the line. Instructions are written with the Cowgod
+//! mnemonics listed in `isa` for the CHIP-8 and SuperChip operations, such as
+//! `LD V0, #12` or `DRW V1, V2, 5`, and `SHR` and `SHL` also take a single
+//! register. Mnemonics, registers and symbols are not case sensitive.
+//!
+//! Numbers are decimal, `#` hexadecimal, `$` binary, where `.` can be written
+//! for 0, or `@` octal. Operands can be expressions of numbers and symbols
+//! using parentheses, unary `-` and `~` and the binary operators `*`, `/`,
+//! `%`, `+`, `-`, `<<`, `>>`, `&`, `^` and `|` with the precedence they have
+//! in C. Symbols can be used before they are defined. The directives are:
+//!
+//! * `NAME EQU VALUE` defines a symbol
+//! * `DB VALUE, ...` writes bytes and `DW VALUE, ...` writes big endian words
+//! * `DA 'TEXT'` writes the characters of a string, which `DB` also accepts
+//! * `ALIGN ON`, the default, pads instructions to even addresses and
+//!   `ALIGN OFF` stops padding them
+//! * `INCLUDE FILE` assembles another file in place, found relative to the
+//!   including file
+//! * `ORG ADDRESS` continues at the address and `END` stops reading the file
+//! * `OPTION` lines are accepted and ignored
+//!
+//! ```
+//! # extern crate okto;
+//! # use okto::chipper;
+//! let source = "
+//! COUNT   EQU 3
+//! START:  LD   V0, COUNT
+//! LOOP    ADD  V0, -1
+//!         SE   V0, 0
+//!         JP   LOOP
+//!         EXIT
+//! ";
+//! let program = chipper::assemble("countdown.chp", source, |_| unreachable!()).unwrap();
+//! assert_eq!(
+//!     vec![0x60, 0x03, 0x70, 0xFF, 0x30, 0x00, 0x12, 0x02, 0x00, 0xFD],
+//!     program.rom
+//! );
+//! ```
+use super::assembler::{self, Image, Location, Program};
+use super::cpu::{Address, Register};
+use super::isa::{self, Operand, OperandKind, OperationInfo, Platform};
+use super::OktoResult;
+
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::path::Path;
+use std::rc::Rc;
+
+/// Directives, which can not be used as labels in the first column.
+const DIRECTIVES: [&str; 9] = [
+    "ALIGN", "DA", "DB", "DW", "END", "EQU", "INCLUDE", "OPTION", "ORG",
+];
+
+/// Binary operators of expressions with their precedence, loosest first.
+const OPERATORS: [(&str, u8); 10] = [
+    ("|", 0),
+    ("^", 1),
+    ("&", 2),
+    ("<<", 3),
+    (">>", 3),
+    ("+", 4),
+    ("-", 4),
+    ("*", 5),
+    ("/", 5),
+    ("%", 5),
+];
+
+/// Most passes made to settle the value of every symbol before the final
+/// pass.
+const MAX_PASSES: usize = 8;
+
+/// Deepest nesting of `INCLUDE` directives, which stops a file that
+/// includes itself.
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// Assemble Chipper source into a ROM. `file` names the source in errors,
+/// and `read` returns the contents of each file it includes.
+pub fn assemble<F>(file: &str, source: &str, read: F) -> OktoResult<Program>
+where
+    F: FnMut(&str) -> io::Result<String>,
+{
+    let file: Rc<str> = Rc::from(file);
+    let mut assembler = Assembler::new(read, file.clone());
+
+    // The first passes find the value of every symbol, so that the final one
+    // can use symbols defined further on. Symbols that depend on others
+    // defined later can take more than one pass to settle.
+    let mut settled = false;
+    for _ in 0..MAX_PASSES {
+        let previous = assembler.symbols.clone();
+        assembler.pass(&file, source, false)?;
+        if assembler.symbols == previous {
+            settled = true;
+            break;
+        }
+    }
+    if !settled {
+        let location = Location {
+            file: file.clone(),
+            line: 1,
+            column: 1,
+        };
+        return Err(location.error("symbol values did not settle"));
+    }
+    assembler.pass(&file, source, true)?;
+    assembler.image.finish()
+}
+
+/// Text from a line of source along with the column it starts at.
+#[derive(Clone, Copy, Debug)]
+struct Span<'a> {
+    text: &'a str,
+    column: usize,
+}
+
+impl<'a> Span<'a> {
+    /// Returns the text between the given byte offsets.
+    fn slice(self, start: usize, end: usize) -> Self {
+        Span {
+            text: &self.text[start..end],
+            column: self.column + start,
+        }
+    }
+
+    /// Returns the text without surrounding whitespace.
+    fn trim(self) -> Self {
+        let start = self.text.len() - self.text.trim_start().len();
+        let end = self.text.trim_end().len().max(start);
+        self.slice(start, end)
+    }
+
+    /// Split off the first word, returning it and the rest of the text.
+    fn split_word(self) -> (Self, Self) {
+        let text = self.trim();
+        let end = text
+            .text
+            .find(char::is_whitespace)
+            .unwrap_or(text.text.len());
+        (text.slice(0, end), text.slice(end, text.text.len()))
+    }
+
+    /// Returns the contents of a quoted string, or `None` if the text is not
+    /// a string.
+    fn string(self) -> Option<&'a str> {
+        let quote = self
+            .text
+            .chars()
+            .next()
+            .filter(|&c| c == '\'' || c == '"')?;
+        if self.text.len() >= 2 && self.text.ends_with(quote) {
+            Some(&self.text[1..self.text.len() - 1])
+        } else {
+            None
+        }
+    }
+}
+
+/// Returns the line without its comment.
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    for (index, c) in line.char_indices() {
+        match quote {
+            Some(open) if c == open => quote = None,
+            Some(_) => {}
+            None if c == '\'' || c == '"' => quote = Some(c),
+            None if c == ';' => return &line[..index],
+            None => {}
+        }
+    }
+    line
+}
+
+/// Parse a decimal, `#` hexadecimal, `$` binary or `@` octal number.
+fn parse_number(text: &str) -> Option<i64> {
+    if let Some(hex) = text.strip_prefix('#') {
+        i64::from_str_radix(hex, 16).ok()
+    } else if let Some(binary) = text.strip_prefix('$') {
+        i64::from_str_radix(&binary.replace('.', "0"), 2).ok()
+    } else if let Some(octal) = text.strip_prefix('@') {
+        i64::from_str_radix(octal, 8).ok()
+    } else if text.bytes().all(|b| b.is_ascii_digit()) {
+        text.parse().ok()
+    } else {
+        None
+    }
+}
+
+/// Returns the register named by the text, such as `V3` or `va`.
+fn register(text: &str) -> Option<Register> {
+    let mut chars = text.chars();
+    match (chars.next(), chars.next(), chars.next()) {
+        (Some('V'), Some(digit), None) | (Some('v'), Some(digit), None) => {
+            digit.to_digit(16).map(|value| value as Register)
+        }
+        _ => None,
+    }
+}
+
+/// Returns true if the text can name a label or symbol.
+fn is_symbol(text: &str) -> bool {
+    text.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && text
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+        && register(text).is_none()
+}
+
+/// An operand of an instruction mnemonic.
+#[derive(Clone, Copy, Debug)]
+enum Part {
+    /// Word written as is, such as `DT` or `[I]`
+    Word(&'static str),
+    /// Operand value
+    Operand(Operand),
+}
+
+/// Instruction mnemonic split into its name and operands.
+#[derive(Clone, Debug)]
+struct Template {
+    info: &'static OperationInfo,
+    mnemonic: &'static str,
+    parts: Vec<Part>,
+}
+
+impl Template {
+    /// Returns true if the operands can be written in the template.
+    fn matches(&self, mnemonic: &str, operands: &[Span]) -> bool {
+        self.mnemonic == mnemonic
+            && self.parts.len() == operands.len()
+            && self
+                .parts
+                .iter()
+                .zip(operands)
+                .all(|(part, operand)| match *part {
+                    Part::Word(word) => operand.text.eq_ignore_ascii_case(word),
+                    Part::Operand(operand_info) => {
+                        (operand_info.kind == OperandKind::Register)
+                            == register(operand.text).is_some()
+                    }
+                })
+    }
+}
+
+/// Returns the mnemonics of the CHIP-8 and SuperChip instructions, those
+/// with the most fixed words first so that, for example, `LD V0, DT` is not
+/// read as loading the value of a symbol named `DT`.
+fn templates() -> Vec<Template> {
+    let mut templates: Vec<Template> = isa::OPERATIONS
+        .iter()
+        .filter(|info| info.is_available(Platform::Chip8) || info.is_available(Platform::SuperChip))
+        .map(|info| {
+            let template = info.cowgod;
+            let (mnemonic, operands) = match template.find(' ') {
+                Some(end) => (&template[..end], &template[end + 1..]),
+                None => (template, ""),
+            };
+            let parts = operands
+                .split(", ")
+                .filter(|operand| !operand.is_empty())
+                .map(|operand| {
+                    match info
+                        .operands
+                        .iter()
+                        .find(|o| operand == format!("{{{}}}", o.name))
+                    {
+                        Some(&operand) => Part::Operand(operand),
+                        None => Part::Word(operand),
+                    }
+                })
+                .collect();
+            Template {
+                info,
+                mnemonic,
+                parts,
+            }
+        })
+        .collect();
+
+    let words = |template: &Template| {
+        template
+            .parts
+            .iter()
+            .filter(|part| matches!(part, Part::Word(_)))
+            .count()
+    };
+    templates.sort_by_key(|template| Reverse(words(template)));
+    templates
+}
+
+/// State of the assembler while it reads the source.
+struct Assembler<F> {
+    /// Reads included files
+    read: F,
+    /// Mnemonics of every instruction
+    templates: Vec<Template>,
+    /// Contents of the files included so far, by path
+    sources: HashMap<String, Rc<str>>,
+    /// Value of every symbol, by upper case name, kept between passes
+    symbols: HashMap<String, i64>,
+    /// Upper case names of the symbols defined in this pass
+    defined: HashSet<String>,
+    /// Memory being assembled
+    image: Image,
+    /// True on the final pass, when every symbol must be defined
+    final_pass: bool,
+    /// True if instructions are padded to even addresses
+    align: bool,
+    /// Labels on lines of their own, which take the address of the next
+    /// statement once any padding before it is known. Each has its upper case
+    /// name, its name as written and where it is defined.
+    pending_labels: Vec<(String, String, Location)>,
+    /// File being read
+    file: Rc<str>,
+    /// Line being read, starting at 1
+    line: usize,
+    /// Number of files being included
+    depth: usize,
+}
+
+impl<F> Assembler<F>
+where
+    F: FnMut(&str) -> io::Result<String>,
+{
+    fn new(read: F, file: Rc<str>) -> Self {
+        Self {
+            read,
+            templates: templates(),
+            sources: HashMap::new(),
+            symbols: HashMap::new(),
+            defined: HashSet::new(),
+            image: Image::new(),
+            final_pass: false,
+            align: true,
+            pending_labels: Vec::new(),
+            file,
+            line: 0,
+            depth: 0,
+        }
+    }
+
+    /// Assemble the whole source from the start.
+    fn pass(&mut self, file: &Rc<str>, source: &str, final_pass: bool) -> OktoResult<()> {
+        self.image = Image::new();
+        self.defined.clear();
+        self.final_pass = final_pass;
+        self.align = true;
+        self.file = file.clone();
+        self.source(source)?;
+        self.define_pending_labels()
+    }
+
+    /// Returns the location of the given column of the current line.
+    fn location(&self, column: usize) -> Location {
+        Location {
+            file: self.file.clone(),
+            line: self.line,
+            column,
+        }
+    }
+
+    /// Assemble the lines of a file, stopping at `END`.
+    fn source(&mut self, source: &str) -> OktoResult<()> {
+        for (index, line) in source.lines().enumerate() {
+            self.line = index + 1;
+            if !self.statement(line)? {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns true if the word is an instruction mnemonic.
+    fn is_instruction(&self, word: &str) -> bool {
+        self.templates
+            .iter()
+            .any(|template| template.mnemonic.eq_ignore_ascii_case(word))
+    }
+
+    /// Returns true if the word is an instruction mnemonic or directive.
+    fn is_keyword(&self, word: &str) -> bool {
+        self.is_instruction(word)
+            || DIRECTIVES
+                .iter()
+                .any(|directive| directive.eq_ignore_ascii_case(word))
+    }
+
+    /// Assemble a line, returning false if it ends the file.
+    fn statement(&mut self, line: &str) -> OktoResult<bool> {
+        let line = Span {
+            text: strip_comment(line),
+            column: 1,
+        };
+        let (mut word, mut rest) = line.split_word();
+        if word.text.is_empty() {
+            return Ok(true);
+        }
+
+        let indented = line.text.starts_with(char::is_whitespace);
+        let label = if word.text.ends_with(':') {
+            Some(word.slice(0, word.text.len() - 1))
+        } else if !indented && !self.is_keyword(word.text) {
+            Some(word)
+        } else {
+            None
+        };
+        if label.is_some() {
+            let (next, after) = rest.split_word();
+            word = next;
+            rest = after;
+        }
+
+        let mnemonic = word.text.to_ascii_uppercase();
+        if mnemonic == "EQU" {
+            let name =
+                label.ok_or_else(|| self.location(word.column).error("EQU without a name"))?;
+            let value = self.evaluate(rest.trim())?;
+            self.define(name, value)?;
+            return Ok(true);
+        }
+
+        if let Some(name) = label {
+            self.define(name, i64::from(self.image.here()))?;
+            self.pending_labels.push((
+                name.text.to_ascii_uppercase(),
+                String::from(name.text),
+                self.location(name.column),
+            ));
+        }
+        if word.text.is_empty() {
+            return Ok(true);
+        }
+
+        if self.align && self.is_instruction(&mnemonic) && self.image.here() % 2 == 1 {
+            self.image.emit(&[0], &self.location(word.column))?;
+        }
+        self.define_pending_labels()?;
+
+        let operands = self.operands(rest)?;
+        match mnemonic.as_str() {
+            "DB" | "DA" => {
+                for operand in operands {
+                    let location = self.location(operand.column);
+                    match operand.string() {
+                        Some(text) => self.image.emit(text.as_bytes(), &location)?,
+                        None => {
+                            let value = self.evaluate(operand)?;
+                            let byte = self.check_operand(value, OperandKind::Byte, &location)?;
+                            self.image.emit(&[byte as u8], &location)?;
+                        }
+                    }
+                }
+            }
+            "DW" => {
+                for operand in operands {
+                    let location = self.location(operand.column);
+                    let value = self.evaluate(operand)?;
+                    if self.final_pass && !(-0x8000..=0xFFFF).contains(&value) {
+                        return Err(location.error(format!("{} does not fit in a word", value)));
+                    }
+                    self.image
+                        .emit(&[(value >> 8) as u8, value as u8], &location)?;
+                }
+            }
+            "ALIGN" => {
+                let operand = self.single_operand(word, &operands)?;
+                self.align = if operand.text.eq_ignore_ascii_case("ON") {
+                    true
+                } else if operand.text.eq_ignore_ascii_case("OFF") {
+                    false
+                } else {
+                    return Err(self
+                        .location(operand.column)
+                        .error(format!("expected ON or OFF, found '{}'", operand.text)));
+                };
+            }
+            "INCLUDE" => {
+                let operand = self.single_operand(word, &operands)?;
+                self.include(operand)?;
+            }
+            "ORG" => {
+                let operand = self.single_operand(word, &operands)?;
+                let address = self.evaluate(operand)?.max(0).min(i64::from(u32::MAX));
+                let moved = self
+                    .image
+                    .set_here(address as u32, &self.location(operand.column));
+                if self.final_pass {
+                    moved?;
+                }
+            }
+            "END" => return Ok(false),
+            "OPTION" => {}
+            _ => self.instruction(word, &mnemonic, operands)?,
+        }
+
+        Ok(true)
+    }
+
+    /// Split the operands of a line at the commas between them.
+    fn operands<'a>(&self, text: Span<'a>) -> OktoResult<Vec<Span<'a>>> {
+        let text = text.trim();
+        if text.text.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut operands = Vec::new();
+        let mut quote = None;
+        let mut start = 0;
+        for (index, c) in text.text.char_indices() {
+            match quote {
+                Some(open) if c == open => quote = None,
+                Some(_) => {}
+                None if c == '\'' || c == '"' => quote = Some(c),
+                None if c == ',' => {
+                    operands.push(text.slice(start, index).trim());
+                    start = index + 1;
+                }
+                None => {}
+            }
+        }
+        operands.push(text.slice(start, text.text.len()).trim());
+
+        match operands.iter().find(|operand| operand.text.is_empty()) {
+            Some(operand) => Err(self.location(operand.column).error("missing operand")),
+            None => Ok(operands),
+        }
+    }
+
+    /// Returns the only operand of a directive.
+    fn single_operand<'a>(&self, directive: Span, operands: &[Span<'a>]) -> OktoResult<Span<'a>> {
+        match *operands {
+            [operand] => Ok(operand),
+            _ => Err(self.location(directive.column).error(format!(
+                "{} takes one operand",
+                directive.text.to_ascii_uppercase()
+            ))),
+        }
+    }
+
+    /// Define a label or `EQU` symbol.
+    fn define(&mut self, name: Span, value: i64) -> OktoResult<()> {
+        let location = self.location(name.column);
+        if !is_symbol(name.text) || self.is_keyword(name.text) {
+            return Err(location.error(format!("'{}' cannot be used as a name", name.text)));
+        }
+        let key = name.text.to_ascii_uppercase();
+        if !self.defined.insert(key.clone()) {
+            return Err(location.error(format!("the symbol '{}' is already defined", name.text)));
+        }
+        self.symbols.insert(key, value);
+        Ok(())
+    }
+
+    /// Give the labels waiting for a statement the current address.
+    fn define_pending_labels(&mut self) -> OktoResult<()> {
+        let address = self.image.here();
+        for (key, name, location) in self.pending_labels.drain(..) {
+            self.symbols.insert(key, i64::from(address));
+            if self.final_pass {
+                self.image.define_at(&name, address as Address, &location)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Assemble the file named by an `INCLUDE` directive.
+    fn include(&mut self, operand: Span) -> OktoResult<()> {
+        let location = self.location(operand.column);
+        if self.depth >= MAX_INCLUDE_DEPTH {
+            return Err(location.error("files are included too deeply"));
+        }
+        let name = operand.string().unwrap_or(operand.text);
+        let path = match Path::new(&*self.file).parent() {
+            Some(directory) => directory.join(name).to_string_lossy().into_owned(),
+            None => String::from(name),
+        };
+
+        let source = match self.sources.get(&path) {
+            Some(source) => source.clone(),
+            None => {
+                let source: Rc<str> = (self.read)(&path)
+                    .map_err(|error| location.error(format!("cannot read '{}': {}", path, error)))?
+                    .into();
+                self.sources.insert(path.clone(), source.clone());
+                source
+            }
+        };
+
+        let file = std::mem::replace(&mut self.file, Rc::from(path));
+        let line = self.line;
+        self.depth += 1;
+        let result = self.source(&source);
+        self.depth -= 1;
+        self.file = file;
+        self.line = line;
+        result
+    }
+
+    /// Assemble an instruction.
+    fn instruction(&mut self, word: Span, mnemonic: &str, operands: Vec<Span>) -> OktoResult<()> {
+        let location = self.location(word.column);
+        if !self.is_instruction(mnemonic) {
+            return Err(location.error(format!("unknown instruction '{}'", word.text)));
+        }
+        let operands = match operands.as_slice() {
+            [register] if mnemonic == "SHR" || mnemonic == "SHL" => vec![*register, *register],
+            _ => operands,
+        };
+
+        let template = self
+            .templates
+            .iter()
+            .find(|template| template.matches(mnemonic, &operands))
+            .ok_or_else(|| location.error(format!("invalid operands for {}", mnemonic)))?;
+        let mut values = vec![0; template.info.operands.len()];
+        for (part, operand) in template.parts.iter().zip(&operands) {
+            let operand_info = match *part {
+                Part::Operand(operand_info) => operand_info,
+                Part::Word(_) => continue,
+            };
+            let value = match register(operand.text) {
+                Some(register) => register as u16,
+                None => {
+                    let value = self.evaluate(*operand)?;
+                    self.check_operand(value, operand_info.kind, &self.location(operand.column))?
+                }
+            };
+            if let Some(index) = template
+                .info
+                .operands
+                .iter()
+                .position(|o| o.name == operand_info.name)
+            {
+                values[index] = value;
+            }
+        }
+
+        let operation = template.info.encode(&values).ok_or_else(|| {
+            location.error(format!(
+                "operands cannot be encoded as {}",
+                template.info.cowgod
+            ))
+        })?;
+        self.image.emit_operation(&operation, &location)
+    }
+
+    /// Check a value fits in an operand. Before the final pass the value can
+    /// depend on symbols that are not defined yet, so it is not checked.
+    fn check_operand(&self, value: i64, kind: OperandKind, location: &Location) -> OktoResult<u16> {
+        match assembler::check_operand(value, kind, location) {
+            Err(_) if !self.final_pass => Ok(0),
+            result => result,
+        }
+    }
+
+    /// Returns the value of an expression.
+    fn evaluate(&self, text: Span) -> OktoResult<i64> {
+        let mut expression = Expression {
+            assembler: self,
+            tokens: self.tokenize(text)?,
+            position: 0,
+            end: text.column + text.text.len(),
+        };
+        let value = expression.binary(0)?;
+        match expression.tokens.get(expression.position) {
+            Some(token) => Err(self
+                .location(token.column)
+                .error(format!("unexpected '{}'", token.text))),
+            None => Ok(value),
+        }
+    }
+
+    /// Split an expression into numbers, symbols, operators and parentheses.
+    fn tokenize<'a>(&self, text: Span<'a>) -> OktoResult<Vec<Span<'a>>> {
+        let is_word = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '.';
+        let mut tokens = Vec::new();
+        let mut chars = text.text.char_indices().peekable();
+
+        while let Some((start, c)) = chars.next() {
+            let mut end = start + c.len_utf8();
+            if c.is_whitespace() {
+                continue;
+            } else if is_word(c) || "#$@".contains(c) {
+                while let Some(&(index, next)) = chars.peek() {
+                    if !is_word(next) {
+                        break;
+                    }
+                    end = index + next.len_utf8();
+                    chars.next();
+                }
+            } else if c == '<' || c == '>' {
+                if chars.peek().map(|&(_, next)| next) != Some(c) {
+                    return Err(self
+                        .location(text.column + start)
+                        .error(format!("unexpected '{}'", c)));
+                }
+                chars.next();
+                end += 1;
+            } else if !"()+-*/%&^|~".contains(c) {
+                return Err(self
+                    .location(text.column + start)
+                    .error(format!("unexpected '{}'", c)));
+            }
+            tokens.push(text.slice(start, end));
+        }
+
+        Ok(tokens)
+    }
+
+    /// Returns the value of a number or symbol.
+    fn value(&self, token: Span) -> OktoResult<i64> {
+        let location = self.location(token.column);
+        if token
+            .text
+            .starts_with(|c: char| c.is_ascii_digit() || "#$@".contains(c))
+        {
+            return parse_number(token.text)
+                .ok_or_else(|| location.error(format!("invalid number '{}'", token.text)));
+        }
+        if !is_symbol(token.text) {
+            return Err(location.error(format!("unexpected '{}'", token.text)));
+        }
+        match self.symbols.get(&token.text.to_ascii_uppercase()) {
+            Some(&value) => Ok(value),
+            None if self.final_pass => {
+                Err(location.error(format!("undefined symbol '{}'", token.text)))
+            }
+            None => Ok(0),
+        }
+    }
+}
+
+/// Evaluation of an operand expression by precedence climbing.
+struct Expression<'a, 'b, F> {
+    assembler: &'b Assembler<F>,
+    tokens: Vec<Span<'a>>,
+    position: usize,
+    /// Column after the end of the expression
+    end: usize,
+}
+
+impl<'a, 'b, F> Expression<'a, 'b, F>
+where
+    F: FnMut(&str) -> io::Result<String>,
+{
+    fn next(&mut self) -> OktoResult<Span<'a>> {
+        let token = self.tokens.get(self.position).cloned().ok_or_else(|| {
+            self.assembler
+                .location(self.end)
+                .error("unexpected end of expression")
+        })?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    /// Evaluate operators binding at least as tightly as the given
+    /// precedence.
+    fn binary(&mut self, min_precedence: u8) -> OktoResult<i64> {
+        let mut left = self.unary()?;
+
+        while let Some(token) = self.tokens.get(self.position).cloned() {
+            let precedence = match OPERATORS.iter().find(|&&(name, _)| name == token.text) {
+                Some(&(_, precedence)) if precedence >= min_precedence => precedence,
+                _ => break,
+            };
+            self.position += 1;
+            let right = self.binary(precedence + 1)?;
+            left = match token.text {
+                "|" => left | right,
+                "^" => left ^ right,
+                "&" => left & right,
+                "<<" => left.wrapping_shl(right as u32),
+                ">>" => left.wrapping_shr(right as u32),
+                "+" => left.wrapping_add(right),
+                "-" => left.wrapping_sub(right),
+                "*" => left.wrapping_mul(right),
+                _ if right == 0 && self.assembler.final_pass => {
+                    return Err(self
+                        .assembler
+                        .location(token.column)
+                        .error("division by zero"))
+                }
+                _ if right == 0 => 0,
+                "/" => left.wrapping_div(right),
+                _ => left.wrapping_rem(right),
+            };
+        }
+
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> OktoResult<i64> {
+        let token = self.next()?;
+        match token.text {
+            "-" => Ok(self.unary()?.wrapping_neg()),
+            "~" => Ok(!self.unary()?),
+            "(" => {
+                let value = self.binary(0)?;
+                let close = self.next()?;
+                if close.text != ")" {
+                    return Err(self
+                        .assembler
+                        .location(close.column)
+                        .error(format!("expected ')', found '{}'", close.text)));
+                }
+                Ok(value)
+            }
+            _ => self.assembler.value(token),
+        }
+    }
+}
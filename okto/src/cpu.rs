@@ -20,6 +20,10 @@ pub const MAX_NUM_STACK_ITEMS: usize = 16;
 pub const DEFAULT_PC_ADDRESS: Address = 0x200;
 /// The number of bytes in an instruction.
 pub const INSTRUCTION_BYTES: Address = 2;
+/// The number of bytes in an XO-CHIP long instruction (`F000 NNNN`).
+pub const LONG_INSTRUCTION_BYTES: Address = 4;
+/// The first word of an XO-CHIP long instruction.
+pub const LONG_INSTRUCTION_PREFIX: Instruction = 0xF000;
 /// The index of the flag register (a.k.a. VF).
 pub const FLAG_REGISTER_INDEX: usize = 0xF;
 /// The number of HP48 registers
@@ -28,6 +32,7 @@ pub const NUM_HP48_REGISTERS: usize = 8;
 pub const CPU_TICK_HZ: u32 = 500;
 
 /// Data structure encapsulating CPU state at a moment in time.
+#[derive(Clone, Debug, PartialEq)]
 pub struct Cpu {
     /// The 16 8-bit registers
     pub v: [Register; NUM_REGISTERS],
@@ -136,7 +141,7 @@ impl Cpu {
     /// assert_eq!(None, cpu.pop_stack());
     /// ```
     pub fn pop_stack(&mut self) -> Option<Address> {
-        if self.sp <= 0 {
+        if self.sp == 0 {
             return None;
         }
 
@@ -182,6 +187,12 @@ impl Cpu {
     }
 }
 
+impl Default for Cpu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 /// Parts of an instruction value that can be retrieved independently.
 pub trait InstructionParts {
     /// Returns the 4-bit x register value from an opcode.
@@ -263,8 +274,8 @@ impl InstructionParts for Instruction {
     }
 }
 
-/// Enumeration of Chip8 and SuperChip8 CPU operations
-#[derive(Debug, PartialEq)]
+/// Enumeration of Chip8, SuperChip8 and XO-CHIP CPU operations
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Operation {
     // Chip8 Opcodes
     Cls,
@@ -292,8 +303,8 @@ pub enum Operation {
     Or(Register, Register),
     And(Register, Register),
     Xor(Register, Register),
-    Shr(Register),
-    Shl(Register),
+    Shr(Register, Register),
+    Shl(Register, Register),
     RandAndImm(Register, Immediate),
     Draw(Register, Register, Nibble),
     SkipKey(Register),
@@ -313,6 +324,15 @@ pub enum Operation {
     LoadAddrBigDigit(Register),
     RplStoreRegs(Register),
     RplLoadRegs(Register),
+
+    // XO-CHIP Opcodes
+    Scu(Nibble),
+    MemStoreRange(Register, Register),
+    MemLoadRange(Register, Register),
+    LoadAddrLong(Address),
+    Plane(Nibble),
+    LoadAudio,
+    LoadPitchReg(Register),
 }
 
 impl Operation {
@@ -351,8 +371,9 @@ impl Operation {
                 0x00FD => Some(Operation::Exit),
                 0x00FE => Some(Operation::Low),
                 0x00FF => Some(Operation::High),
-                _ => match instruction & 0x00F0 {
+                _ => match instruction & 0x0FF0 {
                     0x00C0 => Some(Operation::Scd(instruction.nib())),
+                    0x00D0 => Some(Operation::Scu(instruction.nib())),
                     _ => Some(Operation::Sys(instruction.addr())),
                 },
             },
@@ -362,6 +383,8 @@ impl Operation {
             0x4000 => Some(Operation::SkipNeqImm(instruction.vx(), instruction.imm())),
             0x5000 => match instruction & 0x000F {
                 0x0000 => Some(Operation::SkipEqReg(instruction.vx(), instruction.vy())),
+                0x0002 => Some(Operation::MemStoreRange(instruction.vx(), instruction.vy())),
+                0x0003 => Some(Operation::MemLoadRange(instruction.vx(), instruction.vy())),
                 _ => None,
             },
             0x6000 => Some(Operation::LoadImm(instruction.vx(), instruction.imm())),
@@ -373,9 +396,9 @@ impl Operation {
                 0x0003 => Some(Operation::Xor(instruction.vx(), instruction.vy())),
                 0x0004 => Some(Operation::AddReg(instruction.vx(), instruction.vy())),
                 0x0005 => Some(Operation::Sub(instruction.vx(), instruction.vy())),
-                0x0006 => Some(Operation::Shr(instruction.vx())),
+                0x0006 => Some(Operation::Shr(instruction.vx(), instruction.vy())),
                 0x0007 => Some(Operation::SubNeg(instruction.vx(), instruction.vy())),
-                0x000E => Some(Operation::Shl(instruction.vx())),
+                0x000E => Some(Operation::Shl(instruction.vx(), instruction.vy())),
                 _ => None,
             },
             0x9000 => match instruction & 0x000F {
@@ -396,6 +419,8 @@ impl Operation {
                 _ => None,
             },
             0xF000 => match instruction & 0x00FF {
+                0x0001 if instruction.vx() <= 0x3 => Some(Operation::Plane(instruction.vx())),
+                0x0002 if instruction.vx() == 0x0 => Some(Operation::LoadAudio),
                 0x0007 => Some(Operation::LoadRegDelay(instruction.vx())),
                 0x000A => Some(Operation::WaitKey(instruction.vx())),
                 0x0015 => Some(Operation::LoadDelayReg(instruction.vx())),
@@ -404,6 +429,7 @@ impl Operation {
                 0x0029 => Some(Operation::LoadAddrDigit(instruction.vx())),
                 0x0030 => Some(Operation::LoadAddrBigDigit(instruction.vx())),
                 0x0033 => Some(Operation::MemStoreBcd(instruction.vx())),
+                0x003A => Some(Operation::LoadPitchReg(instruction.vx())),
                 0x0055 => Some(Operation::MemStoreRegs(instruction.vx())),
                 0x0065 => Some(Operation::MemLoadRegs(instruction.vx())),
                 0x0075 => Some(Operation::RplStoreRegs(instruction.vx())),
@@ -413,4 +439,251 @@ impl Operation {
             _ => None,
         }
     }
+
+    /// Returns the number of bytes occupied by the instruction that begins
+    /// with the given word. Every instruction is two bytes long except for
+    /// the XO-CHIP long index load, which is followed by a 16-bit address.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate okto;
+    /// # use okto::cpu::Operation;
+    /// assert_eq!(2, Operation::instruction_size(&0xA123));
+    /// assert_eq!(4, Operation::instruction_size(&0xF000));
+    /// ```
+    pub fn instruction_size(instruction: &Instruction) -> Address {
+        if *instruction == LONG_INSTRUCTION_PREFIX {
+            LONG_INSTRUCTION_BYTES
+        } else {
+            INSTRUCTION_BYTES
+        }
+    }
+
+    /// Attempts to return the `Operation` corresponding to a four byte XO-CHIP
+    /// instruction made up of the given instruction word and the operand word
+    /// which follows it. Returns `None` if the instruction is not a long
+    /// instruction.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate okto;
+    /// # use okto::cpu::Operation;
+    /// assert_eq!(
+    ///   Some(Operation::LoadAddrLong(0x1234)),
+    ///   Operation::from_long_instruction(&0xF000, &0x1234)
+    /// );
+    /// assert_eq!(None, Operation::from_long_instruction(&0xA123, &0x1234));
+    /// ```
+    pub fn from_long_instruction(
+        instruction: &Instruction,
+        operand: &Instruction,
+    ) -> Option<Operation> {
+        match *instruction {
+            LONG_INSTRUCTION_PREFIX => Some(Operation::LoadAddrLong(*operand)),
+            _ => None,
+        }
+    }
+
+    /// Attempts to return the instruction encoding the operation, the inverse
+    /// of `from_instruction`. Returns `None` if the operation has no two byte
+    /// encoding, either because it is the long index load or because an
+    /// operand is out of range for its field or would make the instruction
+    /// decode as a different operation.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate okto;
+    /// # use okto::cpu::Operation;
+    /// assert_eq!(Some(0xD453), Operation::Draw(4, 5, 3).to_instruction());
+    /// assert_eq!(Some(0x00C2), Operation::Scd(2).to_instruction());
+    /// assert_eq!(None, Operation::LoadImm(0x10, 0x25).to_instruction());
+    /// assert_eq!(None, Operation::Sys(0x0E0).to_instruction());
+    /// assert_eq!(None, Operation::LoadAddrLong(0x1234).to_instruction());
+    /// ```
+    pub fn to_instruction(&self) -> Option<Instruction> {
+        let x = |vx: Register| (vx as Instruction) << 8;
+        let xy = |vx: Register, vy: Register| x(vx) | (vy as Instruction) << 4;
+
+        let instruction = match *self {
+            Operation::Cls => 0x00E0,
+            Operation::Ret => 0x00EE,
+            Operation::Sys(addr) => addr,
+            Operation::Jump(addr) => 0x1000 | addr,
+            Operation::JumpAddrPlusV0(addr) => 0xB000 | addr,
+            Operation::Call(addr) => 0x2000 | addr,
+            Operation::SkipEqImm(vx, imm) => 0x3000 | x(vx) | imm as Instruction,
+            Operation::SkipEqReg(vx, vy) => 0x5000 | xy(vx, vy),
+            Operation::SkipNeqImm(vx, imm) => 0x4000 | x(vx) | imm as Instruction,
+            Operation::SkipNeqReg(vx, vy) => 0x9000 | xy(vx, vy),
+            Operation::LoadImm(vx, imm) => 0x6000 | x(vx) | imm as Instruction,
+            Operation::LoadReg(vx, vy) => 0x8000 | xy(vx, vy),
+            Operation::LoadAddr(addr) => 0xA000 | addr,
+            Operation::LoadAddrDigit(vx) => 0xF029 | x(vx),
+            Operation::LoadRegDelay(vx) => 0xF007 | x(vx),
+            Operation::LoadDelayReg(vx) => 0xF015 | x(vx),
+            Operation::LoadSoundReg(vx) => 0xF018 | x(vx),
+            Operation::AddImm(vx, imm) => 0x7000 | x(vx) | imm as Instruction,
+            Operation::AddReg(vx, vy) => 0x8004 | xy(vx, vy),
+            Operation::AddAddrReg(vx) => 0xF01E | x(vx),
+            Operation::Sub(vx, vy) => 0x8005 | xy(vx, vy),
+            Operation::SubNeg(vx, vy) => 0x8007 | xy(vx, vy),
+            Operation::Or(vx, vy) => 0x8001 | xy(vx, vy),
+            Operation::And(vx, vy) => 0x8002 | xy(vx, vy),
+            Operation::Xor(vx, vy) => 0x8003 | xy(vx, vy),
+            Operation::Shr(vx, vy) => 0x8006 | xy(vx, vy),
+            Operation::Shl(vx, vy) => 0x800E | xy(vx, vy),
+            Operation::RandAndImm(vx, imm) => 0xC000 | x(vx) | imm as Instruction,
+            Operation::Draw(vx, vy, nib) => 0xD000 | xy(vx, vy) | nib as Instruction,
+            Operation::SkipKey(vx) => 0xE09E | x(vx),
+            Operation::SkipNotKey(vx) => 0xE0A1 | x(vx),
+            Operation::WaitKey(vx) => 0xF00A | x(vx),
+            Operation::MemStoreBcd(vx) => 0xF033 | x(vx),
+            Operation::MemStoreRegs(vx) => 0xF055 | x(vx),
+            Operation::MemLoadRegs(vx) => 0xF065 | x(vx),
+            Operation::Scd(nib) => 0x00C0 | nib as Instruction,
+            Operation::Scr => 0x00FB,
+            Operation::Scl => 0x00FC,
+            Operation::Exit => 0x00FD,
+            Operation::Low => 0x00FE,
+            Operation::High => 0x00FF,
+            Operation::LoadAddrBigDigit(vx) => 0xF030 | x(vx),
+            Operation::RplStoreRegs(vx) => 0xF075 | x(vx),
+            Operation::RplLoadRegs(vx) => 0xF085 | x(vx),
+            Operation::Scu(nib) => 0x00D0 | nib as Instruction,
+            Operation::MemStoreRange(vx, vy) => 0x5002 | xy(vx, vy),
+            Operation::MemLoadRange(vx, vy) => 0x5003 | xy(vx, vy),
+            Operation::LoadAddrLong(_) => return None,
+            Operation::Plane(planes) => 0xF001 | x(planes),
+            Operation::LoadAudio => 0xF002,
+            Operation::LoadPitchReg(vx) => 0xF03A | x(vx),
+        };
+
+        // Operands too wide for their field spill into neighbouring fields,
+        // which decoding the result catches.
+        match Operation::from_instruction(&instruction) {
+            Some(ref operation) if operation == self => Some(instruction),
+            _ => None,
+        }
+    }
+
+    /// Attempts to return the instruction and operand words of a four byte
+    /// XO-CHIP instruction encoding the operation, the inverse of
+    /// `from_long_instruction`. Returns `None` for operations that have a two
+    /// byte encoding.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate okto;
+    /// # use okto::cpu::Operation;
+    /// assert_eq!(
+    ///   Some((0xF000, 0x1234)),
+    ///   Operation::LoadAddrLong(0x1234).to_long_instruction()
+    /// );
+    /// assert_eq!(None, Operation::LoadAddr(0x123).to_long_instruction());
+    /// ```
+    pub fn to_long_instruction(&self) -> Option<(Instruction, Instruction)> {
+        match *self {
+            Operation::LoadAddrLong(addr) => Some((LONG_INSTRUCTION_PREFIX, addr)),
+            _ => None,
+        }
+    }
+
+    /// Attempts to return the big-endian bytes encoding the operation, using
+    /// the four byte XO-CHIP encoding where the operation needs it. Returns
+    /// `None` if the operation cannot be encoded.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate okto;
+    /// # use okto::cpu::Operation;
+    /// assert_eq!(Some(vec![0x63, 0x25]), Operation::LoadImm(3, 0x25).to_bytes());
+    /// assert_eq!(
+    ///   Some(vec![0xF0, 0x00, 0x12, 0x34]),
+    ///   Operation::LoadAddrLong(0x1234).to_bytes()
+    /// );
+    /// ```
+    pub fn to_bytes(&self) -> Option<Vec<u8>> {
+        let words = match self.to_long_instruction() {
+            Some((instruction, operand)) => vec![instruction, operand],
+            None => vec![self.to_instruction()?],
+        };
+        Some(
+            words
+                .iter()
+                .flat_map(|word| vec![(word >> 8) as u8, *word as u8])
+                .collect(),
+        )
+    }
+
+    /// Returns the name of the operation's variant, which identifies a class
+    /// of operations regardless of their operands.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate okto;
+    /// # use okto::cpu::Operation;
+    /// assert_eq!("Draw", Operation::Draw(1, 2, 3).name());
+    /// assert_eq!("Cls", Operation::Cls.name());
+    /// ```
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Operation::Cls => "Cls",
+            Operation::Ret => "Ret",
+            Operation::Sys(..) => "Sys",
+            Operation::Jump(..) => "Jump",
+            Operation::JumpAddrPlusV0(..) => "JumpAddrPlusV0",
+            Operation::Call(..) => "Call",
+            Operation::SkipEqImm(..) => "SkipEqImm",
+            Operation::SkipEqReg(..) => "SkipEqReg",
+            Operation::SkipNeqImm(..) => "SkipNeqImm",
+            Operation::SkipNeqReg(..) => "SkipNeqReg",
+            Operation::LoadImm(..) => "LoadImm",
+            Operation::LoadReg(..) => "LoadReg",
+            Operation::LoadAddr(..) => "LoadAddr",
+            Operation::LoadAddrDigit(..) => "LoadAddrDigit",
+            Operation::LoadRegDelay(..) => "LoadRegDelay",
+            Operation::LoadDelayReg(..) => "LoadDelayReg",
+            Operation::LoadSoundReg(..) => "LoadSoundReg",
+            Operation::AddImm(..) => "AddImm",
+            Operation::AddReg(..) => "AddReg",
+            Operation::AddAddrReg(..) => "AddAddrReg",
+            Operation::Sub(..) => "Sub",
+            Operation::SubNeg(..) => "SubNeg",
+            Operation::Or(..) => "Or",
+            Operation::And(..) => "And",
+            Operation::Xor(..) => "Xor",
+            Operation::Shr(..) => "Shr",
+            Operation::Shl(..) => "Shl",
+            Operation::RandAndImm(..) => "RandAndImm",
+            Operation::Draw(..) => "Draw",
+            Operation::SkipKey(..) => "SkipKey",
+            Operation::SkipNotKey(..) => "SkipNotKey",
+            Operation::WaitKey(..) => "WaitKey",
+            Operation::MemStoreBcd(..) => "MemStoreBcd",
+            Operation::MemStoreRegs(..) => "MemStoreRegs",
+            Operation::MemLoadRegs(..) => "MemLoadRegs",
+            Operation::Scd(..) => "Scd",
+            Operation::Scr => "Scr",
+            Operation::Scl => "Scl",
+            Operation::Exit => "Exit",
+            Operation::Low => "Low",
+            Operation::High => "High",
+            Operation::LoadAddrBigDigit(..) => "LoadAddrBigDigit",
+            Operation::RplStoreRegs(..) => "RplStoreRegs",
+            Operation::RplLoadRegs(..) => "RplLoadRegs",
+            Operation::Scu(..) => "Scu",
+            Operation::MemStoreRange(..) => "MemStoreRange",
+            Operation::MemLoadRange(..) => "MemLoadRange",
+            Operation::LoadAddrLong(..) => "LoadAddrLong",
+            Operation::Plane(..) => "Plane",
+            Operation::LoadAudio => "LoadAudio",
+            Operation::LoadPitchReg(..) => "LoadPitchReg",
+        }
+    }
 }
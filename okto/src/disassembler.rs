@@ -0,0 +1,445 @@
+//! Separating the code of a ROM from its data.
+//!
+//! A linear sweep decodes every pair of bytes as an instruction, sprites
+//! included. `Disassembly` instead follows the control flow from an entry
+//! point: it continues past ordinary instructions, follows the targets of
+//! `Jump` and `Call`, takes both paths of the skip instructions and stops at
+//! `Ret` and `Exit`. The bytes of every instruction it reaches are code.
+//! `JumpAddrPlusV0` jumps to an address computed while the program runs, so
+//! those jumps are recorded as unresolved rather than followed. Bytes that
+//! the code points `I` at are data, up to the next instruction, and the rest
+//! are unknown.
+//!
+//! The disassembly can be written back out as Octo source, which assembles
+//! to the same ROM.
+//!
+//! ```
+//! # extern crate okto;
+//! # use okto::cpu::Operation;
+//! # use okto::disassembler::{Classification, Disassembly};
+//! let rom = [
+//!     0xA2, 0x06, // i := 0x206
+//!     0xD0, 0x11, // sprite v0 v1 1
+//!     0x12, 0x02, // jump 0x202
+//!     0xFF, // sprite data
+//! ];
+//! let disassembly = Disassembly::new(&rom, 0x200);
+//!
+//! assert_eq!(Some((Operation::Jump(0x202), 2)), disassembly.operation(0x204));
+//! assert_eq!(Classification::Code, disassembly.classification(0x205));
+//! assert_eq!(Classification::Data, disassembly.classification(0x206));
+//! ```
+use super::cpu::{Address, Operation, DEFAULT_PC_ADDRESS, INSTRUCTION_BYTES};
+use super::isa::{self, Syntax};
+use super::memory::{Memory, MAX_ROM_SIZE_BYTES};
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+/// Most bytes written on a single line of data.
+const BYTES_PER_LINE: usize = 8;
+
+/// How a byte of a ROM is used.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Classification {
+    /// Part of an instruction reached from the entry point
+    Code,
+    /// Read by the code through `I`
+    Data,
+    /// Neither reached nor referenced by the code
+    Unknown,
+}
+
+impl Classification {
+    /// Returns the name of the classification in lower case.
+    pub fn name(self) -> &'static str {
+        match self {
+            Classification::Code => "code",
+            Classification::Data => "data",
+            Classification::Unknown => "unknown",
+        }
+    }
+}
+
+/// What the code does with an address it refers to, strongest last.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Reference {
+    /// Loaded into `I`
+    Data,
+    /// Jumped to
+    Label,
+    /// Called as a subroutine
+    Subroutine,
+}
+
+/// A ROM with every byte classified by following its control flow.
+#[derive(Clone, Debug)]
+pub struct Disassembly {
+    /// Contents of the ROM
+    rom: Vec<u8>,
+    /// Classification of each byte of the ROM
+    classifications: Vec<Classification>,
+    /// Every instruction reached along with its size, by address
+    operations: BTreeMap<Address, (Operation, Address)>,
+    /// Addresses of `JumpAddrPlusV0` instructions, whose targets are unknown
+    unresolved: BTreeSet<Address>,
+    /// Addresses each jump, call or skip instruction can branch to, by
+    /// address of the instruction
+    branches: BTreeMap<Address, Vec<Address>>,
+}
+
+impl Disassembly {
+    /// Classify a ROM loaded at `cpu::DEFAULT_PC_ADDRESS` by following the
+    /// code from the given entry point.
+    pub fn new(rom: &[u8], entry: Address) -> Self {
+        let rom = &rom[..rom.len().min(MAX_ROM_SIZE_BYTES)];
+        let mut memory = Memory::new();
+        memory
+            .load(rom, DEFAULT_PC_ADDRESS, rom.len())
+            .expect("ROM fits in memory");
+
+        let mut disassembly = Self {
+            rom: rom.to_vec(),
+            classifications: vec![Classification::Unknown; rom.len()],
+            operations: BTreeMap::new(),
+            unresolved: BTreeSet::new(),
+            branches: BTreeMap::new(),
+        };
+        let mut pending = vec![entry];
+        let mut references = Vec::new();
+
+        while let Some(address) = pending.pop() {
+            // Instructions never overlap, so a jump into the middle of one
+            // is left as is
+            let (operation, size) = match memory.read_operation(address) {
+                Some((operation, size)) if disassembly.is_free(address, size) => (operation, size),
+                _ => continue,
+            };
+            for offset in 0..size {
+                disassembly.set(address + offset, Classification::Code);
+            }
+            disassembly.operations.insert(address, (operation, size));
+
+            let next = address.wrapping_add(size);
+            match operation {
+                Operation::Jump(target) => {
+                    disassembly.branches.insert(address, vec![target]);
+                    pending.push(target);
+                }
+                Operation::Call(target) => {
+                    disassembly.branches.insert(address, vec![target]);
+                    pending.push(next);
+                    pending.push(target);
+                }
+                Operation::Ret | Operation::Exit => {}
+                Operation::JumpAddrPlusV0(_) => {
+                    disassembly.unresolved.insert(address);
+                }
+                Operation::SkipEqImm(..)
+                | Operation::SkipNeqImm(..)
+                | Operation::SkipEqReg(..)
+                | Operation::SkipNeqReg(..)
+                | Operation::SkipKey(_)
+                | Operation::SkipNotKey(_) => {
+                    let skipped = memory
+                        .read_operation(next)
+                        .map_or(INSTRUCTION_BYTES, |(_, size)| size);
+                    let target = next.wrapping_add(skipped);
+                    disassembly.branches.insert(address, vec![target]);
+                    pending.push(target);
+                    pending.push(next);
+                }
+                Operation::LoadAddr(target) | Operation::LoadAddrLong(target) => {
+                    references.push(target);
+                    pending.push(next);
+                }
+                _ => pending.push(next),
+            }
+        }
+
+        for target in references {
+            let mut address = target;
+            while disassembly.classification(address) == Classification::Unknown
+                && disassembly.contains(address, 1)
+            {
+                disassembly.set(address, Classification::Data);
+                address = address.wrapping_add(1);
+            }
+        }
+
+        disassembly
+    }
+
+    /// Returns the index in the ROM of the given address, if it is inside.
+    fn index(&self, address: Address) -> Option<usize> {
+        (address as usize)
+            .checked_sub(DEFAULT_PC_ADDRESS as usize)
+            .filter(|&index| index < self.classifications.len())
+    }
+
+    /// Returns true if the given number of bytes from the address are all
+    /// inside the ROM.
+    fn contains(&self, address: Address, size: Address) -> bool {
+        match self.index(address) {
+            Some(index) => index + size as usize <= self.classifications.len(),
+            None => false,
+        }
+    }
+
+    /// Returns true if the given number of bytes from the address are inside
+    /// the ROM and not yet part of an instruction.
+    fn is_free(&self, address: Address, size: Address) -> bool {
+        self.contains(address, size)
+            && (0..size).all(|offset| self.classification(address + offset) != Classification::Code)
+    }
+
+    fn set(&mut self, address: Address, classification: Classification) {
+        if let Some(index) = self.index(address) {
+            self.classifications[index] = classification;
+        }
+    }
+
+    /// Returns how the byte at the given address is used. Addresses outside
+    /// of the ROM are unknown.
+    pub fn classification(&self, address: Address) -> Classification {
+        self.index(address)
+            .map_or(Classification::Unknown, |index| self.classifications[index])
+    }
+
+    /// Returns the instruction starting at the given address along with its
+    /// size, if the code reaches one there.
+    pub fn operation(&self, address: Address) -> Option<(Operation, Address)> {
+        self.operations.get(&address).cloned()
+    }
+
+    /// Returns true if the address holds a `JumpAddrPlusV0` instruction,
+    /// whose targets are not followed.
+    pub fn is_unresolved(&self, address: Address) -> bool {
+        self.unresolved.contains(&address)
+    }
+
+    /// Returns the addresses the instruction at the given address can branch
+    /// to, other than the next instruction: the target of a jump or call, or
+    /// the address after the instruction a skip jumps over. Unresolved jumps
+    /// have no known targets.
+    pub fn branch_targets(&self, address: Address) -> &[Address] {
+        self.branches.get(&address).map_or(&[], Vec::as_slice)
+    }
+
+    /// Returns the name of every address the code refers to, for writing the
+    /// disassembly as source. Subroutines are named `sub_XXX`, other jump
+    /// targets `label_XXX` and addresses loaded into `I` `data_XXX`, after
+    /// their address. The start of the ROM is always `main`. Addresses
+    /// outside of the ROM or inside an instruction have no name.
+    pub fn labels(&self) -> BTreeMap<Address, String> {
+        let mut references: BTreeMap<Address, Reference> = BTreeMap::new();
+        for &(operation, _) in self.operations.values() {
+            let (target, reference) = match operation {
+                Operation::Call(target) => (target, Reference::Subroutine),
+                Operation::Jump(target) | Operation::JumpAddrPlusV0(target) => {
+                    (target, Reference::Label)
+                }
+                Operation::LoadAddr(target) | Operation::LoadAddrLong(target) => {
+                    (target, Reference::Data)
+                }
+                _ => continue,
+            };
+            let inside_instruction = self.classification(target) == Classification::Code
+                && !self.operations.contains_key(&target);
+            if self.contains(target, 1) && !inside_instruction {
+                let strongest = references.entry(target).or_insert(reference);
+                *strongest = (*strongest).max(reference);
+            }
+        }
+
+        let mut labels: BTreeMap<Address, String> = references
+            .into_iter()
+            .map(|(address, reference)| {
+                let prefix = match reference {
+                    Reference::Data => "data",
+                    Reference::Label => "label",
+                    Reference::Subroutine => "sub",
+                };
+                (address, format!("{}_{:03X}", prefix, address))
+            })
+            .collect();
+        labels.insert(DEFAULT_PC_ADDRESS, String::from("main"));
+        labels
+    }
+
+    /// Write the disassembly as Octo source that assembles back to the same
+    /// ROM. Instructions refer to the addresses named by `labels`, the bytes
+    /// outside of the code are written as numbers and a comment gives the
+    /// address of each line.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate okto;
+    /// # use okto::disassembler::Disassembly;
+    /// # use okto::octo;
+    /// let rom = [0xA2, 0x04, 0x12, 0x00, 0x3C];
+    /// let source = Disassembly::new(&rom, 0x200).to_octo();
+    ///
+    /// assert_eq!(
+    ///     ": main\n    i := data_204 # 200\n    jump main # 202\n\
+    ///      : data_204\n    0x3C # 204\n",
+    ///     source
+    /// );
+    /// assert_eq!(&rom[..], &octo::assemble("rom.8o", &source).unwrap().rom[..]);
+    /// ```
+    pub fn to_octo(&self) -> String {
+        let labels = self.labels();
+        let mut source = String::new();
+        if self.rom.is_empty() {
+            source.push_str(": main\n");
+        }
+
+        let mut index = 0;
+        while index < self.rom.len() {
+            let address = DEFAULT_PC_ADDRESS + index as Address;
+            if let Some(name) = labels.get(&address) {
+                writeln!(source, ": {}", name).unwrap();
+            }
+
+            let instruction = self.operation(address).and_then(|(operation, size)| {
+                isa::format_with_labels(&operation, Syntax::Octo, |target| {
+                    labels.get(&target).cloned()
+                })
+                .map(|text| (text, size))
+            });
+            if let Some((text, size)) = instruction {
+                let note = if self.is_unresolved(address) {
+                    " unresolved"
+                } else {
+                    ""
+                };
+                writeln!(source, "    {} # {:03X}{}", text, address, note).unwrap();
+                index += size as usize;
+                continue;
+            }
+
+            // Bytes of the same kind, up to the next label or instruction
+            let classification = self.classifications[index];
+            let mut bytes = Vec::new();
+            loop {
+                bytes.push(format!("0x{:02X}", self.rom[index]));
+                index += 1;
+                let next = DEFAULT_PC_ADDRESS.wrapping_add(index as Address);
+                if index >= self.rom.len()
+                    || bytes.len() >= BYTES_PER_LINE
+                    || self.classifications[index] != classification
+                    || self.operations.contains_key(&next)
+                    || labels.contains_key(&next)
+                {
+                    break;
+                }
+            }
+            let note = if classification == Classification::Unknown {
+                " unknown"
+            } else {
+                ""
+            };
+            writeln!(source, "    {} # {:03X}{}", bytes.join(" "), address, note).unwrap();
+        }
+
+        source
+    }
+
+    /// Write the disassembly as a JSON array holding a record for each
+    /// instruction and for each byte outside of the code, in address order.
+    /// Each record has the following fields:
+    ///
+    /// * `address`: address of the first byte
+    /// * `raw`: the bytes in hexadecimal
+    /// * `classification`: `code`, `data` or `unknown`
+    /// * `operation`: `null` outside of the code, otherwise the `name` of the
+    ///   operation, its Cowgod `mnemonic` and an object holding the value of
+    ///   each of its `operands` by the name used in the instruction set
+    ///   reference
+    /// * `targets`: addresses the instruction can branch to, as returned by
+    ///   `branch_targets`
+    /// * `unresolved`: true for a jump whose targets are unknown
+    /// * `platform`: `null` outside of the code, otherwise the first
+    ///   platform to provide the operation, `CHIP-8`, `SCHIP` or `XO-CHIP`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate okto;
+    /// # use okto::disassembler::Disassembly;
+    /// let rom = [0x22, 0x04, 0x00, 0xFD, 0x00, 0xEE];
+    /// let json = Disassembly::new(&rom, 0x200).to_json();
+    ///
+    /// assert!(json.starts_with(
+    ///     "[\n  {\"address\": 512, \"raw\": \"2204\", \"classification\": \"code\", \
+    ///      \"operation\": {\"name\": \"Call\", \"mnemonic\": \"CALL\", \
+    ///      \"operands\": {\"nnn\": 516}}, \"targets\": [516], \"unresolved\": false, \
+    ///      \"platform\": \"CHIP-8\"},\n"
+    /// ));
+    /// ```
+    pub fn to_json(&self) -> String {
+        let mut json = String::from("[");
+        let mut index = 0;
+
+        while index < self.rom.len() {
+            let address = DEFAULT_PC_ADDRESS + index as Address;
+            json.push_str(if index == 0 { "\n  " } else { ",\n  " });
+
+            let (operation, size) = match self.operation(address) {
+                Some((operation, size)) => (Some(operation), size as usize),
+                None => (None, 1),
+            };
+            let raw: String = self.rom[index..index + size]
+                .iter()
+                .map(|byte| format!("{:02X}", byte))
+                .collect();
+            write!(
+                json,
+                "{{\"address\": {}, \"raw\": \"{}\", \"classification\": \"{}\", ",
+                address,
+                raw,
+                self.classifications[index].name()
+            )
+            .unwrap();
+
+            match operation {
+                Some(operation) => {
+                    let info = isa::info(&operation);
+                    let operands: Vec<String> = info
+                        .operands
+                        .iter()
+                        .zip(isa::operand_values(&operation))
+                        .map(|(operand, value)| format!("\"{}\": {}", operand.name, value))
+                        .collect();
+                    let targets: Vec<String> = self
+                        .branch_targets(address)
+                        .iter()
+                        .map(|target| target.to_string())
+                        .collect();
+                    write!(
+                        json,
+                        "\"operation\": {{\"name\": \"{}\", \"mnemonic\": \"{}\", \
+                         \"operands\": {{{}}}}}, \"targets\": [{}], \"unresolved\": {}, \
+                         \"platform\": \"{}\"}}",
+                        info.name,
+                        info.cowgod.split(' ').next().unwrap_or(info.cowgod),
+                        operands.join(", "),
+                        targets.join(", "),
+                        self.is_unresolved(address),
+                        info.platforms[0].name()
+                    )
+                    .unwrap();
+                }
+                None => json.push_str(
+                    "\"operation\": null, \"targets\": [], \"unresolved\": false, \
+                     \"platform\": null}",
+                ),
+            }
+            index += size;
+        }
+
+        json.push_str("\n]\n");
+        json
+    }
+}
@@ -9,10 +9,30 @@ pub const DISPLAY_WIDTH: usize = 128;
 pub const MAX_SPRITE_BYTES: usize = 15;
 /// Number of pixels encoded in each byte of sprite data
 pub const PIXELS_PER_BYTE: usize = 8;
+/// Number of XO-CHIP bitplanes layered to form the display
+pub const NUM_PLANES: usize = 2;
+/// Plane mask selecting every bitplane
+pub const ALL_PLANES: u8 = (1 << NUM_PLANES) - 1;
+/// Number of distinct colours produced by layering the bitplanes
+pub const NUM_COLORS: usize = 1 << NUM_PLANES;
+
+/// A pixel whose value was flipped in the given bitplanes by drawing a
+/// sprite. Flipping it again undoes the change.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PixelFlip {
+    /// Column of the pixel
+    pub x: u8,
+    /// Row of the pixel
+    pub y: u8,
+    /// Mask of the bitplanes that were flipped
+    pub planes: u8,
+}
 
 /// Display state data
 pub struct Display {
-    /// Frame buffer for the video display
+    /// Frame buffer for the video display. Bit N of each pixel holds the
+    /// value of the pixel in bitplane N, so each pixel is also the index of
+    /// the colour it should be drawn with.
     ///
     /// ```
     /// # extern crate okto;
@@ -24,21 +44,33 @@ pub struct Display {
     pub data: [[u8; DISPLAY_WIDTH]; DISPLAY_HEIGHT],
     /// Indicates whether or not the display is in high resolution mode
     pub high_resolution: bool,
+    /// Mask of the bitplanes affected by clearing, drawing and scrolling
+    pub planes: u8,
+    /// Set whenever the frame buffer or resolution may have changed. The
+    /// machine clears it at the start of each frame so hosts know whether the
+    /// screen needs redrawing.
+    pub dirty: bool,
+    /// Pixels flipped by drawing since flips started being recorded, if they
+    /// are
+    flips: Option<Vec<PixelFlip>>,
 }
 
 /// Implementation of the display
 impl Display {
     /// Initialize a new display data structure. The display is initially in
-    /// low resolution mode (64x32) and the frame buffer is initialized to all
-    /// zero values.
+    /// low resolution mode (64x32), only the first bitplane is selected and
+    /// the frame buffer is initialized to all zero values.
     pub fn new() -> Self {
         Self {
             data: [[0; DISPLAY_WIDTH]; DISPLAY_HEIGHT],
             high_resolution: false,
+            planes: 0x1,
+            dirty: false,
+            flips: None,
         }
     }
 
-    /// Clear the frame buffer for the display.
+    /// Clear the selected bitplanes of the frame buffer for the display.
     ///
     /// # Examples
     ///
@@ -46,19 +78,70 @@ impl Display {
     /// # extern crate okto;
     /// # use okto::display;
     /// # let mut display = display::Display::new();
-    /// display.data[10][20] = 0x23;
-    /// assert_eq!(0x23, display.data[10][20]);
+    /// display.data[10][20] = 0x1;
+    /// assert_eq!(0x1, display.data[10][20]);
     /// display.clear();
-    /// assert_eq!(0x00, display.data[10][20]);
+    /// assert_eq!(0x0, display.data[10][20]);
+    /// ```
+    ///
+    /// Planes which are not selected are left untouched:
+    ///
+    /// ```
+    /// # extern crate okto;
+    /// # use okto::display;
+    /// # let mut display = display::Display::new();
+    /// display.data[10][20] = 0x3;
+    /// display.planes = 0x2;
+    /// display.clear();
+    /// assert_eq!(0x1, display.data[10][20]);
     /// ```
     pub fn clear(&mut self) {
-        for row in 0..self.height() {
-            for column in 0..self.width() {
-                self.data[row][column] = 0;
+        let (width, height) = (self.width(), self.height());
+        let keep = !self.planes;
+        self.dirty = true;
+
+        for row in self.data[..height].iter_mut() {
+            for pixel in row[..width].iter_mut() {
+                *pixel &= keep;
             }
         }
     }
 
+    /// Returns the number of bitplanes currently selected.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate okto;
+    /// # use okto::display;
+    /// # let mut display = display::Display::new();
+    /// assert_eq!(1, display.selected_planes());
+    /// display.planes = display::ALL_PLANES;
+    /// assert_eq!(2, display.selected_planes());
+    /// ```
+    pub fn selected_planes(&self) -> usize {
+        (self.planes & ALL_PLANES).count_ones() as usize
+    }
+
+    /// Returns the colour index of the pixel at the given coordinates, formed
+    /// by combining the pixel's value in each bitplane. Frontends use this to
+    /// look the pixel up in a palette of `NUM_COLORS` colours.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate okto;
+    /// # use okto::display;
+    /// # let mut display = display::Display::new();
+    /// display.planes = 0x2;
+    /// display.draw(0, 0, &[0x80], false).unwrap();
+    /// assert_eq!(2, display.pixel(0, 0));
+    /// assert_eq!(0, display.pixel(1, 0));
+    /// ```
+    pub fn pixel(&self, x: usize, y: usize) -> u8 {
+        self.data[y][x] & ALL_PLANES
+    }
+
     /// Returns the current effective height of the display give its mode.
     ///
     /// # Examples
@@ -104,7 +187,12 @@ impl Display {
 
     /// Draw sprite data of a pre-specified size onto the screen at the given
     /// coordinates. Returns a value indicating whether or not any pixels were
-    /// erased during the rendering process.
+    /// erased during the rendering process. When `clip` is set, parts of the
+    /// sprite that fall past the edge of the screen are discarded instead of
+    /// wrapping around to the opposite edge.
+    ///
+    /// When several bitplanes are selected the sprite data holds one sprite
+    /// per selected plane, one after another, starting with the lowest plane.
     ///
     /// # Examples
     ///
@@ -112,60 +200,172 @@ impl Display {
     /// # extern crate okto;
     /// # use okto::display;
     /// # let mut display = display::Display::new();
-    /// let pixels_erased = display.draw(0, 0, &[0xFF, 0x1F]).unwrap();
+    /// let pixels_erased = display.draw(0, 0, &[0xFF, 0x1F], false).unwrap();
     /// assert!(!pixels_erased);
     /// assert_eq!(&display.data[0][0..8], &[1, 1, 1, 1, 1, 1, 1, 1]);
     /// assert_eq!(&display.data[1][0..8], &[0, 0, 0, 1, 1, 1, 1, 1]);
     /// ```
-    pub fn draw(&mut self, x: usize, y: usize, sprite_data: &[u8]) -> OktoResult<bool> {
-        if sprite_data.len() > MAX_SPRITE_BYTES {
+    ///
+    /// A clipped sprite does not wrap around the right edge:
+    ///
+    /// ```
+    /// # extern crate okto;
+    /// # use okto::display;
+    /// # let mut display = display::Display::new();
+    /// let x = display.width() - 4;
+    /// display.draw(x, 0, &[0xFF], true).unwrap();
+    /// assert_eq!(&display.data[0][x..x + 4], &[1, 1, 1, 1]);
+    /// assert_eq!(&display.data[0][0..4], &[0, 0, 0, 0]);
+    /// ```
+    pub fn draw(&mut self, x: usize, y: usize, sprite_data: &[u8], clip: bool) -> OktoResult<bool> {
+        let num_planes = self.selected_planes();
+        if num_planes == 0 {
+            return Ok(false);
+        }
+
+        let bytes_per_plane = sprite_data.len() / num_planes;
+        if bytes_per_plane > MAX_SPRITE_BYTES || bytes_per_plane * num_planes != sprite_data.len() {
+            return Err(OktoError::new(OktoErrorKind::InvalidSprite));
+        }
+
+        Ok(self.draw_planes(x, y, sprite_data, bytes_per_plane, 1, clip))
+    }
+
+    /// Draw a 16x16 SuperChip sprite made up of 32 bytes, two per row, onto
+    /// the screen at the given coordinates. Returns a value indicating whether
+    /// or not any pixels were erased. As with `draw`, the sprite data holds
+    /// one sprite per selected bitplane.
+    pub fn draw_large(&mut self, x: usize, y: usize, sprite_data: &[u8], clip: bool) -> OktoResult<bool> {
+        if sprite_data.len() != 32 * self.selected_planes() {
             return Err(OktoError::new(OktoErrorKind::InvalidSprite));
         }
 
+        Ok(self.draw_planes(x, y, sprite_data, 32, 2, clip))
+    }
+
+    /// Draw consecutive chunks of sprite data onto each of the selected
+    /// bitplanes in turn.
+    fn draw_planes(
+        &mut self,
+        x: usize,
+        y: usize,
+        sprite_data: &[u8],
+        bytes_per_plane: usize,
+        bytes_per_row: usize,
+        clip: bool,
+    ) -> bool {
+        let mut pixels_erased = false;
+        let mask = self.planes;
+        let mut planes = (0..NUM_PLANES).filter(|plane| mask & (1 << plane) != 0);
+
+        for plane_data in sprite_data.chunks(bytes_per_plane) {
+            if let Some(plane) = planes.next() {
+                pixels_erased |=
+                    self.draw_sprite(x, y, plane_data, bytes_per_row, 1 << plane, clip);
+            }
+        }
+
+        pixels_erased
+    }
+
+    /// XOR the given sprite rows onto a single bitplane of the frame buffer.
+    /// The starting coordinates always wrap onto the screen, while the
+    /// remainder of the sprite either wraps or is clipped.
+    fn draw_sprite(
+        &mut self,
+        x: usize,
+        y: usize,
+        sprite_data: &[u8],
+        bytes_per_row: usize,
+        plane_bit: u8,
+        clip: bool,
+    ) -> bool {
+        let (width, height) = (self.width(), self.height());
+        let (x, y) = (x % width, y % height);
         let mut pixels_erased = false;
 
-        for row in 0..sprite_data.len() {
-            let ycoord = (y + row) % self.height();
+        for (row, row_data) in sprite_data.chunks(bytes_per_row).enumerate() {
+            let mut ycoord = y + row;
+            if ycoord >= height {
+                if clip {
+                    break;
+                }
+                ycoord %= height;
+            }
 
-            for column in 0..PIXELS_PER_BYTE {
-                let desired_bit = PIXELS_PER_BYTE - column - 1;
-                let pixel_data = (sprite_data[row] >> desired_bit) & 1;
-                let xcoord = (x + column) % self.width();
+            for column in 0..(bytes_per_row * PIXELS_PER_BYTE) {
+                let desired_bit = PIXELS_PER_BYTE - (column % PIXELS_PER_BYTE) - 1;
+                let pixel_data = (row_data[column / PIXELS_PER_BYTE] >> desired_bit) & 1;
+
+                let mut xcoord = x + column;
+                if xcoord >= width {
+                    if clip {
+                        break;
+                    }
+                    xcoord %= width;
+                }
+
+                if pixel_data == 0 {
+                    continue;
+                }
 
                 // If we will end up erasing a set pixel, set the flag register.
-                if self.data[ycoord][xcoord] == 1 && pixel_data == 1 {
+                if self.data[ycoord][xcoord] & plane_bit != 0 {
                     pixels_erased = true;
                 }
 
-                self.data[ycoord][xcoord] ^= pixel_data;
+                self.data[ycoord][xcoord] ^= plane_bit;
+                self.dirty = true;
+                if let Some(ref mut flips) = self.flips {
+                    flips.push(PixelFlip {
+                        x: xcoord as u8,
+                        y: ycoord as u8,
+                        planes: plane_bit,
+                    });
+                }
             }
         }
 
-        Ok(pixels_erased)
+        pixels_erased
     }
 
-    pub fn draw_large(&mut self, x: usize, y: usize, sprite_data: &[u8]) -> OktoResult<bool> {
-        if sprite_data.len() != 32 {
-            return Err(OktoError::new(OktoErrorKind::AddressOutOfRange));
-        }
+    /// Start recording the pixels flipped by `draw` and `draw_large`,
+    /// discarding any flips already recorded.
+    pub fn record_flips(&mut self) {
+        self.flips = Some(Vec::new());
+    }
 
-        let mut pixels_erased = false;
+    /// Stop recording flipped pixels and return those recorded.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate okto;
+    /// # use okto::display;
+    /// # let mut display = display::Display::new();
+    /// display.draw(0, 0, &[0xC0], false).unwrap();
+    /// display.record_flips();
+    /// display.draw(1, 0, &[0x80], false).unwrap();
+    /// let flips = display.take_flips();
+    /// assert_eq!(&display.data[0][0..2], &[1, 0]);
+    ///
+    /// display.flip(&flips);
+    /// assert_eq!(&display.data[0][0..2], &[1, 1]);
+    /// ```
+    pub fn take_flips(&mut self) -> Vec<PixelFlip> {
+        self.flips.take().unwrap_or_default()
+    }
 
-        for yoffset in 0..16 {
-            for xoffset in 0..2 {
-                pixels_erased |= self.draw(
-                    x + (xoffset * 8),
-                    y + yoffset,
-                    &[sprite_data[(yoffset * 2) + xoffset]]
-                )?;
-            }
+    /// Flip the given pixels, undoing the drawing that flipped them.
+    pub fn flip(&mut self, flips: &[PixelFlip]) {
+        for flip in flips.iter() {
+            self.data[flip.y as usize][flip.x as usize] ^= flip.planes;
         }
-
-        Ok(pixels_erased)
+        self.dirty |= !flips.is_empty();
     }
 
-    /// Shift the contents of the frame-buffer down the given number of lines.
-    /// Previous lines are filled with zeros after scrolling.
+    /// Shift the contents of the selected bitplanes down the given number of
+    /// lines. Previous lines are filled with zeros after scrolling.
     ///
     /// # Examples
     ///
@@ -173,7 +373,7 @@ impl Display {
     /// # extern crate okto;
     /// # use okto::display;
     /// # let mut display = display::Display::new();
-    /// display.draw(0, 0, &[0xFF, 0x1F]).unwrap();
+    /// display.draw(0, 0, &[0xFF, 0x1F], false).unwrap();
     /// assert_eq!(&display.data[0][0..8], &[1, 1, 1, 1, 1, 1, 1, 1]);
     /// assert_eq!(&display.data[1][0..8], &[0, 0, 0, 1, 1, 1, 1, 1]);
     /// display.scroll_down(2);
@@ -181,40 +381,76 @@ impl Display {
     /// assert_eq!(&display.data[3][0..8], &[0, 0, 0, 1, 1, 1, 1, 1]);
     /// ```
     pub fn scroll_down(&mut self, num_lines: usize) {
+        let num_lines = num_lines.min(DISPLAY_HEIGHT);
         let mut new_buffer = [[0; DISPLAY_WIDTH]; DISPLAY_HEIGHT];
 
-        for height in num_lines..self.data.len() {
-            for width in 0..self.data[height].len() {
-                new_buffer[height][width] = self.data[(height - num_lines)][width];
-            }
-        }
+        new_buffer[num_lines..].copy_from_slice(&self.data[..DISPLAY_HEIGHT - num_lines]);
 
-        self.data = new_buffer;
+        self.replace_selected_planes(&new_buffer);
     }
 
-    /// Scroll the contents of the frame buffer 4 pixels to the left.
+    /// Shift the contents of the selected bitplanes up the given number of
+    /// lines. Following lines are filled with zeros after scrolling.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate okto;
+    /// # use okto::display;
+    /// # let mut display = display::Display::new();
+    /// display.draw(0, 2, &[0xFF, 0x1F], false).unwrap();
+    /// display.scroll_up(2);
+    /// assert_eq!(&display.data[0][0..8], &[1, 1, 1, 1, 1, 1, 1, 1]);
+    /// assert_eq!(&display.data[1][0..8], &[0, 0, 0, 1, 1, 1, 1, 1]);
+    /// assert_eq!(&display.data[2][0..8], &[0, 0, 0, 0, 0, 0, 0, 0]);
+    /// ```
+    pub fn scroll_up(&mut self, num_lines: usize) {
+        let num_lines = num_lines.min(DISPLAY_HEIGHT);
+        let mut new_buffer = [[0; DISPLAY_WIDTH]; DISPLAY_HEIGHT];
+
+        new_buffer[..DISPLAY_HEIGHT - num_lines].copy_from_slice(&self.data[num_lines..]);
+
+        self.replace_selected_planes(&new_buffer);
+    }
+
+    /// Scroll the contents of the selected bitplanes 4 pixels to the left.
     pub fn scroll_left(&mut self) {
         let mut new_buffer = [[0; DISPLAY_WIDTH]; DISPLAY_HEIGHT];
 
-        for height in 0..DISPLAY_HEIGHT {
-            for width in 0..(DISPLAY_WIDTH - 4) {
-                new_buffer[height][width] = self.data[height][width + 4];
-            }
+        for (new_row, row) in new_buffer.iter_mut().zip(self.data.iter()) {
+            new_row[..DISPLAY_WIDTH - 4].copy_from_slice(&row[4..]);
         }
 
-        self.data = new_buffer;
+        self.replace_selected_planes(&new_buffer);
     }
 
-    /// Scroll the contents of the frame buffer 4 pixels to the right.
+    /// Scroll the contents of the selected bitplanes 4 pixels to the right.
     pub fn scroll_right(&mut self) {
         let mut new_buffer = [[0; DISPLAY_WIDTH]; DISPLAY_HEIGHT];
 
-        for height in 0..DISPLAY_HEIGHT {
-            for width in 4..DISPLAY_WIDTH {
-                new_buffer[height][width] = self.data[height][width - 4];
+        for (new_row, row) in new_buffer.iter_mut().zip(self.data.iter()) {
+            new_row[4..].copy_from_slice(&row[..DISPLAY_WIDTH - 4]);
+        }
+
+        self.replace_selected_planes(&new_buffer);
+    }
+
+    /// Replace the selected bitplanes of the frame buffer with those of the
+    /// given buffer, leaving the other planes untouched.
+    fn replace_selected_planes(&mut self, buffer: &[[u8; DISPLAY_WIDTH]; DISPLAY_HEIGHT]) {
+        let planes = self.planes;
+        self.dirty = true;
+
+        for (row, new_row) in self.data.iter_mut().zip(buffer.iter()) {
+            for (pixel, new_pixel) in row.iter_mut().zip(new_row.iter()) {
+                *pixel = (*pixel & !planes) | (new_pixel & planes);
             }
         }
+    }
+}
 
-        self.data = new_buffer;
+impl Default for Display {
+    fn default() -> Self {
+        Self::new()
     }
 }
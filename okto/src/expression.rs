@@ -0,0 +1,652 @@
+//! Expressions over machine state, used by breakpoint conditions and
+//! tracepoint messages.
+//!
+//! Expressions use C-like syntax and operator precedence. They can read the
+//! registers `v0` to `vf`, `i`, `pc` and `sp`, the timers `delay` and `sound`
+//! (also written `dt` and `st`), and single bytes of memory with `[address]`.
+//! Numbers are decimal, hexadecimal with a `0x` prefix or binary with a `0b`
+//! prefix. Every value is an unsigned 32-bit integer; arithmetic wraps,
+//! division by zero yields zero and comparisons yield 1 or 0.
+//!
+//! ```
+//! # extern crate okto;
+//! # use okto::expression::Expression;
+//! let expression = Expression::parse("v3 == 0x10 && [i + 2] != 0").unwrap();
+//! assert_eq!("v3 == 0x10 && [i + 0x2] != 0x0", expression.to_string());
+//! ```
+use std::fmt;
+
+use super::cpu::{Cpu, Register, NUM_REGISTERS};
+use super::memory::Memory;
+use super::timer::Timer;
+use super::{OktoError, OktoErrorKind, OktoResult};
+
+/// Machine state that expressions are evaluated against.
+#[derive(Clone, Copy)]
+pub struct Context<'a> {
+    /// CPU registers
+    pub cpu: &'a Cpu,
+    /// Memory read by `[address]`
+    pub memory: &'a Memory,
+    /// Value of the delay timer
+    pub delay_timer: Timer,
+    /// Value of the sound timer
+    pub sound_timer: Timer,
+}
+
+/// Named values an expression can read.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Variable {
+    /// General purpose register VX
+    V(Register),
+    /// Index register
+    I,
+    /// Program counter
+    Pc,
+    /// Stack pointer
+    Sp,
+    /// Delay timer
+    Delay,
+    /// Sound timer
+    Sound,
+}
+
+impl Variable {
+    /// Look up a variable by name, ignoring case.
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.to_ascii_lowercase();
+        let variable = match name.as_str() {
+            "i" => Variable::I,
+            "pc" => Variable::Pc,
+            "sp" => Variable::Sp,
+            "delay" | "dt" => Variable::Delay,
+            "sound" | "st" => Variable::Sound,
+            _ if name.len() == 2 && name.starts_with('v') => {
+                Variable::V(u8::from_str_radix(&name[1..], 16).ok()?)
+            }
+            _ => return None,
+        };
+
+        Some(variable)
+    }
+
+    /// Returns the value of the variable.
+    pub fn value(self, context: &Context) -> u32 {
+        match self {
+            Variable::V(vx) => context.cpu.v[vx as usize % NUM_REGISTERS] as u32,
+            Variable::I => context.cpu.i as u32,
+            Variable::Pc => context.cpu.pc as u32,
+            Variable::Sp => context.cpu.sp as u32,
+            Variable::Delay => context.delay_timer as u32,
+            Variable::Sound => context.sound_timer as u32,
+        }
+    }
+}
+
+impl fmt::Display for Variable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Variable::V(vx) => write!(f, "v{:x}", vx),
+            Variable::I => write!(f, "i"),
+            Variable::Pc => write!(f, "pc"),
+            Variable::Sp => write!(f, "sp"),
+            Variable::Delay => write!(f, "delay"),
+            Variable::Sound => write!(f, "sound"),
+        }
+    }
+}
+
+/// Operators applied to a single operand.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UnaryOperator {
+    /// Logical not, `!`
+    Not,
+    /// Two's complement negation, `-`
+    Negate,
+    /// Bitwise complement, `~`
+    Complement,
+}
+
+impl UnaryOperator {
+    fn symbol(self) -> &'static str {
+        match self {
+            UnaryOperator::Not => "!",
+            UnaryOperator::Negate => "-",
+            UnaryOperator::Complement => "~",
+        }
+    }
+}
+
+/// Operators applied to two operands.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BinaryOperator {
+    Multiply,
+    Divide,
+    Remainder,
+    Add,
+    Subtract,
+    ShiftLeft,
+    ShiftRight,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Equal,
+    NotEqual,
+    BitAnd,
+    BitXor,
+    BitOr,
+    And,
+    Or,
+}
+
+/// Binary operators and their symbols, longest symbols first so that the
+/// tokenizer prefers `<=` over `<`.
+const BINARY_OPERATORS: [(&str, BinaryOperator); 18] = [
+    ("<<", BinaryOperator::ShiftLeft),
+    (">>", BinaryOperator::ShiftRight),
+    ("<=", BinaryOperator::LessEqual),
+    (">=", BinaryOperator::GreaterEqual),
+    ("==", BinaryOperator::Equal),
+    ("!=", BinaryOperator::NotEqual),
+    ("&&", BinaryOperator::And),
+    ("||", BinaryOperator::Or),
+    ("*", BinaryOperator::Multiply),
+    ("/", BinaryOperator::Divide),
+    ("%", BinaryOperator::Remainder),
+    ("+", BinaryOperator::Add),
+    ("-", BinaryOperator::Subtract),
+    ("<", BinaryOperator::Less),
+    (">", BinaryOperator::Greater),
+    ("&", BinaryOperator::BitAnd),
+    ("^", BinaryOperator::BitXor),
+    ("|", BinaryOperator::BitOr),
+];
+
+/// Precedence given to unary operators, higher than any binary operator.
+const UNARY_PRECEDENCE: u8 = 11;
+
+impl BinaryOperator {
+    fn symbol(self) -> &'static str {
+        BINARY_OPERATORS
+            .iter()
+            .find(|&&(_, operator)| operator == self)
+            .map(|&(symbol, _)| symbol)
+            .unwrap_or("?")
+    }
+
+    /// Returns how tightly the operator binds; higher binds tighter.
+    fn precedence(self) -> u8 {
+        match self {
+            BinaryOperator::Multiply | BinaryOperator::Divide | BinaryOperator::Remainder => 10,
+            BinaryOperator::Add | BinaryOperator::Subtract => 9,
+            BinaryOperator::ShiftLeft | BinaryOperator::ShiftRight => 8,
+            BinaryOperator::Less
+            | BinaryOperator::LessEqual
+            | BinaryOperator::Greater
+            | BinaryOperator::GreaterEqual => 7,
+            BinaryOperator::Equal | BinaryOperator::NotEqual => 6,
+            BinaryOperator::BitAnd => 5,
+            BinaryOperator::BitXor => 4,
+            BinaryOperator::BitOr => 3,
+            BinaryOperator::And => 2,
+            BinaryOperator::Or => 1,
+        }
+    }
+
+    /// Apply the operator to two values. `And` and `Or` are short-circuited
+    /// by `Expression::evaluate` before reaching this point.
+    fn apply(self, lhs: u32, rhs: u32) -> u32 {
+        match self {
+            BinaryOperator::Multiply => lhs.wrapping_mul(rhs),
+            BinaryOperator::Divide => lhs.checked_div(rhs).unwrap_or(0),
+            BinaryOperator::Remainder => lhs.checked_rem(rhs).unwrap_or(0),
+            BinaryOperator::Add => lhs.wrapping_add(rhs),
+            BinaryOperator::Subtract => lhs.wrapping_sub(rhs),
+            BinaryOperator::ShiftLeft => lhs.checked_shl(rhs).unwrap_or(0),
+            BinaryOperator::ShiftRight => lhs.checked_shr(rhs).unwrap_or(0),
+            BinaryOperator::Less => (lhs < rhs) as u32,
+            BinaryOperator::LessEqual => (lhs <= rhs) as u32,
+            BinaryOperator::Greater => (lhs > rhs) as u32,
+            BinaryOperator::GreaterEqual => (lhs >= rhs) as u32,
+            BinaryOperator::Equal => (lhs == rhs) as u32,
+            BinaryOperator::NotEqual => (lhs != rhs) as u32,
+            BinaryOperator::BitAnd => lhs & rhs,
+            BinaryOperator::BitXor => lhs ^ rhs,
+            BinaryOperator::BitOr => lhs | rhs,
+            BinaryOperator::And => (lhs != 0 && rhs != 0) as u32,
+            BinaryOperator::Or => (lhs != 0 || rhs != 0) as u32,
+        }
+    }
+}
+
+/// A parsed expression.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expression {
+    /// Constant value
+    Literal(u32),
+    /// Register or timer
+    Variable(Variable),
+    /// Byte of memory at the address given by the inner expression
+    Memory(Box<Expression>),
+    /// Operator applied to one operand
+    Unary(UnaryOperator, Box<Expression>),
+    /// Operator applied to two operands
+    Binary(BinaryOperator, Box<Expression>, Box<Expression>),
+}
+
+impl Expression {
+    /// Parse an expression from its textual form.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate okto;
+    /// # use okto::expression::{Expression, Variable};
+    /// assert_eq!(
+    ///     Expression::Variable(Variable::Delay),
+    ///     Expression::parse("dt").unwrap()
+    /// );
+    /// assert!(Expression::parse("v3 ==").is_err());
+    /// assert!(Expression::parse("vg").is_err());
+    /// ```
+    pub fn parse(source: &str) -> OktoResult<Self> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser {
+            tokens: &tokens,
+            position: 0,
+        };
+
+        let expression = parser.expression(0)?;
+        match parser.peek() {
+            None => Ok(expression),
+            Some(token) => Err(invalid(format!("unexpected {}", token))),
+        }
+    }
+
+    /// Evaluate the expression against the given machine state.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate okto;
+    /// # use okto::cpu::Cpu;
+    /// # use okto::expression::{Context, Expression};
+    /// # use okto::memory::Memory;
+    /// let mut cpu = Cpu::new();
+    /// let mut memory = Memory::new();
+    /// cpu.v[0x3] = 0x10;
+    /// cpu.i = 0x300;
+    /// memory.data[0x302] = 0x7;
+    ///
+    /// let context = Context {
+    ///     cpu: &cpu,
+    ///     memory: &memory,
+    ///     delay_timer: 0,
+    ///     sound_timer: 0,
+    /// };
+    /// let expression = Expression::parse("v3 == 0x10 && [i+2] * 2").unwrap();
+    /// assert_eq!(1, expression.evaluate(&context));
+    /// assert_eq!(14, Expression::parse("[i+2] * 2").unwrap().evaluate(&context));
+    /// ```
+    pub fn evaluate(&self, context: &Context) -> u32 {
+        match *self {
+            Expression::Literal(value) => value,
+            Expression::Variable(variable) => variable.value(context),
+            Expression::Memory(ref address) => {
+                let address = address.evaluate(context) as u16;
+                context.memory.data[address as usize] as u32
+            }
+            Expression::Unary(operator, ref operand) => {
+                let value = operand.evaluate(context);
+                match operator {
+                    UnaryOperator::Not => (value == 0) as u32,
+                    UnaryOperator::Negate => value.wrapping_neg(),
+                    UnaryOperator::Complement => !value,
+                }
+            }
+            Expression::Binary(BinaryOperator::And, ref lhs, ref rhs) => {
+                (lhs.is_true(context) && rhs.is_true(context)) as u32
+            }
+            Expression::Binary(BinaryOperator::Or, ref lhs, ref rhs) => {
+                (lhs.is_true(context) || rhs.is_true(context)) as u32
+            }
+            Expression::Binary(operator, ref lhs, ref rhs) => {
+                operator.apply(lhs.evaluate(context), rhs.evaluate(context))
+            }
+        }
+    }
+
+    /// Returns true if the expression evaluates to a non-zero value.
+    pub fn is_true(&self, context: &Context) -> bool {
+        self.evaluate(context) != 0
+    }
+
+    /// Returns the precedence of the outermost operator, used to decide where
+    /// parentheses are needed when formatting.
+    fn precedence(&self) -> u8 {
+        match *self {
+            Expression::Binary(operator, _, _) => operator.precedence(),
+            Expression::Unary(_, _) => UNARY_PRECEDENCE,
+            _ => UNARY_PRECEDENCE + 1,
+        }
+    }
+
+    /// Format an operand, wrapping it in parentheses if it binds less tightly
+    /// than the given precedence.
+    fn fmt_operand(&self, f: &mut fmt::Formatter, precedence: u8) -> fmt::Result {
+        if self.precedence() < precedence {
+            write!(f, "({})", self)
+        } else {
+            write!(f, "{}", self)
+        }
+    }
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Expression::Literal(value) => write!(f, "{:#x}", value),
+            Expression::Variable(variable) => write!(f, "{}", variable),
+            Expression::Memory(ref address) => write!(f, "[{}]", address),
+            Expression::Unary(operator, ref operand) => {
+                write!(f, "{}", operator.symbol())?;
+                operand.fmt_operand(f, UNARY_PRECEDENCE)
+            }
+            Expression::Binary(operator, ref lhs, ref rhs) => {
+                // Operators are left associative, so only the right operand
+                // needs parentheses at equal precedence.
+                lhs.fmt_operand(f, operator.precedence())?;
+                write!(f, " {} ", operator.symbol())?;
+                rhs.fmt_operand(f, operator.precedence() + 1)
+            }
+        }
+    }
+}
+
+/// A message printed by a tracepoint, with `{expression}` placeholders
+/// replaced by their values. Values are printed in decimal unless the
+/// placeholder ends in `:x`, which prints them in hexadecimal. Literal braces
+/// are written `{{` and `}}`.
+///
+/// # Examples
+///
+/// ```
+/// # extern crate okto;
+/// # use okto::cpu::Cpu;
+/// # use okto::expression::{Context, LogMessage};
+/// # use okto::memory::Memory;
+/// let mut cpu = Cpu::new();
+/// cpu.v[0x3] = 12;
+/// let memory = Memory::new();
+/// let context = Context {
+///     cpu: &cpu,
+///     memory: &memory,
+///     delay_timer: 0,
+///     sound_timer: 0,
+/// };
+///
+/// let message = LogMessage::parse("score {v3} at {pc:x} {{ok}}").unwrap();
+/// assert_eq!("score 12 at 200 {ok}", message.render(&context));
+/// assert!(LogMessage::parse("{v3").is_err());
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct LogMessage {
+    source: String,
+    segments: Vec<Segment>,
+}
+
+/// Part of a log message.
+#[derive(Clone, Debug, PartialEq)]
+enum Segment {
+    Text(String),
+    Decimal(Expression),
+    Hexadecimal(Expression),
+}
+
+impl LogMessage {
+    /// Parse a message containing `{expression}` placeholders.
+    pub fn parse(source: &str) -> OktoResult<Self> {
+        let mut segments = Vec::new();
+        let mut text = String::new();
+        let mut chars = source.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '{' => {
+                    let mut placeholder = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => placeholder.push(c),
+                            None => return Err(invalid(String::from("unterminated '{'"))),
+                        }
+                    }
+
+                    if !text.is_empty() {
+                        segments.push(Segment::Text(text.split_off(0)));
+                    }
+                    segments.push(if placeholder.ends_with(":x") {
+                        let end = placeholder.len() - 2;
+                        Segment::Hexadecimal(Expression::parse(&placeholder[..end])?)
+                    } else {
+                        Segment::Decimal(Expression::parse(&placeholder)?)
+                    });
+                }
+                '}' => return Err(invalid(String::from("unmatched '}'"))),
+                _ => text.push(c),
+            }
+        }
+
+        if !text.is_empty() {
+            segments.push(Segment::Text(text));
+        }
+
+        Ok(Self {
+            source: String::from(source),
+            segments,
+        })
+    }
+
+    /// Render the message against the given machine state.
+    pub fn render(&self, context: &Context) -> String {
+        let mut message = String::new();
+
+        for segment in self.segments.iter() {
+            match *segment {
+                Segment::Text(ref text) => message.push_str(text),
+                Segment::Decimal(ref expression) => {
+                    message.push_str(&expression.evaluate(context).to_string())
+                }
+                Segment::Hexadecimal(ref expression) => {
+                    message.push_str(&format!("{:x}", expression.evaluate(context)))
+                }
+            }
+        }
+
+        message
+    }
+}
+
+impl fmt::Display for LogMessage {
+    /// Display the message as it was written.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+fn invalid(message: String) -> OktoError {
+    OktoError::new(OktoErrorKind::InvalidExpression(message))
+}
+
+/// Lexical tokens of the expression language.
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Number(u32),
+    Identifier(String),
+    Binary(BinaryOperator),
+    Not,
+    Complement,
+    OpenParen,
+    CloseParen,
+    OpenBracket,
+    CloseBracket,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Token::Number(value) => write!(f, "number {}", value),
+            Token::Identifier(ref name) => write!(f, "'{}'", name),
+            Token::Binary(operator) => write!(f, "'{}'", operator.symbol()),
+            Token::Not => write!(f, "'!'"),
+            Token::Complement => write!(f, "'~'"),
+            Token::OpenParen => write!(f, "'('"),
+            Token::CloseParen => write!(f, "')'"),
+            Token::OpenBracket => write!(f, "'['"),
+            Token::CloseBracket => write!(f, "']'"),
+        }
+    }
+}
+
+/// Split the source into tokens.
+fn tokenize(source: &str) -> OktoResult<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut rest = source.trim_start();
+
+    while let Some(c) = rest.chars().next() {
+        let length = if c.is_ascii_alphanumeric() || c == '_' {
+            let length = rest
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .unwrap_or(rest.len());
+            let word = &rest[..length];
+            tokens.push(if c.is_ascii_digit() {
+                Token::Number(parse_number(word)?)
+            } else {
+                Token::Identifier(String::from(word))
+            });
+            length
+        } else if let Some(&(symbol, operator)) = BINARY_OPERATORS
+            .iter()
+            .find(|&&(symbol, _)| rest.starts_with(symbol))
+        {
+            tokens.push(Token::Binary(operator));
+            symbol.len()
+        } else {
+            tokens.push(match c {
+                '!' => Token::Not,
+                '~' => Token::Complement,
+                '(' => Token::OpenParen,
+                ')' => Token::CloseParen,
+                '[' => Token::OpenBracket,
+                ']' => Token::CloseBracket,
+                _ => return Err(invalid(format!("unexpected character '{}'", c))),
+            });
+            c.len_utf8()
+        };
+
+        rest = rest[length..].trim_start();
+    }
+
+    Ok(tokens)
+}
+
+/// Parse a decimal, `0x` hexadecimal or `0b` binary number.
+fn parse_number(word: &str) -> OktoResult<u32> {
+    let lower = word.to_ascii_lowercase();
+    let result = if let Some(digits) = lower.strip_prefix("0x") {
+        u32::from_str_radix(digits, 16)
+    } else if let Some(digits) = lower.strip_prefix("0b") {
+        u32::from_str_radix(digits, 2)
+    } else {
+        lower.parse()
+    };
+
+    result.map_err(|_| invalid(format!("invalid number '{}'", word)))
+}
+
+/// Precedence climbing parser over a list of tokens.
+struct Parser<'a> {
+    tokens: &'a [Token],
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&'a Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> OktoResult<&'a Token> {
+        let token = self
+            .tokens
+            .get(self.position)
+            .ok_or_else(|| invalid(String::from("unexpected end of expression")))?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn expect(&mut self, expected: Token) -> OktoResult<()> {
+        let token = self.next()?;
+        if *token != expected {
+            return Err(invalid(format!("expected {}, found {}", expected, token)));
+        }
+        Ok(())
+    }
+
+    /// Parse binary operations whose operators bind at least as tightly as
+    /// the given precedence.
+    fn expression(&mut self, min_precedence: u8) -> OktoResult<Expression> {
+        let mut lhs = self.unary()?;
+
+        while let Some(&Token::Binary(operator)) = self.peek() {
+            if operator.precedence() < min_precedence {
+                break;
+            }
+            self.position += 1;
+            let rhs = self.expression(operator.precedence() + 1)?;
+            lhs = Expression::Binary(operator, Box::new(lhs), Box::new(rhs));
+        }
+
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> OktoResult<Expression> {
+        let operator = match self.peek() {
+            Some(&Token::Not) => UnaryOperator::Not,
+            Some(&Token::Complement) => UnaryOperator::Complement,
+            Some(&Token::Binary(BinaryOperator::Subtract)) => UnaryOperator::Negate,
+            _ => return self.primary(),
+        };
+
+        self.position += 1;
+        Ok(Expression::Unary(operator, Box::new(self.unary()?)))
+    }
+
+    fn primary(&mut self) -> OktoResult<Expression> {
+        match *self.next()? {
+            Token::Number(value) => Ok(Expression::Literal(value)),
+            Token::Identifier(ref name) => Variable::from_name(name)
+                .map(Expression::Variable)
+                .ok_or_else(|| invalid(format!("unknown variable '{}'", name))),
+            Token::OpenParen => {
+                let expression = self.expression(0)?;
+                self.expect(Token::CloseParen)?;
+                Ok(expression)
+            }
+            Token::OpenBracket => {
+                let address = self.expression(0)?;
+                self.expect(Token::CloseBracket)?;
+                Ok(Expression::Memory(Box::new(address)))
+            }
+            ref token => Err(invalid(format!("unexpected {}", token))),
+        }
+    }
+}
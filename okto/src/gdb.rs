@@ -0,0 +1,655 @@
+//! GDB Remote Serial Protocol server for debugging a running machine.
+//!
+//! The server exposes the registers V0 to VF, I, PC and SP, reads and writes
+//! of memory, software breakpoints, watchpoints, single-step and continue on
+//! top of `Machine::step`. Registers are numbered in that order; V0 to VF and
+//! SP are one byte wide, I and PC are two bytes wide and big-endian like the
+//! rest of the machine. The register layout is also published as a target
+//! description through `qXfer:features:read`.
+//!
+//! When the machine's history is enabled, `reverse-stepi` and
+//! `reverse-continue` run it backwards, as described in the `history` module.
+//!
+//! A ROM blocked waiting for a key stops the machine, and keys can then be
+//! pressed with `monitor key KEY` and released with `monitor key KEY up`.
+//!
+//! ```no_run
+//! # extern crate okto;
+//! # use okto::gdb;
+//! # use okto::machine::{Machine, DEFAULT_CYCLES_PER_FRAME};
+//! let mut machine = Machine::new();
+//! // Wait for `target remote localhost:1234`, then debug until detached
+//! gdb::serve_tcp(&mut machine, "127.0.0.1:1234", DEFAULT_CYCLES_PER_FRAME).unwrap();
+//! ```
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use std::path::Path;
+
+use super::breakpoint::{Breakpoint, BreakpointEntry, BreakpointId, HitReason, WatchAccess};
+use super::cpu::{Address, NUM_REGISTERS};
+use super::machine::{Machine, RunLimit, StopReason};
+use super::memory::MEMORY_SIZE_BYTES;
+
+/// Number of registers exposed to the debugger: V0 to VF, I, PC and SP.
+pub const NUM_GDB_REGISTERS: usize = NUM_REGISTERS + 3;
+
+/// Largest packet the server accepts, advertised through `qSupported`.
+const MAX_PACKET_SIZE: usize = 0x1000;
+
+/// Number of instructions run between checks for an interrupt from the
+/// debugger while continuing.
+const CONTINUE_CHUNK: u64 = 1000;
+
+/// Byte sent by the debugger to interrupt a running target.
+const INTERRUPT: u8 = 0x03;
+
+/// Signal reported when the machine stops normally, SIGTRAP.
+const SIGNAL_TRAP: u8 = 5;
+
+/// Signal reported when an instruction fails, SIGILL.
+const SIGNAL_ILLEGAL: u8 = 4;
+
+/// Signal reported when the debugger interrupts the machine, SIGINT.
+const SIGNAL_INTERRUPT: u8 = 2;
+
+/// Target description of the registers.
+const TARGET_XML: &str = "<?xml version=\"1.0\"?>\
+<!DOCTYPE target SYSTEM \"gdb-target.dtd\">\
+<target version=\"1.0\"><feature name=\"org.okto.chip8\">\
+<reg name=\"v0\" bitsize=\"8\"/><reg name=\"v1\" bitsize=\"8\"/>\
+<reg name=\"v2\" bitsize=\"8\"/><reg name=\"v3\" bitsize=\"8\"/>\
+<reg name=\"v4\" bitsize=\"8\"/><reg name=\"v5\" bitsize=\"8\"/>\
+<reg name=\"v6\" bitsize=\"8\"/><reg name=\"v7\" bitsize=\"8\"/>\
+<reg name=\"v8\" bitsize=\"8\"/><reg name=\"v9\" bitsize=\"8\"/>\
+<reg name=\"va\" bitsize=\"8\"/><reg name=\"vb\" bitsize=\"8\"/>\
+<reg name=\"vc\" bitsize=\"8\"/><reg name=\"vd\" bitsize=\"8\"/>\
+<reg name=\"ve\" bitsize=\"8\"/><reg name=\"vf\" bitsize=\"8\"/>\
+<reg name=\"i\" bitsize=\"16\" type=\"data_ptr\"/>\
+<reg name=\"pc\" bitsize=\"16\" type=\"code_ptr\"/>\
+<reg name=\"sp\" bitsize=\"8\"/>\
+</feature></target>";
+
+/// A connection to a debugger that can be polled for data while the machine
+/// is running.
+pub trait Connection: Read + Write {
+    /// Returns the next byte sent by the debugger if one has already arrived.
+    /// Must not block.
+    fn read_available(&mut self) -> io::Result<Option<u8>>;
+}
+
+impl Connection for TcpStream {
+    fn read_available(&mut self) -> io::Result<Option<u8>> {
+        let mut byte = [0u8];
+        self.set_nonblocking(true)?;
+        let result = self.read(&mut byte);
+        self.set_nonblocking(false)?;
+        match result {
+            Ok(1) => Ok(Some(byte[0])),
+            Ok(_) => Ok(None),
+            Err(ref error) if error.kind() == io::ErrorKind::WouldBlock => Ok(None),
+            Err(error) => Err(error),
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Connection for UnixStream {
+    fn read_available(&mut self) -> io::Result<Option<u8>> {
+        let mut byte = [0u8];
+        self.set_nonblocking(true)?;
+        let result = self.read(&mut byte);
+        self.set_nonblocking(false)?;
+        match result {
+            Ok(1) => Ok(Some(byte[0])),
+            Ok(_) => Ok(None),
+            Err(ref error) if error.kind() == io::ErrorKind::WouldBlock => Ok(None),
+            Err(error) => Err(error),
+        }
+    }
+}
+
+/// Wait for a debugger to connect on the given TCP address, then serve it
+/// until it detaches.
+pub fn serve_tcp<A: ToSocketAddrs>(
+    machine: &mut Machine,
+    address: A,
+    cycles_per_frame: u32,
+) -> io::Result<()> {
+    let listener = TcpListener::bind(address)?;
+    let (stream, _) = listener.accept()?;
+    stream.set_nodelay(true)?;
+    serve(machine, stream, cycles_per_frame)
+}
+
+/// Wait for a debugger to connect on a Unix socket created at the given
+/// path, then serve it until it detaches.
+#[cfg(unix)]
+pub fn serve_unix<P: AsRef<Path>>(
+    machine: &mut Machine,
+    path: P,
+    cycles_per_frame: u32,
+) -> io::Result<()> {
+    let listener = UnixListener::bind(path)?;
+    let (stream, _) = listener.accept()?;
+    serve(machine, stream, cycles_per_frame)
+}
+
+/// Serve a connected debugger until it detaches, kills the target or closes
+/// the connection. The delay and sound timers tick once every
+/// `cycles_per_frame` cycles while the machine runs.
+pub fn serve<C: Connection>(
+    machine: &mut Machine,
+    connection: C,
+    cycles_per_frame: u32,
+) -> io::Result<()> {
+    let mut session = Session {
+        machine,
+        connection,
+        cycles_per_frame,
+        no_ack: false,
+        unread: VecDeque::new(),
+        breakpoints: HashMap::new(),
+    };
+    session.run()
+}
+
+/// Result of handling one packet.
+enum Reply {
+    /// Send the packet and wait for the next one
+    Packet(String),
+    /// Send the packet and end the session
+    Close(String),
+    /// End the session without replying
+    Quit,
+}
+
+/// State of a connection to a debugger.
+struct Session<'a, C: Connection> {
+    machine: &'a mut Machine,
+    connection: C,
+    cycles_per_frame: u32,
+    /// Set once the debugger enables `QStartNoAckMode`
+    no_ack: bool,
+    /// Bytes received while polling for an interrupt, which belong to the
+    /// next packet
+    unread: VecDeque<u8>,
+    /// Machine breakpoints inserted by the debugger, keyed by their type,
+    /// address and length
+    breakpoints: HashMap<(u8, Address, Address), BreakpointId>,
+}
+
+impl<'a, C: Connection> Session<'a, C> {
+    fn run(&mut self) -> io::Result<()> {
+        loop {
+            let packet = match self.read_packet()? {
+                Some(packet) => packet,
+                None => return Ok(()),
+            };
+
+            match self.handle(&packet) {
+                Reply::Packet(reply) => self.write_packet(&reply)?,
+                Reply::Close(reply) => return self.write_packet(&reply),
+                Reply::Quit => return Ok(()),
+            }
+        }
+    }
+
+    /// Read the next byte of a packet, starting with any received while the
+    /// machine was running.
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        match self.unread.pop_front() {
+            Some(byte) => Ok(Some(byte)),
+            None => self.receive_byte(),
+        }
+    }
+
+    /// Read the next byte from the connection.
+    fn receive_byte(&mut self) -> io::Result<Option<u8>> {
+        let mut byte = [0u8];
+        match self.connection.read(&mut byte)? {
+            0 => Ok(None),
+            _ => Ok(Some(byte[0])),
+        }
+    }
+
+    /// Read the next packet, acknowledging it unless acknowledgements are
+    /// disabled. Returns `None` once the connection is closed.
+    fn read_packet(&mut self) -> io::Result<Option<Vec<u8>>> {
+        loop {
+            // Skip acknowledgements and stray interrupts between packets.
+            match self.read_byte()? {
+                None => return Ok(None),
+                Some(b'$') => {}
+                Some(_) => continue,
+            }
+
+            // The rest of an oversized packet is read but not kept, so that
+            // the next packet is found after its checksum.
+            let mut data = Vec::new();
+            let mut checksum = 0u8;
+            let mut oversized = false;
+            loop {
+                match self.read_byte()? {
+                    None => return Ok(None),
+                    Some(b'#') => break,
+                    Some(byte) => {
+                        checksum = checksum.wrapping_add(byte);
+                        if data.len() < MAX_PACKET_SIZE {
+                            data.push(byte);
+                        } else {
+                            oversized = true;
+                        }
+                    }
+                }
+            }
+
+            let mut expected = [0u8; 2];
+            for digit in expected.iter_mut() {
+                match self.read_byte()? {
+                    Some(byte) => *digit = byte,
+                    None => return Ok(None),
+                }
+            }
+            let valid = parse_hex(&expected) == Some(checksum as u32);
+
+            if !self.no_ack {
+                self.connection.write_all(if valid { b"+" } else { b"-" })?;
+            }
+            if oversized && (valid || self.no_ack) {
+                self.write_packet("E01")?;
+            } else if valid || self.no_ack {
+                return Ok(Some(unescape(&data)));
+            }
+        }
+    }
+
+    /// Returns true if the debugger has sent an interrupt request, consuming
+    /// it. Any other bytes received are kept for the next packet.
+    fn interrupt_pending(&mut self) -> io::Result<bool> {
+        while let Some(byte) = self.connection.read_available()? {
+            if byte == INTERRUPT {
+                return Ok(true);
+            }
+            self.unread.push_back(byte);
+        }
+        Ok(false)
+    }
+
+    /// Send a packet, resending it until the debugger acknowledges it.
+    fn write_packet(&mut self, data: &str) -> io::Result<()> {
+        let checksum = data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+        let packet = format!("${}#{:02x}", data, checksum);
+
+        loop {
+            self.connection.write_all(packet.as_bytes())?;
+            self.connection.flush()?;
+            if self.no_ack {
+                return Ok(());
+            }
+
+            // The acknowledgement is sent after the packet, so it follows any
+            // bytes received while the machine was running.
+            match self.receive_byte()? {
+                Some(b'-') => continue,
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    fn handle(&mut self, packet: &[u8]) -> Reply {
+        let packet = String::from_utf8_lossy(packet).into_owned();
+        let (command, arguments) = packet.split_at(packet.len().min(1));
+
+        let reply = match command {
+            "?" => Some(self.stop_reply_for_signal(SIGNAL_TRAP)),
+            "g" => self.read_registers(),
+            "G" => self.write_registers(arguments),
+            "p" => self.read_register(arguments),
+            "P" => self.write_register(arguments),
+            "m" => self.read_memory(arguments),
+            "M" => self.write_memory(arguments),
+            "Z" => self.insert_breakpoint(arguments),
+            "z" => self.remove_breakpoint(arguments),
+            "s" => self.resume(true),
+            "c" => self.resume(false),
+            "b" if arguments == "s" => self.reverse(true),
+            "b" if arguments == "c" => self.reverse(false),
+            "H" => Some(String::from("OK")),
+            "D" => return Reply::Close(String::from("OK")),
+            "k" => return Reply::Quit,
+            "q" | "Q" | "v" => self.handle_named(&packet),
+            _ => Some(String::new()),
+        };
+
+        Reply::Packet(reply.unwrap_or_else(|| String::from("E01")))
+    }
+
+    /// Handle general query and set packets, and `v` packets.
+    fn handle_named(&mut self, packet: &str) -> Option<String> {
+        let reply = if packet.starts_with("qSupported") {
+            format!(
+                "PacketSize={:x};qXfer:features:read+;QStartNoAckMode+;vContSupported+;\
+                 ReverseStep+;ReverseContinue+",
+                MAX_PACKET_SIZE
+            )
+        } else if packet == "QStartNoAckMode" {
+            self.no_ack = true;
+            String::from("OK")
+        } else if let Some(arguments) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            let (offset, length) = split_pair(arguments, ',')?;
+            let offset = (offset as usize).min(TARGET_XML.len());
+            let end = (offset + length as usize).min(TARGET_XML.len());
+            let prefix = if end == TARGET_XML.len() { "l" } else { "m" };
+            format!("{}{}", prefix, &TARGET_XML[offset..end])
+        } else if let Some(command) = packet.strip_prefix("qRcmd,") {
+            self.monitor(command)?
+        } else if packet == "qAttached" {
+            String::from("1")
+        } else if packet == "qC" {
+            String::from("QC1")
+        } else if packet == "qfThreadInfo" {
+            String::from("m1")
+        } else if packet == "qsThreadInfo" {
+            String::from("l")
+        } else if packet == "vCont?" {
+            String::from("vCont;c;C;s;S")
+        } else if let Some(actions) = packet.strip_prefix("vCont;") {
+            // There is a single thread, so only the first action matters.
+            let step = actions.starts_with('s') || actions.starts_with('S');
+            return self.resume(step);
+        } else {
+            String::new()
+        };
+
+        Some(reply)
+    }
+
+    /// Handle a `monitor` command sent as hex encoded text.
+    fn monitor(&mut self, command: &str) -> Option<String> {
+        let command = String::from_utf8(decode_hex(command)?).ok()?;
+        let words: Vec<&str> = command.split_whitespace().collect();
+
+        match words[..] {
+            ["key", key] | ["key", key, "down"] => {
+                self.machine.keyboard.press_key(parse_key(key)?);
+            }
+            ["key", key, "up"] => self.machine.keyboard.release_key(parse_key(key)?),
+            _ => {
+                let message = "Commands: key KEY [up|down]\n";
+                return Some(encode_hex(message.as_bytes()));
+            }
+        }
+
+        Some(String::from("OK"))
+    }
+
+    /// Returns the value of a register as big-endian bytes.
+    fn register_bytes(&self, register: usize) -> Option<Vec<u8>> {
+        let cpu = &self.machine.cpu;
+        let bytes = match register {
+            vx if vx < NUM_REGISTERS => vec![cpu.v[vx]],
+            16 => vec![(cpu.i >> 8) as u8, cpu.i as u8],
+            17 => vec![(cpu.pc >> 8) as u8, cpu.pc as u8],
+            18 => vec![cpu.sp],
+            _ => return None,
+        };
+        Some(bytes)
+    }
+
+    /// Set a register from big-endian bytes, which must be of the register's
+    /// width.
+    fn set_register(&mut self, register: usize, bytes: &[u8]) -> Option<()> {
+        let cpu = &mut self.machine.cpu;
+        let word = || ((bytes[0] as u16) << 8) | bytes[1] as u16;
+        match (register, bytes.len()) {
+            (vx, 1) if vx < NUM_REGISTERS => cpu.v[vx] = bytes[0],
+            (16, 2) => cpu.i = word(),
+            (17, 2) => cpu.pc = word(),
+            (18, 1) if bytes[0] as usize <= cpu.stack.len() => cpu.sp = bytes[0],
+            _ => return None,
+        }
+        Some(())
+    }
+
+    fn read_registers(&self) -> Option<String> {
+        let mut reply = String::new();
+        for register in 0..NUM_GDB_REGISTERS {
+            reply.push_str(&encode_hex(&self.register_bytes(register)?));
+        }
+        Some(reply)
+    }
+
+    fn write_registers(&mut self, arguments: &str) -> Option<String> {
+        let bytes = decode_hex(arguments)?;
+        let mut offset = 0;
+        for register in 0..NUM_GDB_REGISTERS {
+            let width = self.register_bytes(register)?.len();
+            self.set_register(register, bytes.get(offset..offset + width)?)?;
+            offset += width;
+        }
+        Some(String::from("OK"))
+    }
+
+    fn read_register(&self, arguments: &str) -> Option<String> {
+        let register = parse_hex(arguments.as_bytes())? as usize;
+        Some(encode_hex(&self.register_bytes(register)?))
+    }
+
+    fn write_register(&mut self, arguments: &str) -> Option<String> {
+        let mut parts = arguments.splitn(2, '=');
+        let register = parse_hex(parts.next()?.as_bytes())? as usize;
+        let bytes = decode_hex(parts.next()?)?;
+        self.set_register(register, &bytes)?;
+        Some(String::from("OK"))
+    }
+
+    /// Parse an `ADDR,LEN` range, checking that it lies within memory.
+    fn memory_range(arguments: &str) -> Option<(usize, usize)> {
+        let (address, length) = split_pair(arguments, ',')?;
+        let (address, length) = (address as usize, length as usize);
+        if address + length > MEMORY_SIZE_BYTES {
+            return None;
+        }
+        Some((address, length))
+    }
+
+    fn read_memory(&self, arguments: &str) -> Option<String> {
+        let (address, length) = Self::memory_range(arguments)?;
+        Some(encode_hex(
+            &self.machine.memory.data[address..address + length],
+        ))
+    }
+
+    fn write_memory(&mut self, arguments: &str) -> Option<String> {
+        let mut parts = arguments.splitn(2, ':');
+        let (address, length) = Self::memory_range(parts.next()?)?;
+        let bytes = decode_hex(parts.next()?)?;
+        if bytes.len() != length {
+            return None;
+        }
+        self.machine.memory.data[address..address + length].copy_from_slice(&bytes);
+        Some(String::from("OK"))
+    }
+
+    /// Parse the `TYPE,ADDR,KIND` arguments of `Z` and `z` packets.
+    fn breakpoint_arguments(arguments: &str) -> Option<(u8, Address, Address)> {
+        let mut parts = arguments.splitn(2, ',');
+        let kind = parse_hex(parts.next()?.as_bytes())?;
+        let (address, length) = split_pair(parts.next()?, ',')?;
+        if kind > 4 || address as usize >= MEMORY_SIZE_BYTES || length > 0xFFFF {
+            return None;
+        }
+        Some((kind as u8, address as Address, length as Address))
+    }
+
+    fn insert_breakpoint(&mut self, arguments: &str) -> Option<String> {
+        let (kind, address, length) = Self::breakpoint_arguments(arguments)?;
+        let end = address.saturating_add(length.max(1) - 1);
+        let breakpoint = match kind {
+            0 | 1 => Breakpoint::Pc(address),
+            2 => Breakpoint::Memory {
+                start: address,
+                end,
+                access: WatchAccess::Write,
+            },
+            3 => Breakpoint::Memory {
+                start: address,
+                end,
+                access: WatchAccess::Read,
+            },
+            _ => Breakpoint::Memory {
+                start: address,
+                end,
+                access: WatchAccess::ReadWrite,
+            },
+        };
+
+        let key = (kind, address, length);
+        if !self.breakpoints.contains_key(&key) {
+            let id = self.machine.add_breakpoint(breakpoint);
+            self.breakpoints.insert(key, id);
+        }
+        Some(String::from("OK"))
+    }
+
+    fn remove_breakpoint(&mut self, arguments: &str) -> Option<String> {
+        let key = Self::breakpoint_arguments(arguments)?;
+        if let Some(id) = self.breakpoints.remove(&key) {
+            self.machine.remove_breakpoint(id);
+        }
+        Some(String::from("OK"))
+    }
+
+    /// Run the machine until the debugger should be told it stopped,
+    /// returning the stop reply.
+    fn resume(&mut self, step: bool) -> Option<String> {
+        loop {
+            let pc = self.machine.cpu.pc;
+            let cycles = self.machine.cycles;
+            let budget = if step { 1 } else { CONTINUE_CHUNK };
+            let limit = RunLimit {
+                cycles_per_frame: self.cycles_per_frame,
+                ..RunLimit::instructions(budget)
+            };
+            let reason = self.machine.run_until(&limit);
+
+            // A breakpoint at the address execution resumes from stops the
+            // machine before it moves; resume again to execute it.
+            let stuck = self.machine.cycles == cycles && self.machine.cpu.pc == pc;
+            if let StopReason::BreakpointHit(_) = reason {
+                if stuck {
+                    continue;
+                }
+            }
+
+            if reason == StopReason::BudgetExhausted && !step {
+                if self.interrupt_pending().ok()? {
+                    return Some(self.stop_reply_for_signal(SIGNAL_INTERRUPT));
+                }
+                continue;
+            }
+            return Some(self.stop_reply(&reason));
+        }
+    }
+
+    /// Run the machine backwards through its history by one instruction or
+    /// until a breakpoint, returning the stop reply.
+    fn reverse(&mut self, step: bool) -> Option<String> {
+        let limit = RunLimit {
+            instructions: if step { Some(1) } else { None },
+            cycles_per_frame: self.cycles_per_frame,
+            ..RunLimit::default()
+        };
+        let reason = self.machine.run_back(&limit);
+        Some(self.stop_reply(&reason))
+    }
+
+    /// Returns the stop reply telling the debugger why the machine stopped.
+    fn stop_reply(&self, reason: &StopReason) -> String {
+        let extra = match *reason {
+            StopReason::Exit => return String::from("W00"),
+            StopReason::Error(_) => return self.stop_reply_for_signal(SIGNAL_ILLEGAL),
+            StopReason::HistoryExhausted => String::from("replaylog:begin;"),
+            StopReason::BreakpointHit(hit) => {
+                let access = match self.machine.breakpoints().get(hit.id) {
+                    Some(&BreakpointEntry {
+                        breakpoint: Breakpoint::Memory { access, .. },
+                        ..
+                    }) => Some(access),
+                    _ => None,
+                };
+                match (hit.reason, access) {
+                    (HitReason::MemoryRead(address), Some(WatchAccess::ReadWrite))
+                    | (HitReason::MemoryWrite(address), Some(WatchAccess::ReadWrite)) => {
+                        format!("awatch:{:x};", address)
+                    }
+                    (HitReason::MemoryWrite(address), _) => format!("watch:{:x};", address),
+                    (HitReason::MemoryRead(address), _) => format!("rwatch:{:x};", address),
+                    _ => String::new(),
+                }
+            }
+            _ => String::new(),
+        };
+        format!("{}{}", self.stop_reply_for_signal(SIGNAL_TRAP), extra)
+    }
+
+    /// Returns a stop reply reporting the signal along with the PC.
+    fn stop_reply_for_signal(&self, signal: u8) -> String {
+        let pc = self.machine.cpu.pc;
+        format!("T{:02x}11:{:04x};", signal, pc)
+    }
+}
+
+/// Decode the `}` escapes used in packet data.
+fn unescape(data: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(data.len());
+    let mut bytes = data.iter();
+    while let Some(&byte) = bytes.next() {
+        if byte == b'}' {
+            if let Some(&escaped) = bytes.next() {
+                result.push(escaped ^ 0x20);
+            }
+        } else {
+            result.push(byte);
+        }
+    }
+    result
+}
+
+fn parse_hex(digits: &[u8]) -> Option<u32> {
+    let digits = ::std::str::from_utf8(digits).ok()?;
+    if digits.is_empty() || digits.len() > 8 {
+        return None;
+    }
+    u32::from_str_radix(digits, 16).ok()
+}
+
+/// Parse two hexadecimal numbers separated by the given character.
+fn split_pair(arguments: &str, separator: char) -> Option<(u32, u32)> {
+    let mut parts = arguments.splitn(2, separator);
+    let first = parse_hex(parts.next()?.as_bytes())?;
+    let second = parse_hex(parts.next()?.as_bytes())?;
+    Some((first, second))
+}
+
+fn parse_key(key: &str) -> Option<u8> {
+    u8::from_str_radix(key, 16).ok().filter(|&key| key < 0x10)
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    text.as_bytes()
+        .chunks(2)
+        .map(|pair| parse_hex(pair).map(|value| value as u8))
+        .collect()
+}
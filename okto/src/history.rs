@@ -0,0 +1,233 @@
+//! Per-instruction undo records used to run the machine backwards.
+//!
+//! While history is enabled with `Machine::start_history`, every step records
+//! just enough to undo itself: the registers it changed, the previous value of
+//! each byte of memory it wrote and the pixels flipped by `Display::draw`,
+//! along with the timers and execution state. The few instructions that
+//! rewrite the whole display, such as clearing and scrolling, save the frame
+//! buffer instead. This is much cheaper than a snapshot per instruction, so
+//! hundreds of thousands of steps can be kept. `Machine::step_back` and
+//! `Machine::run_back` undo the records newest first.
+//!
+//! Key presses come from outside the machine and are not undone.
+use super::cpu::{Address, Cpu, Operation, Register, NUM_HP48_REGISTERS, NUM_REGISTERS};
+use super::display::{Display, PixelFlip, DISPLAY_HEIGHT, DISPLAY_WIDTH};
+use super::machine::ExecutionState;
+use super::memory::MemoryAccess;
+use super::sound::AudioPattern;
+use super::timer::Timer;
+
+use std::collections::VecDeque;
+
+/// Default number of steps retained by a history.
+pub const DEFAULT_CAPACITY: usize = 100_000;
+
+/// Previous value of a CPU register changed by a step.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SavedRegister {
+    /// General purpose register VX
+    V(Register, Register),
+    /// Address register
+    I(Address),
+    /// Stack pointer
+    Sp(Register),
+    /// Entry in the call stack
+    Stack(usize, Address),
+    /// HP48 flag register
+    Hp48(usize, Register),
+}
+
+impl SavedRegister {
+    /// Returns the previous values of the registers that differ between
+    /// `before` and `after`, other than the program counter.
+    pub fn changes(before: &Cpu, after: &Cpu) -> Vec<SavedRegister> {
+        let mut saved = Vec::new();
+
+        for vx in 0..NUM_REGISTERS {
+            if before.v[vx] != after.v[vx] {
+                saved.push(SavedRegister::V(vx as Register, before.v[vx]));
+            }
+        }
+        if before.i != after.i {
+            saved.push(SavedRegister::I(before.i));
+        }
+        if before.sp != after.sp {
+            saved.push(SavedRegister::Sp(before.sp));
+        }
+        for (index, (&old, &new)) in before.stack.iter().zip(after.stack.iter()).enumerate() {
+            if old != new {
+                saved.push(SavedRegister::Stack(index, old));
+            }
+        }
+        for index in 0..NUM_HP48_REGISTERS {
+            if before.hp48[index] != after.hp48[index] {
+                saved.push(SavedRegister::Hp48(index, before.hp48[index]));
+            }
+        }
+
+        saved
+    }
+
+    /// Put the previous value back into the register.
+    pub fn restore(&self, cpu: &mut Cpu) {
+        match *self {
+            SavedRegister::V(vx, value) => cpu.v[vx as usize] = value,
+            SavedRegister::I(value) => cpu.i = value,
+            SavedRegister::Sp(value) => cpu.sp = value,
+            SavedRegister::Stack(index, value) => cpu.stack[index] = value,
+            SavedRegister::Hp48(index, value) => cpu.hp48[index] = value,
+        }
+    }
+}
+
+/// Complete copy of the display, saved by instructions that change more than
+/// the pixels of a sprite.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SavedDisplay {
+    /// Frame buffer
+    pub data: Box<[[u8; DISPLAY_WIDTH]; DISPLAY_HEIGHT]>,
+    /// Whether the display was in high resolution mode
+    pub high_resolution: bool,
+    /// Selected bitplanes
+    pub planes: u8,
+}
+
+impl<'a> From<&'a Display> for SavedDisplay {
+    fn from(display: &'a Display) -> Self {
+        Self {
+            data: Box::new(display.data),
+            high_resolution: display.high_resolution,
+            planes: display.planes,
+        }
+    }
+}
+
+impl SavedDisplay {
+    /// Put the saved state back into the display.
+    pub fn restore(&self, display: &mut Display) {
+        display.data = *self.data;
+        display.high_resolution = self.high_resolution;
+        display.planes = self.planes;
+        display.dirty = true;
+    }
+}
+
+/// Everything needed to undo a single step of the machine.
+#[derive(Clone, Debug, PartialEq)]
+pub struct UndoRecord {
+    /// Cycle count before the step
+    pub cycle: u64,
+    /// Program counter before the step
+    pub pc: Address,
+    /// Operation executed, or `None` if the machine was waiting for a key
+    pub operation: Option<Operation>,
+    /// Previous values of the changed registers
+    pub registers: Vec<SavedRegister>,
+    /// Memory read and written by the operation
+    pub accesses: Vec<MemoryAccess>,
+    /// Previous value of each byte of memory written, in the order written
+    pub memory: Vec<(Address, u8)>,
+    /// Pixels flipped by drawing sprites
+    pub pixels: Vec<PixelFlip>,
+    /// Display before the step, if the operation changed more than pixels
+    pub display: Option<SavedDisplay>,
+    /// Delay timer before the step
+    pub delay_timer: Timer,
+    /// Sound timer before the step
+    pub sound_timer: Timer,
+    /// Audio pattern and pitch before the step, if the operation changed them
+    pub audio: Option<(Option<AudioPattern>, u8)>,
+    /// State of the random number source, if the operation drew from it
+    pub rng_state: Option<Vec<u8>>,
+    /// Execution state before the step
+    pub execution_state: ExecutionState,
+    /// Whether the machine was on before the step
+    pub machine_on: bool,
+}
+
+impl UndoRecord {
+    /// Returns true if undoing the record changes the display.
+    pub fn changes_display(&self) -> bool {
+        !self.pixels.is_empty() || self.display.is_some()
+    }
+}
+
+/// Fixed-capacity history of undo records, discarding the oldest once full.
+///
+/// # Examples
+///
+/// ```
+/// # extern crate okto;
+/// # use okto::machine::Machine;
+/// let mut machine = Machine::new();
+/// // V0 += 1 and loop forever
+/// machine.memory.data[0x200..0x204].copy_from_slice(&[0x70, 0x01, 0x12, 0x00]);
+/// machine.start_history(3);
+///
+/// for _ in 0..5 {
+///     machine.step().unwrap();
+/// }
+/// assert_eq!(3, machine.history().unwrap().len());
+///
+/// while machine.step_back() {}
+/// assert_eq!((1, 0x200), (machine.cpu.v[0x0], machine.cpu.pc));
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct History {
+    /// Maximum number of records retained
+    capacity: usize,
+    /// Records from oldest to newest
+    records: VecDeque<UndoRecord>,
+}
+
+impl History {
+    /// Create a history retaining at most `capacity` steps.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            records: VecDeque::new(),
+        }
+    }
+
+    /// Returns the maximum number of steps retained.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Returns the number of steps that can be undone.
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    /// Returns true if there is nothing to undo.
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    /// Add the record of the newest step, discarding the oldest if the
+    /// history is full.
+    pub fn push(&mut self, record: UndoRecord) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.records.len() == self.capacity {
+            self.records.pop_front();
+        }
+        self.records.push_back(record);
+    }
+
+    /// Remove and return the record of the newest step.
+    pub fn pop(&mut self) -> Option<UndoRecord> {
+        self.records.pop_back()
+    }
+
+    /// Returns the records from oldest to newest.
+    pub fn records(&self) -> impl Iterator<Item = &UndoRecord> {
+        self.records.iter()
+    }
+
+    /// Discard every record.
+    pub fn clear(&mut self) {
+        self.records.clear();
+    }
+}
@@ -0,0 +1,243 @@
+//! Sources of key input for the machine.
+//!
+//! Before each step the machine polls its `InputSource` with the current cycle
+//! count, and the source delivers any key presses and releases that are due
+//! to the keyboard. Interactive hosts can keep `NoInput` and call
+//! `Keyboard::press_key` and `Keyboard::release_key` directly, while headless
+//! hosts and tests replay a `ScriptedInput` or a `RecordedInput`.
+use super::keyboard::{KeyEvent, Keyboard, NUM_KEYS};
+use super::{OktoError, OktoErrorKind, OktoResult};
+
+/// Number of bytes used by each event in a serialized input recording.
+const RECORDED_EVENT_BYTES: usize = 10;
+
+/// Source of key events for the machine.
+///
+/// Sources only deliver events and have no way to query a key. The keyboard
+/// stays the one record of which keys are held, so the key instructions, the
+/// key wait and save states see the same state whichever source fed it.
+pub trait InputSource: Send {
+    /// Deliver to the keyboard every event due at or before the given machine
+    /// cycle.
+    fn poll(&mut self, cycle: u64, keyboard: &mut Keyboard);
+}
+
+/// Input source that never produces any events.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct NoInput;
+
+impl InputSource for NoInput {
+    fn poll(&mut self, _cycle: u64, _keyboard: &mut Keyboard) {}
+}
+
+/// A key event tagged with the machine cycle at which it happens.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TimedKeyEvent {
+    /// Machine cycle at which the event is delivered
+    pub cycle: u64,
+    /// The key press or release
+    pub event: KeyEvent,
+}
+
+/// Delivers every event in the slice due by the given cycle, starting at the
+/// given position, and returns the position of the first undelivered event.
+fn deliver_due_events(
+    events: &[TimedKeyEvent],
+    mut position: usize,
+    cycle: u64,
+    keyboard: &mut Keyboard,
+) -> usize {
+    while position < events.len() && events[position].cycle <= cycle {
+        match events[position].event {
+            KeyEvent::Pressed(key) => keyboard.press_key(key),
+            KeyEvent::Released(key) => keyboard.release_key(key),
+        }
+        position += 1;
+    }
+
+    position
+}
+
+/// Input source that replays a script of key events written by hand, for
+/// tests and automated runs of interactive ROMs.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ScriptedInput {
+    events: Vec<TimedKeyEvent>,
+    position: usize,
+}
+
+impl ScriptedInput {
+    /// Create an empty script.
+    pub fn new() -> Self {
+        Self {
+            events: Vec::new(),
+            position: 0,
+        }
+    }
+
+    /// Press the given key at the given cycle.
+    pub fn press(self, cycle: u64, key: u8) -> Self {
+        self.event(cycle, KeyEvent::Pressed(key))
+    }
+
+    /// Release the given key at the given cycle.
+    pub fn release(self, cycle: u64, key: u8) -> Self {
+        self.event(cycle, KeyEvent::Released(key))
+    }
+
+    /// Press the given key at the given cycle and release it the given number
+    /// of cycles later.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate okto;
+    /// # use okto::input::{InputSource, ScriptedInput};
+    /// # use okto::keyboard::{KeyState, Keyboard};
+    /// let mut input = ScriptedInput::new().tap(10, 0x5, 3);
+    /// let mut keyboard = Keyboard::new();
+    ///
+    /// input.poll(9, &mut keyboard);
+    /// assert_eq!(KeyState::Released, keyboard.keys[0x5]);
+    /// input.poll(10, &mut keyboard);
+    /// assert_eq!(KeyState::Pressed, keyboard.keys[0x5]);
+    /// input.poll(13, &mut keyboard);
+    /// assert_eq!(KeyState::Released, keyboard.keys[0x5]);
+    /// ```
+    pub fn tap(self, cycle: u64, key: u8, duration: u64) -> Self {
+        self.press(cycle, key).release(cycle.saturating_add(duration), key)
+    }
+
+    /// Add an event to the script, keeping events ordered by cycle. Events at
+    /// the same cycle are delivered in the order they were added.
+    pub fn event(mut self, cycle: u64, event: KeyEvent) -> Self {
+        let index = self
+            .events
+            .iter()
+            .position(|timed| timed.cycle > cycle)
+            .unwrap_or(self.events.len());
+        self.events.insert(index, TimedKeyEvent { cycle, event });
+        self
+    }
+
+    /// Returns true once every scripted event has been delivered.
+    pub fn is_finished(&self) -> bool {
+        self.position >= self.events.len()
+    }
+}
+
+impl InputSource for ScriptedInput {
+    fn poll(&mut self, cycle: u64, keyboard: &mut Keyboard) {
+        self.position = deliver_due_events(&self.events, self.position, cycle, keyboard);
+    }
+}
+
+/// Key events captured from a running machine with
+/// `Machine::start_recording`, in the order they were processed.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct InputRecording {
+    /// The recorded events, ordered by cycle
+    pub events: Vec<TimedKeyEvent>,
+}
+
+impl InputRecording {
+    /// Create an empty recording.
+    pub fn new() -> Self {
+        Self { events: Vec::new() }
+    }
+
+    /// Serialize the recording. Each event is stored as a big-endian 64-bit
+    /// cycle, a byte that is 1 for presses and 0 for releases, and the key.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(self.events.len() * RECORDED_EVENT_BYTES);
+
+        for timed in self.events.iter() {
+            for shift in (0..8).rev() {
+                data.push((timed.cycle >> (shift * 8)) as u8);
+            }
+            match timed.event {
+                KeyEvent::Pressed(key) => data.extend_from_slice(&[1, key]),
+                KeyEvent::Released(key) => data.extend_from_slice(&[0, key]),
+            }
+        }
+
+        data
+    }
+
+    /// Deserialize a recording produced by `to_bytes`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate okto;
+    /// # use okto::input::{InputRecording, TimedKeyEvent};
+    /// # use okto::keyboard::KeyEvent;
+    /// let mut recording = InputRecording::new();
+    /// recording.events.push(TimedKeyEvent {
+    ///     cycle: 300,
+    ///     event: KeyEvent::Pressed(0xF),
+    /// });
+    ///
+    /// let data = recording.to_bytes();
+    /// assert_eq!(recording, InputRecording::from_bytes(&data).unwrap());
+    /// assert!(InputRecording::from_bytes(&data[1..]).is_err());
+    /// ```
+    pub fn from_bytes(data: &[u8]) -> OktoResult<Self> {
+        if !data.len().is_multiple_of(RECORDED_EVENT_BYTES) {
+            return Err(OktoError::new(OktoErrorKind::InvalidInputRecording));
+        }
+
+        let mut events = Vec::with_capacity(data.len() / RECORDED_EVENT_BYTES);
+        let mut last_cycle = 0;
+
+        for chunk in data.chunks(RECORDED_EVENT_BYTES) {
+            let cycle = chunk[0..8]
+                .iter()
+                .fold(0u64, |value, &byte| (value << 8) | byte as u64);
+            let key = chunk[9];
+            if cycle < last_cycle || key as usize >= NUM_KEYS {
+                return Err(OktoError::new(OktoErrorKind::InvalidInputRecording));
+            }
+
+            let event = match chunk[8] {
+                0 => KeyEvent::Released(key),
+                1 => KeyEvent::Pressed(key),
+                _ => return Err(OktoError::new(OktoErrorKind::InvalidInputRecording)),
+            };
+
+            events.push(TimedKeyEvent { cycle, event });
+            last_cycle = cycle;
+        }
+
+        Ok(Self { events })
+    }
+}
+
+/// Input source that replays a recording captured from an earlier run, so
+/// that a session can be reproduced exactly.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RecordedInput {
+    recording: InputRecording,
+    position: usize,
+}
+
+impl RecordedInput {
+    /// Create an input source replaying the given recording from the start.
+    pub fn new(recording: InputRecording) -> Self {
+        Self {
+            recording,
+            position: 0,
+        }
+    }
+
+    /// Returns true once every recorded event has been delivered.
+    pub fn is_finished(&self) -> bool {
+        self.position >= self.recording.events.len()
+    }
+}
+
+impl InputSource for RecordedInput {
+    fn poll(&mut self, cycle: u64, keyboard: &mut Keyboard) {
+        self.position = deliver_due_events(&self.recording.events, self.position, cycle, keyboard);
+    }
+}
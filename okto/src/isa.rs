@@ -0,0 +1,955 @@
+//! Instruction set metadata for every `cpu::Operation`.
+//!
+//! `OPERATIONS` lists each operation once, with its opcode pattern, the
+//! operands it takes, its mnemonic in Octo and Cowgod syntax, the platforms
+//! that provide it, what it costs on the COSMAC VIP and what it does. Tools
+//! that print, parse or document instructions read from this table rather
+//! than spelling the instruction set out again.
+//!
+//! Mnemonics are templates in which each operand appears by name in braces,
+//! for example `sprite {x} {y} {n}`. `format` fills them in for a decoded
+//! operation and `OperationInfo::syntax` with generic placeholders.
+use super::cpu::{Instruction, Operation};
+
+use std::fmt;
+
+/// A CHIP-8 family platform providing an operation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Platform {
+    /// The original CHIP-8 interpreter
+    Chip8,
+    /// SuperChip 1.1
+    SuperChip,
+    /// XO-CHIP
+    XoChip,
+}
+
+impl Platform {
+    /// Returns the conventional name of the platform.
+    pub fn name(self) -> &'static str {
+        match self {
+            Platform::Chip8 => "CHIP-8",
+            Platform::SuperChip => "SCHIP",
+            Platform::XoChip => "XO-CHIP",
+        }
+    }
+}
+
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Every platform.
+const ALL: &[Platform] = &[Platform::Chip8, Platform::SuperChip, Platform::XoChip];
+/// Operations added by SuperChip, which XO-CHIP also provides.
+const SCHIP: &[Platform] = &[Platform::SuperChip, Platform::XoChip];
+/// Operations added by XO-CHIP.
+const XO: &[Platform] = &[Platform::XoChip];
+
+/// What an operand of an operation holds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OperandKind {
+    /// General purpose register number, `0` to `F`
+    Register,
+    /// Four bit value
+    Nibble,
+    /// Eight bit value
+    Byte,
+    /// Twelve bit address
+    Address,
+    /// Sixteen bit address, held in the word after the instruction
+    LongAddress,
+}
+
+/// A named operand of an operation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Operand {
+    /// Name used in opcode patterns and mnemonic templates, such as `x`
+    pub name: &'static str,
+    /// What the operand holds
+    pub kind: OperandKind,
+}
+
+const X: Operand = Operand {
+    name: "x",
+    kind: OperandKind::Register,
+};
+const Y: Operand = Operand {
+    name: "y",
+    kind: OperandKind::Register,
+};
+const N: Operand = Operand {
+    name: "n",
+    kind: OperandKind::Nibble,
+};
+const NN: Operand = Operand {
+    name: "nn",
+    kind: OperandKind::Byte,
+};
+const NNN: Operand = Operand {
+    name: "nnn",
+    kind: OperandKind::Address,
+};
+const NNNN: Operand = Operand {
+    name: "nnnn",
+    kind: OperandKind::LongAddress,
+};
+
+/// Assembly language syntax used to write an operation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Syntax {
+    /// Octo, as in `sprite v1 v2 5`
+    Octo,
+    /// Cowgod's technical reference, also used by Chipper, as in
+    /// `DRW V1, V2, 5`
+    Cowgod,
+}
+
+impl Syntax {
+    /// Returns the syntax with the given name, `octo` or `cowgod`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "octo" => Some(Syntax::Octo),
+            "cowgod" => Some(Syntax::Cowgod),
+            _ => None,
+        }
+    }
+
+    /// Write the value of an operand.
+    fn operand(self, kind: OperandKind, value: u16) -> String {
+        match (self, kind) {
+            (Syntax::Octo, OperandKind::Register) => format!("v{:x}", value),
+            (Syntax::Cowgod, OperandKind::Register) => format!("V{:X}", value),
+            (_, OperandKind::Nibble) => format!("{}", value),
+            (Syntax::Octo, OperandKind::Byte) => format!("0x{:02X}", value),
+            (Syntax::Cowgod, OperandKind::Byte) => format!("#{:02X}", value),
+            (Syntax::Octo, OperandKind::Address) => format!("0x{:03X}", value),
+            (Syntax::Cowgod, OperandKind::Address) => format!("#{:03X}", value),
+            (Syntax::Octo, OperandKind::LongAddress) => format!("0x{:04X}", value),
+            (Syntax::Cowgod, OperandKind::LongAddress) => format!("#{:04X}", value),
+        }
+    }
+
+    /// Write a generic placeholder for an operand.
+    fn placeholder(self, operand: &Operand) -> String {
+        match (self, operand.kind) {
+            (Syntax::Octo, OperandKind::Register) => format!("v{}", operand.name),
+            (Syntax::Cowgod, OperandKind::Register) => format!("V{}", operand.name),
+            _ => String::from(operand.name),
+        }
+    }
+}
+
+/// Metadata describing a single operation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OperationInfo {
+    /// Name of the operation, as returned by `Operation::name`
+    pub name: &'static str,
+    /// Opcode with operands named by their letters, such as `DXYN`. Long
+    /// instructions give both words, as in `F000 NNNN`.
+    pub pattern: &'static str,
+    /// Operands in the order they appear in the opcode
+    pub operands: &'static [Operand],
+    /// Octo mnemonic, or `None` if Octo has no statement for the operation
+    pub octo: Option<&'static str>,
+    /// Cowgod mnemonic
+    pub cowgod: &'static str,
+    /// Platforms providing the operation
+    pub platforms: &'static [Platform],
+    /// Machine cycles taken by the COSMAC VIP interpreter, not counting extra
+    /// cycles that depend on the operands or machine state, or `None` if the
+    /// VIP interpreter does not provide the operation
+    pub vip_cycles: Option<u32>,
+    /// One line description of what the operation does
+    pub description: &'static str,
+}
+
+impl OperationInfo {
+    /// Returns the mnemonic template in the given syntax, if there is one.
+    pub fn template(&self, syntax: Syntax) -> Option<&'static str> {
+        match syntax {
+            Syntax::Octo => self.octo,
+            Syntax::Cowgod => Some(self.cowgod),
+        }
+    }
+
+    /// Returns true if the given platform provides the operation.
+    pub fn is_available(&self, platform: Platform) -> bool {
+        self.platforms.contains(&platform)
+    }
+
+    /// Returns the mnemonic in the given syntax with a generic placeholder
+    /// for each operand.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate okto;
+    /// # use okto::isa::{self, Syntax};
+    /// let info = isa::find("Draw").unwrap();
+    /// assert_eq!(Some(String::from("sprite vx vy n")), info.syntax(Syntax::Octo));
+    /// assert_eq!(Some(String::from("DRW Vx, Vy, n")), info.syntax(Syntax::Cowgod));
+    /// ```
+    pub fn syntax(&self, syntax: Syntax) -> Option<String> {
+        self.template(syntax).map(|template| {
+            fill(template, |name| {
+                self.operands
+                    .iter()
+                    .find(|operand| operand.name == name)
+                    .map(|operand| syntax.placeholder(operand))
+            })
+        })
+    }
+
+    /// Returns the operation with the given operand values, in the same order
+    /// as `operands`, or `None` if a value does not fit in its operand.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate okto;
+    /// # use okto::cpu::Operation;
+    /// # use okto::isa;
+    /// let info = isa::find("Draw").unwrap();
+    /// assert_eq!(Some(Operation::Draw(1, 2, 5)), info.encode(&[1, 2, 5]));
+    /// assert_eq!(None, info.encode(&[1, 2, 16]));
+    /// ```
+    pub fn encode(&self, values: &[u16]) -> Option<Operation> {
+        if values.len() != self.operands.len() {
+            return None;
+        }
+
+        let mut words = Vec::new();
+        for word in self.pattern.split(' ') {
+            let mut instruction: u32 = 0;
+            let mut digits = word.chars().peekable();
+            while let Some(digit) = digits.next() {
+                let mut name = digit.to_ascii_lowercase().to_string();
+                let value = match digit.to_digit(16) {
+                    Some(value) => value,
+                    None => {
+                        while digits.peek() == Some(&digit) {
+                            digits.next();
+                            name.push(digit.to_ascii_lowercase());
+                        }
+                        let index = self.operands.iter().position(|o| o.name == name)?;
+                        let value = u32::from(values[index]);
+                        if value >> (4 * name.len()) != 0 {
+                            return None;
+                        }
+                        value
+                    }
+                };
+                instruction = (instruction << (4 * name.len())) | value;
+            }
+            words.push(instruction as Instruction);
+        }
+
+        match words.len() {
+            1 => Operation::from_instruction(&words[0]),
+            _ => Operation::from_long_instruction(&words[0], &words[1]),
+        }
+        .filter(|operation| operation.name() == self.name)
+    }
+}
+
+/// Replace each `{name}` in the template with the given text for the name.
+fn fill<F>(template: &str, mut operand: F) -> String
+where
+    F: FnMut(&str) -> Option<String>,
+{
+    let mut text = String::new();
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        let end = start + rest[start..].find('}').expect("unterminated operand");
+        let name = &rest[start + 1..end];
+        text.push_str(&rest[..start]);
+        text.push_str(&operand(name).expect("unknown operand"));
+        rest = &rest[end + 1..];
+    }
+    text.push_str(rest);
+
+    text
+}
+
+/// Metadata for every operation, in the order of `cpu::Operation`.
+pub const OPERATIONS: &[OperationInfo] = &[
+    // Chip8 Opcodes
+    OperationInfo {
+        name: "Cls",
+        pattern: "00E0",
+        operands: &[],
+        octo: Some("clear"),
+        cowgod: "CLS",
+        platforms: ALL,
+        vip_cycles: Some(24),
+        description: "Clear the display",
+    },
+    OperationInfo {
+        name: "Ret",
+        pattern: "00EE",
+        operands: &[],
+        octo: Some("return"),
+        cowgod: "RET",
+        platforms: ALL,
+        vip_cycles: Some(10),
+        description: "Return from a subroutine",
+    },
+    OperationInfo {
+        name: "Sys",
+        pattern: "0NNN",
+        operands: &[NNN],
+        octo: Some("native {nnn}"),
+        cowgod: "SYS {nnn}",
+        platforms: &[Platform::Chip8],
+        vip_cycles: None,
+        description: "Call a machine language subroutine at NNN",
+    },
+    OperationInfo {
+        name: "Jump",
+        pattern: "1NNN",
+        operands: &[NNN],
+        octo: Some("jump {nnn}"),
+        cowgod: "JP {nnn}",
+        platforms: ALL,
+        vip_cycles: Some(12),
+        description: "Jump to NNN",
+    },
+    OperationInfo {
+        name: "JumpAddrPlusV0",
+        pattern: "BNNN",
+        operands: &[NNN],
+        octo: Some("jump0 {nnn}"),
+        cowgod: "JP V0, {nnn}",
+        platforms: ALL,
+        vip_cycles: Some(22),
+        description: "Jump to NNN plus V0",
+    },
+    OperationInfo {
+        name: "Call",
+        pattern: "2NNN",
+        operands: &[NNN],
+        octo: Some(":call {nnn}"),
+        cowgod: "CALL {nnn}",
+        platforms: ALL,
+        vip_cycles: Some(26),
+        description: "Call the subroutine at NNN",
+    },
+    OperationInfo {
+        name: "SkipEqImm",
+        pattern: "3XNN",
+        operands: &[X, NN],
+        octo: Some("if {x} != {nn} then"),
+        cowgod: "SE {x}, {nn}",
+        platforms: ALL,
+        vip_cycles: Some(10),
+        description: "Skip the next instruction if VX equals NN",
+    },
+    OperationInfo {
+        name: "SkipEqReg",
+        pattern: "5XY0",
+        operands: &[X, Y],
+        octo: Some("if {x} != {y} then"),
+        cowgod: "SE {x}, {y}",
+        platforms: ALL,
+        vip_cycles: Some(14),
+        description: "Skip the next instruction if VX equals VY",
+    },
+    OperationInfo {
+        name: "SkipNeqImm",
+        pattern: "4XNN",
+        operands: &[X, NN],
+        octo: Some("if {x} == {nn} then"),
+        cowgod: "SNE {x}, {nn}",
+        platforms: ALL,
+        vip_cycles: Some(10),
+        description: "Skip the next instruction if VX does not equal NN",
+    },
+    OperationInfo {
+        name: "SkipNeqReg",
+        pattern: "9XY0",
+        operands: &[X, Y],
+        octo: Some("if {x} == {y} then"),
+        cowgod: "SNE {x}, {y}",
+        platforms: ALL,
+        vip_cycles: Some(14),
+        description: "Skip the next instruction if VX does not equal VY",
+    },
+    OperationInfo {
+        name: "LoadImm",
+        pattern: "6XNN",
+        operands: &[X, NN],
+        octo: Some("{x} := {nn}"),
+        cowgod: "LD {x}, {nn}",
+        platforms: ALL,
+        vip_cycles: Some(6),
+        description: "Set VX to NN",
+    },
+    OperationInfo {
+        name: "LoadReg",
+        pattern: "8XY0",
+        operands: &[X, Y],
+        octo: Some("{x} := {y}"),
+        cowgod: "LD {x}, {y}",
+        platforms: ALL,
+        vip_cycles: Some(12),
+        description: "Set VX to VY",
+    },
+    OperationInfo {
+        name: "LoadAddr",
+        pattern: "ANNN",
+        operands: &[NNN],
+        octo: Some("i := {nnn}"),
+        cowgod: "LD I, {nnn}",
+        platforms: ALL,
+        vip_cycles: Some(12),
+        description: "Set I to NNN",
+    },
+    OperationInfo {
+        name: "LoadAddrDigit",
+        pattern: "FX29",
+        operands: &[X],
+        octo: Some("i := hex {x}"),
+        cowgod: "LD F, {x}",
+        platforms: ALL,
+        vip_cycles: Some(16),
+        description: "Point I at the small font sprite for the digit in VX",
+    },
+    OperationInfo {
+        name: "LoadRegDelay",
+        pattern: "FX07",
+        operands: &[X],
+        octo: Some("{x} := delay"),
+        cowgod: "LD {x}, DT",
+        platforms: ALL,
+        vip_cycles: Some(10),
+        description: "Set VX to the delay timer",
+    },
+    OperationInfo {
+        name: "LoadDelayReg",
+        pattern: "FX15",
+        operands: &[X],
+        octo: Some("delay := {x}"),
+        cowgod: "LD DT, {x}",
+        platforms: ALL,
+        vip_cycles: Some(10),
+        description: "Set the delay timer to VX",
+    },
+    OperationInfo {
+        name: "LoadSoundReg",
+        pattern: "FX18",
+        operands: &[X],
+        octo: Some("buzzer := {x}"),
+        cowgod: "LD ST, {x}",
+        platforms: ALL,
+        vip_cycles: Some(10),
+        description: "Set the sound timer to VX",
+    },
+    OperationInfo {
+        name: "AddImm",
+        pattern: "7XNN",
+        operands: &[X, NN],
+        octo: Some("{x} += {nn}"),
+        cowgod: "ADD {x}, {nn}",
+        platforms: ALL,
+        vip_cycles: Some(10),
+        description: "Add NN to VX without setting the carry flag",
+    },
+    OperationInfo {
+        name: "AddReg",
+        pattern: "8XY4",
+        operands: &[X, Y],
+        octo: Some("{x} += {y}"),
+        cowgod: "ADD {x}, {y}",
+        platforms: ALL,
+        vip_cycles: Some(44),
+        description: "Add VY to VX, setting VF to the carry",
+    },
+    OperationInfo {
+        name: "AddAddrReg",
+        pattern: "FX1E",
+        operands: &[X],
+        octo: Some("i += {x}"),
+        cowgod: "ADD I, {x}",
+        platforms: ALL,
+        vip_cycles: Some(12),
+        description: "Add VX to I",
+    },
+    OperationInfo {
+        name: "Sub",
+        pattern: "8XY5",
+        operands: &[X, Y],
+        octo: Some("{x} -= {y}"),
+        cowgod: "SUB {x}, {y}",
+        platforms: ALL,
+        vip_cycles: Some(44),
+        description: "Subtract VY from VX, setting VF when there is no borrow",
+    },
+    OperationInfo {
+        name: "SubNeg",
+        pattern: "8XY7",
+        operands: &[X, Y],
+        octo: Some("{x} =- {y}"),
+        cowgod: "SUBN {x}, {y}",
+        platforms: ALL,
+        vip_cycles: Some(44),
+        description: "Set VX to VY minus VX, setting VF when there is no borrow",
+    },
+    OperationInfo {
+        name: "Or",
+        pattern: "8XY1",
+        operands: &[X, Y],
+        octo: Some("{x} |= {y}"),
+        cowgod: "OR {x}, {y}",
+        platforms: ALL,
+        vip_cycles: Some(44),
+        description: "Set VX to VX or VY",
+    },
+    OperationInfo {
+        name: "And",
+        pattern: "8XY2",
+        operands: &[X, Y],
+        octo: Some("{x} &= {y}"),
+        cowgod: "AND {x}, {y}",
+        platforms: ALL,
+        vip_cycles: Some(44),
+        description: "Set VX to VX and VY",
+    },
+    OperationInfo {
+        name: "Xor",
+        pattern: "8XY3",
+        operands: &[X, Y],
+        octo: Some("{x} ^= {y}"),
+        cowgod: "XOR {x}, {y}",
+        platforms: ALL,
+        vip_cycles: Some(44),
+        description: "Set VX to VX exclusive or VY",
+    },
+    OperationInfo {
+        name: "Shr",
+        pattern: "8XY6",
+        operands: &[X, Y],
+        octo: Some("{x} >>= {y}"),
+        cowgod: "SHR {x}, {y}",
+        platforms: ALL,
+        vip_cycles: Some(44),
+        description: "Shift VX right one bit, setting VF to the bit shifted out",
+    },
+    OperationInfo {
+        name: "Shl",
+        pattern: "8XYE",
+        operands: &[X, Y],
+        octo: Some("{x} <<= {y}"),
+        cowgod: "SHL {x}, {y}",
+        platforms: ALL,
+        vip_cycles: Some(44),
+        description: "Shift VX left one bit, setting VF to the bit shifted out",
+    },
+    OperationInfo {
+        name: "RandAndImm",
+        pattern: "CXNN",
+        operands: &[X, NN],
+        octo: Some("{x} := random {nn}"),
+        cowgod: "RND {x}, {nn}",
+        platforms: ALL,
+        vip_cycles: Some(36),
+        description: "Set VX to a random number masked by NN",
+    },
+    OperationInfo {
+        name: "Draw",
+        pattern: "DXYN",
+        operands: &[X, Y, N],
+        octo: Some("sprite {x} {y} {n}"),
+        cowgod: "DRW {x}, {y}, {n}",
+        platforms: ALL,
+        vip_cycles: Some(22),
+        description: "Draw the N byte sprite at I at VX, VY, setting VF on collision",
+    },
+    OperationInfo {
+        name: "SkipKey",
+        pattern: "EX9E",
+        operands: &[X],
+        octo: Some("if {x} -key then"),
+        cowgod: "SKP {x}",
+        platforms: ALL,
+        vip_cycles: Some(14),
+        description: "Skip the next instruction if the key in VX is pressed",
+    },
+    OperationInfo {
+        name: "SkipNotKey",
+        pattern: "EXA1",
+        operands: &[X],
+        octo: Some("if {x} key then"),
+        cowgod: "SKNP {x}",
+        platforms: ALL,
+        vip_cycles: Some(14),
+        description: "Skip the next instruction if the key in VX is not pressed",
+    },
+    OperationInfo {
+        name: "WaitKey",
+        pattern: "FX0A",
+        operands: &[X],
+        octo: Some("{x} := key"),
+        cowgod: "LD {x}, K",
+        platforms: ALL,
+        vip_cycles: Some(10),
+        description: "Wait for a key press and store the key in VX",
+    },
+    OperationInfo {
+        name: "MemStoreBcd",
+        pattern: "FX33",
+        operands: &[X],
+        octo: Some("bcd {x}"),
+        cowgod: "LD B, {x}",
+        platforms: ALL,
+        vip_cycles: Some(80),
+        description: "Store the decimal digits of VX at I, I + 1 and I + 2",
+    },
+    OperationInfo {
+        name: "MemStoreRegs",
+        pattern: "FX55",
+        operands: &[X],
+        octo: Some("save {x}"),
+        cowgod: "LD [I], {x}",
+        platforms: ALL,
+        vip_cycles: Some(14),
+        description: "Store V0 to VX in memory starting at I",
+    },
+    OperationInfo {
+        name: "MemLoadRegs",
+        pattern: "FX65",
+        operands: &[X],
+        octo: Some("load {x}"),
+        cowgod: "LD {x}, [I]",
+        platforms: ALL,
+        vip_cycles: Some(14),
+        description: "Load V0 to VX from memory starting at I",
+    },
+    // SuperChip8 Opcodes
+    OperationInfo {
+        name: "Scd",
+        pattern: "00CN",
+        operands: &[N],
+        octo: Some("scroll-down {n}"),
+        cowgod: "SCD {n}",
+        platforms: SCHIP,
+        vip_cycles: None,
+        description: "Scroll the display down N pixels",
+    },
+    OperationInfo {
+        name: "Scr",
+        pattern: "00FB",
+        operands: &[],
+        octo: Some("scroll-right"),
+        cowgod: "SCR",
+        platforms: SCHIP,
+        vip_cycles: None,
+        description: "Scroll the display right 4 pixels",
+    },
+    OperationInfo {
+        name: "Scl",
+        pattern: "00FC",
+        operands: &[],
+        octo: Some("scroll-left"),
+        cowgod: "SCL",
+        platforms: SCHIP,
+        vip_cycles: None,
+        description: "Scroll the display left 4 pixels",
+    },
+    OperationInfo {
+        name: "Exit",
+        pattern: "00FD",
+        operands: &[],
+        octo: Some("exit"),
+        cowgod: "EXIT",
+        platforms: SCHIP,
+        vip_cycles: None,
+        description: "Exit the interpreter",
+    },
+    OperationInfo {
+        name: "Low",
+        pattern: "00FE",
+        operands: &[],
+        octo: Some("lores"),
+        cowgod: "LOW",
+        platforms: SCHIP,
+        vip_cycles: None,
+        description: "Switch to the 64x32 low resolution display",
+    },
+    OperationInfo {
+        name: "High",
+        pattern: "00FF",
+        operands: &[],
+        octo: Some("hires"),
+        cowgod: "HIGH",
+        platforms: SCHIP,
+        vip_cycles: None,
+        description: "Switch to the 128x64 high resolution display",
+    },
+    OperationInfo {
+        name: "LoadAddrBigDigit",
+        pattern: "FX30",
+        operands: &[X],
+        octo: Some("i := bighex {x}"),
+        cowgod: "LD HF, {x}",
+        platforms: SCHIP,
+        vip_cycles: None,
+        description: "Point I at the large font sprite for the digit in VX",
+    },
+    OperationInfo {
+        name: "RplStoreRegs",
+        pattern: "FX75",
+        operands: &[X],
+        octo: Some("saveflags {x}"),
+        cowgod: "LD R, {x}",
+        platforms: SCHIP,
+        vip_cycles: None,
+        description: "Store V0 to VX in the HP48 flag registers",
+    },
+    OperationInfo {
+        name: "RplLoadRegs",
+        pattern: "FX85",
+        operands: &[X],
+        octo: Some("loadflags {x}"),
+        cowgod: "LD {x}, R",
+        platforms: SCHIP,
+        vip_cycles: None,
+        description: "Load V0 to VX from the HP48 flag registers",
+    },
+    // XO-CHIP Opcodes
+    OperationInfo {
+        name: "Scu",
+        pattern: "00DN",
+        operands: &[N],
+        octo: Some("scroll-up {n}"),
+        cowgod: "SCU {n}",
+        platforms: XO,
+        vip_cycles: None,
+        description: "Scroll the display up N pixels",
+    },
+    OperationInfo {
+        name: "MemStoreRange",
+        pattern: "5XY2",
+        operands: &[X, Y],
+        octo: Some("save {x} - {y}"),
+        cowgod: "LD [I], {x}-{y}",
+        platforms: XO,
+        vip_cycles: None,
+        description: "Store VX to VY in memory starting at I, leaving I unchanged",
+    },
+    OperationInfo {
+        name: "MemLoadRange",
+        pattern: "5XY3",
+        operands: &[X, Y],
+        octo: Some("load {x} - {y}"),
+        cowgod: "LD {x}-{y}, [I]",
+        platforms: XO,
+        vip_cycles: None,
+        description: "Load VX to VY from memory starting at I, leaving I unchanged",
+    },
+    OperationInfo {
+        name: "LoadAddrLong",
+        pattern: "F000 NNNN",
+        operands: &[NNNN],
+        octo: Some("i := long {nnnn}"),
+        cowgod: "LD I, {nnnn}",
+        platforms: XO,
+        vip_cycles: None,
+        description: "Set I to the 16 bit address NNNN",
+    },
+    OperationInfo {
+        name: "Plane",
+        pattern: "FN01",
+        operands: &[N],
+        octo: Some("plane {n}"),
+        cowgod: "PLANE {n}",
+        platforms: XO,
+        vip_cycles: None,
+        description: "Select the bitplanes drawn to by the mask N",
+    },
+    OperationInfo {
+        name: "LoadAudio",
+        pattern: "F002",
+        operands: &[],
+        octo: Some("audio"),
+        cowgod: "AUDIO",
+        platforms: XO,
+        vip_cycles: None,
+        description: "Load the 16 byte audio pattern at I",
+    },
+    OperationInfo {
+        name: "LoadPitchReg",
+        pattern: "FX3A",
+        operands: &[X],
+        octo: Some("pitch := {x}"),
+        cowgod: "LD PITCH, {x}",
+        platforms: XO,
+        vip_cycles: None,
+        description: "Set the audio pitch to VX",
+    },
+];
+
+/// Returns the metadata for the operation with the given name, as returned
+/// by `Operation::name`.
+pub fn find(name: &str) -> Option<&'static OperationInfo> {
+    OPERATIONS.iter().find(|info| info.name == name)
+}
+
+/// Returns the metadata for an operation.
+pub fn info(operation: &Operation) -> &'static OperationInfo {
+    find(operation.name()).expect("operation missing from the instruction set table")
+}
+
+/// Returns the values of the operands of an operation, in the same order as
+/// `OperationInfo::operands`.
+pub fn operand_values(operation: &Operation) -> Vec<u16> {
+    match *operation {
+        Operation::Sys(nnn)
+        | Operation::Jump(nnn)
+        | Operation::JumpAddrPlusV0(nnn)
+        | Operation::Call(nnn)
+        | Operation::LoadAddr(nnn)
+        | Operation::LoadAddrLong(nnn) => vec![nnn],
+        Operation::SkipEqImm(x, nn)
+        | Operation::SkipNeqImm(x, nn)
+        | Operation::LoadImm(x, nn)
+        | Operation::AddImm(x, nn)
+        | Operation::RandAndImm(x, nn) => vec![x.into(), nn.into()],
+        Operation::SkipEqReg(x, y)
+        | Operation::SkipNeqReg(x, y)
+        | Operation::LoadReg(x, y)
+        | Operation::AddReg(x, y)
+        | Operation::Sub(x, y)
+        | Operation::SubNeg(x, y)
+        | Operation::Or(x, y)
+        | Operation::And(x, y)
+        | Operation::Xor(x, y)
+        | Operation::Shr(x, y)
+        | Operation::Shl(x, y)
+        | Operation::MemStoreRange(x, y)
+        | Operation::MemLoadRange(x, y) => vec![x.into(), y.into()],
+        Operation::LoadAddrDigit(x)
+        | Operation::LoadRegDelay(x)
+        | Operation::LoadDelayReg(x)
+        | Operation::LoadSoundReg(x)
+        | Operation::AddAddrReg(x)
+        | Operation::SkipKey(x)
+        | Operation::SkipNotKey(x)
+        | Operation::WaitKey(x)
+        | Operation::MemStoreBcd(x)
+        | Operation::MemStoreRegs(x)
+        | Operation::MemLoadRegs(x)
+        | Operation::LoadAddrBigDigit(x)
+        | Operation::RplStoreRegs(x)
+        | Operation::RplLoadRegs(x)
+        | Operation::LoadPitchReg(x) => vec![x.into()],
+        Operation::Draw(x, y, n) => vec![x.into(), y.into(), n.into()],
+        Operation::Scd(n) | Operation::Scu(n) | Operation::Plane(n) => vec![n.into()],
+        Operation::Cls
+        | Operation::Ret
+        | Operation::Scr
+        | Operation::Scl
+        | Operation::Exit
+        | Operation::Low
+        | Operation::High
+        | Operation::LoadAudio => Vec::new(),
+    }
+}
+
+/// Write an operation in the given syntax, or return `None` if the syntax has
+/// no mnemonic for it.
+///
+/// # Examples
+///
+/// ```
+/// # extern crate okto;
+/// # use okto::cpu::Operation;
+/// # use okto::isa::{self, Syntax};
+/// let operation = Operation::LoadImm(0xA, 0x12);
+/// assert_eq!(Some(String::from("va := 0x12")), isa::format(&operation, Syntax::Octo));
+/// assert_eq!(Some(String::from("LD VA, #12")), isa::format(&operation, Syntax::Cowgod));
+/// ```
+pub fn format(operation: &Operation, syntax: Syntax) -> Option<String> {
+    format_with_labels(operation, syntax, |_| None)
+}
+
+/// Write an operation like `format`, but write each address operand as the
+/// name `label` returns for it, if any.
+///
+/// # Examples
+///
+/// ```
+/// # extern crate okto;
+/// # use okto::cpu::Operation;
+/// # use okto::isa::{self, Syntax};
+/// let label = |address| match address {
+///     0x2F0 => Some(String::from("draw")),
+///     _ => None,
+/// };
+/// assert_eq!(
+///     Some(String::from(":call draw")),
+///     isa::format_with_labels(&Operation::Call(0x2F0), Syntax::Octo, label)
+/// );
+/// assert_eq!(
+///     Some(String::from("jump 0x300")),
+///     isa::format_with_labels(&Operation::Jump(0x300), Syntax::Octo, label)
+/// );
+/// ```
+pub fn format_with_labels<F>(operation: &Operation, syntax: Syntax, mut label: F) -> Option<String>
+where
+    F: FnMut(u16) -> Option<String>,
+{
+    let info = info(operation);
+    let values = operand_values(operation);
+
+    info.template(syntax).map(|template| {
+        fill(template, |name| {
+            info.operands
+                .iter()
+                .zip(values.iter())
+                .find(|&(operand, _)| operand.name == name)
+                .map(|(operand, &value)| match operand.kind {
+                    OperandKind::Address | OperandKind::LongAddress => {
+                        label(value).unwrap_or_else(|| syntax.operand(operand.kind, value))
+                    }
+                    kind => syntax.operand(kind, value),
+                })
+        })
+    })
+}
+
+/// Returns a Markdown table documenting every operation.
+pub fn reference() -> String {
+    let mut text = String::from(
+        "| Opcode | Octo | Cowgod | Platforms | VIP cycles | Description |\n\
+         |--------|------|--------|-----------|------------|-------------|\n",
+    );
+
+    for info in OPERATIONS {
+        let code = |syntax: Option<String>| match syntax {
+            Some(syntax) => format!("`{}`", syntax.replace('|', "\\|")),
+            None => String::new(),
+        };
+        let platforms: Vec<&str> = info
+            .platforms
+            .iter()
+            .map(|platform| platform.name())
+            .collect();
+        text.push_str(&format!(
+            "| `{}` | {} | {} | {} | {} | {} |\n",
+            info.pattern,
+            code(info.syntax(Syntax::Octo)),
+            code(info.syntax(Syntax::Cowgod)),
+            platforms.join(", "),
+            info.vip_cycles
+                .map(|cycles| cycles.to_string())
+                .unwrap_or_default(),
+            info.description
+        ));
+    }
+
+    text
+}
@@ -3,9 +3,6 @@
 /// The number of keys available on the Chip8 keyboard.
 pub const NUM_KEYS: usize = 16;
 
-/// Type return by keyboard wait key callback
-pub type WaitKeyResult<T> = Result<T, String>;
-
 /// Enumeration of the states of keys in Chip8.
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum KeyState {
@@ -13,36 +10,87 @@ pub enum KeyState {
     Pressed,
 }
 
+/// Key transitions delivered to the keyboard by the host or an input source.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum KeyEvent {
+    Pressed(u8),
+    Released(u8),
+}
+
 /// Data structure representing the state of the Chip8 keyboard.
-pub struct Keyboard<F>
-where
-    F: FnMut() -> WaitKeyResult<u8>,
-{
+pub struct Keyboard {
     /// State of the keys on the keyboard.
     pub keys: [KeyState; NUM_KEYS],
 
-    // TODO: Reimplement the callback fn pointer as a trait object??
-    /// Callback method invoked when wait key instruction occurs.
-    pub wait_key_callback: Box<F>,
+    /// Key events received since the machine last processed them.
+    events: Vec<KeyEvent>,
 }
 
 /// Implementation of keyboard interfaces
-impl<F> Keyboard<F>
-where
-    F: FnMut() -> WaitKeyResult<u8>,
-{
-    /// Initialize a new keyboard structure with all keys released. Uses the
-    /// nop callback function.
-    pub fn new(wait_key_callback: Box<F>) -> Self {
+impl Keyboard {
+    /// Initialize a new keyboard structure with all keys released.
+    pub fn new() -> Self {
         Self {
             keys: [KeyState::Released; NUM_KEYS],
-            wait_key_callback: wait_key_callback,
+            events: Vec::new(),
         }
     }
+
+    /// Returns true if the given key is currently held down.
+    pub fn is_pressed(&self, key: u8) -> bool {
+        self.keys.get(key as usize) == Some(&KeyState::Pressed)
+    }
+
+    /// Mark the given key as pressed and record the press so that a machine
+    /// waiting for a key can react to it. Keys outside of the keypad are
+    /// ignored.
+    pub fn press_key(&mut self, key: u8) {
+        if key as usize >= NUM_KEYS {
+            return;
+        }
+
+        self.keys[key as usize] = KeyState::Pressed;
+        self.events.push(KeyEvent::Pressed(key));
+    }
+
+    /// Mark the given key as released and record the release so that a
+    /// machine waiting for a key can react to it. Keys outside of the keypad
+    /// are ignored.
+    pub fn release_key(&mut self, key: u8) {
+        if key as usize >= NUM_KEYS {
+            return;
+        }
+
+        self.keys[key as usize] = KeyState::Released;
+        self.events.push(KeyEvent::Released(key));
+    }
+
+    /// Remove and return the key events received since the last call, oldest
+    /// first.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate okto;
+    /// # use okto::keyboard::{KeyEvent, KeyState, Keyboard};
+    /// let mut keyboard = Keyboard::new();
+    /// keyboard.press_key(0xA);
+    /// keyboard.release_key(0xA);
+    ///
+    /// assert_eq!(KeyState::Released, keyboard.keys[0xA]);
+    /// assert_eq!(
+    ///     vec![KeyEvent::Pressed(0xA), KeyEvent::Released(0xA)],
+    ///     keyboard.take_events()
+    /// );
+    /// assert!(keyboard.take_events().is_empty());
+    /// ```
+    pub fn take_events(&mut self) -> Vec<KeyEvent> {
+        self.events.drain(..).collect()
+    }
 }
 
-/// Null operation (nop) callback for waitkey. Does nothing and returns a
-/// constant key value.
-pub fn nop_wait_key_callback() -> WaitKeyResult<u8> {
-    Ok(0x00)
+impl Default for Keyboard {
+    fn default() -> Self {
+        Self::new()
+    }
 }
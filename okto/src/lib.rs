@@ -1,13 +1,28 @@
-//! Library for emulating the Chip8 and SuperChip8 virtual machines.
+//! Library for emulating the Chip8, SuperChip8 and XO-CHIP virtual machines.
 extern crate rand;
 
+pub mod assembler;
+pub mod breakpoint;
+pub mod chipper;
 pub mod cpu;
+pub mod disassembler;
 pub mod display;
+pub mod expression;
+pub mod gdb;
+pub mod history;
+pub mod input;
+pub mod isa;
 pub mod keyboard;
 pub mod machine;
 pub mod memory;
+pub mod octo;
+pub mod quirks;
+pub mod random;
+pub mod rewind;
+pub mod savestate;
 pub mod sound;
 pub mod timer;
+pub mod trace;
 
 use std::error::Error;
 use std::fmt;
@@ -44,6 +59,18 @@ pub enum OktoErrorKind {
     InvalidSprite,
     /// Register out of range during HP48 load/store
     RegisterOutOfRange(u8),
+    /// Save state is malformed or truncated
+    InvalidSaveState,
+    /// Save state was written with an unsupported format version
+    UnsupportedSaveStateVersion(u16),
+    /// Input recording is malformed or truncated
+    InvalidInputRecording,
+    /// Breakpoint condition or log message could not be parsed
+    InvalidExpression(String),
+    /// Binary execution trace is malformed or truncated
+    InvalidTrace,
+    /// Assembly language source could not be assembled
+    InvalidSource(assembler::SourceError),
     /// Unknown error along with an error message
     Unknown(String),
 }
@@ -52,14 +79,11 @@ pub enum OktoErrorKind {
 impl OktoError {
     /// Initialize a new error
     pub fn new(kind: OktoErrorKind) -> Self {
-        Self { kind: kind }
+        Self { kind }
     }
-}
 
-/// Implementation of error interface for emulator error
-impl Error for OktoError {
     /// Returns a description derived from the error kind.
-    fn description(&self) -> &str {
+    pub fn description(&self) -> &str {
         match &self.kind {
             OktoErrorKind::StackOverflow => "Stack overflow",
             OktoErrorKind::StackUnderflow => "Stack underflow",
@@ -69,15 +93,28 @@ impl Error for OktoError {
             OktoErrorKind::InvalidSprite => "Invalid sprite",
             OktoErrorKind::InvalidOpcode => "Invalid opcode",
             OktoErrorKind::RegisterOutOfRange(_) => "Register out of range",
+            OktoErrorKind::InvalidSaveState => "Invalid save state",
+            OktoErrorKind::UnsupportedSaveStateVersion(_) => "Unsupported save state version",
+            OktoErrorKind::InvalidInputRecording => "Invalid input recording",
+            OktoErrorKind::InvalidExpression(_) => "Invalid expression",
+            OktoErrorKind::InvalidTrace => "Invalid trace",
+            OktoErrorKind::InvalidSource(_) => "Invalid source",
             OktoErrorKind::Unknown(_) => "Unknown",
         }
     }
 }
 
+/// Implementation of error interface for emulator error
+impl Error for OktoError {}
+
 impl fmt::Display for OktoError {
-    /// Display the error in a textual format
+    /// Display the error in a textual format. Errors in assembly language
+    /// source are shown as `file:line:column: message`.
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Error('{:?}')", self.description())
+        match self.kind {
+            OktoErrorKind::InvalidSource(ref error) => write!(f, "{}", error),
+            _ => write!(f, "Error('{:?}')", self.description()),
+        }
     }
 }
 
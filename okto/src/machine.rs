@@ -1,19 +1,167 @@
 //! Types and data structures representing the assembled Chip8 machine.
+use super::breakpoint;
 use super::cpu;
+use super::cpu::InstructionParts;
 use super::display;
+use super::expression;
+use super::history;
+use super::input;
 use super::keyboard;
 use super::memory;
+use super::quirks;
+use super::random;
+use super::savestate;
 use super::sound;
 use super::timer;
+use super::timer::CountdownTimer;
+use super::trace;
 use super::{OktoError, OktoErrorKind, OktoResult};
 
-use rand::prelude::*;
+/// Whether the machine is executing instructions or blocked on `WaitKey`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExecutionState {
+    /// Instructions are executed normally.
+    Running,
+    /// `WaitKey` is waiting for a key press to store in the register.
+    WaitingForKey(cpu::Register),
+    /// `WaitKey` has seen the given key pressed and, under the
+    /// `wait_key_on_release` quirk, is waiting for it to be released.
+    WaitingForRelease(cpu::Register, u8),
+}
+
+/// Reason that a frame stopped executing instructions early.
+#[derive(Debug, PartialEq)]
+pub enum HaltReason {
+    /// The program executed `Exit` and the machine is off.
+    Exit,
+    /// An instruction failed to execute.
+    Error(OktoError),
+    /// A breakpoint or watchpoint was hit.
+    Breakpoint(breakpoint::BreakpointHit),
+}
+
+/// Summary of the work done by `Machine::run_frame`.
+#[derive(Debug, PartialEq)]
+pub struct FrameSummary {
+    /// Number of instructions executed, not counting cycles spent waiting for
+    /// a key
+    pub instructions_executed: u32,
+    /// Whether the display may have changed during the frame
+    pub display_changed: bool,
+    /// Whether the sound timer is still running at the end of the frame
+    pub sound_active: bool,
+    /// Why the frame ended early, if it did
+    pub halt: Option<HaltReason>,
+}
+
+/// Conditions under which `Machine::run_until` stops. Every condition that is
+/// set applies, so a budget can be combined with a target address to avoid
+/// running forever when the address is never reached.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RunLimit {
+    /// Maximum number of instructions to execute
+    pub instructions: Option<u64>,
+    /// Maximum number of frames to run
+    pub frames: Option<u64>,
+    /// Number of cycles per frame. The timers tick once per frame.
+    pub cycles_per_frame: u32,
+    /// Stop before executing the instruction at this address
+    pub pc: Option<cpu::Address>,
+    /// Stop after an instruction writes to this address
+    pub memory_write: Option<cpu::Address>,
+    /// Stop after an instruction changes the display
+    pub display_change: bool,
+    /// Stop while the machine is waiting for a key
+    pub key_wait: bool,
+}
+
+impl RunLimit {
+    /// Run at most the given number of instructions.
+    pub fn instructions(count: u64) -> Self {
+        Self {
+            instructions: Some(count),
+            ..Self::default()
+        }
+    }
+
+    /// Run at most the given number of frames.
+    pub fn frames(count: u64) -> Self {
+        Self {
+            frames: Some(count),
+            ..Self::default()
+        }
+    }
+
+    /// Also stop when the program counter reaches the given address.
+    pub fn until_pc(mut self, address: cpu::Address) -> Self {
+        self.pc = Some(address);
+        self
+    }
+
+    /// Also stop after the given address is written to.
+    pub fn until_memory_write(mut self, address: cpu::Address) -> Self {
+        self.memory_write = Some(address);
+        self
+    }
+
+    /// Also stop after the display changes.
+    pub fn until_display_change(mut self) -> Self {
+        self.display_change = true;
+        self
+    }
+
+    /// Keep running while the machine waits for a key, relying on the input
+    /// source to deliver one.
+    pub fn through_key_waits(mut self) -> Self {
+        self.key_wait = false;
+        self
+    }
+}
+
+/// The default limit has no budget and stops only when the machine exits,
+/// fails or waits for a key.
+impl Default for RunLimit {
+    fn default() -> Self {
+        Self {
+            instructions: None,
+            frames: None,
+            cycles_per_frame: DEFAULT_CYCLES_PER_FRAME,
+            pc: None,
+            memory_write: None,
+            display_change: false,
+            key_wait: true,
+        }
+    }
+}
+
+/// Reason that `Machine::run_until` stopped.
+#[derive(Debug, PartialEq)]
+pub enum StopReason {
+    /// The program executed `Exit` and the machine is off.
+    Exit,
+    /// An instruction failed to execute.
+    Error(OktoError),
+    /// The program counter reached the address given in the run limit.
+    PcReached(cpu::Address),
+    /// A breakpoint or watchpoint set on the machine was hit.
+    BreakpointHit(breakpoint::BreakpointHit),
+    /// An instruction wrote to the given address.
+    MemoryWritten(cpu::Address),
+    /// An instruction changed the display.
+    DisplayChanged,
+    /// The machine is waiting for a key.
+    WaitingForKey,
+    /// The instruction or frame budget has been used up.
+    BudgetExhausted,
+    /// Running backwards reached the oldest step in the history.
+    HistoryExhausted,
+}
+
+/// Number of cycles per 60 Hz frame needed to run at `cpu::CPU_TICK_HZ`.
+pub const DEFAULT_CYCLES_PER_FRAME: u32 = cpu::CPU_TICK_HZ / timer::TIMER_TICK_HZ;
 
 /// Data structure representing the complete machine.
-pub struct Machine<F>
-where
-    F: FnMut() -> keyboard::WaitKeyResult<u8>,
-{
+pub struct Machine {
     /// CPU component of the machine
     pub cpu: cpu::Cpu,
     /// Delay countdown timer
@@ -21,53 +169,781 @@ where
     /// Video display
     pub display: display::Display,
     /// Keyboard component
-    pub keyboard: keyboard::Keyboard<F>,
+    pub keyboard: keyboard::Keyboard,
     /// Memory component of the machine
     pub memory: memory::Memory,
     /// Sound card, which is really a glorified timer
     pub sound: sound::Sound,
+    /// Interpretation of ambiguous opcodes
+    pub quirks: quirks::Quirks,
+    /// Random number source used by `RandAndImm`
+    pub rng: Box<dyn random::RandomSource>,
     /// Indicates whether or not the machine is still on
     pub machine_on: bool,
+    /// Indicates whether instructions are executed or the machine is blocked
+    /// waiting for a key
+    pub execution_state: ExecutionState,
+    /// Source of key events, polled before each step
+    pub input: Box<dyn input::InputSource>,
+    /// Number of steps taken since the machine was created, including steps
+    /// spent waiting for a key
+    pub cycles: u64,
+    /// Tracer receiving a record of each executed instruction, if any
+    pub tracer: Option<trace::Tracer>,
+    /// Key events processed since recording was started, if it has been
+    recording: Option<input::InputRecording>,
+    /// Memory read and written by the most recently executed operation
+    memory_accesses: Vec<memory::MemoryAccess>,
+    /// Breakpoints and watchpoints checked by `step`
+    breakpoints: breakpoint::Breakpoints,
+    /// Breakpoint that stopped the most recent step, if any
+    breakpoint_hit: Option<breakpoint::BreakpointHit>,
+    /// Address of the instruction a breakpoint stopped the machine before, so
+    /// that the next step executes it instead of stopping again
+    resume_address: Option<cpu::Address>,
+    /// Messages logged by tracepoints and not yet taken
+    log_messages: Vec<String>,
+    /// Undo records of the most recent steps, if history is enabled
+    history: Option<history::History>,
+    /// Undo record of the step in progress along with the registers before
+    /// it, if history is enabled
+    pending_undo: Option<(history::UndoRecord, cpu::Cpu)>,
 }
 
-impl<F> Machine<F>
-where
-    F: FnMut() -> keyboard::WaitKeyResult<u8>,
-{
-    /// Construct a new machine with all of its components.
-    pub fn new(wait_key_callback: Box<F>) -> Self {
+impl Machine {
+    /// Construct a new machine with all of its components. Ambiguous opcodes
+    /// are interpreted using the default quirks and the random number source
+    /// is seeded from entropy. Replace `rng` for reproducible runs. Keys are
+    /// only pressed through the keyboard until `input` is replaced.
+    pub fn new() -> Self {
+        Self::with_quirks(quirks::Quirks::default())
+    }
+
+    /// Construct a new machine that interprets ambiguous opcodes according to
+    /// the given quirks.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate okto;
+    /// # use okto::cpu::Operation;
+    /// # use okto::machine::Machine;
+    /// # use okto::quirks::Quirks;
+    /// let mut machine = Machine::with_quirks(Quirks::cosmac_vip());
+    /// machine.cpu.i = 0x300;
+    /// machine.execute(Operation::MemStoreRegs(0x3)).unwrap();
+    /// assert_eq!(0x304, machine.cpu.i);
+    /// ```
+    pub fn with_quirks(quirks: quirks::Quirks) -> Self {
         Self {
             cpu: cpu::Cpu::new(),
             delay_timer: timer::DelayTimer::new(),
             display: display::Display::new(),
-            keyboard: keyboard::Keyboard::new(wait_key_callback),
+            keyboard: keyboard::Keyboard::new(),
             memory: memory::Memory::new(),
             sound: sound::Sound::new(),
+            quirks,
+            rng: Box::new(random::XorShiftRandom::from_entropy()),
             machine_on: true,
+            execution_state: ExecutionState::Running,
+            input: Box::new(input::NoInput),
+            cycles: 0,
+            tracer: None,
+            recording: None,
+            memory_accesses: Vec::new(),
+            breakpoints: breakpoint::Breakpoints::new(),
+            breakpoint_hit: None,
+            resume_address: None,
+            log_messages: Vec::new(),
+            history: None,
+            pending_undo: None,
         }
     }
 
-    /// Attempt to read the next instruction from memory and execute it.
+    /// Attempt to read the next instruction from memory and execute it. The
+    /// input source is polled first, then the key events delivered to the
+    /// keyboard since the last step are processed. While the machine is
+    /// waiting for a key no instruction is executed. Executed instructions are
+    /// reported to the tracer, if one is set, and each step is recorded in the
+    /// history, if it is enabled.
+    ///
+    /// Breakpoints on the program counter or on an operation stop the machine
+    /// before the instruction executes and without using a cycle; the next
+    /// step then executes the instruction. Watchpoints stop the machine after
+    /// the instruction that triggered them. Either way `breakpoint_hit`
+    /// reports the breakpoint until the next step.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate okto;
+    /// # use okto::machine::{ExecutionState, Machine};
+    /// let mut machine = Machine::new();
+    /// machine.memory.data[0x200..0x202].copy_from_slice(&[0xF3, 0x0A]);
+    ///
+    /// machine.step().unwrap().step().unwrap();
+    /// assert_eq!(ExecutionState::WaitingForKey(0x3), machine.execution_state);
+    /// assert_eq!(0x202, machine.cpu.pc);
+    ///
+    /// machine.keyboard.press_key(0x7);
+    /// machine.step().unwrap();
+    /// assert_eq!(ExecutionState::Running, machine.execution_state);
+    /// assert_eq!(0x7, machine.cpu.v[0x3]);
+    /// ```
     pub fn step(&mut self) -> OktoResult<&mut Self> {
         if !self.machine_on {
             return Ok(self);
         }
 
-        let result = self.memory.read_instruction(self.cpu.pc);
+        self.memory_accesses.clear();
+        self.breakpoint_hit = None;
 
-        if result.is_none() {
-            return Err(OktoError::new(OktoErrorKind::InvalidOpcode));
+        let resume_address = self.resume_address.take();
+        if self.execution_state == ExecutionState::Running && resume_address != Some(self.cpu.pc) {
+            if let Some(hit) = self.check_breakpoints_before() {
+                self.breakpoint_hit = Some(hit);
+                self.resume_address = Some(self.cpu.pc);
+                return Ok(self);
+            }
         }
 
-        let operation = cpu::Operation::from_instruction(&result.unwrap());
+        if self.history.is_some() {
+            self.begin_undo();
+        }
 
-        if operation.is_none() {
+        self.input.poll(self.cycles, &mut self.keyboard);
+        let was_waiting = self.execution_state != ExecutionState::Running;
+        self.process_key_events();
+        self.cycles += 1;
+        if was_waiting {
+            self.finish_undo();
+            return Ok(self);
+        }
+
+        let result = self.memory.read_operation(self.cpu.pc);
+
+        if result.is_none() {
+            self.finish_undo();
             return Err(OktoError::new(OktoErrorKind::InvalidOpcode));
         }
 
+        let (operation, size) = result.unwrap();
+        self.save_undo_state(&operation);
+        let cpu_before = if self.breakpoints.has_watchpoints() {
+            Some(self.cpu.clone())
+        } else {
+            None
+        };
+
+        let trace_before = match self.tracer {
+            Some(ref tracer) if tracer.traces(self.cpu.pc) => Some(trace::TraceRecord {
+                cycle: self.cycles - 1,
+                pc: self.cpu.pc,
+                instruction: self.memory.read_instruction(self.cpu.pc).unwrap_or(0),
+                operand: match operation {
+                    cpu::Operation::LoadAddrLong(address) => Some(address),
+                    _ => None,
+                },
+                operation,
+                before: trace::RegisterSnapshot::from(&self.cpu),
+                after: trace::RegisterSnapshot::default(),
+                writes: Vec::new(),
+            }),
+            _ => None,
+        };
+
         // Move the program counter to the next instruction.
-        self.cpu.skip_next_instr();
-        self.execute(operation.unwrap())
+        self.cpu.pc = self.cpu.pc.wrapping_add(size);
+        let result = self.execute(operation).map(|_| ());
+        self.finish_undo();
+
+        // Trace the instruction even if it failed, as it is the one that shows
+        // why the program went wrong
+        if let Some(record) = trace_before {
+            self.trace(record);
+        }
+        result?;
+
+        if let Some(cpu_before) = cpu_before {
+            let context = expression::Context {
+                cpu: &self.cpu,
+                memory: &self.memory,
+                delay_timer: self.delay_timer.value,
+                sound_timer: *self.sound.timer.read().unwrap(),
+            };
+            self.breakpoint_hit = self.breakpoints.check_after(
+                &self.memory_accesses,
+                &cpu_before,
+                &context,
+                &mut self.log_messages,
+            );
+        }
+
+        Ok(self)
+    }
+
+    /// Complete a trace record started before the instruction executed with
+    /// the registers and memory written afterwards, and send it to the
+    /// tracer.
+    fn trace(&mut self, mut record: trace::TraceRecord) {
+        for access in self.memory_accesses.iter() {
+            if access.kind != memory::AccessKind::Write {
+                continue;
+            }
+            for offset in 0..access.length {
+                let address = access.address.wrapping_add(offset as cpu::Address);
+                record
+                    .writes
+                    .push((address, self.memory.data[address as usize]));
+            }
+        }
+        record.after = trace::RegisterSnapshot::from(&self.cpu);
+
+        if let Some(ref mut tracer) = self.tracer {
+            tracer.record(record);
+        }
+    }
+
+    /// Start the undo record of a step.
+    fn begin_undo(&mut self) {
+        let record = history::UndoRecord {
+            cycle: self.cycles,
+            pc: self.cpu.pc,
+            operation: None,
+            registers: Vec::new(),
+            accesses: Vec::new(),
+            memory: Vec::new(),
+            pixels: Vec::new(),
+            display: None,
+            delay_timer: self.delay_timer.value,
+            sound_timer: *self.sound.timer.read().unwrap(),
+            audio: None,
+            rng_state: None,
+            execution_state: self.execution_state,
+            machine_on: self.machine_on,
+        };
+        self.pending_undo = Some((record, self.cpu.clone()));
+    }
+
+    /// Save the state that the operation about to execute changes wholesale,
+    /// and start recording the pixels it draws.
+    fn save_undo_state(&mut self, operation: &cpu::Operation) {
+        let record = match self.pending_undo {
+            Some((ref mut record, _)) => record,
+            None => return,
+        };
+        record.operation = Some(*operation);
+
+        match *operation {
+            cpu::Operation::Cls
+            | cpu::Operation::Scd(_)
+            | cpu::Operation::Scu(_)
+            | cpu::Operation::Scr
+            | cpu::Operation::Scl
+            | cpu::Operation::High
+            | cpu::Operation::Low
+            | cpu::Operation::Plane(_) => {
+                record.display = Some(history::SavedDisplay::from(&self.display));
+            }
+            cpu::Operation::LoadAudio | cpu::Operation::LoadPitchReg(_) => {
+                record.audio = Some((
+                    *self.sound.pattern.read().unwrap(),
+                    *self.sound.pitch.read().unwrap(),
+                ));
+            }
+            cpu::Operation::RandAndImm(_, _) => {
+                record.rng_state = Some(self.rng.save_state());
+            }
+            _ => {}
+        }
+        self.display.record_flips();
+    }
+
+    /// Complete the undo record of a step and add it to the history.
+    fn finish_undo(&mut self) {
+        if let Some((mut record, cpu_before)) = self.pending_undo.take() {
+            record.registers = history::SavedRegister::changes(&cpu_before, &self.cpu);
+            record.accesses = self.memory_accesses.clone();
+            record.pixels = self.display.take_flips();
+            if let Some(ref mut history) = self.history {
+                history.push(record);
+            }
+        }
+    }
+
+    /// Check the breakpoints that stop the machine before the instruction at
+    /// the program counter executes.
+    fn check_breakpoints_before(&mut self) -> Option<breakpoint::BreakpointHit> {
+        if self.breakpoints.is_empty() {
+            return None;
+        }
+
+        let (operation, _) = self.memory.read_operation(self.cpu.pc)?;
+        let context = expression::Context {
+            cpu: &self.cpu,
+            memory: &self.memory,
+            delay_timer: self.delay_timer.value,
+            sound_timer: *self.sound.timer.read().unwrap(),
+        };
+        self.breakpoints
+            .check_before(self.cpu.pc, &operation, &context, &mut self.log_messages)
+    }
+
+    /// Add a breakpoint or watchpoint and return its identifier. Either a plain
+    /// `Breakpoint` or a `BreakpointEntry` carrying conditions can be given.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate okto;
+    /// # use okto::breakpoint::{Breakpoint, HitReason};
+    /// # use okto::machine::Machine;
+    /// let mut machine = Machine::new();
+    /// // V0 += 1 and loop forever
+    /// machine.memory.data[0x200..0x204].copy_from_slice(&[0x70, 0x01, 0x12, 0x00]);
+    /// let id = machine.add_breakpoint(Breakpoint::Pc(0x202));
+    ///
+    /// machine.step().unwrap();
+    /// assert_eq!(None, machine.breakpoint_hit());
+    /// machine.step().unwrap();
+    /// let hit = machine.breakpoint_hit().unwrap();
+    /// assert_eq!((id, HitReason::Pc(0x202)), (hit.id, hit.reason));
+    ///
+    /// // Stepping again resumes past the breakpoint
+    /// machine.step().unwrap();
+    /// assert_eq!(0x200, machine.cpu.pc);
+    /// ```
+    pub fn add_breakpoint<B: Into<breakpoint::BreakpointEntry>>(
+        &mut self,
+        breakpoint: B,
+    ) -> breakpoint::BreakpointId {
+        self.breakpoints.add(breakpoint)
+    }
+
+    /// Remove the breakpoint with the given identifier, returning it if it
+    /// existed.
+    pub fn remove_breakpoint(
+        &mut self,
+        id: breakpoint::BreakpointId,
+    ) -> Option<breakpoint::BreakpointEntry> {
+        self.breakpoints.remove(id)
+    }
+
+    /// Returns the breakpoint with the given identifier so that its
+    /// conditions can be changed.
+    pub fn breakpoint_mut(
+        &mut self,
+        id: breakpoint::BreakpointId,
+    ) -> Option<&mut breakpoint::BreakpointEntry> {
+        self.breakpoints.get_mut(id)
+    }
+
+    /// Remove every breakpoint and watchpoint.
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    /// Returns the breakpoints and watchpoints set on the machine.
+    pub fn breakpoints(&self) -> &breakpoint::Breakpoints {
+        &self.breakpoints
+    }
+
+    /// Returns the breakpoint that stopped the most recent step, if any.
+    pub fn breakpoint_hit(&self) -> Option<breakpoint::BreakpointHit> {
+        self.breakpoint_hit
+    }
+
+    /// Take the messages logged by tracepoints since the last call.
+    pub fn take_log_messages(&mut self) -> Vec<String> {
+        self.log_messages.split_off(0)
+    }
+
+    /// Evaluate an expression against the current state of the machine.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate okto;
+    /// # use okto::expression::Expression;
+    /// # use okto::machine::Machine;
+    /// let mut machine = Machine::new();
+    /// machine.delay_timer.value = 30;
+    /// let expression = Expression::parse("delay / 2 + pc").unwrap();
+    /// assert_eq!(0x20F, machine.evaluate(&expression));
+    /// ```
+    pub fn evaluate(&self, expression: &expression::Expression) -> u32 {
+        let context = expression::Context {
+            cpu: &self.cpu,
+            memory: &self.memory,
+            delay_timer: self.delay_timer.value,
+            sound_timer: *self.sound.timer.read().unwrap(),
+        };
+        expression.evaluate(&context)
+    }
+
+    /// Run a single 60 Hz frame: step the machine until the end of the
+    /// current frame, then tick the delay and sound timers exactly once.
+    /// Frames end every `cycles_per_frame` cycles counted from power on, the
+    /// same boundaries at which `run_until` ticks the timers, so a frame left
+    /// unfinished by `run_until` is completed by the next call. Execution
+    /// stops early if the machine is turned off, an instruction fails or a
+    /// breakpoint is hit, in which case the frame is left unfinished and the
+    /// timers are not ticked.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate okto;
+    /// # use okto::machine::Machine;
+    /// let mut machine = Machine::new();
+    /// // V0 = 3, draw the digit in V0, then loop forever
+    /// machine.memory.data[0x200..0x208]
+    ///     .copy_from_slice(&[0x60, 0x03, 0xF0, 0x29, 0xD1, 0x15, 0x12, 0x06]);
+    /// machine.delay_timer.value = 10;
+    ///
+    /// let summary = machine.run_frame(8);
+    /// assert_eq!(8, summary.instructions_executed);
+    /// assert!(summary.display_changed);
+    /// assert!(!summary.sound_active);
+    /// assert_eq!(None, summary.halt);
+    /// assert_eq!(9, machine.delay_timer.value);
+    /// ```
+    pub fn run_frame(&mut self, cycles_per_frame: u32) -> FrameSummary {
+        let cycles_per_frame = cycles_per_frame.max(1) as u64;
+        let mut instructions_executed = 0;
+        let mut halt = None;
+        self.display.dirty = false;
+
+        while self.machine_on {
+            let executing = self.execution_state == ExecutionState::Running;
+            let cycles_before = self.cycles;
+            if let Err(error) = self.step() {
+                halt = Some(HaltReason::Error(error));
+                break;
+            }
+            if executing && self.cycles != cycles_before {
+                instructions_executed += 1;
+            }
+
+            let end_of_frame = self.finish_cycle(cycles_before, cycles_per_frame);
+            if let Some(hit) = self.breakpoint_hit {
+                halt = Some(HaltReason::Breakpoint(hit));
+                break;
+            }
+            if end_of_frame {
+                break;
+            }
+        }
+
+        if halt.is_none() && !self.machine_on {
+            halt = Some(HaltReason::Exit);
+        }
+
+        FrameSummary {
+            instructions_executed,
+            display_changed: self.display.dirty,
+            sound_active: *self.sound.timer.read().unwrap() > 0,
+            halt,
+        }
+    }
+
+    /// Run the machine until one of the conditions in the given limit is met,
+    /// ticking the timers once every `cycles_per_frame` cycles. Unless a
+    /// breakpoint set on the machine stops it first, at least one cycle is run
+    /// per call, so stopping at the `pc` limit does not prevent execution from
+    /// resuming.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate okto;
+    /// # use okto::machine::{Machine, RunLimit, StopReason};
+    /// let mut machine = Machine::new();
+    /// // V0 += 1 and loop forever
+    /// machine.memory.data[0x200..0x204].copy_from_slice(&[0x70, 0x01, 0x12, 0x00]);
+    ///
+    /// let limit = RunLimit::instructions(100).until_pc(0x202);
+    /// assert_eq!(StopReason::PcReached(0x202), machine.run_until(&limit));
+    /// assert_eq!(StopReason::PcReached(0x202), machine.run_until(&limit));
+    /// assert_eq!(2, machine.cpu.v[0x0]);
+    ///
+    /// let limit = RunLimit::instructions(10);
+    /// assert_eq!(StopReason::BudgetExhausted, machine.run_until(&limit));
+    /// assert_eq!(7, machine.cpu.v[0x0]);
+    /// ```
+    pub fn run_until(&mut self, limit: &RunLimit) -> StopReason {
+        let cycles_per_frame = limit.cycles_per_frame.max(1) as u64;
+        let mut instructions = 0;
+        let mut frames = 0;
+        let mut first_cycle = true;
+
+        loop {
+            if !self.machine_on {
+                return StopReason::Exit;
+            }
+
+            let budget_exhausted = limit.instructions.is_some_and(|max| instructions >= max)
+                || limit.frames.is_some_and(|max| frames >= max);
+            if budget_exhausted {
+                return StopReason::BudgetExhausted;
+            }
+
+            let executing = self.execution_state == ExecutionState::Running;
+            if executing && !first_cycle && limit.pc == Some(self.cpu.pc) {
+                return StopReason::PcReached(self.cpu.pc);
+            }
+            first_cycle = false;
+
+            let display_was_dirty = self.display.dirty;
+            let cycles_before = self.cycles;
+            self.display.dirty = false;
+            let result = self.step().map(|_| ());
+            let display_changed = self.display.dirty;
+            self.display.dirty |= display_was_dirty;
+
+            if let Err(error) = result {
+                return StopReason::Error(error);
+            }
+            if self.cycles == cycles_before {
+                if let Some(hit) = self.breakpoint_hit {
+                    return StopReason::BreakpointHit(hit);
+                }
+            }
+
+            if self.finish_cycle(cycles_before, cycles_per_frame) {
+                frames += 1;
+            }
+            if executing {
+                instructions += 1;
+            }
+
+            if let Some(hit) = self.breakpoint_hit {
+                return StopReason::BreakpointHit(hit);
+            }
+
+            if let Some(address) = limit.memory_write {
+                let written = self.memory_accesses.iter().any(|access| {
+                    access.kind == memory::AccessKind::Write && access.contains(address)
+                });
+                if written {
+                    return StopReason::MemoryWritten(address);
+                }
+            }
+            if limit.display_change && display_changed {
+                return StopReason::DisplayChanged;
+            }
+            if limit.key_wait && self.execution_state != ExecutionState::Running {
+                return StopReason::WaitingForKey;
+            }
+        }
+    }
+
+    /// Tick the delay and sound timers if the step that started at
+    /// `cycles_before` ran a cycle ending a frame, returning true if it did.
+    fn finish_cycle(&mut self, cycles_before: u64, cycles_per_frame: u64) -> bool {
+        let end_of_frame =
+            self.cycles != cycles_before && self.cycles.is_multiple_of(cycles_per_frame);
+        if end_of_frame {
+            self.delay_timer.tick();
+            self.sound.tick();
+        }
+        end_of_frame
+    }
+
+    /// Start recording the most recent `capacity` steps so that they can be
+    /// undone with `step_back` and `run_back`, discarding any history already
+    /// recorded. See the `history` module.
+    pub fn start_history(&mut self, capacity: usize) {
+        self.history = Some(history::History::new(capacity));
+    }
+
+    /// Stop recording steps, returning the history recorded.
+    pub fn stop_history(&mut self) -> Option<history::History> {
+        self.history.take()
+    }
+
+    /// Returns the history of recent steps, if it is enabled.
+    pub fn history(&self) -> Option<&history::History> {
+        self.history.as_ref()
+    }
+
+    /// Undo the most recent step in the history, returning false if there is
+    /// none. The instruction at the program counter then executes on the next
+    /// step even if a breakpoint is set on it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate okto;
+    /// # use okto::machine::Machine;
+    /// let mut machine = Machine::new();
+    /// // I = 0x300, V0 = 7, store V0 as BCD, then draw the digit V0 at (7, 7)
+    /// machine.memory.data[0x200..0x20A]
+    ///     .copy_from_slice(&[0xA3, 0x00, 0x60, 0x07, 0xF0, 0x33, 0xF0, 0x29, 0xD0, 0x05]);
+    /// machine.start_history(100);
+    ///
+    /// for _ in 0..5 {
+    ///     machine.step().unwrap();
+    /// }
+    /// assert_eq!(7, machine.memory.data[0x302]);
+    /// assert_eq!(1, machine.display.data[7][7]);
+    ///
+    /// assert!(machine.step_back());
+    /// assert_eq!((0x208, 0), (machine.cpu.pc, machine.display.data[7][7]));
+    /// machine.step_back();
+    /// machine.step_back();
+    /// assert_eq!((0x204, 0), (machine.cpu.pc, machine.memory.data[0x302]));
+    /// ```
+    pub fn step_back(&mut self) -> bool {
+        match self.history.as_mut().and_then(|history| history.pop()) {
+            Some(record) => {
+                self.undo(&record);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Run the machine backwards, undoing steps from the history until a
+    /// condition in `limit` is met or the history runs out. The budgets count
+    /// instructions and frames undone, `pc` stops once the instruction at
+    /// that address has been undone, and `memory_write` and `display_change`
+    /// stop after undoing an instruction that wrote the address or changed
+    /// the display. Breakpoints and watchpoints stop the machine as described
+    /// on `Breakpoints::check_reverse`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate okto;
+    /// # use okto::breakpoint::Breakpoint;
+    /// # use okto::machine::{Machine, RunLimit, StopReason};
+    /// let mut machine = Machine::new();
+    /// // V0 += 1, V1 += 2 and loop forever
+    /// machine.memory.data[0x200..0x206]
+    ///     .copy_from_slice(&[0x70, 0x01, 0x71, 0x02, 0x12, 0x00]);
+    /// machine.start_history(100);
+    /// machine.run_until(&RunLimit::instructions(30));
+    /// assert_eq!((10, 20), (machine.cpu.v[0x0], machine.cpu.v[0x1]));
+    ///
+    /// machine.add_breakpoint(Breakpoint::Pc(0x202));
+    /// let reason = machine.run_back(&RunLimit::default());
+    /// assert!(matches!(reason, StopReason::BreakpointHit(_)));
+    /// assert_eq!((10, 18), (machine.cpu.v[0x0], machine.cpu.v[0x1]));
+    ///
+    /// machine.clear_breakpoints();
+    /// assert_eq!(StopReason::HistoryExhausted, machine.run_back(&RunLimit::default()));
+    /// assert_eq!((0x200, 0, 0), (machine.cpu.pc, machine.cpu.v[0x0], machine.cycles));
+    /// ```
+    pub fn run_back(&mut self, limit: &RunLimit) -> StopReason {
+        let cycles_per_frame = limit.cycles_per_frame.max(1) as u64;
+        let mut instructions = 0;
+        let mut frames = 0;
+
+        loop {
+            let budget_exhausted = limit.instructions.is_some_and(|max| instructions >= max)
+                || limit.frames.is_some_and(|max| frames >= max);
+            if budget_exhausted {
+                return StopReason::BudgetExhausted;
+            }
+
+            let record = match self.history.as_mut().and_then(|history| history.pop()) {
+                Some(record) => record,
+                None => return StopReason::HistoryExhausted,
+            };
+            if self.cycles.is_multiple_of(cycles_per_frame) {
+                frames += 1;
+            }
+            let cpu_after = self.cpu.clone();
+            self.undo(&record);
+
+            let operation = match record.operation {
+                Some(operation) => operation,
+                None => continue,
+            };
+            instructions += 1;
+
+            if limit.pc == Some(self.cpu.pc) {
+                return StopReason::PcReached(self.cpu.pc);
+            }
+
+            let context = expression::Context {
+                cpu: &self.cpu,
+                memory: &self.memory,
+                delay_timer: self.delay_timer.value,
+                sound_timer: *self.sound.timer.read().unwrap(),
+            };
+            let hit = self.breakpoints.check_reverse(
+                self.cpu.pc,
+                &operation,
+                &record.accesses,
+                &cpu_after,
+                &context,
+            );
+            if let Some(hit) = hit {
+                self.breakpoint_hit = Some(hit);
+                return StopReason::BreakpointHit(hit);
+            }
+
+            if let Some(address) = limit.memory_write {
+                let written = record.accesses.iter().any(|access| {
+                    access.kind == memory::AccessKind::Write && access.contains(address)
+                });
+                if written {
+                    return StopReason::MemoryWritten(address);
+                }
+            }
+            if limit.display_change && record.changes_display() {
+                return StopReason::DisplayChanged;
+            }
+        }
+    }
+
+    /// Put the machine back into the state it was in before the recorded
+    /// step.
+    fn undo(&mut self, record: &history::UndoRecord) {
+        self.cpu.pc = record.pc;
+        for register in record.registers.iter() {
+            register.restore(&mut self.cpu);
+        }
+        // Restore bytes newest first in case a step wrote one twice.
+        for &(address, value) in record.memory.iter().rev() {
+            self.memory.data[address as usize] = value;
+        }
+
+        self.display.flip(&record.pixels);
+        if let Some(ref display) = record.display {
+            display.restore(&mut self.display);
+        }
+
+        self.delay_timer.value = record.delay_timer;
+        *self.sound.timer.write().unwrap() = record.sound_timer;
+        if let Some((pattern, pitch)) = record.audio {
+            *self.sound.pattern.write().unwrap() = pattern;
+            *self.sound.pitch.write().unwrap() = pitch;
+        }
+        if let Some(ref rng_state) = record.rng_state {
+            // The state came from this source, so it is always accepted.
+            let _ = self.rng.load_state(rng_state);
+        }
+
+        self.execution_state = record.execution_state;
+        self.machine_on = record.machine_on;
+        self.cycles = record.cycle;
+        self.memory_accesses.clear();
+        self.breakpoint_hit = None;
+        self.resume_address = Some(self.cpu.pc);
+    }
+
+    /// Skips the instruction at the program counter, taking into account that
+    /// XO-CHIP long instructions occupy four bytes.
+    fn skip_next_instr(&mut self) {
+        let size = self.memory
+            .read_instruction(self.cpu.pc)
+            .map_or(cpu::INSTRUCTION_BYTES, |instruction| {
+                cpu::Operation::instruction_size(&instruction)
+            });
+        self.cpu.pc = self.cpu.pc.wrapping_add(size);
     }
 
     /// Executes a single operation on the machine and return the resulting
@@ -79,10 +955,7 @@ where
     /// # extern crate okto;
     /// # use okto::cpu::Operation;
     /// # use okto::machine::Machine;
-    /// # use okto::keyboard;
-    /// # let mut machine = Machine::new(
-    /// #   Box::new(keyboard::nop_wait_key_callback)
-    /// # );
+    /// # let mut machine = Machine::new();
     /// assert_eq!(0, machine.cpu.v[0x3]);
     /// machine.execute(Operation::LoadImm(0x3, 0x25));
     /// assert_eq!(0x25, machine.cpu.v[0x3]);
@@ -95,14 +968,13 @@ where
     /// # extern crate okto;
     /// # use okto::cpu::Operation;
     /// # use okto::machine::Machine;
-    /// # use okto::keyboard;
-    /// # let mut machine = Machine::new(
-    /// #   Box::new(keyboard::nop_wait_key_callback)
-    /// # );
+    /// # let mut machine = Machine::new();
     /// let result = machine.execute(Operation::Ret);
     /// assert!(result.is_err());
     /// ```
     pub fn execute(&mut self, operation: cpu::Operation) -> OktoResult<&mut Self> {
+        self.memory_accesses.clear();
+
         match operation {
             cpu::Operation::Cls => self.display.clear(),
             cpu::Operation::Ret => {
@@ -115,7 +987,14 @@ where
             cpu::Operation::Sys(addr) => self.cpu.pc = addr,
             cpu::Operation::Jump(addr) => self.cpu.pc = addr,
             cpu::Operation::JumpAddrPlusV0(addr) => {
-                self.cpu.pc = (addr + (self.cpu.v[0] as u16)) % 0x1000;
+                let offset_reg = if self.quirks.jump_uses_vx {
+                    addr.vx()
+                } else {
+                    0
+                };
+                // The sum is not wrapped to 12 bits: as in Octo, the jump can
+                // land past 0xFFF in the 64 KB XO-CHIP address space.
+                self.cpu.pc = addr.wrapping_add(self.cpu.v[offset_reg as usize] as u16);
             }
             cpu::Operation::Call(addr) => {
                 let pc = self.cpu.pc;
@@ -127,22 +1006,22 @@ where
             }
             cpu::Operation::SkipEqImm(vx, imm) => {
                 if self.cpu.v[vx as usize] == imm {
-                    self.cpu.skip_next_instr();
+                    self.skip_next_instr();
                 }
             }
             cpu::Operation::SkipEqReg(vx, vy) => {
                 if self.cpu.v[vx as usize] == self.cpu.v[vy as usize] {
-                    self.cpu.skip_next_instr();
+                    self.skip_next_instr();
                 }
             }
             cpu::Operation::SkipNeqImm(vx, imm) => {
                 if self.cpu.v[vx as usize] != imm {
-                    self.cpu.skip_next_instr();
+                    self.skip_next_instr();
                 }
             }
             cpu::Operation::SkipNeqReg(vx, vy) => {
                 if self.cpu.v[vx as usize] != self.cpu.v[vy as usize] {
-                    self.cpu.skip_next_instr();
+                    self.skip_next_instr();
                 }
             }
             cpu::Operation::LoadImm(vx, imm) => self.cpu.v[vx as usize] = imm,
@@ -162,6 +1041,16 @@ where
                     return Err(OktoError::new(OktoErrorKind::InvalidDigitSprite));
                 }
             }
+            cpu::Operation::LoadAddrBigDigit(vx) => {
+                let sprite_address = self.memory
+                    .big_sprite_address_for_digit(self.cpu.v[vx as usize]);
+
+                if let Some(digit_addr) = sprite_address {
+                    self.cpu.i = digit_addr;
+                } else {
+                    return Err(OktoError::new(OktoErrorKind::InvalidDigitSprite));
+                }
+            }
             cpu::Operation::LoadRegDelay(vx) => {
                 self.cpu.v[vx as usize] = self.delay_timer.value;
             }
@@ -207,52 +1096,62 @@ where
             }
             cpu::Operation::Or(vx, vy) => {
                 self.cpu.v[vx as usize] |= self.cpu.v[vy as usize];
+                if self.quirks.logic_resets_vf {
+                    self.cpu.set_flag_reg(0x00);
+                }
             }
             cpu::Operation::And(vx, vy) => {
                 self.cpu.v[vx as usize] &= self.cpu.v[vy as usize];
+                if self.quirks.logic_resets_vf {
+                    self.cpu.set_flag_reg(0x00);
+                }
             }
             cpu::Operation::Xor(vx, vy) => {
                 self.cpu.v[vx as usize] ^= self.cpu.v[vy as usize];
+                if self.quirks.logic_resets_vf {
+                    self.cpu.set_flag_reg(0x00);
+                }
             }
-            cpu::Operation::Shr(vx) => {
-                let flag_value = self.cpu.v[vx as usize] & 0x1;
-                self.cpu.set_flag_reg(flag_value & 1);
-                self.cpu.v[vx as usize] >>= 1;
+            cpu::Operation::Shr(vx, vy) => {
+                let source = if self.quirks.shift_uses_vy { vy } else { vx };
+                let value = self.cpu.v[source as usize];
+                self.cpu.set_flag_reg(value & 0x1);
+                self.cpu.v[vx as usize] = value >> 1;
             }
-            cpu::Operation::Shl(vx) => {
-                let flag_value = (self.cpu.v[vx as usize] & 0x80) >> 7;
-                self.cpu.set_flag_reg(flag_value & 1);
-                self.cpu.v[vx as usize] <<= 1;
+            cpu::Operation::Shl(vx, vy) => {
+                let source = if self.quirks.shift_uses_vy { vy } else { vx };
+                let value = self.cpu.v[source as usize];
+                self.cpu.set_flag_reg((value & 0x80) >> 7);
+                self.cpu.v[vx as usize] = value << 1;
             }
             cpu::Operation::RandAndImm(vx, imm) => {
-                self.cpu.v[vx as usize] = random::<u8>() & imm;
+                self.cpu.v[vx as usize] = self.rng.next_byte() & imm;
             }
             cpu::Operation::Draw(vx, vy, size_bytes) => {
                 if size_bytes == 0 {
-                    let sprite_data = self.memory.read_bytes(self.cpu.i, 32)?;
+                    let num_bytes = 32 * self.display.selected_planes();
+                    self.log_access(memory::AccessKind::Read, self.cpu.i, num_bytes);
+                    let sprite_data = self.memory.read_bytes(self.cpu.i, num_bytes)?;
 
                     let pixels_erased = self.display.draw_large(
                         self.cpu.v[vx as usize] as usize,
                         self.cpu.v[vy as usize] as usize,
                         sprite_data,
+                        self.quirks.clip_sprites,
                     )?;
 
                     self.cpu
                         .set_flag_reg(if pixels_erased { 0x01 } else { 0x00 });
                 } else {
-                    let mut num_bytes = size_bytes;
-                    if size_bytes == 0 {
-                        num_bytes = 16;
-                    }
-
-                    let sprite_data = self.memory.read_bytes(
-                        self.cpu.i, num_bytes as usize
-                    )?;
+                    let num_bytes = size_bytes as usize * self.display.selected_planes();
+                    self.log_access(memory::AccessKind::Read, self.cpu.i, num_bytes);
+                    let sprite_data = self.memory.read_bytes(self.cpu.i, num_bytes)?;
 
                     let pixels_erased = self.display.draw(
                         self.cpu.v[vx as usize] as usize,
                         self.cpu.v[vy as usize] as usize,
                         sprite_data,
+                        self.quirks.clip_sprites,
                     )?;
 
                     self.cpu
@@ -262,38 +1161,42 @@ where
             cpu::Operation::SkipKey(vx) => {
                 let index = self.cpu.v[vx as usize] as usize;
                 if self.keyboard.keys[index] == keyboard::KeyState::Pressed {
-                    self.cpu.skip_next_instr();
+                    self.skip_next_instr();
                 }
             }
             cpu::Operation::SkipNotKey(vx) => {
                 let index = self.cpu.v[vx as usize] as usize;
                 if self.keyboard.keys[index] == keyboard::KeyState::Released {
-                    self.cpu.skip_next_instr();
+                    self.skip_next_instr();
                 }
             }
-            cpu::Operation::WaitKey(vx) => match (self.keyboard.wait_key_callback)() {
-                Ok(value) => self.cpu.v[vx as usize] = value,
-                Err(err) => return Err(OktoError::new(OktoErrorKind::Unknown(err))),
-            },
+            cpu::Operation::WaitKey(vx) => {
+                self.execution_state = ExecutionState::WaitingForKey(vx);
+            }
             cpu::Operation::MemStoreBcd(vx) => {
-                let addr = self.cpu.i as usize;
+                let addr = self.cpu.i;
                 let value = self.cpu.v[vx as usize];
+                self.log_access(memory::AccessKind::Write, addr, 3);
 
-                self.memory.data[addr] = value / 100;
-                self.memory.data[addr + 1] = (value / 10) % 10;
-                self.memory.data[addr + 2] = value % 10;
+                self.memory.data[addr as usize] = value / 100;
+                self.memory.data[addr.wrapping_add(1) as usize] = (value / 10) % 10;
+                self.memory.data[addr.wrapping_add(2) as usize] = value % 10;
             }
             cpu::Operation::MemStoreRegs(vx) => {
+                self.log_access(memory::AccessKind::Write, self.cpu.i, vx as usize + 1);
                 for index in 0..=vx {
-                    self.memory.data[(self.cpu.i + index as u16) as usize] =
+                    self.memory.data[self.cpu.i.wrapping_add(index as u16) as usize] =
                         self.cpu.v[index as usize];
                 }
+                self.increment_index_after_bulk_access(vx);
             }
             cpu::Operation::MemLoadRegs(vx) => {
+                self.log_access(memory::AccessKind::Read, self.cpu.i, vx as usize + 1);
                 for index in 0..=vx {
                     self.cpu.v[index as usize] =
-                        self.memory.data[(self.cpu.i + index as u16) as usize];
+                        self.memory.data[self.cpu.i.wrapping_add(index as u16) as usize];
                 }
+                self.increment_index_after_bulk_access(vx);
             }
 
             // SuperChip8 operations
@@ -311,9 +1214,11 @@ where
             },
             cpu::Operation::High => {
                 self.display.high_resolution = true;
+                self.display.dirty = true;
             },
             cpu::Operation::Low => {
                 self.display.high_resolution = false;
+                self.display.dirty = true;
             },
             cpu::Operation::RplStoreRegs(vx) => {
                 if vx > 8 {
@@ -333,9 +1238,353 @@ where
                     self.cpu.v[index as usize] = self.cpu.hp48[index as usize];
                 }
             },
-            _ => return Err(OktoError::new(OktoErrorKind::InvalidOpcode)),
+
+            // XO-CHIP operations
+            cpu::Operation::Scu(num_lines) => {
+                self.display.scroll_up(num_lines as usize);
+            }
+            cpu::Operation::MemStoreRange(vx, vy) => {
+                let length = vx.abs_diff(vy) as usize + 1;
+                self.log_access(memory::AccessKind::Write, self.cpu.i, length);
+                for (offset, index) in register_range(vx, vy).enumerate() {
+                    self.memory.data[self.cpu.i.wrapping_add(offset as u16) as usize] =
+                        self.cpu.v[index as usize];
+                }
+            }
+            cpu::Operation::MemLoadRange(vx, vy) => {
+                let length = vx.abs_diff(vy) as usize + 1;
+                self.log_access(memory::AccessKind::Read, self.cpu.i, length);
+                for (offset, index) in register_range(vx, vy).enumerate() {
+                    self.cpu.v[index as usize] =
+                        self.memory.data[self.cpu.i.wrapping_add(offset as u16) as usize];
+                }
+            }
+            cpu::Operation::LoadAddrLong(addr) => {
+                self.cpu.i = addr;
+            }
+            cpu::Operation::Plane(planes) => {
+                if planes > display::ALL_PLANES {
+                    return Err(OktoError::new(OktoErrorKind::InvalidOpcode));
+                }
+
+                self.display.planes = planes;
+            }
+            cpu::Operation::LoadAudio => {
+                let mut pattern = [0; sound::AUDIO_PATTERN_BYTES];
+                self.log_access(
+                    memory::AccessKind::Read,
+                    self.cpu.i,
+                    sound::AUDIO_PATTERN_BYTES,
+                );
+                pattern.copy_from_slice(
+                    self.memory.read_bytes(self.cpu.i, sound::AUDIO_PATTERN_BYTES)?
+                );
+                *self.sound.pattern.write().unwrap() = Some(pattern);
+            }
+            cpu::Operation::LoadPitchReg(vx) => {
+                *self.sound.pitch.write().unwrap() = self.cpu.v[vx as usize];
+            }
         }
 
         Ok(self)
     }
+
+    /// Serialize the complete state of the machine into the versioned binary
+    /// format described in the `savestate` module. The quirks and the input
+    /// source are part of the machine's configuration and are not saved, but
+    /// the state of the random number source is.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate okto;
+    /// # use okto::machine::Machine;
+    /// let mut machine = Machine::new();
+    /// machine.cpu.v[0x3] = 0x25;
+    /// let state = machine.save_state();
+    ///
+    /// let mut restored = Machine::new();
+    /// restored.load_state(&state).unwrap();
+    /// assert_eq!(0x25, restored.cpu.v[0x3]);
+    /// ```
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut writer = savestate::StateWriter::new();
+
+        writer.write_bytes(&self.cpu.v);
+        writer.write_u16(self.cpu.i);
+        writer.write_u16(self.cpu.pc);
+        writer.write_u8(self.cpu.sp);
+        for &address in self.cpu.stack.iter() {
+            writer.write_u16(address);
+        }
+        writer.write_bytes(&self.cpu.hp48);
+
+        writer.write_bytes(&self.memory.data);
+
+        writer.write_bool(self.display.high_resolution);
+        writer.write_u8(self.display.planes);
+        for row in self.display.data.iter() {
+            writer.write_bytes(row);
+        }
+
+        writer.write_u8(self.delay_timer.value);
+        writer.write_u8(*self.sound.timer.read().unwrap());
+        match *self.sound.pattern.read().unwrap() {
+            Some(ref pattern) => {
+                writer.write_bool(true);
+                writer.write_bytes(pattern);
+            }
+            None => {
+                writer.write_bool(false);
+                writer.write_bytes(&[0; sound::AUDIO_PATTERN_BYTES]);
+            }
+        }
+        writer.write_u8(*self.sound.pitch.read().unwrap());
+
+        for &key in self.keyboard.keys.iter() {
+            writer.write_bool(key == keyboard::KeyState::Pressed);
+        }
+
+        writer.write_bool(self.machine_on);
+        match self.execution_state {
+            ExecutionState::Running => writer.write_bytes(&[0, 0, 0]),
+            ExecutionState::WaitingForKey(vx) => writer.write_bytes(&[1, vx, 0]),
+            ExecutionState::WaitingForRelease(vx, key) => writer.write_bytes(&[2, vx, key]),
+        }
+        writer.write_u64(self.cycles);
+
+        let rng_state = self.rng.save_state();
+        writer.write_u16(rng_state.len() as u16);
+        writer.write_bytes(&rng_state);
+
+        writer.finish()
+    }
+
+    /// Restore the machine from a save state produced by `save_state`. The
+    /// machine is left unchanged if the save state is invalid or was written
+    /// by a newer, unsupported version of the format.
+    pub fn load_state(&mut self, state: &[u8]) -> OktoResult<()> {
+        let mut reader = savestate::StateReader::new(state)?;
+
+        let mut cpu = cpu::Cpu::new();
+        reader.read_into(&mut cpu.v)?;
+        cpu.i = reader.read_u16()?;
+        cpu.pc = reader.read_u16()?;
+        cpu.sp = reader.read_u8()?;
+        if cpu.sp as usize > cpu::MAX_NUM_STACK_ITEMS {
+            return Err(OktoError::new(OktoErrorKind::InvalidSaveState));
+        }
+        for address in cpu.stack.iter_mut() {
+            *address = reader.read_u16()?;
+        }
+        reader.read_into(&mut cpu.hp48)?;
+
+        let mut memory = memory::Memory::new();
+        reader.read_into(&mut memory.data)?;
+
+        let mut display = display::Display::new();
+        display.high_resolution = reader.read_bool()?;
+        display.planes = reader.read_u8()?;
+        if display.planes > display::ALL_PLANES {
+            return Err(OktoError::new(OktoErrorKind::InvalidSaveState));
+        }
+        for row in display.data.iter_mut() {
+            reader.read_into(row)?;
+        }
+
+        let delay_timer_value = reader.read_u8()?;
+        let sound_timer_value = reader.read_u8()?;
+        let has_pattern = reader.read_bool()?;
+        let mut pattern = [0; sound::AUDIO_PATTERN_BYTES];
+        reader.read_into(&mut pattern)?;
+        let pitch = reader.read_u8()?;
+
+        let mut keys = [keyboard::KeyState::Released; keyboard::NUM_KEYS];
+        for key in keys.iter_mut() {
+            if reader.read_bool()? {
+                *key = keyboard::KeyState::Pressed;
+            }
+        }
+
+        let machine_on = reader.read_bool()?;
+        let execution = reader.read_u8()?;
+        let vx = reader.read_u8()?;
+        let key = reader.read_u8()?;
+        if vx as usize >= cpu::NUM_REGISTERS || key as usize >= keyboard::NUM_KEYS {
+            return Err(OktoError::new(OktoErrorKind::InvalidSaveState));
+        }
+        let execution_state = match execution {
+            0 => ExecutionState::Running,
+            1 => ExecutionState::WaitingForKey(vx),
+            2 => ExecutionState::WaitingForRelease(vx, key),
+            _ => return Err(OktoError::new(OktoErrorKind::InvalidSaveState)),
+        };
+        let cycles = reader.read_u64()?;
+
+        let rng_size = reader.read_u16()? as usize;
+        let rng_state = reader.read_bytes(rng_size)?;
+        reader.finish()?;
+
+        self.rng.load_state(rng_state)?;
+
+        self.cpu = cpu;
+        self.memory = memory;
+        self.display = display;
+        self.display.dirty = true;
+        self.delay_timer.value = delay_timer_value;
+        *self.sound.timer.write().unwrap() = sound_timer_value;
+        *self.sound.pattern.write().unwrap() = if has_pattern { Some(pattern) } else { None };
+        *self.sound.pitch.write().unwrap() = pitch;
+        self.keyboard.keys = keys;
+        self.machine_on = machine_on;
+        self.execution_state = execution_state;
+        self.cycles = cycles;
+        self.keyboard.take_events();
+        if let Some(ref mut history) = self.history {
+            history.clear();
+        }
+
+        Ok(())
+    }
+
+    /// Returns the memory read and written by the most recently executed
+    /// operation, not counting the fetch of the instruction itself.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate okto;
+    /// # use okto::cpu::Operation;
+    /// # use okto::machine::Machine;
+    /// # use okto::memory::{AccessKind, MemoryAccess};
+    /// let mut machine = Machine::new();
+    /// machine.cpu.i = 0x300;
+    /// machine.execute(Operation::MemStoreBcd(0x0)).unwrap();
+    /// assert_eq!(
+    ///     &[MemoryAccess {
+    ///         kind: AccessKind::Write,
+    ///         address: 0x300,
+    ///         length: 3,
+    ///     }],
+    ///     machine.memory_accesses()
+    /// );
+    /// ```
+    pub fn memory_accesses(&self) -> &[memory::MemoryAccess] {
+        &self.memory_accesses
+    }
+
+    /// Record a memory access made by the operation being executed.
+    fn log_access(&mut self, kind: memory::AccessKind, address: cpu::Address, length: usize) {
+        if let Some((ref mut record, _)) = self.pending_undo {
+            if kind == memory::AccessKind::Write {
+                for offset in 0..length {
+                    let address = address.wrapping_add(offset as cpu::Address);
+                    record
+                        .memory
+                        .push((address, self.memory.data[address as usize]));
+                }
+            }
+        }
+        self.memory_accesses.push(memory::MemoryAccess {
+            kind,
+            address,
+            length,
+        });
+    }
+
+    /// Start recording the key events processed by the machine, discarding any
+    /// recording in progress.
+    pub fn start_recording(&mut self) {
+        self.recording = Some(input::InputRecording::new());
+    }
+
+    /// Stop recording and return the key events processed since recording
+    /// started. Replaying the recording with `input::RecordedInput` on a
+    /// machine in the same starting state reproduces the run.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate okto;
+    /// # use okto::input::RecordedInput;
+    /// # use okto::machine::Machine;
+    /// let mut machine = Machine::new();
+    /// machine.memory.data[0x200..0x202].copy_from_slice(&[0xF3, 0x0A]);
+    /// machine.start_recording();
+    /// machine.step().unwrap().step().unwrap();
+    /// machine.keyboard.press_key(0x7);
+    /// machine.step().unwrap();
+    /// let recording = machine.stop_recording().unwrap();
+    ///
+    /// let mut replay = Machine::new();
+    /// replay.memory.data[0x200..0x202].copy_from_slice(&[0xF3, 0x0A]);
+    /// replay.input = Box::new(RecordedInput::new(recording));
+    /// for _ in 0..3 {
+    ///     replay.step().unwrap();
+    /// }
+    /// assert_eq!(0x7, replay.cpu.v[0x3]);
+    /// ```
+    pub fn stop_recording(&mut self) -> Option<input::InputRecording> {
+        self.recording.take()
+    }
+
+    /// Consume the key events delivered to the keyboard, completing a pending
+    /// `WaitKey` when the awaited press (or release) has happened. Events that
+    /// arrive while the machine is running are discarded.
+    fn process_key_events(&mut self) {
+        for event in self.keyboard.take_events() {
+            if let Some(ref mut recording) = self.recording {
+                recording.events.push(input::TimedKeyEvent {
+                    cycle: self.cycles,
+                    event,
+                });
+            }
+
+            match (self.execution_state, event) {
+                (ExecutionState::WaitingForKey(vx), keyboard::KeyEvent::Pressed(key)) => {
+                    if self.quirks.wait_key_on_release {
+                        self.execution_state = ExecutionState::WaitingForRelease(vx, key);
+                    } else {
+                        self.cpu.v[vx as usize] = key;
+                        self.execution_state = ExecutionState::Running;
+                    }
+                }
+                (
+                    ExecutionState::WaitingForRelease(vx, pressed),
+                    keyboard::KeyEvent::Released(key),
+                ) if key == pressed => {
+                    self.cpu.v[vx as usize] = key;
+                    self.execution_state = ExecutionState::Running;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Update the index register after `MemStoreRegs` or `MemLoadRegs` has
+    /// accessed registers V0 through VX, according to the machine's quirks.
+    fn increment_index_after_bulk_access(&mut self, vx: cpu::Register) {
+        let increment = match self.quirks.index_increment {
+            quirks::IndexIncrement::Unchanged => 0,
+            quirks::IndexIncrement::ByX => vx as cpu::Address,
+            quirks::IndexIncrement::ByXPlusOne => vx as cpu::Address + 1,
+        };
+        self.cpu.i = self.cpu.i.wrapping_add(increment);
+    }
+}
+
+impl Default for Machine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Returns the registers from VX to VY inclusive, in descending order if X is
+/// greater than Y, as accessed by the XO-CHIP range load and store operations.
+fn register_range(vx: cpu::Register, vy: cpu::Register) -> Box<dyn Iterator<Item = cpu::Register>> {
+    if vx <= vy {
+        Box::new(vx..=vy)
+    } else {
+        Box::new((vy..=vx).rev())
+    }
 }
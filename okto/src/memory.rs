@@ -1,9 +1,10 @@
 //! Chip8 memory access and loading
-use super::cpu::{Address, Instruction, DEFAULT_PC_ADDRESS};
+use super::cpu::{Address, Instruction, Operation, DEFAULT_PC_ADDRESS, INSTRUCTION_BYTES};
 use super::{OktoError, OktoErrorKind, OktoResult};
 
-/// The size of the Chip8 memory in bytes.
-pub const MEMORY_SIZE_BYTES: usize = 0x1000;
+/// The size of the machine memory in bytes. XO-CHIP extends the original 4 KiB
+/// of Chip8 memory to the full 64 KiB addressable by the index register.
+pub const MEMORY_SIZE_BYTES: usize = 0x10000;
 /// The maximum size of a Chip8 ROM in bytes.
 pub const MAX_ROM_SIZE_BYTES: usize = MEMORY_SIZE_BYTES - DEFAULT_PC_ADDRESS as usize;
 /// The number of bytes per digit sprite.
@@ -94,12 +95,225 @@ pub const DIGIT_SPRITES: [u8; NUM_DIGIT_SPRITES] = [
     0x80, // F
 ];
 
+/// The number of bytes per SuperChip large digit sprite.
+pub const BYTES_PER_BIG_DIGIT_SPRITE: Address = 10;
+/// The number of large digit sprites.
+pub const NUM_BIG_DIGIT_SPRITES: usize = 0x10 * BYTES_PER_BIG_DIGIT_SPRITE as usize;
+/// The address of the first large digit sprite, which follows the small ones.
+pub const BIG_DIGIT_SPRITES_ADDRESS: Address = NUM_DIGIT_SPRITES as Address;
+/// Hexadecimal digits represented as 8x10 pixel, 10 byte sprites. Digits 0-9
+/// are the SuperChip 1.1 font and A-F come from Octo.
+pub const BIG_DIGIT_SPRITES: [u8; NUM_BIG_DIGIT_SPRITES] = [
+    0x3C,
+    0x7E,
+    0xE7,
+    0xC3,
+    0xC3,
+    0xC3,
+    0xC3,
+    0xE7,
+    0x7E,
+    0x3C, // 0
+    0x18,
+    0x38,
+    0x58,
+    0x18,
+    0x18,
+    0x18,
+    0x18,
+    0x18,
+    0x18,
+    0x3C, // 1
+    0x3E,
+    0x7F,
+    0xC3,
+    0x06,
+    0x0C,
+    0x18,
+    0x30,
+    0x60,
+    0xFF,
+    0xFF, // 2
+    0x3C,
+    0x7E,
+    0xC3,
+    0x03,
+    0x0E,
+    0x0E,
+    0x03,
+    0xC3,
+    0x7E,
+    0x3C, // 3
+    0x06,
+    0x0E,
+    0x1E,
+    0x36,
+    0x66,
+    0xC6,
+    0xFF,
+    0xFF,
+    0x06,
+    0x06, // 4
+    0xFF,
+    0xFF,
+    0xC0,
+    0xC0,
+    0xFC,
+    0xFE,
+    0x03,
+    0xC3,
+    0x7E,
+    0x3C, // 5
+    0x3E,
+    0x7C,
+    0xC0,
+    0xC0,
+    0xFC,
+    0xFE,
+    0xC3,
+    0xC3,
+    0x7E,
+    0x3C, // 6
+    0xFF,
+    0xFF,
+    0x03,
+    0x06,
+    0x0C,
+    0x18,
+    0x30,
+    0x60,
+    0x60,
+    0x60, // 7
+    0x3C,
+    0x7E,
+    0xC3,
+    0xC3,
+    0x7E,
+    0x7E,
+    0xC3,
+    0xC3,
+    0x7E,
+    0x3C, // 8
+    0x3C,
+    0x7E,
+    0xC3,
+    0xC3,
+    0x7F,
+    0x3F,
+    0x03,
+    0x03,
+    0x3E,
+    0x7C, // 9
+    0x7E,
+    0xFF,
+    0xC3,
+    0xC3,
+    0xC3,
+    0xFF,
+    0xFF,
+    0xC3,
+    0xC3,
+    0xC3, // A
+    0xFC,
+    0xFC,
+    0xC3,
+    0xC3,
+    0xFC,
+    0xFC,
+    0xC3,
+    0xC3,
+    0xFC,
+    0xFC, // B
+    0x3C,
+    0xFF,
+    0xC3,
+    0xC0,
+    0xC0,
+    0xC0,
+    0xC0,
+    0xC3,
+    0xFF,
+    0x3C, // C
+    0xFC,
+    0xFE,
+    0xC3,
+    0xC3,
+    0xC3,
+    0xC3,
+    0xC3,
+    0xC3,
+    0xFE,
+    0xFC, // D
+    0xFF,
+    0xFF,
+    0xC0,
+    0xC0,
+    0xFF,
+    0xFF,
+    0xC0,
+    0xC0,
+    0xFF,
+    0xFF, // E
+    0xFF,
+    0xFF,
+    0xC0,
+    0xC0,
+    0xFF,
+    0xFF,
+    0xC0,
+    0xC0,
+    0xC0,
+    0xC0, // F
+];
+
 /// Encapsulates memory subsystem for Chip8.
 pub struct Memory {
     /// Byte array representing memory.
     pub data: [u8; MEMORY_SIZE_BYTES],
 }
 
+/// Direction of a memory access made by an instruction.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AccessKind {
+    Read,
+    Write,
+}
+
+/// A run of consecutive bytes read or written by an instruction, such as the
+/// sprite read by `Draw` or the registers stored by `MemStoreRegs`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MemoryAccess {
+    /// Whether the bytes were read or written
+    pub kind: AccessKind,
+    /// Address of the first byte accessed
+    pub address: Address,
+    /// Number of bytes accessed
+    pub length: usize,
+}
+
+impl MemoryAccess {
+    /// Returns true if the access touched the given address. Accesses wrap
+    /// around the end of memory just as the index register does.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate okto;
+    /// # use okto::memory::{AccessKind, MemoryAccess};
+    /// let access = MemoryAccess {
+    ///     kind: AccessKind::Write,
+    ///     address: 0xFFFE,
+    ///     length: 3,
+    /// };
+    /// assert!(access.contains(0xFFFF));
+    /// assert!(access.contains(0x0000));
+    /// assert!(!access.contains(0x0001));
+    /// ```
+    pub fn contains(&self, address: Address) -> bool {
+        (address.wrapping_sub(self.address) as usize) < self.length
+    }
+}
+
 /// Creates a 16-bit value by concatenating two 8-bit values. Used to read
 /// instructions from Chip8 memory.
 ///
@@ -123,7 +337,11 @@ impl Memory {
         let mut result = Self {
             data: [0; MEMORY_SIZE_BYTES],
         };
+        let big_digits_start = BIG_DIGIT_SPRITES_ADDRESS as usize;
+
         result.data[0..NUM_DIGIT_SPRITES].copy_from_slice(&DIGIT_SPRITES);
+        result.data[big_digits_start..big_digits_start + NUM_BIG_DIGIT_SPRITES]
+            .copy_from_slice(&BIG_DIGIT_SPRITES);
         result
     }
 
@@ -136,6 +354,30 @@ impl Memory {
         Some((digit as Address) * BYTES_PER_DIGIT_SPRITE)
     }
 
+    /// Returns the address of the large sprite corresponding to the given hex
+    /// digit.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate okto;
+    /// # use okto::memory::Memory;
+    /// # let memory = Memory::new();
+    /// let address = memory.big_sprite_address_for_digit(0x8).unwrap();
+    /// assert_eq!(
+    ///     &[0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C],
+    ///     memory.read_bytes(address, 10).unwrap()
+    /// );
+    /// assert_eq!(None, memory.big_sprite_address_for_digit(0x10));
+    /// ```
+    pub fn big_sprite_address_for_digit(&self, digit: u8) -> Option<Address> {
+        if digit > 0xF {
+            return None;
+        }
+
+        Some(BIG_DIGIT_SPRITES_ADDRESS + (digit as Address) * BYTES_PER_BIG_DIGIT_SPRITE)
+    }
+
     /// Load a slice of bytes of a given size into memory starting at the
     /// given address.
     ///
@@ -158,13 +400,13 @@ impl Memory {
     /// # use okto::memory::Memory;
     /// # let mut memory = Memory::new();
     /// let bytes: [u8; 6] = [0x12, 0x34, 0x56, 0x78, 0x9A, 0xBC];
-    /// assert!(memory.load(&bytes, 0xFFA, bytes.len()).is_err());
+    /// assert!(memory.load(&bytes, 0xFFFC, bytes.len()).is_err());
     /// ```
     pub fn load(&mut self, data: &[u8], start_address: Address, size: usize) -> OktoResult<()> {
         let start = start_address as usize;
         let end = (start_address as usize) + size;
 
-        if end >= MEMORY_SIZE_BYTES as usize {
+        if end > MEMORY_SIZE_BYTES {
             return Err(OktoError::new(OktoErrorKind::RomTooLarge));
         }
 
@@ -184,18 +426,10 @@ impl Memory {
     /// # use okto::memory::Memory;
     /// # let mut memory = Memory::new();
     /// assert!(memory.write_byte(0x345, 0x12).is_ok());
-    /// ```
-    ///
-    /// An attempt to write out of bounds will result in an error:
-    ///
-    /// ```
-    /// # extern crate okto;
-    /// # use okto::memory::Memory;
-    /// # let mut memory = Memory::new();
-    /// assert!(memory.write_byte(0x1000, 0x12).is_err());
+    /// assert!(memory.write_byte(0xFFFF, 0x12).is_ok());
     /// ```
     pub fn write_byte(&mut self, address: Address, value: u8) -> OktoResult<()> {
-        if address >= MEMORY_SIZE_BYTES as Address {
+        if address as usize >= MEMORY_SIZE_BYTES {
             return Err(OktoError::new(OktoErrorKind::AddressOutOfRange));
         }
 
@@ -224,7 +458,7 @@ impl Memory {
     pub fn read_bytes(&self, address: Address, size_bytes: usize) -> OktoResult<&[u8]> {
         let start = address as usize;
         let end = start + size_bytes;
-        if start >= MEMORY_SIZE_BYTES || end >= MEMORY_SIZE_BYTES {
+        if start >= MEMORY_SIZE_BYTES || end > MEMORY_SIZE_BYTES {
             return Err(OktoError::new(OktoErrorKind::AddressOutOfRange));
         }
 
@@ -233,7 +467,7 @@ impl Memory {
 
     /// Reads an instruction from the given address. Returns the reconstructed
     /// instruction on success, or None if the address is too high to read two
-    /// bytes from memory (ex. 0xFFF).
+    /// bytes from memory (ex. 0xFFFF).
     ///
     /// # Examples
     ///
@@ -254,12 +488,12 @@ impl Memory {
     /// # extern crate okto;
     /// # use okto::memory::Memory;
     /// # let mut memory = Memory::new();
-    /// memory.write_byte(0xFFF, 0x3F).unwrap();
-    /// assert_eq!(None, memory.read_instruction(0xFFF));
+    /// memory.write_byte(0xFFFF, 0x3F).unwrap();
+    /// assert_eq!(None, memory.read_instruction(0xFFFF));
     /// ```
     pub fn read_instruction(&self, address: Address) -> Option<Instruction> {
         // If we can't read two bytes, then we cannot read an instruction
-        if address >= (MEMORY_SIZE_BYTES - 1) as Address {
+        if address as usize >= MEMORY_SIZE_BYTES - 1 {
             return None;
         }
 
@@ -270,4 +504,40 @@ impl Memory {
 
         Some(result)
     }
+
+    /// Reads and decodes the operation stored at the given address. Returns
+    /// the operation along with the number of bytes it occupies, or `None` if
+    /// the bytes at the address do not form a valid instruction.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate okto;
+    /// # use okto::cpu::Operation;
+    /// # use okto::memory::Memory;
+    /// # let mut memory = Memory::new();
+    /// memory.load(&[0xF0, 0x00, 0x12, 0x34], 0x200, 4).unwrap();
+    /// assert_eq!(
+    ///     Some((Operation::LoadAddrLong(0x1234), 4)),
+    ///     memory.read_operation(0x200)
+    /// );
+    /// ```
+    pub fn read_operation(&self, address: Address) -> Option<(Operation, Address)> {
+        let instruction = self.read_instruction(address)?;
+        let size = Operation::instruction_size(&instruction);
+
+        if size == INSTRUCTION_BYTES {
+            Operation::from_instruction(&instruction).map(|operation| (operation, size))
+        } else {
+            let operand = self.read_instruction(address.checked_add(INSTRUCTION_BYTES)?)?;
+            Operation::from_long_instruction(&instruction, &operand)
+                .map(|operation| (operation, size))
+        }
+    }
+}
+
+impl Default for Memory {
+    fn default() -> Self {
+        Self::new()
+    }
 }
@@ -0,0 +1,1123 @@
+//! Assembler for the Octo assembly language.
+//!
+//! Every instruction is written with the Octo mnemonic listed in `isa`, such
+//! as `v0 := 5` or `sprite v1 v2 8`. Alongside those the assembler
+//! understands:
+//!
+//! * labels, defined with `: name` and called by writing their name
+//! * `:const NAME VALUE`, `:alias NAME REGISTER` and `:calc NAME { EXPR }`
+//! * `:macro NAME ARGS... { BODY }`, expanded wherever `NAME` is written
+//! * `:org ADDRESS`, `:byte VALUE` and bare numbers, which are written as
+//!   bytes of data
+//! * `:call ADDRESS`, `:unpack NIBBLE LABEL`, `:next LABEL` and `;` for
+//!   `return`
+//! * `loop`, `while CONDITION` and `again`
+//! * `if CONDITION then STATEMENT` and `if CONDITION begin ... else ... end`
+//!
+//! Conditions compare a register with a register or a byte using `==`, `!=`,
+//! `<`, `>`, `<=` or `>=`, or test a key with `key` or `-key`. The ordered
+//! comparisons use `vf` as a temporary. As in Octo, a jump to the label
+//! `main` is placed at 0x200 unless `main` is the first label, and `:calc`
+//! expressions are evaluated from right to left without operator precedence,
+//! so `2 * 3 + 1` is 8.
+//!
+//! ```
+//! # extern crate okto;
+//! # use okto::octo;
+//! let source = "
+//!     : main
+//!         v0 := 3
+//!         loop
+//!             v0 += -1
+//!             if v0 == 0 then exit
+//!         again
+//! ";
+//! let program = octo::assemble("countdown.8o", source).unwrap();
+//! assert_eq!(
+//!     vec![0x60, 0x03, 0x70, 0xFF, 0x40, 0x00, 0x00, 0xFD, 0x12, 0x02],
+//!     program.rom
+//! );
+//! ```
+use super::assembler::{self, Field, Image, Location, Program};
+use super::cpu::{Address, Operation, Register, DEFAULT_PC_ADDRESS, INSTRUCTION_BYTES};
+use super::isa::{self, Operand, OperandKind, OperationInfo, Syntax};
+use super::OktoResult;
+
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::f64::consts;
+use std::rc::Rc;
+
+/// Most macro expansions allowed in a program, which stops a macro that
+/// expands to itself from running forever.
+const MAX_MACRO_EXPANSIONS: usize = 100_000;
+
+/// Words with a meaning of their own outside of instruction mnemonics.
+const KEYWORDS: [&str; 14] = [
+    ":", ";", "loop", "again", "while", "if", "then", "begin", "else", "end", "key", "-key", "{",
+    "}",
+];
+
+/// Operators of `:calc` expressions taking two values.
+const BINARY_OPERATORS: [&str; 19] = [
+    "+", "-", "*", "/", "%", "&", "|", "^", "<<", ">>", "pow", "min", "max", "<", "<=", ">", ">=",
+    "==", "!=",
+];
+
+/// Operators of `:calc` expressions taking one value.
+const UNARY_OPERATORS: [&str; 14] = [
+    "-", "~", "!", "sin", "cos", "tan", "exp", "log", "abs", "sqrt", "sign", "ceil", "floor", "@",
+];
+
+/// Assemble Octo source into a ROM. `file` names the source in errors.
+pub fn assemble(file: &str, source: &str) -> OktoResult<Program> {
+    let file: Rc<str> = Rc::from(file);
+    let mut tokens = tokenize(&file, source);
+    tokens.reverse();
+
+    let mut assembler = Assembler::new(
+        tokens,
+        Location {
+            file,
+            line: 1,
+            column: 1,
+        },
+    );
+    assembler.run()?;
+    assembler.image.finish()
+}
+
+/// A word of source.
+#[derive(Clone, Debug)]
+struct Token {
+    text: String,
+    location: Location,
+}
+
+/// Split the source into words, dropping comments. Braces and parentheses
+/// are always words of their own.
+fn tokenize(file: &Rc<str>, source: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+
+    for (line, text) in source.lines().enumerate() {
+        let mut word = String::new();
+        let mut start = 0;
+        let mut flush = |word: &mut String, column: usize| {
+            if !word.is_empty() {
+                tokens.push(Token {
+                    text: word.split_off(0),
+                    location: Location {
+                        file: file.clone(),
+                        line: line + 1,
+                        column,
+                    },
+                });
+            }
+        };
+
+        for (index, c) in text.chars().enumerate() {
+            if c == '#' {
+                break;
+            } else if c.is_whitespace() {
+                flush(&mut word, start);
+            } else if "{}()".contains(c) {
+                flush(&mut word, start);
+                flush(&mut c.to_string(), index + 1);
+            } else {
+                if word.is_empty() {
+                    start = index + 1;
+                }
+                word.push(c);
+            }
+        }
+        flush(&mut word, start);
+    }
+
+    tokens
+}
+
+/// Parse a decimal, `0x` hexadecimal or `0b` binary integer, optionally
+/// negative.
+fn parse_number(text: &str) -> Option<f64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(binary) = digits.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2).ok()?
+    } else if !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()) {
+        digits.parse().ok()?
+    } else {
+        return None;
+    };
+
+    Some(if negative { -value } else { value } as f64)
+}
+
+/// Returns true if the text can name a label, constant, alias or macro.
+fn is_name(text: &str) -> bool {
+    text.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && text
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_-.".contains(c))
+}
+
+/// A word of an instruction mnemonic.
+#[derive(Clone, Copy, Debug)]
+enum Part {
+    /// Word written as is
+    Word(&'static str),
+    /// Operand value
+    Operand(Operand),
+}
+
+/// Instruction mnemonic split into words.
+#[derive(Clone, Debug)]
+struct Template {
+    info: &'static OperationInfo,
+    parts: Vec<Part>,
+}
+
+/// Returns the mnemonics of the instructions, longest first so that, for
+/// example, `v0 := random 5` is not read as `v0 := random`. Conditional
+/// skips are written with `if` and are left out.
+fn templates() -> Vec<Template> {
+    let mut templates: Vec<Template> = isa::OPERATIONS
+        .iter()
+        .filter_map(|info| {
+            let template = info.template(Syntax::Octo)?;
+            if template.starts_with("if ") {
+                return None;
+            }
+            let parts = template
+                .split_whitespace()
+                .map(|word| {
+                    match info
+                        .operands
+                        .iter()
+                        .find(|o| word == format!("{{{}}}", o.name))
+                    {
+                        Some(&operand) => Part::Operand(operand),
+                        None => Part::Word(word),
+                    }
+                })
+                .collect();
+            Some(Template { info, parts })
+        })
+        .collect();
+
+    let words = |template: &Template| {
+        template
+            .parts
+            .iter()
+            .filter(|part| matches!(part, Part::Word(_)))
+            .count()
+    };
+    templates.sort_by_key(|template| Reverse((template.parts.len(), words(template))));
+    templates
+}
+
+/// How a condition compares its values.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    Greater,
+    LessOrEqual,
+    GreaterOrEqual,
+    Key,
+    NotKey,
+}
+
+impl Comparison {
+    fn from_word(word: &str) -> Option<Self> {
+        match word {
+            "==" => Some(Comparison::Equal),
+            "!=" => Some(Comparison::NotEqual),
+            "<" => Some(Comparison::Less),
+            ">" => Some(Comparison::Greater),
+            "<=" => Some(Comparison::LessOrEqual),
+            ">=" => Some(Comparison::GreaterOrEqual),
+            "key" => Some(Comparison::Key),
+            "-key" => Some(Comparison::NotKey),
+            _ => None,
+        }
+    }
+
+    /// Returns the comparison that holds exactly when this one does not.
+    fn negate(self) -> Self {
+        match self {
+            Comparison::Equal => Comparison::NotEqual,
+            Comparison::NotEqual => Comparison::Equal,
+            Comparison::Less => Comparison::GreaterOrEqual,
+            Comparison::Greater => Comparison::LessOrEqual,
+            Comparison::LessOrEqual => Comparison::Greater,
+            Comparison::GreaterOrEqual => Comparison::Less,
+            Comparison::Key => Comparison::NotKey,
+            Comparison::NotKey => Comparison::Key,
+        }
+    }
+}
+
+/// Value compared by a condition.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Term {
+    Register(Register),
+    Byte(u8),
+}
+
+/// Condition of an `if` or `while`.
+#[derive(Clone, Copy, Debug)]
+struct Condition {
+    register: Register,
+    comparison: Comparison,
+    term: Term,
+}
+
+impl Condition {
+    fn negate(self) -> Self {
+        Self {
+            comparison: self.comparison.negate(),
+            ..self
+        }
+    }
+}
+
+/// Macro defined by `:macro`.
+#[derive(Clone, Debug)]
+struct Macro {
+    parameters: Vec<String>,
+    body: Vec<Token>,
+}
+
+/// Loop whose `again` has not been reached yet.
+#[derive(Clone, Debug)]
+struct Loop {
+    start: Address,
+    /// Jumps out of the loop written by `while`
+    exits: Vec<Address>,
+    location: Location,
+}
+
+/// `if ... begin` whose `end` has not been reached yet.
+#[derive(Clone, Debug)]
+struct Branch {
+    /// Jump to the `else` or `end`
+    jump: Address,
+    has_else: bool,
+    location: Location,
+}
+
+struct Assembler {
+    /// Tokens still to be read, last first
+    tokens: Vec<Token>,
+    /// Start of the source, where errors about the whole program point
+    start: Location,
+    image: Image,
+    templates: Vec<Template>,
+    constants: HashMap<String, f64>,
+    aliases: HashMap<String, Register>,
+    macros: HashMap<String, Macro>,
+    expansions: usize,
+    loops: Vec<Loop>,
+    branches: Vec<Branch>,
+    /// Whether 0x200 is kept for a jump to `main`
+    main_jump: bool,
+}
+
+impl Assembler {
+    fn new(tokens: Vec<Token>, start: Location) -> Self {
+        let aliases = [
+            ("compare-temp", 0xF),
+            ("unpack-hi", 0x0),
+            ("unpack-lo", 0x1),
+        ]
+        .iter()
+        .map(|&(name, register)| (String::from(name), register))
+        .collect();
+
+        Self {
+            tokens,
+            start,
+            image: Image::new(),
+            templates: templates(),
+            constants: HashMap::new(),
+            aliases,
+            macros: HashMap::new(),
+            expansions: 0,
+            loops: Vec::new(),
+            branches: Vec::new(),
+            main_jump: true,
+        }
+    }
+
+    /// Assemble every statement into the image.
+    fn run(&mut self) -> OktoResult<()> {
+        let start = self.start.clone();
+        self.image
+            .set_here((DEFAULT_PC_ADDRESS + INSTRUCTION_BYTES) as u32, &start)?;
+
+        while let Some(token) = self.tokens.pop() {
+            self.statement(token)?;
+        }
+
+        if let Some(open) = self.loops.first() {
+            return Err(open.location.error("'loop' is missing its 'again'"));
+        }
+        if let Some(open) = self.branches.first() {
+            return Err(open.location.error("'begin' is missing its 'end'"));
+        }
+
+        if self.main_jump {
+            let main = self
+                .image
+                .label("main")
+                .ok_or_else(|| start.error("the program is missing a 'main' label"))?;
+            self.image.set_here(DEFAULT_PC_ADDRESS as u32, &start)?;
+            self.image.emit_operation(&Operation::Jump(0), &start)?;
+            self.image
+                .patch(DEFAULT_PC_ADDRESS, Field::Address, main as u32, &start)?;
+        }
+
+        Ok(())
+    }
+
+    fn next(&mut self, after: &Token) -> OktoResult<Token> {
+        self.tokens.pop().ok_or_else(|| {
+            after
+                .location
+                .error(format!("unexpected end of source after '{}'", after.text))
+        })
+    }
+
+    fn peek(&self, offset: usize) -> Option<&Token> {
+        self.tokens
+            .len()
+            .checked_sub(offset + 1)
+            .map(|index| &self.tokens[index])
+    }
+
+    /// Returns the offset of the token after the value starting at the given
+    /// offset, skipping over `{ ... }` expressions.
+    fn skip_value(&self, offset: usize) -> usize {
+        let mut depth = 0;
+        let mut offset = offset;
+        while let Some(token) = self.peek(offset) {
+            offset += 1;
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" => depth -= 1,
+                _ => {}
+            }
+            if depth <= 0 {
+                break;
+            }
+        }
+        offset
+    }
+
+    /// Returns the register named by the text, either `v0` to `vf` or an
+    /// alias.
+    fn register(&self, text: &str) -> Option<Register> {
+        if let Some(&register) = self.aliases.get(text) {
+            return Some(register);
+        }
+        let mut chars = text.chars();
+        match (chars.next(), chars.next(), chars.next()) {
+            (Some('v'), Some(digit), None) | (Some('V'), Some(digit), None) => {
+                digit.to_digit(16).map(|digit| digit as Register)
+            }
+            _ => None,
+        }
+    }
+
+    fn is_keyword(&self, text: &str) -> bool {
+        KEYWORDS.contains(&text)
+            || self.templates.iter().any(|template| {
+                template
+                    .parts
+                    .iter()
+                    .any(|part| matches!(*part, Part::Word(word) if word == text))
+            })
+    }
+
+    /// Check that a token can name something new.
+    fn check_name(&self, token: &Token) -> OktoResult<()> {
+        let name = token.text.as_str();
+        if !is_name(name) || self.is_keyword(name) || self.register(name).is_some() {
+            return Err(token
+                .location
+                .error(format!("'{}' is not a valid name", name)));
+        }
+        if self.image.label(name).is_some()
+            || self.constants.contains_key(name)
+            || self.macros.contains_key(name)
+        {
+            return Err(token
+                .location
+                .error(format!("the name '{}' is already defined", name)));
+        }
+        Ok(())
+    }
+
+    /// Returns the value of a number, constant, label or `{ ... }`
+    /// expression, or `None` for a name that is not defined yet.
+    fn value(&mut self, token: &Token) -> OktoResult<Option<f64>> {
+        if token.text == "{" {
+            return self.calc(token).map(Some);
+        }
+        if let Some(value) = parse_number(&token.text) {
+            return Ok(Some(value));
+        }
+        if let Some(&value) = self.constants.get(&token.text) {
+            return Ok(Some(value));
+        }
+        if let Some(address) = self.image.label(&token.text) {
+            return Ok(Some(address as f64));
+        }
+        if is_name(&token.text) && !self.is_keyword(&token.text) {
+            return Ok(None);
+        }
+        Err(token
+            .location
+            .error(format!("expected a value, found '{}'", token.text)))
+    }
+
+    /// Returns the value of a token that must already be defined, checked
+    /// against the range of the given operand kind.
+    fn defined_value(&mut self, token: &Token, kind: OperandKind) -> OktoResult<u16> {
+        match self.value(token)? {
+            Some(value) => check_range(value, kind, &token.location),
+            None => Err(token
+                .location
+                .error(format!("undefined name '{}'", token.text))),
+        }
+    }
+
+    fn register_operand(&self, token: &Token) -> OktoResult<Register> {
+        self.register(&token.text).ok_or_else(|| {
+            token
+                .location
+                .error(format!("expected a register, found '{}'", token.text))
+        })
+    }
+
+    fn expect(&mut self, after: &Token, word: &str) -> OktoResult<Token> {
+        let token = self.next(after)?;
+        if token.text != word {
+            return Err(token
+                .location
+                .error(format!("expected '{}', found '{}'", word, token.text)));
+        }
+        Ok(token)
+    }
+
+    fn statement(&mut self, token: Token) -> OktoResult<()> {
+        match token.text.as_str() {
+            ":" => {
+                let name = self.next(&token)?;
+                self.check_name(&name)?;
+                if name.text == "main"
+                    && self.main_jump
+                    && self.image.here() == (DEFAULT_PC_ADDRESS + INSTRUCTION_BYTES) as u32
+                {
+                    self.main_jump = false;
+                    self.image
+                        .set_here(DEFAULT_PC_ADDRESS as u32, &name.location)?;
+                }
+                self.image.define(&name.text, &name.location)
+            }
+            ":const" => {
+                let name = self.next(&token)?;
+                self.check_name(&name)?;
+                let value = self.next(&name)?;
+                let value = match self.value(&value)? {
+                    Some(value) => value,
+                    None => {
+                        return Err(value
+                            .location
+                            .error(format!("undefined name '{}'", value.text)))
+                    }
+                };
+                self.constants.insert(name.text, value);
+                Ok(())
+            }
+            ":alias" => {
+                let name = self.next(&token)?;
+                if !self.aliases.contains_key(&name.text) {
+                    self.check_name(&name)?;
+                }
+                let register = self.next(&name)?;
+                let register = self.register_operand(&register)?;
+                self.aliases.insert(name.text, register);
+                Ok(())
+            }
+            ":calc" => {
+                let name = self.next(&token)?;
+                if !self.constants.contains_key(&name.text) {
+                    self.check_name(&name)?;
+                }
+                let open = self.expect(&name, "{")?;
+                let value = self.calc(&open)?;
+                self.constants.insert(name.text, value);
+                Ok(())
+            }
+            ":macro" => self.define_macro(&token),
+            ":org" => {
+                let address = self.next(&token)?;
+                let value = self.defined_value(&address, OperandKind::LongAddress)?;
+                self.image.set_here(value as u32, &address.location)
+            }
+            ":byte" => {
+                let value = self.next(&token)?;
+                let byte = self.defined_value(&value, OperandKind::Byte)?;
+                self.image.emit(&[byte as u8], &value.location)
+            }
+            ":unpack" => {
+                let nibble = self.next(&token)?;
+                let nibble = self.defined_value(&nibble, OperandKind::Nibble)?;
+                let label = self.next(&token)?;
+                let address = self.defined_value(&label, OperandKind::Address)?;
+                let hi = self.aliases["unpack-hi"];
+                let lo = self.aliases["unpack-lo"];
+                self.image.emit_operation(
+                    &Operation::LoadImm(hi, (nibble << 4 | address >> 8) as u8),
+                    &label.location,
+                )?;
+                self.image
+                    .emit_operation(&Operation::LoadImm(lo, address as u8), &label.location)
+            }
+            ":next" => {
+                let name = self.next(&token)?;
+                self.check_name(&name)?;
+                let address = (self.image.here() + 1) as Address;
+                self.image.define_at(&name.text, address, &name.location)
+            }
+            ";" => self.image.emit_operation(&Operation::Ret, &token.location),
+            "loop" => {
+                self.loops.push(Loop {
+                    start: self.image.here() as Address,
+                    exits: Vec::new(),
+                    location: token.location,
+                });
+                Ok(())
+            }
+            "while" => {
+                if self.loops.is_empty() {
+                    return Err(token.location.error("'while' is not inside a loop"));
+                }
+                let condition = self.condition(&token)?;
+                self.skip_unless(condition.negate(), &token.location)?;
+                let exit = self.jump(&token.location)?;
+                if let Some(open) = self.loops.last_mut() {
+                    open.exits.push(exit);
+                }
+                Ok(())
+            }
+            "again" => {
+                let open = self
+                    .loops
+                    .pop()
+                    .ok_or_else(|| token.location.error("'again' without a 'loop'"))?;
+                let jump = self.jump(&token.location)?;
+                self.image
+                    .patch(jump, Field::Address, open.start as u32, &token.location)?;
+                let here = self.image.here();
+                for exit in open.exits {
+                    self.image
+                        .patch(exit, Field::Address, here, &token.location)?;
+                }
+                Ok(())
+            }
+            "if" => {
+                let condition = self.condition(&token)?;
+                let word = self.next(&token)?;
+                match word.text.as_str() {
+                    "then" => self.skip_unless(condition, &token.location),
+                    "begin" => {
+                        self.skip_unless(condition.negate(), &token.location)?;
+                        let jump = self.jump(&token.location)?;
+                        self.branches.push(Branch {
+                            jump,
+                            has_else: false,
+                            location: word.location,
+                        });
+                        Ok(())
+                    }
+                    _ => Err(word
+                        .location
+                        .error(format!("expected 'then' or 'begin', found '{}'", word.text))),
+                }
+            }
+            "else" => {
+                let open = match self.branches.pop() {
+                    Some(ref open) if !open.has_else => open.clone(),
+                    _ => return Err(token.location.error("'else' without an 'if ... begin'")),
+                };
+                let jump = self.jump(&token.location)?;
+                let here = self.image.here();
+                self.image
+                    .patch(open.jump, Field::Address, here, &token.location)?;
+                self.branches.push(Branch {
+                    jump,
+                    has_else: true,
+                    location: token.location,
+                });
+                Ok(())
+            }
+            "end" => {
+                let open = self
+                    .branches
+                    .pop()
+                    .ok_or_else(|| token.location.error("'end' without an 'if ... begin'"))?;
+                let here = self.image.here();
+                self.image
+                    .patch(open.jump, Field::Address, here, &token.location)
+            }
+            _ => self.other_statement(token),
+        }
+    }
+
+    /// Assemble a macro expansion, instruction, byte of data or call.
+    fn other_statement(&mut self, token: Token) -> OktoResult<()> {
+        if let Some(definition) = self.macros.get(&token.text).cloned() {
+            return self.expand(&token, &definition);
+        }
+
+        let template = self
+            .templates
+            .iter()
+            .position(|template| self.matches(&token, &template.parts));
+        if let Some(index) = template {
+            let template = self.templates[index].clone();
+            return self.instruction(token, &template);
+        }
+
+        // Octo also lets `vx -= nn` add the negated byte
+        if self.register(&token.text).is_some()
+            && self.peek(0).is_some_and(|word| word.text == "-=")
+        {
+            let register = self.register_operand(&token)?;
+            let operator = self.next(&token)?;
+            let value = self.next(&operator)?;
+            let byte = self.defined_value(&value, OperandKind::Byte)?;
+            let operation = Operation::AddImm(register, (byte as u8).wrapping_neg());
+            return self.image.emit_operation(&operation, &token.location);
+        }
+
+        if let Some(value) = parse_number(&token.text) {
+            let byte = check_range(value, OperandKind::Byte, &token.location)?;
+            return self.image.emit(&[byte as u8], &token.location);
+        }
+
+        if token.text.starts_with(':') && token.text.len() > 1 {
+            return Err(token
+                .location
+                .error(format!("unknown directive '{}'", token.text)));
+        }
+        if !is_name(&token.text) || self.is_keyword(&token.text) {
+            return Err(token.location.error(format!("unexpected '{}'", token.text)));
+        }
+        if self.register(&token.text).is_some() {
+            return Err(token
+                .location
+                .error(format!("invalid statement starting with '{}'", token.text)));
+        }
+
+        let address = self.image.here() as Address;
+        self.image
+            .emit_operation(&Operation::Call(0), &token.location)?;
+        self.image
+            .refer(address, Field::Address, &token.text, &token.location);
+        Ok(())
+    }
+
+    /// Returns true if the mnemonic starting with the given token matches
+    /// the template.
+    fn matches(&self, first: &Token, parts: &[Part]) -> bool {
+        let mut offset = 0;
+
+        for (index, part) in parts.iter().enumerate() {
+            let token = if index == 0 {
+                first
+            } else {
+                match self.peek(offset) {
+                    Some(token) => token,
+                    None => return false,
+                }
+            };
+            let matched = match *part {
+                Part::Word(word) => token.text == word,
+                Part::Operand(operand) => {
+                    let is_register = self.register(&token.text).is_some();
+                    (operand.kind == OperandKind::Register) == is_register && token.text != "}"
+                }
+            };
+            if !matched {
+                return false;
+            }
+            if index > 0 {
+                offset = self.skip_value(offset);
+            }
+        }
+
+        true
+    }
+
+    /// Assemble an instruction whose mnemonic matched the template.
+    fn instruction(&mut self, first: Token, template: &Template) -> OktoResult<()> {
+        let mut values = Vec::new();
+        let mut reference = None;
+
+        for (index, part) in template.parts.iter().enumerate() {
+            let token = if index == 0 {
+                first.clone()
+            } else {
+                self.next(&first)?
+            };
+            let operand = match *part {
+                Part::Operand(operand) => operand,
+                Part::Word(_) => continue,
+            };
+            let value = match operand.kind {
+                OperandKind::Register => self.register_operand(&token)? as u16,
+                OperandKind::Address | OperandKind::LongAddress => match self.value(&token)? {
+                    Some(value) => check_range(value, operand.kind, &token.location)?,
+                    None => {
+                        reference = Some((operand.kind, token));
+                        0
+                    }
+                },
+                kind => self.defined_value(&token, kind)?,
+            };
+            values.push(value);
+        }
+
+        let operation = template.info.encode(&values).ok_or_else(|| {
+            first.location.error(format!(
+                "operands cannot be encoded as {}",
+                template
+                    .info
+                    .template(Syntax::Octo)
+                    .unwrap_or(template.info.name)
+            ))
+        })?;
+        let address = self.image.here() as Address;
+        self.image.emit_operation(&operation, &first.location)?;
+
+        match reference {
+            Some((OperandKind::LongAddress, token)) => self.image.refer(
+                address + INSTRUCTION_BYTES,
+                Field::LongAddress,
+                &token.text,
+                &token.location,
+            ),
+            Some((_, token)) => {
+                self.image
+                    .refer(address, Field::Address, &token.text, &token.location)
+            }
+            None => {}
+        }
+        Ok(())
+    }
+
+    /// Write a jump whose target is filled in later, returning its address.
+    fn jump(&mut self, location: &Location) -> OktoResult<Address> {
+        let address = self.image.here() as Address;
+        self.image.emit_operation(&Operation::Jump(0), location)?;
+        Ok(address)
+    }
+
+    /// Read the condition of an `if` or `while`.
+    fn condition(&mut self, keyword: &Token) -> OktoResult<Condition> {
+        let register = self.next(keyword)?;
+        let register = self.register_operand(&register)?;
+        let word = self.next(keyword)?;
+        let comparison = Comparison::from_word(&word.text).ok_or_else(|| {
+            word.location
+                .error(format!("expected a comparison, found '{}'", word.text))
+        })?;
+
+        let term = match comparison {
+            Comparison::Key | Comparison::NotKey => Term::Register(register),
+            _ => {
+                let token = self.next(&word)?;
+                match self.register(&token.text) {
+                    Some(other) => Term::Register(other),
+                    None => Term::Byte(self.defined_value(&token, OperandKind::Byte)? as u8),
+                }
+            }
+        };
+
+        Ok(Condition {
+            register,
+            comparison,
+            term,
+        })
+    }
+
+    /// Write instructions that skip the next instruction unless the
+    /// condition holds.
+    fn skip_unless(&mut self, condition: Condition, location: &Location) -> OktoResult<()> {
+        let x = condition.register;
+        let skip = match (condition.comparison, condition.term) {
+            (Comparison::Equal, Term::Register(y)) => Operation::SkipNeqReg(x, y),
+            (Comparison::Equal, Term::Byte(nn)) => Operation::SkipNeqImm(x, nn),
+            (Comparison::NotEqual, Term::Register(y)) => Operation::SkipEqReg(x, y),
+            (Comparison::NotEqual, Term::Byte(nn)) => Operation::SkipEqImm(x, nn),
+            (Comparison::Key, _) => Operation::SkipNotKey(x),
+            (Comparison::NotKey, _) => Operation::SkipKey(x),
+            (comparison, term) => {
+                // Subtracting sets vf to 1 when there is no borrow, that is
+                // when the first value is greater than or equal to the second
+                let flag = self.aliases["compare-temp"];
+                let (greater, lesser) = match comparison {
+                    Comparison::Less | Comparison::GreaterOrEqual => (Term::Register(x), term),
+                    _ => (term, Term::Register(x)),
+                };
+                let operations = match (greater, lesser) {
+                    (Term::Register(p), Term::Register(q)) => {
+                        [Operation::LoadReg(flag, q), Operation::SubNeg(flag, p)]
+                    }
+                    (Term::Register(p), Term::Byte(q)) => {
+                        [Operation::LoadImm(flag, q), Operation::SubNeg(flag, p)]
+                    }
+                    (Term::Byte(p), Term::Register(q)) => {
+                        [Operation::LoadImm(flag, p), Operation::Sub(flag, q)]
+                    }
+                    (Term::Byte(_), Term::Byte(_)) => unreachable!(),
+                };
+                for operation in operations.iter() {
+                    self.image.emit_operation(operation, location)?;
+                }
+                match comparison {
+                    Comparison::Less | Comparison::Greater => Operation::SkipNeqImm(flag, 0),
+                    _ => Operation::SkipNeqImm(flag, 1),
+                }
+            }
+        };
+
+        self.image.emit_operation(&skip, location)
+    }
+
+    /// Read the parameters and body of a `:macro`.
+    fn define_macro(&mut self, keyword: &Token) -> OktoResult<()> {
+        let name = self.next(keyword)?;
+        self.check_name(&name)?;
+
+        let mut parameters = Vec::new();
+        loop {
+            let token = self.next(&name)?;
+            if token.text == "{" {
+                break;
+            }
+            parameters.push(token.text);
+        }
+
+        let mut body = Vec::new();
+        let mut depth = 1;
+        loop {
+            let token = self
+                .tokens
+                .pop()
+                .ok_or_else(|| name.location.error("macro body is missing its '}'"))?;
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" => depth -= 1,
+                _ => {}
+            }
+            if depth == 0 {
+                break;
+            }
+            body.push(token);
+        }
+
+        self.macros.insert(name.text, Macro { parameters, body });
+        Ok(())
+    }
+
+    /// Replace a use of a macro with its body.
+    fn expand(&mut self, name: &Token, definition: &Macro) -> OktoResult<()> {
+        self.expansions += 1;
+        if self.expansions > MAX_MACRO_EXPANSIONS {
+            return Err(name.location.error("too many macro expansions"));
+        }
+
+        let mut arguments = Vec::new();
+        for _ in &definition.parameters {
+            arguments.push(self.next(name)?);
+        }
+
+        for token in definition.body.iter().rev() {
+            let position = definition
+                .parameters
+                .iter()
+                .position(|parameter| *parameter == token.text);
+            self.tokens.push(match position {
+                Some(index) => arguments[index].clone(),
+                None => token.clone(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Evaluate the `:calc` expression after an opening brace, up to its
+    /// closing brace.
+    fn calc(&mut self, open: &Token) -> OktoResult<f64> {
+        let mut body = Vec::new();
+        let mut depth = 1;
+        loop {
+            let token = self
+                .tokens
+                .pop()
+                .ok_or_else(|| open.location.error("expression is missing its '}'"))?;
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" => depth -= 1,
+                _ => {}
+            }
+            if depth == 0 {
+                break;
+            }
+            body.push(token);
+        }
+
+        let mut calc = Calc {
+            assembler: self,
+            tokens: &body,
+            position: 0,
+            open,
+        };
+        let value = calc.expression()?;
+        match body.get(calc.position) {
+            Some(token) => Err(token
+                .location
+                .error(format!("unexpected '{}' in expression", token.text))),
+            None => Ok(value),
+        }
+    }
+}
+
+/// Check a calculated value fits in an operand.
+fn check_range(value: f64, kind: OperandKind, location: &Location) -> OktoResult<u16> {
+    if !value.is_finite() {
+        return Err(location.error(format!("{} is not a number", value)));
+    }
+    assembler::check_operand(value as i64, kind, location)
+}
+
+/// Right to left evaluation of a `:calc` expression.
+struct Calc<'a> {
+    assembler: &'a Assembler,
+    tokens: &'a [Token],
+    position: usize,
+    /// Opening brace, where errors at the end of the expression point
+    open: &'a Token,
+}
+
+impl<'a> Calc<'a> {
+    fn next(&mut self) -> OktoResult<&'a Token> {
+        let token = self
+            .tokens
+            .get(self.position)
+            .ok_or_else(|| self.open.location.error("unexpected end of expression"))?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn expression(&mut self) -> OktoResult<f64> {
+        let lhs = self.term()?;
+        let operator = match self.tokens.get(self.position) {
+            Some(token) if BINARY_OPERATORS.contains(&token.text.as_str()) => token,
+            _ => return Ok(lhs),
+        };
+        self.position += 1;
+        let rhs = self.expression()?;
+
+        let int = |value: f64| value as i64;
+        let bool = |value: bool| if value { 1.0 } else { 0.0 };
+        Ok(match operator.text.as_str() {
+            "+" => lhs + rhs,
+            "-" => lhs - rhs,
+            "*" => lhs * rhs,
+            "/" => lhs / rhs,
+            "%" => lhs % rhs,
+            "&" => (int(lhs) & int(rhs)) as f64,
+            "|" => (int(lhs) | int(rhs)) as f64,
+            "^" => (int(lhs) ^ int(rhs)) as f64,
+            "<<" => int(lhs).checked_shl(int(rhs) as u32).unwrap_or(0) as f64,
+            ">>" => int(lhs).checked_shr(int(rhs) as u32).unwrap_or(0) as f64,
+            "pow" => lhs.powf(rhs),
+            "min" => lhs.min(rhs),
+            "max" => lhs.max(rhs),
+            "<" => bool(lhs < rhs),
+            "<=" => bool(lhs <= rhs),
+            ">" => bool(lhs > rhs),
+            ">=" => bool(lhs >= rhs),
+            "==" => bool(lhs == rhs),
+            _ => bool(lhs != rhs),
+        })
+    }
+
+    fn term(&mut self) -> OktoResult<f64> {
+        let token = self.next()?;
+        let text = token.text.as_str();
+
+        if text == "(" {
+            let value = self.expression()?;
+            let close = self.next()?;
+            if close.text != ")" {
+                return Err(close
+                    .location
+                    .error(format!("expected ')', found '{}'", close.text)));
+            }
+            return Ok(value);
+        }
+        if let Some(value) = parse_number(text) {
+            return Ok(value);
+        }
+        if UNARY_OPERATORS.contains(&text) {
+            let value = self.term()?;
+            return Ok(match text {
+                "-" => -value,
+                "~" => !(value as i64) as f64,
+                "!" => (value == 0.0) as i64 as f64,
+                "sin" => value.sin(),
+                "cos" => value.cos(),
+                "tan" => value.tan(),
+                "exp" => value.exp(),
+                "log" => value.ln(),
+                "abs" => value.abs(),
+                "sqrt" => value.sqrt(),
+                "sign" => {
+                    if value == 0.0 {
+                        0.0
+                    } else {
+                        value.signum()
+                    }
+                }
+                "ceil" => value.ceil(),
+                "floor" => value.floor(),
+                _ => f64::from(self.assembler.image.read(value as u32)),
+            });
+        }
+
+        match text {
+            "PI" => Ok(consts::PI),
+            "E" => Ok(consts::E),
+            "HERE" => Ok(self.assembler.image.here() as f64),
+            _ => {
+                if let Some(&value) = self.assembler.constants.get(text) {
+                    return Ok(value);
+                }
+                if let Some(address) = self.assembler.image.label(text) {
+                    return Ok(address as f64);
+                }
+                Err(token.location.error(format!("undefined name '{}'", text)))
+            }
+        }
+    }
+}
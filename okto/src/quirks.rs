@@ -0,0 +1,136 @@
+//! Behavioural differences between Chip8 interpreter implementations.
+//!
+//! Several Chip8 opcodes were implemented differently by the original COSMAC
+//! VIP interpreter, CHIP-48 on the HP48 calculators, SuperChip and XO-CHIP.
+//! ROMs written for one platform often rely on the behaviour of that platform,
+//! so the machine consults a `Quirks` profile whenever it executes one of the
+//! ambiguous operations.
+
+/// The names accepted by `Quirks::from_profile_name`.
+pub const PROFILE_NAMES: [&str; 6] = ["okto", "vip", "chip48", "schip10", "schip11", "xochip"];
+
+/// How the index register is changed by bulk register loads and stores
+/// (`FX55` and `FX65`).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum IndexIncrement {
+    /// I is left untouched.
+    Unchanged,
+    /// I is incremented by X.
+    ByX,
+    /// I is incremented by X + 1, leaving it just past the last register.
+    ByXPlusOne,
+}
+
+/// Set of selectable behaviours for ambiguous opcodes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quirks {
+    /// `8XY6` and `8XYE` shift VY and store the result in VX instead of
+    /// shifting VX in place.
+    pub shift_uses_vy: bool,
+    /// How `FX55` and `FX65` change the index register.
+    pub index_increment: IndexIncrement,
+    /// `BNNN` jumps to NNN plus VX, where X is the high nibble of NNN, instead
+    /// of NNN plus V0.
+    pub jump_uses_vx: bool,
+    /// Sprites drawn past the edge of the screen are clipped instead of
+    /// wrapping around to the opposite edge.
+    pub clip_sprites: bool,
+    /// `8XY1`, `8XY2` and `8XY3` reset VF to zero.
+    pub logic_resets_vf: bool,
+    /// `FX0A` completes when the pressed key is released rather than as soon
+    /// as it is pressed.
+    pub wait_key_on_release: bool,
+}
+
+impl Quirks {
+    /// The behaviour the okto interpreter has always had. This matches
+    /// SuperChip 1.1 except that sprites wrap around the screen edges and
+    /// `BNNN` always uses V0.
+    pub fn okto() -> Self {
+        Self {
+            shift_uses_vy: false,
+            index_increment: IndexIncrement::Unchanged,
+            jump_uses_vx: false,
+            clip_sprites: false,
+            logic_resets_vf: false,
+            wait_key_on_release: false,
+        }
+    }
+
+    /// The original CHIP-8 interpreter on the COSMAC VIP.
+    pub fn cosmac_vip() -> Self {
+        Self {
+            shift_uses_vy: true,
+            index_increment: IndexIncrement::ByXPlusOne,
+            jump_uses_vx: false,
+            clip_sprites: true,
+            logic_resets_vf: true,
+            wait_key_on_release: true,
+        }
+    }
+
+    /// CHIP-48 for the HP48 graphing calculators.
+    pub fn chip48() -> Self {
+        Self {
+            shift_uses_vy: false,
+            index_increment: IndexIncrement::ByX,
+            jump_uses_vx: true,
+            clip_sprites: true,
+            logic_resets_vf: false,
+            wait_key_on_release: false,
+        }
+    }
+
+    /// SuperChip 1.0, which inherits its quirks from CHIP-48.
+    pub fn schip10() -> Self {
+        Self::chip48()
+    }
+
+    /// SuperChip 1.1, which no longer changes I during bulk loads and stores.
+    pub fn schip11() -> Self {
+        Self {
+            index_increment: IndexIncrement::Unchanged,
+            ..Self::chip48()
+        }
+    }
+
+    /// XO-CHIP as implemented by Octo.
+    pub fn xochip() -> Self {
+        Self {
+            shift_uses_vy: true,
+            index_increment: IndexIncrement::ByXPlusOne,
+            jump_uses_vx: false,
+            clip_sprites: false,
+            logic_resets_vf: false,
+            wait_key_on_release: false,
+        }
+    }
+
+    /// Returns the preset with the given name, if there is one.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate okto;
+    /// # use okto::quirks::Quirks;
+    /// assert_eq!(Some(Quirks::schip11()), Quirks::from_profile_name("schip11"));
+    /// assert_eq!(None, Quirks::from_profile_name("megachip"));
+    /// ```
+    pub fn from_profile_name(name: &str) -> Option<Self> {
+        match name {
+            "okto" => Some(Self::okto()),
+            "vip" => Some(Self::cosmac_vip()),
+            "chip48" => Some(Self::chip48()),
+            "schip10" => Some(Self::schip10()),
+            "schip11" => Some(Self::schip11()),
+            "xochip" => Some(Self::xochip()),
+            _ => None,
+        }
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Self::okto()
+    }
+}
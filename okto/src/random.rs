@@ -0,0 +1,163 @@
+//! Random number sources used by the `RandAndImm` (CXNN) operation.
+//!
+//! The machine owns its random number source so that runs can be reproduced
+//! from a seed, and so that the source's state can be included in save
+//! states. Tests can inject a fixed sequence of values with `SequenceRandom`.
+use super::{OktoError, OktoErrorKind, OktoResult};
+
+use rand::random;
+
+/// Source of random bytes for the machine.
+pub trait RandomSource: Send {
+    /// Returns the next random byte.
+    fn next_byte(&mut self) -> u8;
+
+    /// Serialize the internal state of the source so that it can be stored in
+    /// a save state.
+    fn save_state(&self) -> Vec<u8>;
+
+    /// Restore the internal state of the source from data produced by
+    /// `save_state`.
+    fn load_state(&mut self, state: &[u8]) -> OktoResult<()>;
+}
+
+/// Seedable xorshift pseudo-random number generator. The sequence of values
+/// depends only on the seed, so two machines seeded alike behave identically.
+pub struct XorShiftRandom {
+    state: u32,
+}
+
+impl XorShiftRandom {
+    /// Seed used in place of zero, which would make xorshift produce zeros
+    /// forever.
+    const ZERO_SEED_REPLACEMENT: u32 = 0x2545_F491;
+
+    /// Create a generator from the given seed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate okto;
+    /// # use okto::random::{RandomSource, XorShiftRandom};
+    /// let mut first = XorShiftRandom::new(1234);
+    /// let mut second = XorShiftRandom::new(1234);
+    /// for _ in 0..100 {
+    ///     assert_eq!(first.next_byte(), second.next_byte());
+    /// }
+    /// ```
+    pub fn new(seed: u32) -> Self {
+        Self {
+            state: if seed == 0 {
+                Self::ZERO_SEED_REPLACEMENT
+            } else {
+                seed
+            },
+        }
+    }
+
+    /// Create a generator seeded from the thread-local entropy source.
+    pub fn from_entropy() -> Self {
+        Self::new(random())
+    }
+}
+
+impl RandomSource for XorShiftRandom {
+    fn next_byte(&mut self) -> u8 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 17;
+        self.state ^= self.state << 5;
+        (self.state >> 24) as u8
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        vec![
+            (self.state >> 24) as u8,
+            (self.state >> 16) as u8,
+            (self.state >> 8) as u8,
+            self.state as u8,
+        ]
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> OktoResult<()> {
+        if state.len() != 4 {
+            return Err(OktoError::new(OktoErrorKind::InvalidSaveState));
+        }
+
+        let value = state
+            .iter()
+            .fold(0u32, |value, &byte| (value << 8) | byte as u32);
+        if value == 0 {
+            return Err(OktoError::new(OktoErrorKind::InvalidSaveState));
+        }
+
+        self.state = value;
+        Ok(())
+    }
+}
+
+/// Random source that repeats a fixed sequence of values, for tests and bug
+/// reproductions that need to control exactly what CXNN produces.
+pub struct SequenceRandom {
+    values: Vec<u8>,
+    position: usize,
+}
+
+impl SequenceRandom {
+    /// Create a source producing the given values in order, starting over
+    /// once they have all been used. An empty sequence always produces zero.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate okto;
+    /// # use okto::random::{RandomSource, SequenceRandom};
+    /// let mut random = SequenceRandom::new(vec![0x12, 0x34]);
+    /// assert_eq!(0x12, random.next_byte());
+    /// assert_eq!(0x34, random.next_byte());
+    /// assert_eq!(0x12, random.next_byte());
+    /// ```
+    pub fn new(values: Vec<u8>) -> Self {
+        Self {
+            values,
+            position: 0,
+        }
+    }
+}
+
+impl RandomSource for SequenceRandom {
+    fn next_byte(&mut self) -> u8 {
+        if self.values.is_empty() {
+            return 0;
+        }
+
+        let value = self.values[self.position];
+        self.position = (self.position + 1) % self.values.len();
+        value
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        let position = self.position as u32;
+        vec![
+            (position >> 24) as u8,
+            (position >> 16) as u8,
+            (position >> 8) as u8,
+            position as u8,
+        ]
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> OktoResult<()> {
+        if state.len() != 4 {
+            return Err(OktoError::new(OktoErrorKind::InvalidSaveState));
+        }
+
+        let position = state
+            .iter()
+            .fold(0usize, |value, &byte| (value << 8) | byte as usize);
+        if position != 0 && position >= self.values.len() {
+            return Err(OktoError::new(OktoErrorKind::InvalidSaveState));
+        }
+
+        self.position = position;
+        Ok(())
+    }
+}
@@ -0,0 +1,257 @@
+//! Ring buffer of machine snapshots used to step the emulator back in time.
+//!
+//! Snapshots are taken with `Machine::save_state`. Only the newest snapshot is
+//! kept in full; every older snapshot is stored as a run-length encoded XOR
+//! delta against the snapshot that followed it. Consecutive frames usually
+//! differ in a handful of registers and display rows, so each delta is a tiny
+//! fraction of the size of a full save state.
+use super::machine::Machine;
+use super::{OktoError, OktoErrorKind, OktoResult};
+
+use std::collections::VecDeque;
+
+/// Default number of snapshots retained by a rewind buffer.
+pub const DEFAULT_CAPACITY: usize = 600;
+/// Default number of frames between snapshots.
+pub const DEFAULT_FRAMES_PER_SNAPSHOT: u32 = 1;
+
+/// Fixed-capacity history of delta-compressed machine snapshots.
+pub struct RewindBuffer {
+    /// Maximum number of snapshots retained before the oldest are discarded
+    capacity: usize,
+    /// Number of frames between snapshots
+    frames_per_snapshot: u32,
+    /// Number of frames recorded since the last snapshot was taken
+    frames_since_snapshot: u32,
+    /// Newest snapshot, stored in full
+    latest: Option<Vec<u8>>,
+    /// Deltas for older snapshots. The last delta turns the newest snapshot
+    /// into the one before it, and so on back to the oldest snapshot.
+    deltas: VecDeque<Vec<u8>>,
+}
+
+impl RewindBuffer {
+    /// Create a rewind buffer retaining at most `capacity` snapshots, taken
+    /// every `frames_per_snapshot` frames.
+    pub fn new(capacity: usize, frames_per_snapshot: u32) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            frames_per_snapshot: frames_per_snapshot.max(1),
+            frames_since_snapshot: 0,
+            latest: None,
+            deltas: VecDeque::new(),
+        }
+    }
+
+    /// Returns the number of snapshots currently available.
+    pub fn len(&self) -> usize {
+        match self.latest {
+            Some(_) => self.deltas.len() + 1,
+            None => 0,
+        }
+    }
+
+    /// Returns true if no snapshots have been recorded.
+    pub fn is_empty(&self) -> bool {
+        self.latest.is_none()
+    }
+
+    /// Returns the number of frames between snapshots.
+    pub fn frames_per_snapshot(&self) -> u32 {
+        self.frames_per_snapshot
+    }
+
+    /// Returns the approximate number of bytes used to store the snapshots.
+    pub fn memory_usage(&self) -> usize {
+        let latest = self.latest.as_ref().map_or(0, |state| state.len());
+        latest + self.deltas.iter().map(|delta| delta.len()).sum::<usize>()
+    }
+
+    /// Discard every snapshot.
+    pub fn clear(&mut self) {
+        self.latest = None;
+        self.deltas.clear();
+        self.frames_since_snapshot = 0;
+    }
+
+    /// Notify the buffer that a frame has been emulated. A snapshot of the
+    /// machine is taken every `frames_per_snapshot` frames.
+    pub fn record_frame(&mut self, machine: &Machine) {
+        self.frames_since_snapshot += 1;
+        if self.frames_since_snapshot >= self.frames_per_snapshot {
+            self.push(machine);
+        }
+    }
+
+    /// Take a snapshot of the machine immediately.
+    pub fn push(&mut self, machine: &Machine) {
+        let state = machine.save_state();
+
+        if let Some(previous) = self.latest.take() {
+            self.deltas.push_back(encode_delta(&state, &previous));
+            if self.deltas.len() >= self.capacity {
+                self.deltas.pop_front();
+            }
+        }
+
+        self.latest = Some(state);
+        self.frames_since_snapshot = 0;
+    }
+
+    /// Rewind the machine by at least the given number of frames and return
+    /// the number of frames actually rewound. Frames recorded since the last
+    /// snapshot are undone by restoring that snapshot, and older frames are
+    /// undone a whole snapshot at a time, so the result is rounded up to the
+    /// snapshot granularity. Snapshots newer than the restored one are
+    /// discarded, so execution resumes from the restored state and rewinding
+    /// again continues further back in time. When fewer frames have been
+    /// recorded the oldest snapshot is restored. If the snapshot cannot be
+    /// restored the machine and the buffer are left unchanged.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate okto;
+    /// # use okto::cpu::Operation;
+    /// # use okto::machine::Machine;
+    /// # use okto::rewind::RewindBuffer;
+    /// let mut machine = Machine::new();
+    /// let mut rewind = RewindBuffer::new(10, 1);
+    ///
+    /// for value in 0..5 {
+    ///     machine.execute(Operation::LoadImm(0x0, value)).unwrap();
+    ///     rewind.record_frame(&machine);
+    /// }
+    ///
+    /// assert_eq!(2, rewind.rewind(&mut machine, 2).unwrap());
+    /// assert_eq!(2, machine.cpu.v[0x0]);
+    /// assert_eq!(3, rewind.len());
+    /// ```
+    pub fn rewind(&mut self, machine: &mut Machine, frames: u32) -> OktoResult<u32> {
+        let latest = match self.latest {
+            Some(ref state) => state,
+            None => return Ok(0),
+        };
+
+        let older_frames = frames.saturating_sub(self.frames_since_snapshot);
+        let snapshots =
+            older_frames.saturating_add(self.frames_per_snapshot - 1) / self.frames_per_snapshot;
+        let snapshots = (snapshots as usize).min(self.deltas.len());
+
+        // Rebuild the snapshot on a copy so that a corrupt delta leaves every
+        // snapshot in place
+        let mut state = latest.clone();
+        for delta in self.deltas.iter().rev().take(snapshots) {
+            state = apply_delta(&state, delta)?;
+        }
+        machine.load_state(&state)?;
+
+        let remaining = self.deltas.len() - snapshots;
+        self.deltas.truncate(remaining);
+        self.latest = Some(state);
+
+        let rewound = self.frames_since_snapshot + snapshots as u32 * self.frames_per_snapshot;
+        self.frames_since_snapshot = 0;
+        Ok(rewound)
+    }
+}
+
+impl Default for RewindBuffer {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY, DEFAULT_FRAMES_PER_SNAPSHOT)
+    }
+}
+
+/// Append a variable-length encoded integer to the given buffer.
+fn write_varint(buffer: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        buffer.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buffer.push(value as u8);
+}
+
+/// Read a variable-length encoded integer starting at the given position,
+/// advancing the position past it.
+fn read_varint(buffer: &[u8], position: &mut usize) -> OktoResult<usize> {
+    let mut value = 0;
+    let mut shift = 0;
+
+    loop {
+        let byte = match buffer.get(*position) {
+            Some(&byte) if shift < 64 => byte,
+            _ => return Err(OktoError::new(OktoErrorKind::InvalidSaveState)),
+        };
+        *position += 1;
+
+        value |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+        shift += 7;
+    }
+}
+
+/// Encode the difference between two snapshots. The delta stores the length
+/// of the target followed by alternating runs of unchanged bytes and XOR
+/// literals, which `apply_delta` uses to rebuild the target from the source.
+fn encode_delta(source: &[u8], target: &[u8]) -> Vec<u8> {
+    let length = source.len().max(target.len());
+    let xor_at = |index: usize| {
+        source.get(index).cloned().unwrap_or(0) ^ target.get(index).cloned().unwrap_or(0)
+    };
+
+    let mut delta = Vec::new();
+    write_varint(&mut delta, target.len());
+
+    let mut index = 0;
+    while index < length {
+        let run_start = index;
+        while index < length && xor_at(index) == 0 {
+            index += 1;
+        }
+        write_varint(&mut delta, index - run_start);
+
+        let literal_start = index;
+        while index < length && xor_at(index) != 0 {
+            index += 1;
+        }
+        write_varint(&mut delta, index - literal_start);
+        delta.extend((literal_start..index).map(&xor_at));
+    }
+
+    delta
+}
+
+/// Rebuild the target snapshot from a source snapshot and the delta produced
+/// by `encode_delta`.
+fn apply_delta(source: &[u8], delta: &[u8]) -> OktoResult<Vec<u8>> {
+    let mut position = 0;
+    let target_length = read_varint(delta, &mut position)?;
+
+    let mut target = source.to_vec();
+    target.resize(target_length.max(source.len()), 0);
+
+    let mut index = 0;
+    while position < delta.len() {
+        index += read_varint(delta, &mut position)?;
+        let literal_length = read_varint(delta, &mut position)?;
+
+        if index + literal_length > target.len() || position + literal_length > delta.len() {
+            return Err(OktoError::new(OktoErrorKind::InvalidSaveState));
+        }
+
+        for (byte, xor) in target[index..index + literal_length]
+            .iter_mut()
+            .zip(delta[position..position + literal_length].iter())
+        {
+            *byte ^= xor;
+        }
+
+        index += literal_length;
+        position += literal_length;
+    }
+
+    target.truncate(target_length);
+    Ok(target)
+}
@@ -0,0 +1,194 @@
+//! Binary save-state format for the assembled machine.
+//!
+//! A save state starts with the 8 byte magic header `OKTOSAVE` followed by a
+//! big-endian 16-bit format version. The remainder of the file is the machine
+//! state in the order below, with all multi-byte values stored big-endian:
+//!
+//! | Field              | Size (bytes)   | Notes                               |
+//! |--------------------|----------------|-------------------------------------|
+//! | V0 - VF            | 16             |                                     |
+//! | I                  | 2              |                                     |
+//! | PC                 | 2              |                                     |
+//! | SP                 | 1              |                                     |
+//! | Stack              | 16 x 2         |                                     |
+//! | HP48 flags         | 8              |                                     |
+//! | Memory             | 65536          |                                     |
+//! | High resolution    | 1              | 0 or 1                              |
+//! | Plane mask         | 1              |                                     |
+//! | Frame buffer       | 64 x 128       | Row-major colour indices            |
+//! | Delay timer        | 1              |                                     |
+//! | Sound timer        | 1              |                                     |
+//! | Has audio pattern  | 1              | 0 or 1                              |
+//! | Audio pattern      | 16             | Zero when no pattern is loaded      |
+//! | Pitch              | 1              |                                     |
+//! | Keys               | 16             | 0 released, 1 pressed               |
+//! | Machine on         | 1              | 0 or 1                              |
+//! | Execution state    | 1              | 0 running, 1 waiting for key,       |
+//! |                    |                | 2 waiting for release               |
+//! | Waiting register   | 1              |                                     |
+//! | Waiting key        | 1              |                                     |
+//! | Cycles             | 8              | Cycles run since power on           |
+//! | RNG state length   | 2              |                                     |
+//! | RNG state          | variable       |                                     |
+//!
+//! Readers reject states with a different magic header, a version newer than
+//! `SAVE_STATE_VERSION`, or trailing data.
+use super::{OktoError, OktoErrorKind, OktoResult};
+
+/// Magic header identifying okto save states.
+pub const SAVE_STATE_MAGIC: &[u8; 8] = b"OKTOSAVE";
+/// Current version of the save-state format.
+pub const SAVE_STATE_VERSION: u16 = 1;
+
+/// Serializes values into a save-state buffer.
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    /// Create a writer whose buffer already contains the magic header and the
+    /// current format version.
+    pub fn new() -> Self {
+        let mut writer = Self { data: Vec::new() };
+        writer.write_bytes(SAVE_STATE_MAGIC);
+        writer.write_u16(SAVE_STATE_VERSION);
+        writer
+    }
+
+    /// Append a single byte.
+    pub fn write_u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    /// Append a big-endian 16-bit value.
+    pub fn write_u16(&mut self, value: u16) {
+        self.data.push((value >> 8) as u8);
+        self.data.push(value as u8);
+    }
+
+    /// Append a big-endian 64-bit value.
+    pub fn write_u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_be_bytes());
+    }
+
+    /// Append a boolean as a single byte.
+    pub fn write_bool(&mut self, value: bool) {
+        self.write_u8(if value { 1 } else { 0 });
+    }
+
+    /// Append a slice of bytes.
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+    }
+
+    /// Returns the serialized save state.
+    pub fn finish(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+impl Default for StateWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Deserializes values from a save-state buffer.
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    position: usize,
+    version: u16,
+}
+
+impl<'a> StateReader<'a> {
+    /// Create a reader over the given save state, validating its header.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate okto;
+    /// # use okto::savestate::{StateReader, StateWriter, SAVE_STATE_VERSION};
+    /// let state = StateWriter::new().finish();
+    /// assert_eq!(SAVE_STATE_VERSION, StateReader::new(&state).unwrap().version());
+    /// assert!(StateReader::new(b"NOTASAVE\x00\x01").is_err());
+    /// ```
+    pub fn new(data: &'a [u8]) -> OktoResult<Self> {
+        let mut reader = Self {
+            data,
+            position: 0,
+            version: 0,
+        };
+
+        if reader.read_bytes(SAVE_STATE_MAGIC.len())? != SAVE_STATE_MAGIC {
+            return Err(OktoError::new(OktoErrorKind::InvalidSaveState));
+        }
+
+        reader.version = reader.read_u16()?;
+        if reader.version == 0 || reader.version > SAVE_STATE_VERSION {
+            return Err(OktoError::new(
+                OktoErrorKind::UnsupportedSaveStateVersion(reader.version),
+            ));
+        }
+
+        Ok(reader)
+    }
+
+    /// Returns the format version of the save state being read.
+    pub fn version(&self) -> u16 {
+        self.version
+    }
+
+    /// Read a single byte.
+    pub fn read_u8(&mut self) -> OktoResult<u8> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    /// Read a big-endian 16-bit value.
+    pub fn read_u16(&mut self) -> OktoResult<u16> {
+        let bytes = self.read_bytes(2)?;
+        Ok(((bytes[0] as u16) << 8) | bytes[1] as u16)
+    }
+
+    /// Read a big-endian 64-bit value.
+    pub fn read_u64(&mut self) -> OktoResult<u64> {
+        let mut bytes = [0; 8];
+        self.read_into(&mut bytes)?;
+        Ok(u64::from_be_bytes(bytes))
+    }
+
+    /// Read a boolean stored as a single byte.
+    pub fn read_bool(&mut self) -> OktoResult<bool> {
+        match self.read_u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(OktoError::new(OktoErrorKind::InvalidSaveState)),
+        }
+    }
+
+    /// Read the given number of bytes.
+    pub fn read_bytes(&mut self, size: usize) -> OktoResult<&'a [u8]> {
+        let end = self.position + size;
+        if end > self.data.len() {
+            return Err(OktoError::new(OktoErrorKind::InvalidSaveState));
+        }
+
+        let bytes = &self.data[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    /// Read bytes into the given buffer, filling it completely.
+    pub fn read_into(&mut self, buffer: &mut [u8]) -> OktoResult<()> {
+        buffer.copy_from_slice(self.read_bytes(buffer.len())?);
+        Ok(())
+    }
+
+    /// Check that the whole save state has been consumed.
+    pub fn finish(self) -> OktoResult<()> {
+        if self.position != self.data.len() {
+            return Err(OktoError::new(OktoErrorKind::InvalidSaveState));
+        }
+
+        Ok(())
+    }
+}
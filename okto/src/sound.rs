@@ -5,11 +5,30 @@ use std::sync::{Arc, RwLock};
 
 /// Number of times that the sound timer should tick per second
 pub const SOUND_TIMER_TICK_HZ: u32 = 60;
+/// Number of bytes in an XO-CHIP audio pattern
+pub const AUDIO_PATTERN_BYTES: usize = 16;
+/// Number of 1-bit samples in an XO-CHIP audio pattern
+pub const AUDIO_PATTERN_BITS: usize = AUDIO_PATTERN_BYTES * 8;
+/// Pitch register value at which patterns play back at 4000 bits per second
+pub const DEFAULT_PITCH: u8 = 64;
+/// Frequency of the square wave played when no audio pattern has been loaded
+pub const DEFAULT_TONE_HZ: f32 = 440.0;
+/// Default amplitude of generated samples
+pub const DEFAULT_VOLUME: f32 = 0.25;
+
+/// XO-CHIP audio pattern buffer, played back one bit at a time starting with
+/// the most significant bit of the first byte.
+pub type AudioPattern = [u8; AUDIO_PATTERN_BYTES];
 
 /// Sound subsystem state data.
 pub struct Sound {
     /// Sound timer
     pub timer: Arc<RwLock<timer::Timer>>,
+    /// Audio pattern loaded by XO-CHIP programs. When no pattern has been
+    /// loaded a plain square wave tone is played instead.
+    pub pattern: Arc<RwLock<Option<AudioPattern>>>,
+    /// XO-CHIP pitch register controlling the pattern playback rate
+    pub pitch: Arc<RwLock<u8>>,
 }
 
 /// Implementation of sound subsystem
@@ -18,17 +37,126 @@ impl Sound {
     pub fn new() -> Self {
         Self {
             timer: Arc::new(RwLock::new(0)),
+            pattern: Arc::new(RwLock::new(None)),
+            pitch: Arc::new(RwLock::new(DEFAULT_PITCH)),
+        }
+    }
+
+    /// Returns the number of pattern bits played per second for the given
+    /// pitch register value.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate okto;
+    /// # use okto::sound::Sound;
+    /// assert_eq!(4000.0, Sound::playback_rate(64));
+    /// assert_eq!(8000.0, Sound::playback_rate(112));
+    /// ```
+    pub fn playback_rate(pitch: u8) -> f32 {
+        4000.0 * 2f32.powf((pitch as f32 - 64.0) / 48.0)
+    }
+
+    /// Create a generator producing PCM samples for this sound system at the
+    /// given sample rate. The generator shares state with the sound system, so
+    /// it may be moved to an audio thread while the machine keeps running.
+    pub fn sample_generator(&self, sample_rate: u32) -> SampleGenerator {
+        SampleGenerator {
+            timer: self.timer.clone(),
+            pattern: self.pattern.clone(),
+            pitch: self.pitch.clone(),
+            sample_rate: sample_rate as f32,
+            phase: 0.0,
+            volume: DEFAULT_VOLUME,
         }
     }
 }
 
+impl Default for Sound {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 /// Implementation of countdown timer behavior for sound system.
 impl timer::CountdownTimer for Sound {
     /// Decrement the sound counter by 1 if it is greater than 0.
-    fn tick(&mut self) -> () {
+    fn tick(&mut self) {
         let mut timer = self.timer.write().unwrap();
         if *timer > 0 {
             *timer -= 1;
         }
     }
 }
+
+/// Synthesises the output of the sound system as floating point PCM samples.
+pub struct SampleGenerator {
+    timer: Arc<RwLock<timer::Timer>>,
+    pattern: Arc<RwLock<Option<AudioPattern>>>,
+    pitch: Arc<RwLock<u8>>,
+    sample_rate: f32,
+    /// Position within the current waveform. For audio patterns this is the
+    /// index of the bit being played, otherwise it is the fraction of the
+    /// square wave period elapsed.
+    phase: f32,
+    /// Amplitude of the generated samples
+    pub volume: f32,
+}
+
+impl SampleGenerator {
+    /// Fill the given buffer with samples. Silence is produced while the sound
+    /// timer is zero.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate okto;
+    /// # use okto::sound::Sound;
+    /// let sound = Sound::new();
+    /// let mut generator = sound.sample_generator(8000);
+    /// let mut samples = [1.0; 4];
+    ///
+    /// generator.fill(&mut samples);
+    /// assert_eq!([0.0; 4], samples);
+    ///
+    /// *sound.timer.write().unwrap() = 10;
+    /// *sound.pattern.write().unwrap() = Some([0xAA; 16]);
+    /// generator.fill(&mut samples);
+    /// assert_eq!([0.25, 0.25, -0.25, -0.25], samples);
+    /// ```
+    pub fn fill(&mut self, out: &mut [f32]) {
+        if *self.timer.read().unwrap() == 0 {
+            for sample in out.iter_mut() {
+                *sample = 0.0;
+            }
+            return;
+        }
+
+        match *self.pattern.read().unwrap() {
+            Some(ref pattern) => {
+                let rate = Sound::playback_rate(*self.pitch.read().unwrap());
+                let phase_inc = rate / self.sample_rate;
+
+                for sample in out.iter_mut() {
+                    let bit = self.phase as usize % AUDIO_PATTERN_BITS;
+                    let value = (pattern[bit / 8] >> (7 - (bit % 8))) & 1;
+
+                    *sample = if value == 1 { self.volume } else { -self.volume };
+                    self.phase = (self.phase + phase_inc) % AUDIO_PATTERN_BITS as f32;
+                }
+            }
+            None => {
+                let phase_inc = DEFAULT_TONE_HZ / self.sample_rate;
+
+                for sample in out.iter_mut() {
+                    *sample = if self.phase <= 0.5 {
+                        self.volume
+                    } else {
+                        -self.volume
+                    };
+                    self.phase = (self.phase + phase_inc) % 1.0;
+                }
+            }
+        }
+    }
+}
@@ -20,6 +20,12 @@ impl DelayTimer {
     }
 }
 
+impl Default for DelayTimer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 /// Trait implemented by all countdown timer systems.
 pub trait CountdownTimer {
     /// Count down exactly one time. All Chip8 timers should call tick at a
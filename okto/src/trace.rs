@@ -0,0 +1,462 @@
+//! Execution tracing for debugging misbehaving ROMs.
+//!
+//! A `Tracer` set on `Machine::tracer` receives a `TraceRecord` for every
+//! instruction the machine executes, optionally limited to instructions at
+//! certain addresses. Records are either written out as they arrive, as text
+//! lines or in a compact binary format, or kept in memory in a ring buffer
+//! holding the last N instructions.
+//!
+//! Binary traces start with the 8 byte magic header `OKTOTRCE` followed by a
+//! big-endian 16-bit format version. Each record is then stored as follows,
+//! with all multi-byte values big-endian:
+//!
+//! | Field              | Size (bytes)   | Notes                               |
+//! |--------------------|----------------|-------------------------------------|
+//! | Cycle              | 8              |                                     |
+//! | PC                 | 2              |                                     |
+//! | Instruction        | 2              |                                     |
+//! | Operand            | 2              | Only for long instructions          |
+//! | V0 - VF before     | 16             |                                     |
+//! | I before           | 2              |                                     |
+//! | Changed registers  | 2              | Bit X set if VX changed             |
+//! | Changed values     | variable       | New value of each changed register  |
+//! | I changed          | 1              | 0 or 1                              |
+//! | I after            | 2              | Only if I changed                   |
+//! | Write count        | 2              |                                     |
+//! | Writes             | count x 3      | Address and value written           |
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use super::cpu::{Address, Cpu, Instruction, Operation, LONG_INSTRUCTION_BYTES, NUM_REGISTERS};
+use super::{OktoError, OktoErrorKind, OktoResult};
+
+/// Magic header identifying okto binary traces.
+pub const TRACE_MAGIC: &[u8; 8] = b"OKTOTRCE";
+/// Current version of the binary trace format.
+pub const TRACE_VERSION: u16 = 1;
+
+/// Values of the general purpose registers and I at one point in time.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RegisterSnapshot {
+    /// Registers V0 to VF
+    pub v: [u8; NUM_REGISTERS],
+    /// Index register
+    pub i: Address,
+}
+
+impl<'a> From<&'a Cpu> for RegisterSnapshot {
+    fn from(cpu: &'a Cpu) -> Self {
+        Self { v: cpu.v, i: cpu.i }
+    }
+}
+
+impl fmt::Display for RegisterSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "v=")?;
+        for value in self.v.iter() {
+            write!(f, "{:02X}", value)?;
+        }
+        write!(f, " i={:04X}", self.i)
+    }
+}
+
+/// Everything recorded about one executed instruction.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TraceRecord {
+    /// Machine cycle at which the instruction executed
+    pub cycle: u64,
+    /// Address of the instruction
+    pub pc: Address,
+    /// Raw instruction
+    pub instruction: Instruction,
+    /// Second word of a long instruction
+    pub operand: Option<Instruction>,
+    /// Decoded operation
+    pub operation: Operation,
+    /// Registers before the instruction executed
+    pub before: RegisterSnapshot,
+    /// Registers after the instruction executed
+    pub after: RegisterSnapshot,
+    /// Address and new value of each byte of memory written
+    pub writes: Vec<(Address, u8)>,
+}
+
+impl fmt::Display for TraceRecord {
+    /// Format the record as a single line of text, for example
+    /// `12 0204 F033 MemStoreBcd(0) v=2A.. i=0300 -> v=2A.. i=0300 w=0300:04`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {:04X} {:04X}", self.cycle, self.pc, self.instruction)?;
+        if let Some(operand) = self.operand {
+            write!(f, " {:04X}", operand)?;
+        }
+        write!(f, " {:?} {} -> {}", self.operation, self.before, self.after)?;
+
+        for (index, &(address, value)) in self.writes.iter().enumerate() {
+            let separator = if index == 0 { " w=" } else { "," };
+            write!(f, "{}{:04X}:{:02X}", separator, address, value)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl TraceRecord {
+    /// Append the record to a binary trace.
+    pub fn write_binary(&self, data: &mut Vec<u8>) {
+        write_u64(data, self.cycle);
+        write_u16(data, self.pc);
+        write_u16(data, self.instruction);
+        if Operation::instruction_size(&self.instruction) == LONG_INSTRUCTION_BYTES {
+            write_u16(data, self.operand.unwrap_or(0));
+        }
+
+        data.extend_from_slice(&self.before.v);
+        write_u16(data, self.before.i);
+
+        let mut changed = 0u16;
+        for vx in 0..NUM_REGISTERS {
+            if self.before.v[vx] != self.after.v[vx] {
+                changed |= 1 << vx;
+            }
+        }
+        write_u16(data, changed);
+        for vx in (0..NUM_REGISTERS).filter(|&vx| changed & (1 << vx) != 0) {
+            data.push(self.after.v[vx]);
+        }
+
+        if self.before.i == self.after.i {
+            data.push(0);
+        } else {
+            data.push(1);
+            write_u16(data, self.after.i);
+        }
+
+        write_u16(data, self.writes.len() as u16);
+        for &(address, value) in self.writes.iter() {
+            write_u16(data, address);
+            data.push(value);
+        }
+    }
+
+    /// Read every record from a binary trace, including its header.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate okto;
+    /// # use okto::machine::Machine;
+    /// # use okto::trace::{TraceFormat, TraceRecord, Tracer};
+    /// # use std::sync::{Arc, Mutex};
+    /// # use std::io::{self, Write};
+    /// # #[derive(Clone, Default)]
+    /// # struct Shared(Arc<Mutex<Vec<u8>>>);
+    /// # impl Write for Shared {
+    /// #     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    /// #         self.0.lock().unwrap().write(buf)
+    /// #     }
+    /// #     fn flush(&mut self) -> io::Result<()> { Ok(()) }
+    /// # }
+    /// let output = Shared::default();
+    /// let mut machine = Machine::new();
+    /// // V0 = 5, I = 0x300, store V0 and loop
+    /// machine.memory.data[0x200..0x208]
+    ///     .copy_from_slice(&[0x60, 0x05, 0xA3, 0x00, 0xF0, 0x55, 0x12, 0x06]);
+    /// machine.tracer = Some(Tracer::to_writer(output.clone(), TraceFormat::Binary));
+    /// for _ in 0..4 {
+    ///     machine.step().unwrap();
+    /// }
+    /// machine.tracer.take().unwrap().finish().unwrap();
+    ///
+    /// let data = output.0.lock().unwrap();
+    /// let records = TraceRecord::read_binary(&data).unwrap();
+    /// assert_eq!(4, records.len());
+    /// assert_eq!(vec![(0x300, 5)], records[2].writes);
+    /// ```
+    pub fn read_binary(data: &[u8]) -> OktoResult<Vec<TraceRecord>> {
+        let mut reader = TraceReader { data, position: 0 };
+        if reader.read_bytes(TRACE_MAGIC.len())? != TRACE_MAGIC {
+            return Err(OktoError::new(OktoErrorKind::InvalidTrace));
+        }
+        if reader.read_u16()? != TRACE_VERSION {
+            return Err(OktoError::new(OktoErrorKind::InvalidTrace));
+        }
+
+        let mut records = Vec::new();
+        while reader.position < data.len() {
+            records.push(reader.read_record()?);
+        }
+
+        Ok(records)
+    }
+}
+
+fn write_u16(data: &mut Vec<u8>, value: u16) {
+    data.push((value >> 8) as u8);
+    data.push(value as u8);
+}
+
+fn write_u64(data: &mut Vec<u8>, value: u64) {
+    for shift in (0..8).rev() {
+        data.push((value >> (shift * 8)) as u8);
+    }
+}
+
+/// Deserializes records from a binary trace.
+struct TraceReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> TraceReader<'a> {
+    fn read_bytes(&mut self, size: usize) -> OktoResult<&'a [u8]> {
+        let end = self.position + size;
+        if end > self.data.len() {
+            return Err(OktoError::new(OktoErrorKind::InvalidTrace));
+        }
+
+        let bytes = &self.data[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn read_u8(&mut self) -> OktoResult<u8> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    fn read_u16(&mut self) -> OktoResult<u16> {
+        let bytes = self.read_bytes(2)?;
+        Ok(((bytes[0] as u16) << 8) | bytes[1] as u16)
+    }
+
+    fn read_u64(&mut self) -> OktoResult<u64> {
+        let bytes = self.read_bytes(8)?;
+        Ok(bytes
+            .iter()
+            .fold(0u64, |value, &byte| (value << 8) | byte as u64))
+    }
+
+    fn read_record(&mut self) -> OktoResult<TraceRecord> {
+        let cycle = self.read_u64()?;
+        let pc = self.read_u16()?;
+        let instruction = self.read_u16()?;
+        let (operand, operation) =
+            if Operation::instruction_size(&instruction) == LONG_INSTRUCTION_BYTES {
+                let operand = self.read_u16()?;
+                let operation = Operation::from_long_instruction(&instruction, &operand);
+                (Some(operand), operation)
+            } else {
+                (None, Operation::from_instruction(&instruction))
+            };
+        let operation = operation.ok_or_else(|| OktoError::new(OktoErrorKind::InvalidTrace))?;
+
+        let mut before = RegisterSnapshot::default();
+        before.v.copy_from_slice(self.read_bytes(NUM_REGISTERS)?);
+        before.i = self.read_u16()?;
+
+        let mut after = before;
+        let changed = self.read_u16()?;
+        for vx in (0..NUM_REGISTERS).filter(|&vx| changed & (1 << vx) != 0) {
+            after.v[vx] = self.read_u8()?;
+        }
+        match self.read_u8()? {
+            0 => {}
+            1 => after.i = self.read_u16()?,
+            _ => return Err(OktoError::new(OktoErrorKind::InvalidTrace)),
+        }
+
+        let count = self.read_u16()?;
+        let mut writes = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            writes.push((self.read_u16()?, self.read_u8()?));
+        }
+
+        Ok(TraceRecord {
+            cycle,
+            pc,
+            instruction,
+            operand,
+            operation,
+            before,
+            after,
+            writes,
+        })
+    }
+}
+
+/// Format in which a tracer writes records.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TraceFormat {
+    /// One line of text per record, as produced by `TraceRecord`'s `Display`
+    Text,
+    /// The compact binary format described in the module documentation
+    Binary,
+}
+
+/// Where a tracer sends its records.
+enum TraceOutput {
+    Writer {
+        writer: Box<dyn Write + Send>,
+        format: TraceFormat,
+    },
+    Ring {
+        capacity: usize,
+        records: VecDeque<TraceRecord>,
+    },
+}
+
+/// Receives a record of each instruction executed by the machine.
+pub struct Tracer {
+    output: TraceOutput,
+    /// Inclusive address ranges to trace; empty traces every address
+    ranges: Vec<(Address, Address)>,
+    /// First error encountered while writing, after which writing stops
+    error: Option<io::Error>,
+}
+
+impl Tracer {
+    /// Create a tracer writing records to the given writer in the given
+    /// format. Binary traces start with their header.
+    pub fn to_writer<W: Write + Send + 'static>(writer: W, format: TraceFormat) -> Self {
+        let mut tracer = Self {
+            output: TraceOutput::Writer {
+                writer: Box::new(writer),
+                format,
+            },
+            ranges: Vec::new(),
+            error: None,
+        };
+
+        if format == TraceFormat::Binary {
+            let mut header = TRACE_MAGIC.to_vec();
+            write_u16(&mut header, TRACE_VERSION);
+            tracer.write(&header);
+        }
+
+        tracer
+    }
+
+    /// Create a tracer writing records to a new file at the given path.
+    pub fn to_file<P: AsRef<Path>>(path: P, format: TraceFormat) -> io::Result<Self> {
+        let file = File::create(path)?;
+        Ok(Self::to_writer(BufWriter::new(file), format))
+    }
+
+    /// Create a tracer keeping only the most recent records in memory, up to
+    /// the given capacity.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate okto;
+    /// # use okto::machine::Machine;
+    /// # use okto::trace::Tracer;
+    /// let mut machine = Machine::new();
+    /// // V0 += 1 and loop forever
+    /// machine.memory.data[0x200..0x204].copy_from_slice(&[0x70, 0x01, 0x12, 0x00]);
+    /// machine.tracer = Some(Tracer::ring(3).filter(0x200, 0x201));
+    /// for _ in 0..20 {
+    ///     machine.step().unwrap();
+    /// }
+    ///
+    /// let tracer = machine.tracer.as_ref().unwrap();
+    /// let values: Vec<u8> = tracer.records().map(|record| record.after.v[0]).collect();
+    /// assert_eq!(vec![8, 9, 10], values);
+    /// ```
+    pub fn ring(capacity: usize) -> Self {
+        Self {
+            output: TraceOutput::Ring {
+                capacity,
+                records: VecDeque::with_capacity(capacity),
+            },
+            ranges: Vec::new(),
+            error: None,
+        }
+    }
+
+    /// Only trace instructions at addresses between `start` and `end`
+    /// inclusive. Ranges added by repeated calls are combined.
+    pub fn filter(mut self, start: Address, end: Address) -> Self {
+        self.ranges.push((start, end));
+        self
+    }
+
+    /// Returns true if instructions at the given address are traced.
+    pub fn traces(&self, address: Address) -> bool {
+        self.ranges.is_empty()
+            || self
+                .ranges
+                .iter()
+                .any(|&(start, end)| address >= start && address <= end)
+    }
+
+    /// Record an executed instruction.
+    pub fn record(&mut self, record: TraceRecord) {
+        let bytes = match self.output {
+            TraceOutput::Ring {
+                capacity,
+                ref mut records,
+            } => {
+                if capacity > 0 {
+                    if records.len() == capacity {
+                        records.pop_front();
+                    }
+                    records.push_back(record);
+                }
+                return;
+            }
+            TraceOutput::Writer {
+                format: TraceFormat::Text,
+                ..
+            } => format!("{}\n", record).into_bytes(),
+            TraceOutput::Writer {
+                format: TraceFormat::Binary,
+                ..
+            } => {
+                let mut data = Vec::new();
+                record.write_binary(&mut data);
+                data
+            }
+        };
+
+        self.write(&bytes);
+    }
+
+    /// Write bytes to the output unless an earlier write failed.
+    fn write(&mut self, bytes: &[u8]) {
+        if self.error.is_some() {
+            return;
+        }
+
+        if let TraceOutput::Writer { ref mut writer, .. } = self.output {
+            if let Err(error) = writer.write_all(bytes) {
+                self.error = Some(error);
+            }
+        }
+    }
+
+    /// Returns the records kept by a ring tracer, oldest first. Tracers
+    /// writing their records out keep none.
+    pub fn records(&self) -> impl Iterator<Item = &TraceRecord> {
+        let records = match self.output {
+            TraceOutput::Ring { ref records, .. } => Some(records.iter()),
+            TraceOutput::Writer { .. } => None,
+        };
+        records.into_iter().flatten()
+    }
+
+    /// Flush the output, returning the first error encountered while writing
+    /// records, if any.
+    pub fn finish(mut self) -> io::Result<()> {
+        if let Some(error) = self.error.take() {
+            return Err(error);
+        }
+
+        match self.output {
+            TraceOutput::Writer { ref mut writer, .. } => writer.flush(),
+            TraceOutput::Ring { .. } => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,183 @@
+extern crate okto;
+
+use okto::breakpoint::{
+    Breakpoint, BreakpointEntry, BreakpointHit, HitCondition, HitReason, WatchAccess,
+    WatchedRegister,
+};
+use okto::cpu::Operation;
+use okto::expression::{Expression, LogMessage};
+use okto::isa;
+use okto::machine::{Machine, RunLimit, StopReason};
+
+/// I = 0x300, V0 = 0x2A, store V0 as BCD, load V0-V2, draw a sprite from I,
+/// then loop forever.
+const ROM: [u8; 14] = [
+    0xA3, 0x00, 0x60, 0x2A, 0xF0, 0x33, 0xF2, 0x65, 0xD0, 0x13, 0x00, 0xE0, 0x12, 0x0C,
+];
+
+fn machine_with_rom() -> Machine {
+    let mut machine = Machine::new();
+    machine.memory.load(&ROM, 0x200, ROM.len()).unwrap();
+    machine
+}
+
+fn run(machine: &mut Machine) -> StopReason {
+    machine.run_until(&RunLimit::instructions(100))
+}
+
+#[test]
+fn pc_and_operation_breakpoints() {
+    let mut machine = machine_with_rom();
+    let pc_id = machine.add_breakpoint(Breakpoint::Pc(0x204));
+    let draw_id = machine.add_breakpoint(Breakpoint::Operation(isa::find("Draw").unwrap()));
+
+    match run(&mut machine) {
+        StopReason::BreakpointHit(hit) => {
+            assert_eq!(pc_id, hit.id);
+            assert_eq!(HitReason::Pc(0x204), hit.reason);
+        }
+        reason => panic!("unexpected stop reason {:?}", reason),
+    }
+    assert_eq!(0x204, machine.cpu.pc);
+    assert_eq!(2, machine.cycles);
+
+    match run(&mut machine) {
+        StopReason::BreakpointHit(hit) => {
+            assert_eq!(draw_id, hit.id);
+            assert_eq!(HitReason::Operation(Operation::Draw(0, 1, 3)), hit.reason);
+        }
+        reason => panic!("unexpected stop reason {:?}", reason),
+    }
+    assert_eq!(0x208, machine.cpu.pc);
+
+    // Removed breakpoints no longer trigger
+    assert!(machine.remove_breakpoint(pc_id).is_some());
+    assert!(machine.remove_breakpoint(pc_id).is_none());
+    machine.clear_breakpoints();
+    assert!(machine.breakpoints().is_empty());
+    assert_eq!(StopReason::BudgetExhausted, run(&mut machine));
+}
+
+#[test]
+fn memory_watchpoints() {
+    let mut machine = machine_with_rom();
+    let write_id = machine.add_breakpoint(Breakpoint::Memory {
+        start: 0x302,
+        end: 0x3FF,
+        access: WatchAccess::Write,
+    });
+
+    // MemStoreBcd writes 0x300-0x302
+    let hit = machine.run_until(&RunLimit::instructions(100));
+    assert_eq!(0x206, machine.cpu.pc);
+    match hit {
+        StopReason::BreakpointHit(hit) => {
+            assert_eq!(write_id, hit.id);
+            assert_eq!(HitReason::MemoryWrite(0x302), hit.reason);
+        }
+        reason => panic!("unexpected stop reason {:?}", reason),
+    }
+
+    // MemLoadRegs and Draw read from memory
+    machine.clear_breakpoints();
+    machine.add_breakpoint(Breakpoint::Memory {
+        start: 0x300,
+        end: 0x300,
+        access: WatchAccess::Read,
+    });
+    run(&mut machine);
+    assert_eq!(0x208, machine.cpu.pc);
+    assert_eq!(
+        Some(HitReason::MemoryRead(0x300)),
+        machine.breakpoint_hit().map(|hit| hit.reason)
+    );
+    run(&mut machine);
+    assert_eq!(0x20A, machine.cpu.pc);
+
+    // The loop never touches memory again
+    assert_eq!(StopReason::BudgetExhausted, run(&mut machine));
+}
+
+#[test]
+fn register_watchpoints() {
+    let mut machine = machine_with_rom();
+    machine.add_breakpoint(Breakpoint::Register(WatchedRegister::V(0x2)));
+    machine.add_breakpoint(Breakpoint::Register(WatchedRegister::I));
+
+    match run(&mut machine) {
+        StopReason::BreakpointHit(hit) => assert_eq!(
+            HitReason::RegisterChanged {
+                register: WatchedRegister::I,
+                old: 0x000,
+                new: 0x300,
+            },
+            hit.reason
+        ),
+        reason => panic!("unexpected stop reason {:?}", reason),
+    }
+
+    // Loading V0-V2 from the BCD digits changes V2 to 2
+    match run(&mut machine) {
+        StopReason::BreakpointHit(hit) => assert_eq!(
+            HitReason::RegisterChanged {
+                register: WatchedRegister::V(0x2),
+                old: 0,
+                new: 2,
+            },
+            hit.reason
+        ),
+        reason => panic!("unexpected stop reason {:?}", reason),
+    }
+    assert_eq!(0x208, machine.cpu.pc);
+}
+
+#[test]
+fn conditional_breakpoints() {
+    // V0 += 1, store V0 at 0x300 and loop
+    let mut machine = Machine::new();
+    machine
+        .memory
+        .load(&[0x70, 0x01, 0xA3, 0x00, 0xF0, 0x55, 0x12, 0x00], 0x200, 8)
+        .unwrap();
+
+    let loop_id = machine.add_breakpoint(
+        BreakpointEntry::new(Breakpoint::Pc(0x200)).when(Expression::parse("v0 == 5").unwrap()),
+    );
+    assert_eq!(
+        StopReason::BreakpointHit(BreakpointHit {
+            id: loop_id,
+            reason: HitReason::Pc(0x200),
+        }),
+        run(&mut machine)
+    );
+    assert_eq!(5, machine.cpu.v[0x0]);
+    assert_eq!(1, machine.breakpoints().get(loop_id).unwrap().hits());
+
+    // Only stop on the third write of an odd value
+    machine.clear_breakpoints();
+    let write_id = machine.add_breakpoint(
+        BreakpointEntry::new(Breakpoint::Memory {
+            start: 0x300,
+            end: 0x300,
+            access: WatchAccess::Write,
+        })
+        .when(Expression::parse("[0x300] & 1").unwrap())
+        .hit_condition(HitCondition::Equal(3)),
+    );
+    run(&mut machine);
+    assert_eq!(Some(write_id), machine.breakpoint_hit().map(|hit| hit.id));
+    assert_eq!(11, machine.memory.data[0x300]);
+
+    // Tracepoints log instead of stopping
+    machine.breakpoint_mut(write_id).unwrap().log_message =
+        Some(LogMessage::parse("wrote {[0x300]:x}").unwrap());
+    machine.breakpoint_mut(write_id).unwrap().hit_condition = HitCondition::AtLeast(1);
+    assert!(machine.breakpoints().get(write_id).unwrap().is_tracepoint());
+    for _ in 0..16 {
+        machine.step().unwrap();
+    }
+    assert_eq!(None, machine.breakpoint_hit());
+    assert_eq!(vec!["wrote d", "wrote f"], machine.take_log_messages());
+    assert!(machine.take_log_messages().is_empty());
+    assert_eq!(5, machine.breakpoints().get(write_id).unwrap().hits());
+}
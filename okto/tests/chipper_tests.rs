@@ -0,0 +1,212 @@
+extern crate okto;
+
+use std::io;
+
+use okto::assembler::SourceError;
+use okto::chipper;
+use okto::cpu::Operation;
+use okto::isa::{self, Platform, Syntax};
+use okto::OktoErrorKind;
+
+/// Returns the contents of the files included by the tests.
+fn read(path: &str) -> io::Result<String> {
+    match path {
+        "lib/sprites.chp" => Ok(String::from(
+            "; Sprite data\nSMILE:  DB $..1..1.., #00\n        DW SMILE\n        END\n        DB 1\n",
+        )),
+        "lib/loop.chp" => Ok(String::from("INCLUDE 'loop.chp'\n")),
+        _ => Err(io::Error::new(io::ErrorKind::NotFound, "no such file")),
+    }
+}
+
+fn error(source: &str) -> SourceError {
+    match chipper::assemble("lib/test.chp", source, read)
+        .unwrap_err()
+        .kind
+    {
+        OktoErrorKind::InvalidSource(error) => error,
+        kind => panic!("unexpected error {:?}", kind),
+    }
+}
+
+#[test]
+fn assemble_program() {
+    let source = "
+; Draw a face and scroll it down
+SPEED   EQU 2 + 3 * 2           ; 8 with precedence
+ROWS    equ (SPEED - 4) / 2
+
+start:  high
+        ld    i, smile
+        LD    V0, #1F
+        ld    v1, @10
+        drw   v0, v1, ROWS
+        scd   ROWS + 1
+        shr   v1
+        Se    v0, 31
+        jp    v0, table
+        call  done
+        jp    start
+        da    'OK'
+TABLE   ld    dt, v0            ; padded to an even address
+done:   ld    v2, dt
+        LD    [I], V2
+        ret
+        ALIGN OFF
+        DB    -1, 'A;B'
+        DW    #1234, -2
+        ld    f, v2
+        INCLUDE \"sprites.chp\"
+        option schip
+        ORG   #300
+        EXIT
+";
+    let program = chipper::assemble("lib/program.chp", source, read).unwrap();
+
+    assert_eq!(Some(&0x200), program.labels.get("start"));
+    assert_eq!(Some(&0x218), program.labels.get("TABLE"));
+    assert_eq!(Some(&0x21A), program.labels.get("done"));
+    assert_eq!(Some(&0x22A), program.labels.get("SMILE"));
+
+    let expected = [
+        0x00, 0xFF, // high
+        0xA2, 0x2A, // ld i, smile
+        0x60, 0x1F, // LD V0, #1F
+        0x61, 0x08, // ld v1, @10
+        0xD0, 0x12, // drw v0, v1, ROWS
+        0x00, 0xC3, // scd ROWS + 1
+        0x81, 0x16, // shr v1
+        0x30, 0x1F, // Se v0, 31
+        0xB2, 0x18, // jp v0, table
+        0x22, 0x1A, // call done
+        0x12, 0x00, // jp start
+        0x4F, 0x4B, // da 'OK'
+        0xF0, 0x15, // ld dt, v0
+        0xF2, 0x07, // ld v2, dt
+        0xF2, 0x55, // LD [I], V2
+        0x00, 0xEE, // ret
+        0xFF, 0x41, 0x3B, 0x42, // DB -1, 'A;B'
+        0x12, 0x34, 0xFF, 0xFE, // DW #1234, -2
+        0xF2, 0x29, // ld f, v2
+        0x24, 0x00, // SMILE
+        0x02, 0x2A, // DW SMILE
+    ];
+    assert_eq!(&expected[..], &program.rom[..expected.len()]);
+    assert_eq!(0x302 - 0x200, program.rom.len());
+    assert_eq!([0x00, 0xFD], program.rom[0x100..]);
+}
+
+#[test]
+fn assemble_every_instruction() {
+    let mut operations: Vec<Operation> = (0..=0xFFFF)
+        .filter_map(|instruction| Operation::from_instruction(&instruction))
+        .filter(|operation| {
+            let info = isa::info(operation);
+            info.is_available(Platform::Chip8) || info.is_available(Platform::SuperChip)
+        })
+        .collect();
+    operations.dedup();
+
+    // Every mnemonic written by the disassembler assembles back to the same
+    // bytes, in chunks that fit in memory
+    for chunk in operations.chunks(0x4000) {
+        let mut source = String::new();
+        let mut expected = Vec::new();
+        for operation in chunk {
+            source.push_str("    ");
+            source.push_str(&isa::format(operation, Syntax::Cowgod).unwrap());
+            source.push('\n');
+            expected.extend_from_slice(&operation.to_bytes().unwrap());
+        }
+        let program = chipper::assemble("all.chp", &source, read).unwrap();
+        assert_eq!(expected, program.rom);
+    }
+}
+
+#[test]
+fn forward_references() {
+    let assemble = |source| chipper::assemble("forward.chp", source, read).unwrap().rom;
+
+    assert_eq!(
+        vec![0x12, 0x00, 0x00, 0xFD],
+        assemble("START:  JP FINISH - 2\nFINISH: EXIT")
+    );
+    assert_eq!(
+        vec![0x60, 0xC8],
+        assemble("        LD V0, 300 - LATER\nLATER   EQU 100")
+    );
+
+    let rom = assemble("        ORG FOO\n        EXIT\nFOO     EQU BAR + #100\nBAR     EQU #200");
+    assert_eq!(0x102, rom.len());
+    assert_eq!([0x00, 0xFD], rom[0x100..]);
+}
+
+#[test]
+fn align_labels_on_their_own_line() {
+    // The label takes the address of the instruction after the padding
+    let program = chipper::assemble(
+        "align.chp",
+        "  DB #01\nLOOP:\n  CLS\n  JP LOOP\nDATA:\n  DB 2\nLAST:\n",
+        read,
+    )
+    .unwrap();
+    assert_eq!(vec![0x01, 0x00, 0x00, 0xE0, 0x12, 0x02, 0x02], program.rom);
+    assert_eq!(Some(&0x202), program.labels.get("LOOP"));
+    assert_eq!(Some(&0x206), program.labels.get("DATA"));
+    assert_eq!(Some(&0x207), program.labels.get("LAST"));
+}
+
+#[test]
+fn report_errors() {
+    let cases = [
+        ("  LD V0, 256", 1, 10, "256 does not fit in a byte"),
+        ("  DRW V0, V1, x", 1, 15, "undefined symbol 'x'"),
+        ("  JP V1, 2", 1, 3, "invalid operands for JP"),
+        ("  MOV V0, V1", 1, 3, "unknown instruction 'MOV'"),
+        ("A EQU 1\na: CLS", 2, 1, "the symbol 'a' is already defined"),
+        ("  EQU 1", 1, 3, "EQU without a name"),
+        ("  ORG #100", 1, 7, "address 0x100 is outside of the ROM"),
+        ("  ALIGN MAYBE", 1, 9, "expected ON or OFF, found 'MAYBE'"),
+        ("  DB 1,,2", 1, 8, "missing operand"),
+        ("  LD V0, (1 + 2", 1, 16, "unexpected end of expression"),
+        ("  LD V0, 1 / 0", 1, 12, "division by zero"),
+        ("  LD V0, #1G", 1, 10, "invalid number '#1G'"),
+        ("  ORG #402 - B\nB:", 1, 1, "symbol values did not settle"),
+        (
+            "\n  INCLUDE 'missing.chp'",
+            2,
+            11,
+            "cannot read 'lib/missing.chp': no such file",
+        ),
+    ];
+
+    for &(source, line, column, message) in cases.iter() {
+        let error = error(source);
+        assert_eq!(
+            (line, column, message),
+            (error.line, error.column, error.message.as_str()),
+            "{}",
+            source
+        );
+        assert_eq!("lib/test.chp", error.file);
+    }
+
+    // Errors are displayed with their location
+    assert_eq!(
+        "lib/test.chp:1:10: 256 does not fit in a byte",
+        chipper::assemble("lib/test.chp", "  LD V0, 256", read)
+            .unwrap_err()
+            .to_string()
+    );
+
+    let error = error("  INCLUDE loop.chp");
+    assert_eq!(
+        ("lib/loop.chp", 1, 9, "files are included too deeply"),
+        (
+            error.file.as_str(),
+            error.line,
+            error.column,
+            error.message.as_str()
+        )
+    );
+}
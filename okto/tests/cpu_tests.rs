@@ -77,7 +77,7 @@ fn chip8_opcodes() {
         Operation::from_instruction(&0x8895)
     );
     assert_eq!(
-        Some(Operation::Shr(0x8)),
+        Some(Operation::Shr(0x8, 0x9)),
         Operation::from_instruction(&0x8896)
     );
     assert_eq!(
@@ -85,7 +85,7 @@ fn chip8_opcodes() {
         Operation::from_instruction(&0x8897)
     );
     assert_eq!(
-        Some(Operation::Shl(0x8)),
+        Some(Operation::Shl(0x8, 0x9)),
         Operation::from_instruction(&0x889E)
     );
 
@@ -168,6 +168,7 @@ fn chip8_opcodes() {
 #[test]
 fn superchip8_opcodes() {
     assert_eq!(Some(Operation::Scd(0x3)), Operation::from_instruction(&0x00C3));
+    assert_eq!(Some(Operation::Sys(0x1C3)), Operation::from_instruction(&0x01C3));
     assert_eq!(Some(Operation::Scr), Operation::from_instruction(&0x00FB));
     assert_eq!(Some(Operation::Scl), Operation::from_instruction(&0x00FC));
     assert_eq!(Some(Operation::Exit), Operation::from_instruction(&0x00FD));
@@ -199,3 +200,61 @@ fn bad_opcodes() {
     assert_eq!(None, Operation::from_instruction(&0xFA20));
     assert_eq!(None, Operation::from_instruction(&0xFAFF));
 }
+
+#[test]
+fn xochip_opcodes() {
+    assert_eq!(Some(Operation::Scu(0x4)), Operation::from_instruction(&0x00D4));
+    assert_eq!(
+        Some(Operation::MemStoreRange(0x1, 0x4)),
+        Operation::from_instruction(&0x5142)
+    );
+    assert_eq!(
+        Some(Operation::MemLoadRange(0x4, 0x1)),
+        Operation::from_instruction(&0x5413)
+    );
+    assert_eq!(Some(Operation::Plane(0x3)), Operation::from_instruction(&0xF301));
+    assert_eq!(Some(Operation::LoadAudio), Operation::from_instruction(&0xF002));
+    assert_eq!(
+        Some(Operation::LoadPitchReg(0x7)),
+        Operation::from_instruction(&0xF73A)
+    );
+
+    // Long index load
+    assert_eq!(None, Operation::from_instruction(&0xF000));
+    assert_eq!(4, Operation::instruction_size(&0xF000));
+    assert_eq!(2, Operation::instruction_size(&0xF001));
+    assert_eq!(
+        Some(Operation::LoadAddrLong(0xBEEF)),
+        Operation::from_long_instruction(&0xF000, &0xBEEF)
+    );
+}
+
+#[test]
+fn encode_opcodes() {
+    // Every instruction that decodes encodes back to itself
+    for instruction in 0..=0xFFFF {
+        if let Some(operation) = Operation::from_instruction(&instruction) {
+            assert_eq!(
+                Some(instruction),
+                operation.to_instruction(),
+                "{:?}",
+                operation
+            );
+            assert_eq!(None, operation.to_long_instruction());
+        }
+    }
+
+    for operand in 0..=0xFFFF {
+        let operation = Operation::from_long_instruction(&0xF000, &operand).unwrap();
+        assert_eq!(Some((0xF000, operand)), operation.to_long_instruction());
+        assert_eq!(None, operation.to_instruction());
+    }
+
+    // Operands that do not fit their fields cannot be encoded
+    assert_eq!(None, Operation::Jump(0x1000).to_instruction());
+    assert_eq!(None, Operation::AddReg(0x1, 0x10).to_instruction());
+    assert_eq!(None, Operation::Draw(0x1, 0x2, 0x10).to_instruction());
+    assert_eq!(None, Operation::Plane(0x4).to_instruction());
+    assert_eq!(None, Operation::Scd(0x10).to_bytes());
+    assert_eq!(Some(vec![0x00, 0xFF]), Operation::High.to_bytes());
+}
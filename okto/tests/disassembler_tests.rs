@@ -0,0 +1,167 @@
+extern crate okto;
+
+use okto::cpu::Operation;
+use okto::disassembler::{Classification, Disassembly};
+use okto::octo;
+use okto::random::{RandomSource, XorShiftRandom};
+
+/// Returns the classification of every byte of the ROM as a string of `C`,
+/// `D` and `?` characters.
+fn classifications(disassembly: &Disassembly, length: usize) -> String {
+    (0..length)
+        .map(
+            |index| match disassembly.classification(0x200 + index as u16) {
+                Classification::Code => 'C',
+                Classification::Data => 'D',
+                Classification::Unknown => '?',
+            },
+        )
+        .collect()
+}
+
+#[test]
+fn follow_control_flow() {
+    let rom = [
+        0xA2, 0x14, // 200: i := 0x214
+        0x22, 0x0C, // 202: :call 0x20C
+        0x30, 0x01, // 204: if v0 != 0x01 then
+        0x12, 0x0A, // 206:   jump 0x20A
+        0x00, 0xFD, // 208: exit
+        0xB2, 0x12, // 20A: jump0 0x212
+        0xD0, 0x12, // 20C: sprite v0 v1 2
+        0x00, 0xE0, // 20E: clear
+        0x00, 0xEE, // 210: return
+        0x12, 0x00, // 212: jump table
+        0x81, // 214: sprite
+        0x7E, 0x00, 0xEE, // 215: data, as nothing jumps here
+    ];
+    let disassembly = Disassembly::new(&rom, 0x200);
+
+    assert_eq!(
+        "CCCCCCCCCCCCCCCCCC??DDDD",
+        classifications(&disassembly, rom.len())
+    );
+    assert_eq!(
+        Some((Operation::JumpAddrPlusV0(0x212), 2)),
+        disassembly.operation(0x20A)
+    );
+    assert!(disassembly.is_unresolved(0x20A));
+    assert!(!disassembly.is_unresolved(0x206));
+    assert_eq!(None, disassembly.operation(0x212));
+    assert_eq!(None, disassembly.operation(0x216));
+    assert_eq!(Classification::Unknown, disassembly.classification(0x100));
+    assert_eq!(Classification::Unknown, disassembly.classification(0x300));
+}
+
+#[test]
+fn skip_long_instructions() {
+    let rom = [
+        0x40, 0x00, // 200: if v0 == 0x00 then
+        0xF0, 0x00, 0x02, 0x0C, // 202:   i := long 0x20C
+        0x22, 0x0A, // 206: :call 0x20A
+        0x12, 0x08, // 208: jump 0x208
+        0x00, 0xEE, // 20A: return
+        0x3C, 0x3C, // 20C: sprite
+        0x01, // 20E: data up to the end of the ROM
+    ];
+    let disassembly = Disassembly::new(&rom, 0x200);
+
+    assert_eq!("CCCCCCCCCCCCDDD", classifications(&disassembly, rom.len()));
+    assert_eq!(
+        Some((Operation::LoadAddrLong(0x20C), 4)),
+        disassembly.operation(0x202)
+    );
+    assert_eq!(None, disassembly.operation(0x204));
+
+    // Code starting further into the ROM
+    let disassembly = Disassembly::new(&rom, 0x206);
+    assert_eq!("??????CCCCCC???", classifications(&disassembly, rom.len()));
+
+    // Instructions running off the end of the ROM are not code
+    let disassembly = Disassembly::new(&[0x60, 0x01, 0xF0], 0x200);
+    assert_eq!("CC?", classifications(&disassembly, 3));
+}
+
+#[test]
+fn write_octo_source() {
+    let source = "
+        : main
+            i := long face
+            loop
+                v0 := random 0x3F
+                if v0 == 3 then :call draw
+                if v1 key then jump0 table
+            again
+        : table
+            jump main
+            exit
+        : draw
+            sprite v0 v1 2
+            return
+        : face
+            0x24 0x00 0x81
+    ";
+    let rom = octo::assemble("face.8o", source).unwrap().rom;
+    let disassembly = Disassembly::new(&rom, 0x200);
+    let labels = disassembly.labels();
+
+    assert_eq!(Some("main"), labels.get(&0x200).map(String::as_str));
+    assert_eq!(Some("label_204"), labels.get(&0x204).map(String::as_str));
+    assert_eq!(Some("label_210"), labels.get(&0x210).map(String::as_str));
+    assert_eq!(Some("sub_214"), labels.get(&0x214).map(String::as_str));
+    assert_eq!(Some("data_218"), labels.get(&0x218).map(String::as_str));
+    assert_eq!(5, labels.len());
+
+    let disassembled = disassembly.to_octo();
+    for line in [
+        "    i := long data_218 # 200",
+        "    :call sub_214 # 208",
+        "    jump0 label_210 # 20C unresolved",
+        ": label_210",
+        "    0x12 0x00 0x00 0xFD # 210 unknown",
+        "    0x24 0x00 0x81 # 218",
+    ]
+    .iter()
+    {
+        assert!(
+            disassembled.lines().any(|text| text == *line),
+            "{} missing from\n{}",
+            line,
+            disassembled
+        );
+    }
+    assert_eq!(rom, octo::assemble("out.8o", &disassembled).unwrap().rom);
+}
+
+#[test]
+fn reassemble_random_roms() {
+    let mut random = XorShiftRandom::new(0x0C70);
+    for length in 0..64 {
+        let rom: Vec<u8> = (0..length * 8).map(|_| random.next_byte()).collect();
+        let source = Disassembly::new(&rom, 0x200).to_octo();
+        let program = octo::assemble("random.8o", &source).unwrap();
+        assert_eq!(rom, program.rom, "{}", source);
+    }
+}
+
+#[test]
+fn write_json() {
+    let rom = [
+        0x30, 0x01, // 200: if v0 != 0x01 then
+        0xB2, 0x08, // 202:   jump0 0x208
+        0xF0, 0x00, 0x02, 0x0A, // 204: i := long 0x20A
+        0x00, 0xFD, // 208: exit
+        0x7E, // 20A: sprite
+    ];
+    let json = Disassembly::new(&rom, 0x200).to_json();
+    let expected = "[
+  {\"address\": 512, \"raw\": \"3001\", \"classification\": \"code\", \"operation\": {\"name\": \"SkipEqImm\", \"mnemonic\": \"SE\", \"operands\": {\"x\": 0, \"nn\": 1}}, \"targets\": [516], \"unresolved\": false, \"platform\": \"CHIP-8\"},
+  {\"address\": 514, \"raw\": \"B208\", \"classification\": \"code\", \"operation\": {\"name\": \"JumpAddrPlusV0\", \"mnemonic\": \"JP\", \"operands\": {\"nnn\": 520}}, \"targets\": [], \"unresolved\": true, \"platform\": \"CHIP-8\"},
+  {\"address\": 516, \"raw\": \"F000020A\", \"classification\": \"code\", \"operation\": {\"name\": \"LoadAddrLong\", \"mnemonic\": \"LD\", \"operands\": {\"nnnn\": 522}}, \"targets\": [], \"unresolved\": false, \"platform\": \"XO-CHIP\"},
+  {\"address\": 520, \"raw\": \"00FD\", \"classification\": \"code\", \"operation\": {\"name\": \"Exit\", \"mnemonic\": \"EXIT\", \"operands\": {}}, \"targets\": [], \"unresolved\": false, \"platform\": \"SCHIP\"},
+  {\"address\": 522, \"raw\": \"7E\", \"classification\": \"data\", \"operation\": null, \"targets\": [], \"unresolved\": false, \"platform\": null}
+]
+";
+    assert_eq!(expected, json);
+    assert_eq!("[\n]\n", Disassembly::new(&[], 0x200).to_json());
+}
@@ -0,0 +1,115 @@
+extern crate okto;
+
+use okto::cpu::Cpu;
+use okto::expression::{BinaryOperator, Context, Expression, LogMessage, Variable};
+use okto::memory::Memory;
+use okto::{OktoError, OktoErrorKind};
+
+fn evaluate(source: &str, cpu: &Cpu, memory: &Memory) -> u32 {
+    let context = Context {
+        cpu,
+        memory,
+        delay_timer: 5,
+        sound_timer: 0,
+    };
+    Expression::parse(source).unwrap().evaluate(&context)
+}
+
+#[test]
+fn parse_expressions() {
+    assert_eq!(
+        Expression::Binary(
+            BinaryOperator::Greater,
+            Box::new(Expression::Variable(Variable::I)),
+            Box::new(Expression::Literal(0x300)),
+        ),
+        Expression::parse("  I>0x300 ").unwrap()
+    );
+    assert_eq!(
+        Expression::Variable(Variable::V(0xA)),
+        Expression::parse("VA").unwrap()
+    );
+
+    // Formatting adds only the parentheses needed to preserve the meaning
+    for &(source, formatted) in [
+        ("1+2*3", "0x1 + 0x2 * 0x3"),
+        ("(1+2)*3", "(0x1 + 0x2) * 0x3"),
+        ("1-(2-3)", "0x1 - (0x2 - 0x3)"),
+        ("!(v0 == 0b11) || [i]", "!(v0 == 0x3) || [i]"),
+        ("-dt", "-delay"),
+    ]
+    .iter()
+    {
+        let expression = Expression::parse(source).unwrap();
+        assert_eq!(formatted, expression.to_string());
+        assert_eq!(expression, Expression::parse(formatted).unwrap());
+    }
+
+    for source in [
+        "",
+        "1 +",
+        "(1",
+        "[i",
+        "v10",
+        "x",
+        "0x",
+        "1 $ 2",
+        "1 2",
+        "99999999999",
+    ]
+    .iter()
+    {
+        match Expression::parse(source) {
+            Err(OktoError {
+                kind: OktoErrorKind::InvalidExpression(_),
+            }) => {}
+            result => panic!("{:?} parsed as {:?}", source, result),
+        }
+    }
+}
+
+#[test]
+fn evaluate_expressions() {
+    let mut cpu = Cpu::new();
+    let mut memory = Memory::new();
+    cpu.v[0x3] = 0x10;
+    cpu.i = 0x300;
+    memory.data[0x302] = 0x80;
+    memory.data[0xFFFF] = 0x1;
+
+    assert_eq!(1, evaluate("v3 == 0x10 && i > 0x300 - 1", &cpu, &memory));
+    assert_eq!(0, evaluate("v3 == 0x10 && i > 0x300", &cpu, &memory));
+    assert_eq!(1, evaluate("[i+2] != 0", &cpu, &memory));
+    assert_eq!(0x100, evaluate("[i + 2] << 1", &cpu, &memory));
+    assert_eq!(0, evaluate("delay == 0", &cpu, &memory));
+    assert_eq!(7, evaluate("1 + 2 * 3", &cpu, &memory));
+    assert_eq!(1, evaluate("1 | 2 == 2", &cpu, &memory));
+    assert_eq!(0, evaluate("v3 / 0 + v3 % 0", &cpu, &memory));
+    assert_eq!(0xFFFF_FFFF, evaluate("0 - 1", &cpu, &memory));
+    assert_eq!(0xFFFF_FFEF, evaluate("~v3", &cpu, &memory));
+
+    // Memory addresses wrap at 16 bits
+    assert_eq!(1, evaluate("[-1]", &cpu, &memory));
+    assert_eq!(1, evaluate("[0x1FFFF]", &cpu, &memory));
+}
+
+#[test]
+fn log_messages() {
+    let mut cpu = Cpu::new();
+    let memory = Memory::new();
+    cpu.i = 0xABC;
+    let context = Context {
+        cpu: &cpu,
+        memory: &memory,
+        delay_timer: 0,
+        sound_timer: 9,
+    };
+
+    let message = LogMessage::parse("i={i:x} sound={st}").unwrap();
+    assert_eq!("i=abc sound=9", message.render(&context));
+    assert_eq!("i={i:x} sound={st}", message.to_string());
+    assert_eq!("{}", LogMessage::parse("{{}}").unwrap().render(&context));
+
+    assert!(LogMessage::parse("}").is_err());
+    assert!(LogMessage::parse("{v0 +}").is_err());
+}
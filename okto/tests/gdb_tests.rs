@@ -0,0 +1,159 @@
+extern crate okto;
+
+use okto::gdb;
+use okto::machine::{Machine, DEFAULT_CYCLES_PER_FRAME};
+
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+
+/// V0 = 5, V0 += 1, I = 0x300, store V0 at I, then exit.
+const ROM: [u8; 10] = [0x60, 0x05, 0x70, 0x01, 0xA3, 0x00, 0xF0, 0x55, 0x00, 0xFD];
+
+/// Accept a debugger on a local port and serve it with a machine running the
+/// given ROM, returning the machine once the debugger detaches.
+fn spawn_server(rom: &'static [u8]) -> (Client, thread::JoinHandle<Machine>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let server = thread::spawn(move || {
+        let mut machine = Machine::new();
+        machine.memory.load(rom, 0x200, rom.len()).unwrap();
+        machine.start_history(100);
+        let (stream, _) = listener.accept().unwrap();
+        stream.set_nodelay(true).unwrap();
+        gdb::serve(&mut machine, stream, DEFAULT_CYCLES_PER_FRAME).unwrap();
+        machine
+    });
+
+    let stream = TcpStream::connect(address).unwrap();
+    stream.set_nodelay(true).unwrap();
+    (Client { stream }, server)
+}
+
+/// A scripted remote protocol client.
+struct Client {
+    stream: TcpStream,
+}
+
+impl Client {
+    fn read_byte(&mut self) -> u8 {
+        let mut byte = [0u8];
+        self.stream.read_exact(&mut byte).unwrap();
+        byte[0]
+    }
+
+    fn send_raw(&mut self, data: &str, checksum: u8) -> u8 {
+        let packet = format!("${}#{:02x}", data, checksum);
+        self.stream.write_all(packet.as_bytes()).unwrap();
+        self.read_byte()
+    }
+
+    /// Send a packet and return the reply, acknowledging both.
+    fn request(&mut self, data: &str) -> String {
+        let checksum = data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+        assert_eq!(b'+', self.send_raw(data, checksum));
+        self.reply()
+    }
+
+    /// Read the next packet and acknowledge it.
+    fn reply(&mut self) -> String {
+        assert_eq!(b'$', self.read_byte());
+        let mut reply = Vec::new();
+        loop {
+            match self.read_byte() {
+                b'#' => break,
+                byte => reply.push(byte),
+            }
+        }
+        let mut checksum = [0u8; 2];
+        self.stream.read_exact(&mut checksum).unwrap();
+        self.stream.write_all(b"+").unwrap();
+        String::from_utf8(reply).unwrap()
+    }
+}
+
+#[test]
+fn remote_debugging() {
+    let (mut client, server) = spawn_server(&ROM);
+
+    // Packets with a bad checksum are rejected
+    assert_eq!(b'-', client.send_raw("?", 0));
+
+    assert!(client
+        .request("qSupported:swbreak+")
+        .starts_with("PacketSize=1000;"));
+    assert!(client
+        .request("qXfer:features:read:target.xml:0,10")
+        .starts_with("m<?xml"));
+    assert_eq!("", client.request("vMustReplyEmpty"));
+    assert_eq!("T0511:0200;", client.request("?"));
+    assert_eq!(format!("{}0000020000", "00".repeat(16)), client.request("g"));
+
+    // Software breakpoints stop before the instruction executes
+    assert_eq!("OK", client.request("Z0,204,2"));
+    assert_eq!("T0511:0204;", client.request("c"));
+    assert_eq!("06", client.request("p0"));
+
+    // Continuing from a breakpoint runs on to the write watchpoint
+    assert_eq!("OK", client.request("Z2,300,1"));
+    assert_eq!("T0511:0208;watch:300;", client.request("c"));
+    assert_eq!("06", client.request("m300,1"));
+    assert_eq!("OK", client.request("z2,300,1"));
+    assert_eq!("OK", client.request("z0,204,2"));
+
+    // Registers and memory can be written
+    assert_eq!("OK", client.request("M300,2:abcd"));
+    assert_eq!("abcd", client.request("m300,2"));
+    assert_eq!("OK", client.request("P10=0123"));
+    assert_eq!("0123", client.request("p10"));
+    assert_eq!("E01", client.request("p13"));
+    assert_eq!("E01", client.request("mffff,2"));
+
+    // Monitor commands press keys, sent as hex encoded text
+    assert_eq!("OK", client.request("qRcmd,6b65792035"));
+
+    // Running backwards undoes the store and stops at the oldest step
+    assert_eq!("T0511:0206;", client.request("bs"));
+    assert_eq!("00cd", client.request("m300,2"));
+    assert_eq!("T0511:0200;replaylog:begin;", client.request("bc"));
+
+    // Continuing runs through the exit instruction, which ends the program
+    assert_eq!("W00", client.request("c"));
+    assert_eq!("OK", client.request("D"));
+
+    let machine = server.join().unwrap();
+    assert!(machine.keyboard.is_pressed(0x5));
+    assert_eq!([0x06, 0xCD], machine.memory.data[0x300..0x302]);
+}
+
+#[test]
+fn oversized_packets() {
+    let (mut client, server) = spawn_server(&ROM);
+
+    // The whole packet is read and rejected, and the next one is handled
+    let data = format!("M300,1:{}", "00".repeat(0x1000));
+    let checksum = data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+    assert_eq!(b'+', client.send_raw(&data, checksum));
+    assert_eq!("E01", client.reply());
+    assert_eq!("T0511:0200;", client.request("?"));
+    assert_eq!("OK", client.request("D"));
+
+    server.join().unwrap();
+}
+
+#[test]
+fn interrupt_running_machine() {
+    // Jump to itself forever
+    let (mut client, server) = spawn_server(&[0x12, 0x00]);
+
+    // Only the interrupt byte stops the machine; the packet sent before it is
+    // handled afterwards
+    assert_eq!(b'+', client.send_raw("c", b'c'));
+    client.stream.write_all(b"$?#3f\x03").unwrap();
+    assert_eq!("T0211:0200;", client.reply());
+    assert_eq!(b'+', client.read_byte());
+    assert_eq!("T0511:0200;", client.reply());
+    assert_eq!("OK", client.request("D"));
+
+    server.join().unwrap();
+}
@@ -0,0 +1,148 @@
+extern crate okto;
+
+use okto::breakpoint::{Breakpoint, BreakpointHit, HitReason, WatchAccess};
+use okto::machine::{Machine, RunLimit, StopReason};
+use okto::random::XorShiftRandom;
+
+/// Loops through timers, random numbers, BCD, register loads and stores,
+/// drawing, a subroutine call, scrolling, resolution changes and clearing.
+const ROM: [u8; 40] = [
+    0x6A, 0x05, // 200: VA = 5
+    0xFA, 0x15, // 202: delay = VA
+    0xFA, 0x18, // 204: sound = VA
+    0xA3, 0x00, // 206: I = 0x300
+    0xC0, 0xFF, // 208: V0 = random
+    0xF0, 0x33, // 20A: store V0 as BCD
+    0xF2, 0x65, // 20C: load V0 to V2
+    0xF0, 0x29, // 20E: I = digit V0
+    0xD1, 0x25, // 210: draw at V1, V2
+    0x22, 0x20, // 212: call 0x220
+    0x00, 0xC2, // 214: scroll down 2
+    0x00, 0xFF, // 216: high resolution
+    0x12, 0x06, // 218: loop to 0x206
+    0x00, 0x00, // 21A
+    0x00, 0x00, // 21C
+    0x00, 0x00, // 21E
+    0xA3, 0x10, // 220: I = 0x310
+    0xF2, 0x55, // 222: store V0 to V2
+    0x71, 0x01, // 224: V1 += 1
+    0x00, 0xE0, // 226: clear
+];
+
+fn machine() -> Machine {
+    let mut machine = Machine::new();
+    machine.rng = Box::new(XorShiftRandom::new(7));
+    machine.memory.load(&ROM, 0x200, ROM.len()).unwrap();
+    machine.memory.data[0x228..0x22A].copy_from_slice(&[0x00, 0xEE]);
+    machine.start_history(1000);
+    machine
+}
+
+#[test]
+fn step_back_restores_every_step() {
+    let mut machine = machine();
+    let limit = RunLimit {
+        cycles_per_frame: 3,
+        ..RunLimit::instructions(1)
+    };
+
+    let mut states = Vec::new();
+    for _ in 0..80 {
+        states.push((machine.save_state(), machine.cycles));
+        assert_eq!(StopReason::BudgetExhausted, machine.run_until(&limit));
+    }
+    let history = machine.history().unwrap();
+    assert_eq!(80, history.len());
+    assert!(history.records().any(|record| record.rng_state.is_some()));
+    assert!(history.records().any(|record| record.display.is_some()));
+    assert!(history.records().any(|record| !record.pixels.is_empty()));
+
+    while let Some((state, cycles)) = states.pop() {
+        assert!(machine.step_back());
+        assert_eq!(state, machine.save_state());
+        assert_eq!(cycles, machine.cycles);
+    }
+    assert!(!machine.step_back());
+
+    // Running forwards again repeats the same steps
+    machine.run_until(&RunLimit::instructions(80));
+    let mut replayed = self::machine();
+    replayed.run_until(&RunLimit::instructions(80));
+    assert_eq!(replayed.save_state(), machine.save_state());
+}
+
+#[test]
+fn run_back_to_breakpoints() {
+    let mut machine = machine();
+    machine.run_until(&RunLimit::instructions(40));
+
+    // Budgets count instructions undone
+    let pc = machine.cpu.pc;
+    assert_eq!(
+        StopReason::BudgetExhausted,
+        machine.run_back(&RunLimit::instructions(3))
+    );
+    assert_eq!(37, machine.cycles);
+    machine.run_until(&RunLimit::instructions(3));
+    assert_eq!(pc, machine.cpu.pc);
+
+    // Breakpoints stop once their instruction is undone, without counting a
+    // hit, and the instruction then executes when running forwards
+    let id = machine.add_breakpoint(Breakpoint::Pc(0x212));
+    let reason = machine.run_back(&RunLimit::default());
+    assert_eq!(
+        StopReason::BreakpointHit(BreakpointHit {
+            id,
+            reason: HitReason::Pc(0x212),
+        }),
+        reason
+    );
+    assert_eq!(0x212, machine.cpu.pc);
+    assert_eq!(
+        Some(0),
+        machine.breakpoints().get(id).map(|entry| entry.hits())
+    );
+    machine.step().unwrap();
+    assert_eq!(0x220, machine.cpu.pc);
+    machine.remove_breakpoint(id);
+
+    // Watchpoints stop before the instruction that touched them
+    machine.add_breakpoint(Breakpoint::Memory {
+        start: 0x300,
+        end: 0x302,
+        access: WatchAccess::Write,
+    });
+    let reason = machine.run_back(&RunLimit::default());
+    assert!(matches!(
+        reason,
+        StopReason::BreakpointHit(BreakpointHit {
+            reason: HitReason::MemoryWrite(0x300),
+            ..
+        })
+    ));
+    assert_eq!(0x20A, machine.cpu.pc);
+    machine.clear_breakpoints();
+
+    assert_eq!(
+        StopReason::PcReached(0x210),
+        machine.run_back(&RunLimit::default().until_pc(0x210))
+    );
+    assert_eq!(
+        StopReason::DisplayChanged,
+        machine.run_back(&RunLimit::default().until_display_change())
+    );
+    assert_eq!(
+        StopReason::HistoryExhausted,
+        machine.run_back(&RunLimit::default())
+    );
+    assert_eq!((0x200, 0), (machine.cpu.pc, machine.cycles));
+
+    // Loading a state discards the history
+    machine.run_until(&RunLimit::instructions(5));
+    let state = machine.save_state();
+    machine.load_state(&state).unwrap();
+    assert!(machine.history().unwrap().is_empty());
+    assert!(machine.stop_history().is_some());
+    machine.step().unwrap();
+    assert!(!machine.step_back());
+}
@@ -0,0 +1,92 @@
+extern crate okto;
+
+use okto::input::{InputRecording, RecordedInput, ScriptedInput};
+use okto::keyboard::KeyEvent;
+use okto::machine::{ExecutionState, Machine};
+use okto::{OktoError, OktoErrorKind};
+
+use std::thread;
+
+/// Waits for a key into V0, then adds it to V1 and loops.
+const ADD_KEYS_ROM: [u8; 6] = [0xF0, 0x0A, 0x81, 0x04, 0x12, 0x00];
+
+fn add_keys_machine() -> Machine {
+    let mut machine = Machine::new();
+    machine
+        .memory
+        .load(&ADD_KEYS_ROM, okto::cpu::DEFAULT_PC_ADDRESS, ADD_KEYS_ROM.len())
+        .unwrap();
+    machine
+}
+
+#[test]
+fn scripted_input() {
+    let mut machine = add_keys_machine();
+    let input = ScriptedInput::new().tap(5, 0x3, 2).tap(20, 0x4, 2);
+    machine.input = Box::new(input);
+
+    for _ in 0..5 {
+        machine.step().unwrap();
+    }
+    assert_eq!(ExecutionState::WaitingForKey(0x0), machine.execution_state);
+    assert_eq!(0, machine.cpu.v[0x1]);
+
+    for _ in 5..30 {
+        machine.step().unwrap();
+    }
+    assert_eq!(30, machine.cycles);
+    assert_eq!(0x7, machine.cpu.v[0x1]);
+    assert!(!machine.keyboard.is_pressed(0x4));
+}
+
+#[test]
+fn recorded_input() {
+    let mut machine = add_keys_machine();
+    machine.start_recording();
+
+    for cycle in 0..50 {
+        match cycle {
+            10 => machine.keyboard.press_key(0x9),
+            12 => machine.keyboard.release_key(0x9),
+            31 => machine.keyboard.press_key(0x2),
+            _ => {}
+        }
+        machine.step().unwrap();
+    }
+
+    let recording = machine.stop_recording().unwrap();
+    assert_eq!(3, recording.events.len());
+    assert_eq!(31, recording.events[2].cycle);
+    assert_eq!(KeyEvent::Pressed(0x2), recording.events[2].event);
+    assert_eq!(0xB, machine.cpu.v[0x1]);
+
+    // Replaying the serialized recording reproduces the run exactly
+    let recording = InputRecording::from_bytes(&recording.to_bytes()).unwrap();
+    let mut replay = add_keys_machine();
+    replay.input = Box::new(RecordedInput::new(recording));
+    for _ in 0..50 {
+        replay.step().unwrap();
+    }
+    assert_eq!(machine.cpu.v, replay.cpu.v);
+    assert_eq!(machine.keyboard.keys, replay.keyboard.keys);
+
+    assert_eq!(
+        Some(OktoError::new(OktoErrorKind::InvalidInputRecording)),
+        InputRecording::from_bytes(&[0, 0, 0, 0, 0, 0, 0, 1, 2, 0]).err()
+    );
+}
+
+#[test]
+fn machine_is_send() {
+    let mut machine = add_keys_machine();
+    machine.input = Box::new(ScriptedInput::new().press(1, 0x6));
+
+    let machine = thread::spawn(move || {
+        machine.step().unwrap().step().unwrap();
+        machine
+    })
+    .join()
+    .unwrap();
+
+    assert_eq!(0x6, machine.cpu.v[0x0]);
+}
@@ -0,0 +1,103 @@
+extern crate okto;
+
+use okto::cpu::Operation;
+use okto::isa::{self, OperandKind, Platform, Syntax, OPERATIONS};
+
+/// Rebuild an operation from its operand values and opcode pattern.
+fn encode(operation: &Operation) -> Option<Operation> {
+    let info = isa::info(operation);
+    let values = isa::operand_values(operation);
+    assert_eq!(info.operands.len(), values.len(), "{:?}", operation);
+    info.encode(&values)
+}
+
+#[test]
+fn table_covers_every_operation() {
+    for (index, info) in OPERATIONS.iter().enumerate() {
+        assert!(
+            OPERATIONS[..index]
+                .iter()
+                .all(|other| other.name != info.name),
+            "{} listed twice",
+            info.name
+        );
+        assert!(!info.platforms.is_empty());
+        assert!(info.vip_cycles.is_none() || info.is_available(Platform::Chip8));
+    }
+
+    let mut seen = Vec::new();
+    for instruction in 0..=0xFFFF {
+        let operation = match Operation::from_instruction(&instruction) {
+            Some(operation) => operation,
+            None => continue,
+        };
+        if operation == Operation::LoadAddrLong(0) {
+            continue;
+        }
+        assert_eq!(Some(operation), encode(&operation));
+        if !seen.contains(&operation.name()) {
+            seen.push(operation.name());
+        }
+    }
+    let long = Operation::from_long_instruction(&0xF000, &0x1234).unwrap();
+    assert_eq!(Some(long), encode(&long));
+    seen.push(long.name());
+
+    assert_eq!(OPERATIONS.len(), seen.len());
+}
+
+#[test]
+fn format_operations() {
+    let cases = [
+        (Operation::Cls, Some("clear"), "CLS"),
+        (Operation::Sys(0x2A0), Some("native 0x2A0"), "SYS #2A0"),
+        (Operation::Call(0x2F0), Some(":call 0x2F0"), "CALL #2F0"),
+        (
+            Operation::SkipEqImm(0x3, 0x0B),
+            Some("if v3 != 0x0B then"),
+            "SE V3, #0B",
+        ),
+        (Operation::Or(0xA, 0xF), Some("va |= vf"), "OR VA, VF"),
+        (
+            Operation::Draw(1, 2, 5),
+            Some("sprite v1 v2 5"),
+            "DRW V1, V2, 5",
+        ),
+        (Operation::SkipKey(0xC), Some("if vc -key then"), "SKP VC"),
+        (Operation::Scd(4), Some("scroll-down 4"), "SCD 4"),
+        (
+            Operation::MemLoadRange(2, 5),
+            Some("load v2 - v5"),
+            "LD V2-V5, [I]",
+        ),
+        (
+            Operation::LoadAddrLong(0xBEEF),
+            Some("i := long 0xBEEF"),
+            "LD I, #BEEF",
+        ),
+        (Operation::Plane(3), Some("plane 3"), "PLANE 3"),
+    ];
+
+    for &(ref operation, octo, cowgod) in cases.iter() {
+        assert_eq!(octo.map(String::from), isa::format(operation, Syntax::Octo));
+        assert_eq!(
+            Some(String::from(cowgod)),
+            isa::format(operation, Syntax::Cowgod)
+        );
+    }
+
+    let info = isa::find("LoadAddrLong").unwrap();
+    assert_eq!(OperandKind::LongAddress, info.operands[0].kind);
+    assert!(!info.is_available(Platform::SuperChip));
+    assert!(isa::find("Nop").is_none());
+    assert_eq!(Some(Syntax::Cowgod), Syntax::from_name("cowgod"));
+}
+
+#[test]
+fn reference_documentation_is_current() {
+    let documented = include_str!("../../docs/instruction-set.md");
+    assert!(
+        documented.contains(&isa::reference()),
+        "docs/instruction-set.md is out of date, regenerate it from isa::reference()"
+    );
+}
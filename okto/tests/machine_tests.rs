@@ -2,12 +2,15 @@ extern crate okto;
 
 use okto::cpu;
 use okto::keyboard;
-use okto::machine::Machine;
+use okto::machine::{ExecutionState, HaltReason, Machine, RunLimit, StopReason};
+use okto::quirks::Quirks;
+use okto::random::{SequenceRandom, XorShiftRandom};
+use okto::timer::CountdownTimer;
 use okto::{OktoError, OktoErrorKind};
 
 #[test]
 fn machine_initialization() {
-    let machine = Machine::new(Box::new(keyboard::nop_wait_key_callback));
+    let machine = Machine::new();
 
     // Initial CPU state
     assert!(machine.cpu.v.iter().all(|&x| x == 0));
@@ -44,7 +47,7 @@ fn machine_initialization() {
 
 #[test]
 fn chip8_cpu_operations() {
-    let mut machine = Machine::new(Box::new(keyboard::nop_wait_key_callback));
+    let mut machine = Machine::new();
 
     // Ret
     assert_eq!(
@@ -66,6 +69,13 @@ fn chip8_cpu_operations() {
         .unwrap();
     assert_eq!(0x236, machine.cpu.pc);
 
+    // The offset can carry the jump past the first 4 KB of memory
+    machine.cpu.v[0] = 0xFF;
+    machine
+        .execute(cpu::Operation::JumpAddrPlusV0(0xFF0))
+        .unwrap();
+    assert_eq!(0x10EF, machine.cpu.pc);
+
     // Call
     machine.cpu.pc = 0x200;
     machine.execute(cpu::Operation::Call(0x233)).unwrap();
@@ -241,25 +251,25 @@ fn chip8_cpu_operations() {
 
     // Shr - Bit is 0
     machine.cpu.v[0x1] = 0x0C;
-    machine.execute(cpu::Operation::Shr(0x1)).unwrap();
+    machine.execute(cpu::Operation::Shr(0x1, 0x2)).unwrap();
     assert_eq!(0x06, machine.cpu.v[0x1]);
     assert_eq!(0, machine.cpu.v[0xF]);
 
     // Shr - Bit is 1
     machine.cpu.v[0x1] = 0x03;
-    machine.execute(cpu::Operation::Shr(0x1)).unwrap();
+    machine.execute(cpu::Operation::Shr(0x1, 0x2)).unwrap();
     assert_eq!(0x01, machine.cpu.v[0x1]);
     assert_eq!(1, machine.cpu.v[0xF]);
 
     // Shl - Bit is 0
     machine.cpu.v[0x1] = 0x10;
-    machine.execute(cpu::Operation::Shl(0x1)).unwrap();
+    machine.execute(cpu::Operation::Shl(0x1, 0x2)).unwrap();
     assert_eq!(0x20, machine.cpu.v[0x1]);
     assert_eq!(0, machine.cpu.v[0xF]);
 
     // Shl - Bit is 1
     machine.cpu.v[0x1] = 0xC0;
-    machine.execute(cpu::Operation::Shl(0x1)).unwrap();
+    machine.execute(cpu::Operation::Shl(0x1, 0x2)).unwrap();
     assert_eq!(0x80, machine.cpu.v[0x1]);
     assert_eq!(1, machine.cpu.v[0xF]);
 
@@ -271,9 +281,34 @@ fn chip8_cpu_operations() {
     assert!(machine.cpu.v[0x1] <= 3);
 }
 
+#[test]
+fn random_sources() {
+    let mut machine = Machine::new();
+    machine.rng = Box::new(SequenceRandom::new(vec![0xAB, 0xFF]));
+    machine
+        .execute(cpu::Operation::RandAndImm(0x1, 0x0F))
+        .unwrap();
+    assert_eq!(0x0B, machine.cpu.v[0x1]);
+    machine
+        .execute(cpu::Operation::RandAndImm(0x1, 0xF0))
+        .unwrap();
+    assert_eq!(0xF0, machine.cpu.v[0x1]);
+
+    // Machines seeded alike produce identical sequences
+    let mut first = Machine::new();
+    let mut second = Machine::new();
+    first.rng = Box::new(XorShiftRandom::new(42));
+    second.rng = Box::new(XorShiftRandom::new(42));
+    for _ in 0..64 {
+        first.execute(cpu::Operation::RandAndImm(0x0, 0xFF)).unwrap();
+        second.execute(cpu::Operation::RandAndImm(0x0, 0xFF)).unwrap();
+        assert_eq!(first.cpu.v[0x0], second.cpu.v[0x0]);
+    }
+}
+
 #[test]
 fn chip8_memory_operations() {
-    let mut machine = Machine::new(Box::new(keyboard::nop_wait_key_callback));
+    let mut machine = Machine::new();
 
     // MemStoreBcd
     machine.cpu.i = 0x200;
@@ -301,7 +336,7 @@ fn chip8_memory_operations() {
 
 #[test]
 fn chip8_timer_operations() {
-    let mut machine = Machine::new(Box::new(keyboard::nop_wait_key_callback));
+    let mut machine = Machine::new();
 
     // LoadRegDelay
     machine.delay_timer.value = 0x23;
@@ -318,7 +353,7 @@ fn chip8_timer_operations() {
 
 #[test]
 fn chip8_video_operations() {
-    let mut machine = Machine::new(Box::new(keyboard::nop_wait_key_callback));
+    let mut machine = Machine::new();
 
     // Draw - Pixels not overwritten
     machine.memory.data[0x202] = 0xFF;
@@ -361,7 +396,7 @@ fn chip8_video_operations() {
 
 #[test]
 fn chip8_keyboard_operations() {
-    let mut machine = Machine::new(Box::new(keyboard::nop_wait_key_callback));
+    let mut machine = Machine::new();
 
     // SkipKey - Key pressed
     machine.keyboard.keys[3] = keyboard::KeyState::Pressed;
@@ -391,21 +426,397 @@ fn chip8_keyboard_operations() {
     machine.execute(cpu::Operation::SkipNotKey(0xA)).unwrap();
     assert_eq!(0x202, machine.cpu.pc);
 
-    // WaitKey
-    let wait_key_callback = || -> keyboard::WaitKeyResult<u8> { Ok(12) };
-    let mut machine = Machine::new(Box::new(wait_key_callback));
+    // WaitKey - Completes on key press
+    let mut machine = Machine::new();
+    machine.memory.data[0x200..0x204].copy_from_slice(&[0xFA, 0x0A, 0x60, 0x01]);
+
+    machine.keyboard.press_key(0x5);
+    machine.step().unwrap();
+    assert_eq!(ExecutionState::WaitingForKey(0xA), machine.execution_state);
+
+    // Presses before the wait started are ignored and nothing executes while
+    // waiting, but the timers keep running
+    machine.delay_timer.value = 2;
+    machine.step().unwrap().step().unwrap();
+    machine.delay_timer.tick();
+    assert_eq!(0x202, machine.cpu.pc);
+    assert_eq!(1, machine.delay_timer.value);
 
-    machine.cpu.v[0xA] = 0;
-    machine.execute(cpu::Operation::WaitKey(0xA)).unwrap();
-    assert_eq!(12, machine.cpu.v[0xA]);
+    machine.keyboard.release_key(0x5);
+    machine.keyboard.press_key(0xC);
+    machine.step().unwrap();
+    assert_eq!(ExecutionState::Running, machine.execution_state);
+    assert_eq!(0xC, machine.cpu.v[0xA]);
+    assert_eq!(0x202, machine.cpu.pc);
+    machine.step().unwrap();
+    assert_eq!(0x1, machine.cpu.v[0x0]);
+
+    // WaitKey - Completes on key release
+    let quirks = Quirks {
+        wait_key_on_release: true,
+        ..Quirks::default()
+    };
+    let mut machine = Machine::with_quirks(quirks);
+    machine.execute(cpu::Operation::WaitKey(0x3)).unwrap();
+
+    machine.keyboard.press_key(0x9);
+    machine.keyboard.press_key(0x4);
+    machine.step().unwrap();
+    assert_eq!(ExecutionState::WaitingForRelease(0x3, 0x9), machine.execution_state);
+
+    machine.keyboard.release_key(0x4);
+    machine.step().unwrap();
+    assert_eq!(ExecutionState::WaitingForRelease(0x3, 0x9), machine.execution_state);
+
+    machine.keyboard.release_key(0x9);
+    machine.step().unwrap();
+    assert_eq!(ExecutionState::Running, machine.execution_state);
+    assert_eq!(0x9, machine.cpu.v[0x3]);
 }
 
 #[test]
 fn chip8_sound_operations() {
-    let mut machine = Machine::new(Box::new(keyboard::nop_wait_key_callback));
+    let mut machine = Machine::new();
 
     // LoadSoundReg
     machine.cpu.v[0x2] = 0xAB;
     machine.execute(cpu::Operation::LoadSoundReg(0x2)).unwrap();
     assert_eq!(0xAB, *machine.sound.timer.read().unwrap());
 }
+
+#[test]
+fn xochip_sound_operations() {
+    let mut machine = Machine::new();
+    assert_eq!(None, *machine.sound.pattern.read().unwrap());
+
+    // LoadAudio
+    let pattern = [
+        0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00,
+        0xF0, 0x0F, 0xF0, 0x0F, 0xF0, 0x0F, 0xF0, 0x0F,
+    ];
+    machine.memory.data[0x300..0x310].clone_from_slice(&pattern);
+    machine.cpu.i = 0x300;
+    machine.execute(cpu::Operation::LoadAudio).unwrap();
+    assert_eq!(Some(pattern), *machine.sound.pattern.read().unwrap());
+
+    // LoadPitchReg
+    machine.cpu.v[0x4] = 112;
+    machine.execute(cpu::Operation::LoadPitchReg(0x4)).unwrap();
+    assert_eq!(112, *machine.sound.pitch.read().unwrap());
+
+    // Sample generation - 8000 bits per second played at 8000Hz produces one
+    // sample per pattern bit
+    let mut generator = machine.sound.sample_generator(8000);
+    let mut samples = [0.0; 16];
+    machine.cpu.v[0x2] = 0x2;
+    machine.execute(cpu::Operation::LoadSoundReg(0x2)).unwrap();
+    generator.fill(&mut samples);
+    assert!(samples[0..8].iter().all(|&x| x == generator.volume));
+    assert!(samples[8..16].iter().all(|&x| x == -generator.volume));
+
+    // Sample generation - Silence once the sound timer expires
+    machine.sound.tick();
+    machine.sound.tick();
+    generator.fill(&mut samples);
+    assert!(samples.iter().all(|&x| x == 0.0));
+}
+
+#[test]
+fn quirk_profiles() {
+    // Shift - VY is shifted into VX
+    let mut machine = Machine::with_quirks(Quirks::cosmac_vip());
+    machine.cpu.v[0x1] = 0x00;
+    machine.cpu.v[0x2] = 0x81;
+    machine.execute(cpu::Operation::Shr(0x1, 0x2)).unwrap();
+    assert_eq!(0x40, machine.cpu.v[0x1]);
+    assert_eq!(0x81, machine.cpu.v[0x2]);
+    assert_eq!(1, machine.cpu.v[0xF]);
+    machine.execute(cpu::Operation::Shl(0x1, 0x2)).unwrap();
+    assert_eq!(0x02, machine.cpu.v[0x1]);
+    assert_eq!(1, machine.cpu.v[0xF]);
+
+    // Logic ops - VF is reset
+    machine.cpu.v[0xF] = 0x01;
+    machine.execute(cpu::Operation::Or(0x1, 0x2)).unwrap();
+    assert_eq!(0, machine.cpu.v[0xF]);
+
+    // MemStoreRegs / MemLoadRegs - I is incremented
+    machine.cpu.i = 0x300;
+    machine.execute(cpu::Operation::MemStoreRegs(0x2)).unwrap();
+    assert_eq!(0x303, machine.cpu.i);
+
+    let mut machine = Machine::with_quirks(Quirks::chip48());
+    machine.cpu.i = 0x300;
+    machine.execute(cpu::Operation::MemLoadRegs(0x2)).unwrap();
+    assert_eq!(0x302, machine.cpu.i);
+
+    // JumpAddrPlusV0 - VX is used as the offset
+    machine.cpu.v[0x0] = 0x01;
+    machine.cpu.v[0x3] = 0x10;
+    machine
+        .execute(cpu::Operation::JumpAddrPlusV0(0x345))
+        .unwrap();
+    assert_eq!(0x355, machine.cpu.pc);
+
+    // Draw - Sprites are clipped at the screen edge
+    machine.memory.data[0x300] = 0xFF;
+    machine.cpu.i = 0x300;
+    machine.cpu.v[0x0] = (machine.display.width() - 2) as u8;
+    machine.cpu.v[0x1] = 0;
+    machine.execute(cpu::Operation::Draw(0x0, 0x1, 1)).unwrap();
+    let width = machine.display.width();
+    assert_eq!(&machine.display.data[0][width - 2..width], &[1, 1]);
+    assert_eq!(&machine.display.data[0][0..6], &[0, 0, 0, 0, 0, 0]);
+
+    // SuperChip 1.1 - I is left untouched
+    let mut machine = Machine::with_quirks(Quirks::schip11());
+    machine.cpu.i = 0x300;
+    machine.execute(cpu::Operation::MemStoreRegs(0x2)).unwrap();
+    assert_eq!(0x300, machine.cpu.i);
+}
+
+#[test]
+fn xochip_operations() {
+    let mut machine = Machine::new();
+
+    // LoadAddrLong - Step moves past both words of the instruction
+    machine
+        .memory
+        .load(&[0xF0, 0x00, 0xE0, 0x12], cpu::DEFAULT_PC_ADDRESS, 4)
+        .unwrap();
+    machine.step().unwrap();
+    assert_eq!(0xE012, machine.cpu.i);
+    assert_eq!(0x204, machine.cpu.pc);
+
+    // SkipEqImm - Skipping a long instruction skips all four bytes
+    machine
+        .memory
+        .load(&[0x30, 0x00, 0xF0, 0x00, 0x12, 0x34], 0x300, 6)
+        .unwrap();
+    machine.cpu.pc = 0x300;
+    machine.cpu.v[0x0] = 0;
+    machine.step().unwrap();
+    assert_eq!(0x306, machine.cpu.pc);
+
+    // MemStoreRange - Registers are stored in the given order without
+    // changing I
+    machine.cpu.i = 0xE000;
+    machine.cpu.v[0x1..0x4].clone_from_slice(&[0x1, 0x2, 0x3]);
+    machine
+        .execute(cpu::Operation::MemStoreRange(0x3, 0x1))
+        .unwrap();
+    assert_eq!(&machine.memory.data[0xE000..0xE003], &[0x3, 0x2, 0x1]);
+    assert_eq!(0xE000, machine.cpu.i);
+
+    // MemLoadRange
+    machine.memory.data[0xE000..0xE003].clone_from_slice(&[0x7, 0x8, 0x9]);
+    machine
+        .execute(cpu::Operation::MemLoadRange(0x5, 0x7))
+        .unwrap();
+    assert_eq!(&machine.cpu.v[0x5..0x8], &[0x7, 0x8, 0x9]);
+
+    // Scu
+    machine.display.data[5][0] = 1;
+    machine.execute(cpu::Operation::Scu(0x5)).unwrap();
+    assert_eq!(1, machine.display.data[0][0]);
+    assert_eq!(0, machine.display.data[5][0]);
+}
+
+#[test]
+fn xochip_bitplane_operations() {
+    let mut machine = Machine::new();
+
+    // Plane - Both planes selected, sprite data for each plane follows the
+    // other in memory
+    machine.execute(cpu::Operation::Plane(0x3)).unwrap();
+    assert_eq!(0x3, machine.display.planes);
+    machine.memory.data[0x300..0x304].clone_from_slice(&[0xF0, 0xF0, 0xFF, 0x00]);
+    machine.cpu.i = 0x300;
+    machine.execute(cpu::Operation::Draw(0x0, 0x0, 2)).unwrap();
+    assert_eq!(0x00, machine.cpu.v[0xF]);
+    assert_eq!(3, machine.display.pixel(0, 0));
+    assert_eq!(2, machine.display.pixel(4, 0));
+    assert_eq!(1, machine.display.pixel(0, 1));
+    assert_eq!(0, machine.display.pixel(4, 1));
+
+    // Draw - Collisions are detected on any selected plane
+    machine.execute(cpu::Operation::Plane(0x2)).unwrap();
+    machine.memory.data[0x300] = 0x08;
+    machine.execute(cpu::Operation::Draw(0x0, 0x0, 1)).unwrap();
+    assert_eq!(0x01, machine.cpu.v[0xF]);
+    assert_eq!(0, machine.display.pixel(4, 0));
+
+    // Cls - Only the selected plane is cleared
+    machine.execute(cpu::Operation::Cls).unwrap();
+    assert_eq!(1, machine.display.pixel(0, 0));
+    assert_eq!(1, machine.display.pixel(0, 1));
+    assert_eq!(0, machine.display.pixel(5, 0));
+
+    // Scroll - Only the selected plane moves
+    machine.execute(cpu::Operation::Plane(0x1)).unwrap();
+    machine.execute(cpu::Operation::Scd(0x2)).unwrap();
+    assert_eq!(0, machine.display.pixel(0, 0));
+    assert_eq!(1, machine.display.pixel(0, 2));
+
+    // Plane - Masks beyond the available planes are rejected
+    assert_eq!(
+        Some(OktoError::new(OktoErrorKind::InvalidOpcode)),
+        machine.execute(cpu::Operation::Plane(0x4)).err()
+    );
+}
+
+#[test]
+fn superchip8_video_operations() {
+    let mut machine = Machine::new();
+
+    // LoadAddrBigDigit
+    machine.cpu.v[0x2] = 0x7;
+    machine.execute(cpu::Operation::LoadAddrBigDigit(0x2)).unwrap();
+    assert_eq!(
+        machine.memory.big_sprite_address_for_digit(0x7).unwrap(),
+        machine.cpu.i
+    );
+
+    machine.cpu.v[0x2] = 0x10;
+    assert_eq!(
+        Some(OktoError::new(OktoErrorKind::InvalidDigitSprite)),
+        machine.execute(cpu::Operation::LoadAddrBigDigit(0x2)).err()
+    );
+
+    // Draw - Large digits are 10 rows tall
+    machine.cpu.v[0x2] = 0x1;
+    machine.execute(cpu::Operation::LoadAddrBigDigit(0x2)).unwrap();
+    machine.cpu.v[0x0] = 0;
+    machine.cpu.v[0x1] = 0;
+    machine.execute(cpu::Operation::Draw(0x0, 0x1, 10)).unwrap();
+    assert_eq!(&machine.display.data[0][0..8], &[0, 0, 0, 1, 1, 0, 0, 0]);
+    assert_eq!(&machine.display.data[9][0..8], &[0, 0, 1, 1, 1, 1, 0, 0]);
+
+    // Draw - 16x16 sprites in low resolution mode
+    machine.execute(cpu::Operation::Cls).unwrap();
+    assert!(!machine.display.high_resolution);
+    for offset in 0..32 {
+        machine.memory.data[0x300 + offset] = if offset % 2 == 0 { 0xFF } else { 0x01 };
+    }
+    machine.cpu.i = 0x300;
+    machine.cpu.v[0x0] = 60;
+    machine.cpu.v[0x1] = 20;
+    machine.execute(cpu::Operation::Draw(0x0, 0x1, 0)).unwrap();
+    assert_eq!(0x00, machine.cpu.v[0xF]);
+    assert_eq!(&machine.display.data[20][60..64], &[1, 1, 1, 1]);
+    assert_eq!(&machine.display.data[20][0..12], &[1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0, 1]);
+    assert_eq!(&machine.display.data[3][0..12], &[1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0, 1]);
+
+    machine.execute(cpu::Operation::Draw(0x0, 0x1, 0)).unwrap();
+    assert_eq!(0x01, machine.cpu.v[0xF]);
+    assert!(
+        machine
+            .display
+            .data
+            .iter()
+            .all(|&y| y.iter().all(|&x| x == 0))
+    );
+}
+
+#[test]
+fn run_frames() {
+    let mut machine = Machine::new();
+    // V0 += 1 and loop forever
+    machine.memory.data[0x200..0x204].copy_from_slice(&[0x70, 0x01, 0x12, 0x00]);
+    machine.delay_timer.value = 5;
+    *machine.sound.timer.write().unwrap() = 2;
+
+    let summary = machine.run_frame(10);
+    assert_eq!(10, summary.instructions_executed);
+    assert!(!summary.display_changed);
+    assert!(summary.sound_active);
+    assert_eq!(None, summary.halt);
+    assert_eq!(5, machine.cpu.v[0x0]);
+    assert_eq!(4, machine.delay_timer.value);
+
+    let summary = machine.run_frame(10);
+    assert!(!summary.sound_active);
+    assert_eq!(3, machine.delay_timer.value);
+
+    // Frames end on the same cycles whether run with run_until or run_frame,
+    // so a frame started by run_until is finished by run_frame
+    let limit = RunLimit {
+        cycles_per_frame: 10,
+        ..RunLimit::instructions(13)
+    };
+    assert_eq!(StopReason::BudgetExhausted, machine.run_until(&limit));
+    assert_eq!(2, machine.delay_timer.value);
+    assert_eq!(7, machine.run_frame(10).instructions_executed);
+    assert_eq!(1, machine.delay_timer.value);
+    assert_eq!(10, machine.run_frame(10).instructions_executed);
+    assert_eq!(0, machine.delay_timer.value);
+
+    // No instructions execute while waiting for a key, but timers still run
+    let mut machine = Machine::new();
+    machine.memory.data[0x200..0x204].copy_from_slice(&[0xF0, 0x0A, 0x00, 0xE0]);
+    machine.delay_timer.value = 5;
+    assert_eq!(1, machine.run_frame(10).instructions_executed);
+    assert_eq!(0, machine.run_frame(10).instructions_executed);
+    assert_eq!(3, machine.delay_timer.value);
+
+    machine.keyboard.press_key(0x1);
+    let summary = machine.run_frame(2);
+    assert_eq!(1, summary.instructions_executed);
+    assert!(summary.display_changed);
+
+    // Errors halt the frame
+    let mut machine = Machine::new();
+    machine.memory.data[0x200..0x202].copy_from_slice(&[0x00, 0xEE]);
+    let summary = machine.run_frame(10);
+    assert_eq!(0, summary.instructions_executed);
+    assert_eq!(
+        Some(HaltReason::Error(OktoError::new(OktoErrorKind::StackUnderflow))),
+        summary.halt
+    );
+}
+
+#[test]
+fn run_until_stop_reasons() {
+    // I = 0x300, V0 += 1, store V0 at I, clear the screen, wait for a key,
+    // then exit
+    let rom = [
+        0xA3, 0x00, 0x70, 0x01, 0xF0, 0x55, 0x00, 0xE0, 0xF1, 0x0A, 0x00, 0xFD,
+    ];
+    let mut machine = Machine::new();
+    machine.memory.load(&rom, 0x200, rom.len()).unwrap();
+
+    assert_eq!(
+        StopReason::MemoryWritten(0x300),
+        machine.run_until(&RunLimit::default().until_memory_write(0x300))
+    );
+    assert_eq!(0x206, machine.cpu.pc);
+    assert_eq!(
+        StopReason::DisplayChanged,
+        machine.run_until(&RunLimit::default().until_display_change())
+    );
+    assert_eq!(StopReason::WaitingForKey, machine.run_until(&RunLimit::default()));
+    assert_eq!(StopReason::WaitingForKey, machine.run_until(&RunLimit::default()));
+
+    machine.keyboard.press_key(0x2);
+    assert_eq!(StopReason::Exit, machine.run_until(&RunLimit::default()));
+    assert_eq!(0x2, machine.cpu.v[0x1]);
+    assert_eq!(StopReason::Exit, machine.run_until(&RunLimit::instructions(5)));
+
+    // Budgets in frames tick the timers once per frame
+    let mut machine = Machine::new();
+    machine.memory.data[0x200..0x202].copy_from_slice(&[0x12, 0x00]);
+    machine.delay_timer.value = 10;
+    let mut limit = RunLimit::frames(3);
+    limit.cycles_per_frame = 4;
+    assert_eq!(StopReason::BudgetExhausted, machine.run_until(&limit));
+    assert_eq!(12, machine.cycles);
+    assert_eq!(7, machine.delay_timer.value);
+
+    // Errors stop execution
+    let mut machine = Machine::new();
+    machine.memory.data[0x200..0x202].copy_from_slice(&[0x00, 0xEE]);
+    assert_eq!(
+        StopReason::Error(OktoError::new(OktoErrorKind::StackUnderflow)),
+        machine.run_until(&RunLimit::instructions(10))
+    );
+}
@@ -0,0 +1,171 @@
+extern crate okto;
+
+use okto::assembler::SourceError;
+use okto::cpu::Operation;
+use okto::isa::{self, Syntax};
+use okto::octo;
+use okto::OktoErrorKind;
+
+fn error(source: &str) -> SourceError {
+    match octo::assemble("test.8o", source).unwrap_err().kind {
+        OktoErrorKind::InvalidSource(error) => error,
+        kind => panic!("unexpected error {:?}", kind),
+    }
+}
+
+#[test]
+fn assemble_program() {
+    let source = "
+        :const SPEED 3
+        :alias x v4
+        :calc HALF { SPEED * 2 + 1 / 2 }
+        :macro bump register amount { register += amount }
+
+        # Data before main, so 0x200 jumps over it
+        : smile
+            0b00100100 0x00 :byte { SPEED + 1 }
+
+        : main
+            i := smile
+            x := SPEED
+            bump x 2
+            loop
+                while x != 9
+                if x < 7 begin
+                    x += 1
+                else
+                    draw
+                end
+            again
+            if v1 key then :call draw
+            jump later
+
+        : draw
+            sprite x v0 3
+            ;
+
+        :org 0x280
+        : later
+            i := long later
+            :unpack 0xA smile
+            exit
+    ";
+    let program = octo::assemble("program.8o", source).unwrap();
+
+    assert_eq!(Some(&0x205), program.labels.get("main"));
+    assert_eq!(Some(&0x280), program.labels.get("later"));
+    assert_eq!(0x28A - 0x200, program.rom.len());
+
+    let expected = [
+        0x12, 0x05, // jump main
+        0x24, 0x00, 0x04, // smile
+        0xA2, 0x02, // i := smile
+        0x64, 0x03, // x := SPEED
+        0x74, 0x02, // bump x 2
+        0x44, 0x09, // while x != 9
+        0x12, 0x1F, //   jump past again
+        0x6F, 0x07, // if x < 7 begin
+        0x8F, 0x47, //   vf =- x
+        0x4F, 0x01, //   skip unless x >= 7
+        0x12, 0x1B, //   jump to else
+        0x74, 0x01, //   x += 1
+        0x12, 0x1D, // else
+        0x22, 0x25, //   draw
+        0x12, 0x0B, // again
+        0xE1, 0xA1, // if v1 key then
+        0x22, 0x25, // :call draw
+        0x12, 0x80, // jump later
+        0xD4, 0x03, // : draw
+        0x00, 0xEE,
+    ];
+    assert_eq!(&expected[..], &program.rom[..expected.len()]);
+    assert_eq!(
+        [0xF0, 0x00, 0x02, 0x80, 0x60, 0xA2, 0x61, 0x02, 0x00, 0xFD],
+        program.rom[0x80..]
+    );
+
+    // Right to left evaluation makes HALF 3 * (2 + (1 / 2))
+    let program =
+        octo::assemble("calc.8o", ":calc HALF { 3 * 2 + 1 / 2 } : main :byte HALF").unwrap();
+    assert_eq!(vec![7], program.rom);
+}
+
+#[test]
+fn assemble_every_instruction() {
+    let mut operations: Vec<Operation> = (0..=0xFFFF)
+        .filter_map(|instruction| Operation::from_instruction(&instruction))
+        .filter(|operation| operation.name() != "LoadAddrLong")
+        .collect();
+    operations.push(Operation::LoadAddrLong(0xBEEF));
+
+    // Every mnemonic written by the disassembler assembles back to the same
+    // bytes, in chunks that fit in memory
+    for chunk in operations.chunks(0x4000) {
+        let mut source = String::from(": main\n");
+        let mut expected = Vec::new();
+        for operation in chunk {
+            source.push_str(&isa::format(operation, Syntax::Octo).unwrap());
+            source.push('\n');
+            expected.extend_from_slice(&operation.to_bytes().unwrap());
+        }
+        assert_eq!(expected, octo::assemble("all.8o", &source).unwrap().rom);
+    }
+}
+
+#[test]
+fn report_errors() {
+    let cases = [
+        (": main\n  v0 := 256", 2, 9, "256 does not fit in a byte"),
+        (": main\n  sprite v0 v1 x", 2, 16, "undefined name 'x'"),
+        (": main\n  jump nowhere", 2, 8, "undefined label 'nowhere'"),
+        (
+            ": main\n  loop\n  v0 := 1",
+            2,
+            3,
+            "'loop' is missing its 'again'",
+        ),
+        (": main\n  else", 2, 3, "'else' without an 'if ... begin'"),
+        (
+            ": main\n  if v0 <> 3 then",
+            2,
+            9,
+            "expected a comparison, found '<>'",
+        ),
+        (
+            ": main\n  : main",
+            2,
+            5,
+            "the name 'main' is already defined",
+        ),
+        (
+            ": main\n  :org 0x100",
+            2,
+            8,
+            "address 0x100 is outside of the ROM",
+        ),
+        (": main\n  :fill 3", 2, 3, "unknown directive ':fill'"),
+        ("v0 := 1", 1, 1, "the program is missing a 'main' label"),
+        (
+            ": main :calc x { 1 + }",
+            1,
+            16,
+            "unexpected end of expression",
+        ),
+    ];
+
+    for &(source, line, column, message) in cases.iter() {
+        let error = error(source);
+        assert_eq!(
+            (line, column, message),
+            (error.line, error.column, error.message.as_str()),
+            "{}",
+            source
+        );
+        assert_eq!("test.8o", error.file);
+    }
+
+    assert_eq!(
+        "test.8o:2:9: 256 does not fit in a byte",
+        error(": main\n  v0 := 256").to_string()
+    );
+}
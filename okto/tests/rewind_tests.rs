@@ -0,0 +1,89 @@
+extern crate okto;
+
+use okto::cpu;
+use okto::machine::Machine;
+use okto::rewind::RewindBuffer;
+
+#[test]
+fn rewind_and_resume() {
+    let mut machine = Machine::new();
+    let mut rewind = RewindBuffer::new(100, 2);
+
+    // Nothing to rewind yet
+    assert!(rewind.is_empty());
+    assert_eq!(0, rewind.rewind(&mut machine, 10).unwrap());
+
+    // Snapshots are taken every other frame
+    for frame in 0..20 {
+        machine.execute(cpu::Operation::LoadImm(0x0, frame)).unwrap();
+        machine.memory.data[0x300 + frame as usize] = frame;
+        rewind.record_frame(&machine);
+    }
+    assert_eq!(10, rewind.len());
+
+    // Deltas are much smaller than full snapshots
+    let state_size = machine.save_state().len();
+    assert!(rewind.memory_usage() < state_size * 2);
+
+    // Rewinding rounds up to whole snapshots
+    assert_eq!(4, rewind.rewind(&mut machine, 3).unwrap());
+    assert_eq!(15, machine.cpu.v[0x0]);
+    assert_eq!(15, machine.memory.data[0x30F]);
+    assert_eq!(0, machine.memory.data[0x310]);
+
+    // Execution resumes from the restored state
+    machine.execute(cpu::Operation::LoadImm(0x0, 0xAA)).unwrap();
+    rewind.record_frame(&machine);
+    rewind.record_frame(&machine);
+    assert_eq!(9, rewind.len());
+    assert_eq!(2, rewind.rewind(&mut machine, 2).unwrap());
+    assert_eq!(15, machine.cpu.v[0x0]);
+
+    // Rewinding past the oldest snapshot restores the oldest snapshot
+    assert_eq!(14, rewind.rewind(&mut machine, 100).unwrap());
+    assert_eq!(1, machine.cpu.v[0x0]);
+    assert_eq!(1, rewind.len());
+}
+
+#[test]
+fn rewind_partial_snapshot() {
+    let mut machine = Machine::new();
+    let mut rewind = RewindBuffer::new(100, 4);
+
+    for frame in 0..10 {
+        machine.execute(cpu::Operation::LoadImm(0x0, frame)).unwrap();
+        rewind.record_frame(&machine);
+    }
+    assert_eq!(2, rewind.len());
+
+    // The two frames since the last snapshot are undone by restoring it
+    assert_eq!(2, rewind.rewind(&mut machine, 1).unwrap());
+    assert_eq!(7, machine.cpu.v[0x0]);
+    assert_eq!(2, rewind.len());
+
+    for frame in 0..3 {
+        machine.execute(cpu::Operation::LoadImm(0x0, 0x10 + frame)).unwrap();
+        rewind.record_frame(&machine);
+    }
+
+    // Older frames are undone a whole snapshot at a time
+    assert_eq!(7, rewind.rewind(&mut machine, 5).unwrap());
+    assert_eq!(3, machine.cpu.v[0x0]);
+    assert_eq!(1, rewind.len());
+}
+
+#[test]
+fn rewind_capacity() {
+    let mut machine = Machine::new();
+    let mut rewind = RewindBuffer::new(5, 1);
+
+    for frame in 0..20 {
+        machine.execute(cpu::Operation::LoadImm(0x0, frame)).unwrap();
+        rewind.record_frame(&machine);
+    }
+
+    // Only the five newest snapshots are kept
+    assert_eq!(5, rewind.len());
+    assert_eq!(4, rewind.rewind(&mut machine, 10).unwrap());
+    assert_eq!(15, machine.cpu.v[0x0]);
+}
@@ -0,0 +1,134 @@
+extern crate okto;
+
+use okto::cpu::Operation;
+use okto::keyboard;
+use okto::machine::{ExecutionState, Machine};
+use okto::random::XorShiftRandom;
+use okto::savestate::{SAVE_STATE_MAGIC, SAVE_STATE_VERSION};
+use okto::{OktoError, OktoErrorKind};
+
+#[test]
+fn save_state_round_trip() {
+    let mut machine = Machine::new();
+
+    machine.cpu.v[0x0..0x4].clone_from_slice(&[0x1, 0x2, 0x3, 0x4]);
+    machine.cpu.i = 0xABCD;
+    machine.cpu.pc = 0x246;
+    machine.cpu.push_stack(0x208).unwrap();
+    machine.cpu.push_stack(0x30A).unwrap();
+    machine.cpu.hp48[0x7] = 0x77;
+    machine.memory.data[0xFFFF] = 0x99;
+    machine.display.high_resolution = true;
+    machine.display.planes = 0x3;
+    machine.display.data[63][127] = 0x2;
+    machine.delay_timer.value = 0x30;
+    *machine.sound.timer.write().unwrap() = 0x40;
+    *machine.sound.pattern.write().unwrap() = Some([0xA5; 16]);
+    *machine.sound.pitch.write().unwrap() = 0x50;
+    machine.keyboard.keys[0xC] = keyboard::KeyState::Pressed;
+    machine.machine_on = false;
+    machine.execution_state = ExecutionState::WaitingForRelease(0x4, 0xB);
+    machine.cycles = 0x0123_4567_89AB;
+
+    let state = machine.save_state();
+    assert_eq!(&state[0..8], SAVE_STATE_MAGIC);
+    assert_eq!(&state[8..10], &[0x00, SAVE_STATE_VERSION as u8]);
+
+    let mut restored = Machine::new();
+    restored.load_state(&state).unwrap();
+
+    // Saving the restored machine reproduces the original state exactly
+    assert!(state == restored.save_state());
+
+    assert_eq!(machine.cpu.v, restored.cpu.v);
+    assert_eq!(0xABCD, restored.cpu.i);
+    assert_eq!(0x246, restored.cpu.pc);
+    assert_eq!(2, restored.cpu.sp);
+    assert_eq!(Some(0x30A), restored.cpu.pop_stack());
+    assert_eq!(Some(0x208), restored.cpu.pop_stack());
+    assert_eq!(0x77, restored.cpu.hp48[0x7]);
+    assert_eq!(&machine.memory.data[..], &restored.memory.data[..]);
+    assert!(restored.display.high_resolution);
+    assert_eq!(0x3, restored.display.planes);
+    assert_eq!(0x2, restored.display.pixel(127, 63));
+    assert_eq!(0x30, restored.delay_timer.value);
+    assert_eq!(0x40, *restored.sound.timer.read().unwrap());
+    assert_eq!(Some([0xA5; 16]), *restored.sound.pattern.read().unwrap());
+    assert_eq!(0x50, *restored.sound.pitch.read().unwrap());
+    assert_eq!(keyboard::KeyState::Pressed, restored.keyboard.keys[0xC]);
+    assert!(!restored.machine_on);
+    assert_eq!(ExecutionState::WaitingForRelease(0x4, 0xB), restored.execution_state);
+    assert_eq!(0x0123_4567_89AB, restored.cycles);
+}
+
+#[test]
+fn invalid_save_states() {
+    let mut machine = Machine::new();
+    machine.cpu.v[0x1] = 0x42;
+    let state = machine.save_state();
+
+    let mut target = Machine::new();
+
+    // Bad magic header
+    let mut bad_magic = state.clone();
+    bad_magic[0] = b'X';
+    assert_eq!(
+        Some(OktoError::new(OktoErrorKind::InvalidSaveState)),
+        target.load_state(&bad_magic).err()
+    );
+
+    // Version from the future
+    let mut future_version = state.clone();
+    future_version[8] = 0xFF;
+    assert_eq!(
+        Some(OktoError::new(OktoErrorKind::UnsupportedSaveStateVersion(
+            0xFF00 | SAVE_STATE_VERSION
+        ))),
+        target.load_state(&future_version).err()
+    );
+
+    // Truncated and oversized states
+    assert_eq!(
+        Some(OktoError::new(OktoErrorKind::InvalidSaveState)),
+        target.load_state(&state[..state.len() - 1]).err()
+    );
+    let mut oversized = state.clone();
+    oversized.push(0);
+    assert_eq!(
+        Some(OktoError::new(OktoErrorKind::InvalidSaveState)),
+        target.load_state(&oversized).err()
+    );
+
+    // Plane mask selecting planes that do not exist, after the header,
+    // registers, stack, HP48 flags, memory and resolution
+    let mut bad_planes = state.clone();
+    bad_planes[10 + 16 + 2 + 2 + 1 + 32 + 8 + 0x10000 + 1] = 0x4;
+    assert_eq!(
+        Some(OktoError::new(OktoErrorKind::InvalidSaveState)),
+        target.load_state(&bad_planes).err()
+    );
+
+    // A failed load leaves the machine untouched
+    assert_eq!(0, target.cpu.v[0x1]);
+    target.load_state(&state).unwrap();
+    assert_eq!(0x42, target.cpu.v[0x1]);
+}
+
+#[test]
+fn random_source_state() {
+    let mut machine = Machine::new();
+    machine.rng = Box::new(XorShiftRandom::new(1234));
+    machine.execute(Operation::RandAndImm(0x0, 0xFF)).unwrap();
+    let state = machine.save_state();
+
+    let mut restored = Machine::new();
+    restored.rng = Box::new(XorShiftRandom::new(1));
+    restored.load_state(&state).unwrap();
+
+    // Both machines continue with the same random sequence
+    for _ in 0..16 {
+        machine.execute(Operation::RandAndImm(0x0, 0xFF)).unwrap();
+        restored.execute(Operation::RandAndImm(0x0, 0xFF)).unwrap();
+        assert_eq!(machine.cpu.v[0x0], restored.cpu.v[0x0]);
+    }
+}
@@ -0,0 +1,129 @@
+extern crate okto;
+
+use okto::cpu::Operation;
+use okto::machine::Machine;
+use okto::trace::{RegisterSnapshot, TraceFormat, TraceRecord, Tracer};
+use okto::{OktoError, OktoErrorKind};
+
+use std::env;
+use std::fs;
+use std::process;
+
+/// I = 0x1234 (long form), V0 = 0x7B, I = 0x300, store V0 as BCD, V1 = V0,
+/// then loop forever.
+const ROM: [u8; 14] = [
+    0xF0, 0x00, 0x12, 0x34, 0x60, 0x7B, 0xA3, 0x00, 0xF0, 0x33, 0x81, 0x00, 0x12, 0x0C,
+];
+
+fn machine_with_tracer(tracer: Tracer) -> Machine {
+    let mut machine = Machine::new();
+    machine.memory.load(&ROM, 0x200, ROM.len()).unwrap();
+    machine.tracer = Some(tracer);
+    machine
+}
+
+fn trace_to_file(format: TraceFormat, steps: usize) -> Vec<u8> {
+    let path = env::temp_dir().join(format!("okto-trace-{}-{:?}", process::id(), format));
+    let mut machine = machine_with_tracer(Tracer::to_file(&path, format).unwrap());
+    for _ in 0..steps {
+        machine.step().unwrap();
+    }
+    machine.tracer.take().unwrap().finish().unwrap();
+
+    let data = fs::read(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    data
+}
+
+#[test]
+fn text_traces() {
+    let text = String::from_utf8(trace_to_file(TraceFormat::Text, 6)).unwrap();
+    let lines: Vec<&str> = text.lines().collect();
+
+    assert_eq!(6, lines.len());
+    assert!(lines[0].starts_with("0 0200 F000 1234 LoadAddrLong(4660) v=00"));
+    assert!(lines[0].ends_with("i=0000 -> v=00000000000000000000000000000000 i=1234"));
+    assert!(lines[3].starts_with("3 0208 F033 MemStoreBcd(0) v=7B"));
+    assert!(lines[3].ends_with(" w=0300:01,0301:02,0302:03"));
+    assert!(lines[5].starts_with("5 020C 120C Jump(524)"));
+}
+
+#[test]
+fn binary_traces() {
+    let data = trace_to_file(TraceFormat::Binary, 6);
+    let records = TraceRecord::read_binary(&data).unwrap();
+
+    assert_eq!(6, records.len());
+    assert_eq!(Some(0x1234), records[0].operand);
+    assert_eq!(Operation::LoadAddrLong(0x1234), records[0].operation);
+    assert_eq!(0x1234, records[0].after.i);
+    assert_eq!(vec![(0x300, 1), (0x301, 2), (0x302, 3)], records[3].writes);
+    assert_eq!(0x7B, records[4].after.v[0x1]);
+    assert_eq!(records[4].before.v[0x0], records[4].after.v[0x0]);
+
+    // Records round trip through the binary format exactly
+    let mut rewritten = data[..10].to_vec();
+    for record in records.iter() {
+        record.write_binary(&mut rewritten);
+    }
+    assert_eq!(data, rewritten);
+
+    assert_eq!(
+        Some(OktoError::new(OktoErrorKind::InvalidTrace)),
+        TraceRecord::read_binary(&data[..data.len() - 1]).err()
+    );
+    assert!(TraceRecord::read_binary(b"NOTATRCE\x00\x01").is_err());
+}
+
+#[test]
+fn filtered_ring_traces() {
+    let tracer = Tracer::ring(2).filter(0x204, 0x205).filter(0x20A, 0x20B);
+    assert!(tracer.traces(0x20A));
+    assert!(!tracer.traces(0x206));
+
+    let mut machine = machine_with_tracer(tracer);
+    for _ in 0..10 {
+        machine.step().unwrap();
+    }
+
+    let tracer = machine.tracer.take().unwrap();
+    let pcs: Vec<u16> = tracer.records().map(|record| record.pc).collect();
+    assert_eq!(vec![0x204, 0x20A], pcs);
+    let mut expected = RegisterSnapshot {
+        i: 0x300,
+        ..RegisterSnapshot::default()
+    };
+    expected.v[0x0] = 0x7B;
+    expected.v[0x1] = 0x7B;
+    assert_eq!(expected, tracer.records().last().unwrap().after);
+    tracer.finish().unwrap();
+
+    // A ring without capacity keeps nothing
+    let mut machine = machine_with_tracer(Tracer::ring(0));
+    machine.step().unwrap();
+    assert_eq!(0, machine.tracer.as_ref().unwrap().records().count());
+}
+
+#[test]
+fn trace_failing_instruction() {
+    // V0 = 5, then return with nothing on the stack
+    let mut machine = Machine::new();
+    machine
+        .memory
+        .load(&[0x60, 0x05, 0x00, 0xEE], 0x200, 4)
+        .unwrap();
+    machine.tracer = Some(Tracer::ring(4));
+
+    machine.step().unwrap();
+    assert_eq!(
+        Some(OktoError::new(OktoErrorKind::StackUnderflow)),
+        machine.step().err()
+    );
+
+    let tracer = machine.tracer.take().unwrap();
+    let records: Vec<&TraceRecord> = tracer.records().collect();
+    assert_eq!(2, records.len());
+    assert_eq!(0x202, records[1].pc);
+    assert_eq!(Operation::Ret, records[1].operation);
+    assert_eq!(0x05, records[1].after.v[0x0]);
+}
@@ -0,0 +1,95 @@
+extern crate clap;
+extern crate okto;
+
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+use std::process;
+
+use clap::{App, Arg};
+
+use okto::assembler::Program;
+use okto::{chipper, octo, OktoResult};
+
+/// Assemble the source in the given syntax.
+fn assemble(syntax: &str, source_path: &str, source: &str) -> OktoResult<Program> {
+    match syntax {
+        "chipper" => chipper::assemble(source_path, source, |path| fs::read_to_string(path)),
+        _ => octo::assemble(source_path, source),
+    }
+}
+
+/// Write the assembled ROM, and list its labels if asked to.
+fn write_program(program: &Program, output_path: &str, list_labels: bool) -> io::Result<()> {
+    let mut file = File::create(output_path)?;
+    file.write_all(&program.rom)?;
+
+    if list_labels {
+        for (name, address) in &program.labels {
+            println!("{:03X} {}", address, name);
+        }
+    }
+
+    Ok(())
+}
+
+fn main() -> io::Result<()> {
+    let matches = App::new("oktoasm")
+        .version("1.0")
+        .author("Eric Scrivner <eric.t.scrivner@gmail.com>")
+        .about("Assembles Octo or Chipper source code into Chip8 ROMs")
+        .arg(
+            Arg::with_name("SOURCE")
+                .help("Path to the Octo or Chipper source file.")
+                .required(true)
+                .index(1),
+        )
+        .arg(
+            Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .value_name("ROMFILE")
+                .help("path of the ROM to write, the source with a .ch8 extension by default")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("syntax")
+                .short("s")
+                .long("syntax")
+                .value_name("SYNTAX")
+                .help("assembly syntax of the source, chipper for .chp files and octo otherwise by default")
+                .possible_values(&["octo", "chipper"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("labels")
+                .short("l")
+                .long("labels")
+                .help("list the address of every label"),
+        )
+        .get_matches();
+
+    let source_path = matches.value_of("SOURCE").unwrap();
+    let output_path = match matches.value_of("output") {
+        Some(path) => String::from(path),
+        None => Path::new(source_path)
+            .with_extension("ch8")
+            .to_string_lossy()
+            .into_owned(),
+    };
+    let syntax = matches.value_of("syntax").unwrap_or_else(|| {
+        match Path::new(source_path).extension().and_then(|e| e.to_str()) {
+            Some("chp") => "chipper",
+            _ => "octo",
+        }
+    });
+    let source = fs::read_to_string(source_path)?;
+
+    match assemble(syntax, source_path, &source) {
+        Ok(program) => write_program(&program, &output_path, matches.is_present("labels")),
+        Err(error) => {
+            eprintln!("{}", error);
+            process::exit(1);
+        }
+    }
+}
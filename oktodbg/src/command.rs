@@ -0,0 +1,519 @@
+//! Parsing of debugger command lines.
+use okto::breakpoint::{BreakpointId, WatchAccess, WatchedRegister};
+use okto::expression::{Expression, LogMessage, Variable};
+use okto::isa::{self, OperationInfo};
+use okto::{OktoError, OktoErrorKind};
+
+/// Help text listing every command.
+pub const HELP: &str = "\
+Execution:
+  step [COUNT]              (s)    execute instructions, entering subroutines
+  next [COUNT]              (n)    execute instructions, stepping over calls
+  finish                    (fin)  run until the current subroutine returns
+  continue [COUNT]          (c)    run until a breakpoint, exit or key wait
+  reverse-step [COUNT]      (rs)   undo instructions
+  reverse-continue          (rc)   run backwards until a breakpoint
+  key KEY [up]                     press or release a key (0-F)
+Breakpoints:
+  break ADDR|OPNAME [if EXPR] (b)  stop at an address or on an operation
+  trace ADDR MESSAGE               log MESSAGE at an address, {EXPR} is replaced
+  watch REG|ADDR[:LEN]             stop when a register or memory is written
+  rwatch ADDR[:LEN]                stop when memory is read
+  awatch ADDR[:LEN]                stop when memory is read or written
+  cond ID [EXPR]                   set or clear a breakpoint condition
+  ignore ID COUNT                  ignore the next COUNT hits of a breakpoint
+  delete [ID]               (del)  delete one or every breakpoint
+  breakpoints               (bl)   list breakpoints
+Inspection:
+  regs                             show registers and timers
+  print EXPR                (p)    evaluate an expression
+  set TARGET EXPR                  set a register, timer or [ADDR] byte
+  write ADDR BYTE...               write bytes to memory
+  mem ADDR [LEN]            (x)    dump memory
+  disasm [ADDR [COUNT]]     (dis)  disassemble, around PC by default
+  stack                     (bt)   show the call stack
+  screen                           draw the display as text
+  help                      (h)    show this help
+  quit                      (q)    exit the debugger
+
+Expressions can use v0-vf, i, pc, sp, delay, sound and [ADDR] memory reads.
+Arguments other than the last cannot contain spaces. An empty line repeats
+the previous command.";
+
+/// What a watchpoint watches.
+#[derive(Clone, Debug, PartialEq)]
+pub enum WatchTarget {
+    /// A register
+    Register(WatchedRegister),
+    /// A range of memory
+    Memory {
+        address: Expression,
+        length: Option<Expression>,
+    },
+}
+
+/// A parsed debugger command.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    Help,
+    Step(u64),
+    Next(u64),
+    Finish,
+    Continue(Option<u64>),
+    ReverseStep(u64),
+    ReverseContinue,
+    Key {
+        key: u8,
+        pressed: bool,
+    },
+    Break {
+        address: Expression,
+        condition: Option<Expression>,
+    },
+    BreakOperation {
+        operation: &'static OperationInfo,
+        condition: Option<Expression>,
+    },
+    Trace {
+        address: Expression,
+        message: LogMessage,
+    },
+    Watch {
+        target: WatchTarget,
+        access: WatchAccess,
+    },
+    Condition {
+        id: BreakpointId,
+        condition: Option<Expression>,
+    },
+    Ignore {
+        id: BreakpointId,
+        count: u64,
+    },
+    Delete(Option<BreakpointId>),
+    Breakpoints,
+    Registers,
+    Print(Expression),
+    Set {
+        target: Expression,
+        value: Expression,
+    },
+    Write {
+        address: Expression,
+        bytes: Vec<Expression>,
+    },
+    Memory {
+        address: Expression,
+        length: Option<Expression>,
+    },
+    Disassemble {
+        address: Option<Expression>,
+        count: Option<u64>,
+    },
+    Stack,
+    Screen,
+    Quit,
+}
+
+impl Command {
+    /// Parse a command line. Returns `None` for an empty line.
+    pub fn parse(line: &str) -> Result<Option<Command>, String> {
+        let line = line.trim();
+        let (name, rest) = split_word(line);
+        if name.is_empty() {
+            return Ok(None);
+        }
+
+        let command = match name {
+            "help" | "h" => Command::Help,
+            "step" | "s" => Command::Step(parse_optional_count(rest)?.unwrap_or(1)),
+            "next" | "n" => Command::Next(parse_optional_count(rest)?.unwrap_or(1)),
+            "finish" | "fin" => Command::Finish,
+            "continue" | "c" => Command::Continue(parse_optional_count(rest)?),
+            "reverse-step" | "rs" => Command::ReverseStep(parse_optional_count(rest)?.unwrap_or(1)),
+            "reverse-continue" | "rc" => Command::ReverseContinue,
+            "key" => {
+                let (key, state) = split_word(rest);
+                let key = u8::from_str_radix(key, 16)
+                    .ok()
+                    .filter(|&key| key < 0x10)
+                    .ok_or_else(|| format!("invalid key '{}'", key))?;
+                let pressed = match state {
+                    "" | "down" => true,
+                    "up" => false,
+                    _ => return Err(format!("expected 'up' or 'down', found '{}'", state)),
+                };
+                Command::Key { key, pressed }
+            }
+            "break" | "b" => {
+                let (location, rest) = split_word(rest);
+                let condition = match split_word(rest) {
+                    ("", _) => None,
+                    ("if", condition) => Some(parse_expression(condition)?),
+                    (word, _) => return Err(format!("expected 'if', found '{}'", word)),
+                };
+                if let Some(operation) = isa::find(location) {
+                    Command::BreakOperation {
+                        operation,
+                        condition,
+                    }
+                } else {
+                    let address = parse_expression(location).map_err(|error| {
+                        if location.starts_with(|c: char| c.is_ascii_uppercase())
+                            && Variable::from_name(location).is_none()
+                        {
+                            format!("unknown operation '{}'", location)
+                        } else {
+                            error
+                        }
+                    })?;
+                    Command::Break { address, condition }
+                }
+            }
+            "trace" => {
+                let (address, message) = split_word(rest);
+                Command::Trace {
+                    address: parse_expression(address)?,
+                    message: LogMessage::parse(message).map_err(|error| error_message(&error))?,
+                }
+            }
+            "watch" => Command::Watch {
+                target: parse_watch_target(rest, true)?,
+                access: WatchAccess::Write,
+            },
+            "rwatch" => Command::Watch {
+                target: parse_watch_target(rest, false)?,
+                access: WatchAccess::Read,
+            },
+            "awatch" => Command::Watch {
+                target: parse_watch_target(rest, false)?,
+                access: WatchAccess::ReadWrite,
+            },
+            "cond" => {
+                let (id, condition) = split_word(rest);
+                Command::Condition {
+                    id: parse_id(id)?,
+                    condition: parse_optional_expression(condition)?,
+                }
+            }
+            "ignore" => {
+                let (id, count) = split_word(rest);
+                Command::Ignore {
+                    id: parse_id(id)?,
+                    count: parse_count(count)?,
+                }
+            }
+            "delete" | "del" => Command::Delete(if rest.is_empty() {
+                None
+            } else {
+                Some(parse_id(rest)?)
+            }),
+            "breakpoints" | "bl" => Command::Breakpoints,
+            "regs" => Command::Registers,
+            "print" | "p" => Command::Print(parse_expression(rest)?),
+            "set" => {
+                let (target, value) = split_word(rest);
+                let target = parse_expression(target)?;
+                match target {
+                    Expression::Variable(_) | Expression::Memory(_) => {}
+                    _ => return Err(format!("cannot assign to '{}'", target)),
+                }
+                Command::Set {
+                    target,
+                    value: parse_expression(value)?,
+                }
+            }
+            "write" => {
+                let (address, bytes) = split_word(rest);
+                let bytes = bytes
+                    .split_whitespace()
+                    .map(parse_expression)
+                    .collect::<Result<Vec<_>, _>>()?;
+                if bytes.is_empty() {
+                    return Err(String::from("expected bytes to write"));
+                }
+                Command::Write {
+                    address: parse_expression(address)?,
+                    bytes,
+                }
+            }
+            "mem" | "x" => {
+                let (address, length) = split_word(rest);
+                Command::Memory {
+                    address: parse_expression(address)?,
+                    length: parse_optional_expression(length)?,
+                }
+            }
+            "disasm" | "dis" => {
+                let (address, count) = split_word(rest);
+                Command::Disassemble {
+                    address: parse_optional_expression(address)?,
+                    count: parse_optional_count(count)?,
+                }
+            }
+            "stack" | "bt" => Command::Stack,
+            "screen" => Command::Screen,
+            "quit" | "q" => Command::Quit,
+            _ => return Err(format!("unknown command '{}', try 'help'", name)),
+        };
+
+        Ok(Some(command))
+    }
+}
+
+/// Split off the first whitespace separated word, returning it and the
+/// remainder of the line with surrounding whitespace removed.
+fn split_word(line: &str) -> (&str, &str) {
+    let line = line.trim();
+    match line.find(char::is_whitespace) {
+        Some(index) => (&line[..index], line[index..].trim()),
+        None => (line, ""),
+    }
+}
+
+/// Returns the detail of an error for display to the user.
+pub fn error_message(error: &OktoError) -> String {
+    match error.kind {
+        OktoErrorKind::InvalidExpression(ref message) => message.clone(),
+        _ => String::from(error.description()),
+    }
+}
+
+fn parse_expression(source: &str) -> Result<Expression, String> {
+    Expression::parse(source).map_err(|error| error_message(&error))
+}
+
+fn parse_optional_expression(source: &str) -> Result<Option<Expression>, String> {
+    if source.is_empty() {
+        return Ok(None);
+    }
+
+    parse_expression(source).map(Some)
+}
+
+fn parse_count(source: &str) -> Result<u64, String> {
+    source
+        .parse()
+        .map_err(|_| format!("invalid count '{}'", source))
+}
+
+fn parse_optional_count(source: &str) -> Result<Option<u64>, String> {
+    if source.is_empty() {
+        return Ok(None);
+    }
+
+    parse_count(source).map(Some)
+}
+
+fn parse_id(source: &str) -> Result<BreakpointId, String> {
+    source
+        .parse()
+        .map_err(|_| format!("invalid breakpoint number '{}'", source))
+}
+
+/// Parse a register name, if allowed, or an `ADDR[:LEN]` memory range.
+fn parse_watch_target(source: &str, allow_register: bool) -> Result<WatchTarget, String> {
+    if allow_register {
+        let register = match Variable::from_name(source) {
+            Some(Variable::V(vx)) => Some(WatchedRegister::V(vx)),
+            Some(Variable::I) => Some(WatchedRegister::I),
+            Some(Variable::Sp) => Some(WatchedRegister::Sp),
+            Some(variable) => return Err(format!("cannot watch '{}'", variable)),
+            None => None,
+        };
+        if let Some(register) = register {
+            return Ok(WatchTarget::Register(register));
+        }
+    }
+
+    let mut parts = source.splitn(2, ':');
+    let address = parse_expression(parts.next().unwrap_or(""))?;
+    let length = match parts.next() {
+        Some(length) => Some(parse_expression(length)?),
+        None => None,
+    };
+    Ok(WatchTarget::Memory { address, length })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(line: &str) -> Command {
+        Command::parse(line).unwrap().unwrap()
+    }
+
+    fn expression(source: &str) -> Expression {
+        Expression::parse(source).unwrap()
+    }
+
+    #[test]
+    fn parse_execution() {
+        assert_eq!(None, Command::parse("   ").unwrap());
+        assert_eq!(Command::Step(1), parse("s"));
+        assert_eq!(Command::Next(10), parse("next 10"));
+        assert_eq!(Command::Continue(None), parse("c"));
+        assert_eq!(Command::ReverseStep(3), parse("  rs   3  "));
+        assert_eq!(
+            Command::Key {
+                key: 0xA,
+                pressed: false
+            },
+            parse("key a up")
+        );
+
+        assert_eq!(
+            Err(String::from("invalid count 'x'")),
+            Command::parse("step x")
+        );
+        assert_eq!(
+            Err(String::from("invalid key '10'")),
+            Command::parse("key 10")
+        );
+        assert_eq!(
+            Err(String::from("expected 'up' or 'down', found 'left'")),
+            Command::parse("key 1 left")
+        );
+        assert_eq!(
+            Err(String::from("unknown command 'jump', try 'help'")),
+            Command::parse("jump 0x200")
+        );
+    }
+
+    #[test]
+    fn parse_breakpoints() {
+        assert_eq!(
+            Command::Break {
+                address: expression("0x200 + 4"),
+                condition: None,
+            },
+            parse("b 0x200+4")
+        );
+        assert_eq!(
+            Command::Break {
+                address: expression("0x20A"),
+                condition: Some(expression("v0 == 3 && [i] != 0")),
+            },
+            parse("break 0x20A if v0 == 3 && [i] != 0")
+        );
+        assert_eq!(
+            Command::BreakOperation {
+                operation: isa::find("Ret").unwrap(),
+                condition: Some(expression("sp > 1")),
+            },
+            parse("break Ret if sp > 1")
+        );
+        assert_eq!(
+            Command::Condition {
+                id: 2,
+                condition: None,
+            },
+            parse("cond 2")
+        );
+        assert_eq!(Command::Ignore { id: 1, count: 5 }, parse("ignore 1 5"));
+        assert_eq!(Command::Delete(None), parse("del"));
+
+        assert_eq!(
+            Err(String::from("unknown operation 'Return'")),
+            Command::parse("break Return")
+        );
+        assert_eq!(
+            Err(String::from("expected 'if', found 'when'")),
+            Command::parse("break 0x200 when v0 == 1")
+        );
+        assert!(Command::parse("break 0x200 if v0 ==").is_err());
+        assert!(Command::parse("break vg").is_err());
+        assert_eq!(
+            Err(String::from("invalid breakpoint number 'one'")),
+            Command::parse("delete one")
+        );
+    }
+
+    #[test]
+    fn parse_watchpoints() {
+        assert_eq!(
+            Command::Watch {
+                target: WatchTarget::Register(WatchedRegister::V(0xF)),
+                access: WatchAccess::Write,
+            },
+            parse("watch vf")
+        );
+        assert_eq!(
+            Command::Watch {
+                target: WatchTarget::Memory {
+                    address: expression("0x300"),
+                    length: Some(expression("2")),
+                },
+                access: WatchAccess::Read,
+            },
+            parse("rwatch 0x300:2")
+        );
+        assert_eq!(
+            Command::Watch {
+                target: WatchTarget::Memory {
+                    address: expression("i"),
+                    length: None,
+                },
+                access: WatchAccess::ReadWrite,
+            },
+            parse("awatch i")
+        );
+
+        assert_eq!(
+            Err(String::from("cannot watch 'pc'")),
+            Command::parse("watch pc")
+        );
+        assert!(Command::parse("watch 0x300:").is_err());
+    }
+
+    #[test]
+    fn parse_inspection() {
+        assert_eq!(
+            Command::Print(expression("(v1 << 2) | 1")),
+            parse("p (v1 << 2) | 1")
+        );
+        assert_eq!(
+            Command::Set {
+                target: expression("[i + 1]"),
+                value: expression("v0 - 1"),
+            },
+            parse("set [i+1] v0 - 1")
+        );
+        assert_eq!(
+            Command::Write {
+                address: expression("0x300"),
+                bytes: vec![expression("0xAB"), expression("v2")],
+            },
+            parse("write 0x300 0xAB v2")
+        );
+        assert_eq!(
+            Command::Memory {
+                address: expression("pc"),
+                length: Some(expression("16")),
+            },
+            parse("x pc 16")
+        );
+        assert_eq!(
+            Command::Disassemble {
+                address: None,
+                count: None,
+            },
+            parse("dis")
+        );
+
+        assert_eq!(
+            Err(String::from("cannot assign to '0x1'")),
+            Command::parse("set 1 2")
+        );
+        assert_eq!(
+            Err(String::from("expected bytes to write")),
+            Command::parse("write 0x300")
+        );
+        assert!(Command::parse("print").is_err());
+        assert!(Command::parse("mem 0x300 +").is_err());
+        assert_eq!(
+            Err(String::from("invalid count '-1'")),
+            Command::parse("disasm 0x200 -1")
+        );
+    }
+}
@@ -0,0 +1,593 @@
+//! Execution of debugger commands against a machine.
+use okto::breakpoint::{
+    Breakpoint, BreakpointEntry, BreakpointHit, HitCondition, HitReason, WatchedRegister,
+};
+use okto::cpu::{self, Address, Operation};
+use okto::expression::{Expression, Variable};
+use okto::isa::{self, Syntax};
+use okto::machine::{ExecutionState, Machine, RunLimit, StopReason};
+use okto::memory::MEMORY_SIZE_BYTES;
+
+use command::{error_message, Command, WatchTarget, HELP};
+
+/// Default number of instructions `continue` runs before giving up, so that
+/// a ROM spinning in a loop does not hang the debugger.
+pub const DEFAULT_CONTINUE_BUDGET: u64 = 1_000_000;
+
+/// Number of instructions shown by `disasm` without a count.
+const DEFAULT_DISASSEMBLY_COUNT: u64 = 8;
+
+/// Number of instructions `disasm` shows before the PC by default.
+const DISASSEMBLY_CONTEXT: Address = 3;
+
+/// Number of bytes shown by `mem` without a length.
+const DEFAULT_DUMP_LENGTH: u32 = 64;
+
+/// Number of bytes shown on each line of a memory dump.
+const BYTES_PER_DUMP_LINE: usize = 16;
+
+/// Characters used to draw each display colour.
+const SCREEN_CHARS: [char; 4] = ['.', '#', 'o', '@'];
+
+/// Whether the debugger should keep reading commands.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Flow {
+    Continue,
+    Quit,
+}
+
+/// Debugger session wrapping a machine.
+pub struct Debugger {
+    /// The machine being debugged
+    pub machine: Machine,
+    /// Number of instructions per 60 Hz timer tick
+    pub cycles_per_frame: u32,
+}
+
+impl Debugger {
+    /// Create a debugger for the given machine.
+    pub fn new(machine: Machine, cycles_per_frame: u32) -> Self {
+        Self {
+            machine,
+            cycles_per_frame,
+        }
+    }
+
+    /// Execute a command, printing its output.
+    pub fn execute(&mut self, command: &Command) -> Result<Flow, String> {
+        match *command {
+            Command::Help => println!("{}", HELP),
+            Command::Step(count) => {
+                let reason = self.run(RunLimit::instructions(count));
+                self.report_stop(reason, None);
+            }
+            Command::Next(count) => {
+                let mut reason = StopReason::BudgetExhausted;
+                for _ in 0..count {
+                    reason = self.next();
+                    if !is_quiet_stop(&reason) {
+                        break;
+                    }
+                }
+                self.report_stop(reason, None);
+            }
+            Command::Finish => {
+                let sp = self.machine.cpu.sp;
+                if sp == 0 {
+                    return Err(String::from("not inside a subroutine"));
+                }
+                let return_address = self.machine.cpu.stack[sp as usize - 1];
+                let reason = self.run_to(return_address, sp - 1);
+                self.report_stop(reason, None);
+            }
+            Command::Continue(count) => {
+                let count = count.unwrap_or(DEFAULT_CONTINUE_BUDGET);
+                let reason = self.run(RunLimit::instructions(count));
+                self.report_stop(reason, Some(count));
+            }
+            Command::ReverseStep(count) => {
+                let reason = self.run_back(RunLimit::instructions(count));
+                self.report_stop(reason, None);
+            }
+            Command::ReverseContinue => {
+                let reason = self.run_back(RunLimit::default());
+                self.report_stop(reason, None);
+            }
+            Command::Key { key, pressed } => {
+                if pressed {
+                    self.machine.keyboard.press_key(key);
+                } else {
+                    self.machine.keyboard.release_key(key);
+                }
+            }
+            Command::Break {
+                ref address,
+                ref condition,
+            } => {
+                let address = self.address(address)?;
+                let mut entry = BreakpointEntry::new(Breakpoint::Pc(address));
+                entry.condition = condition.clone();
+                let id = self.machine.add_breakpoint(entry);
+                println!("Breakpoint {} at {:03X}", id, address);
+            }
+            Command::BreakOperation {
+                operation,
+                ref condition,
+            } => {
+                let mut entry = BreakpointEntry::new(Breakpoint::Operation(operation));
+                entry.condition = condition.clone();
+                let id = self.machine.add_breakpoint(entry);
+                match operation.syntax(Syntax::Octo) {
+                    Some(syntax) => {
+                        println!("Breakpoint {} on {} ({})", id, operation.name, syntax)
+                    }
+                    None => println!("Breakpoint {} on {}", id, operation.name),
+                }
+            }
+            Command::Trace {
+                ref address,
+                ref message,
+            } => {
+                let address = self.address(address)?;
+                let entry = BreakpointEntry::new(Breakpoint::Pc(address)).log(message.clone());
+                let id = self.machine.add_breakpoint(entry);
+                println!("Tracepoint {} at {:03X}", id, address);
+            }
+            Command::Watch { ref target, access } => {
+                let breakpoint = match *target {
+                    WatchTarget::Register(register) => Breakpoint::Register(register),
+                    WatchTarget::Memory {
+                        ref address,
+                        ref length,
+                    } => {
+                        let start = self.address(address)?;
+                        let length = match *length {
+                            Some(ref length) => self.machine.evaluate(length),
+                            None => 1,
+                        };
+                        if length == 0 || length > MEMORY_SIZE_BYTES as u32 - start as u32 {
+                            return Err(String::from("watched range is outside of memory"));
+                        }
+                        Breakpoint::Memory {
+                            start,
+                            end: (start as u32 + length - 1) as Address,
+                            access,
+                        }
+                    }
+                };
+                let description = describe_breakpoint(&breakpoint);
+                let id = self.machine.add_breakpoint(breakpoint);
+                println!("Watchpoint {}: {}", id, description);
+            }
+            Command::Condition { id, ref condition } => {
+                self.breakpoint_mut(id)?.condition = condition.clone();
+            }
+            Command::Ignore { id, count } => {
+                let entry = self.breakpoint_mut(id)?;
+                entry.hit_condition = if count == 0 {
+                    HitCondition::Always
+                } else {
+                    HitCondition::AtLeast(entry.hits() + count + 1)
+                };
+            }
+            Command::Delete(Some(id)) => {
+                if self.machine.remove_breakpoint(id).is_none() {
+                    return Err(format!("no breakpoint number {}", id));
+                }
+            }
+            Command::Delete(None) => self.machine.clear_breakpoints(),
+            Command::Breakpoints => self.print_breakpoints(),
+            Command::Registers => self.print_registers(),
+            Command::Print(ref expression) => {
+                let value = self.machine.evaluate(expression);
+                println!("{} = {} ({:#X})", expression, value, value);
+            }
+            Command::Set {
+                ref target,
+                ref value,
+            } => {
+                let value = self.machine.evaluate(value);
+                self.set(target, value)?;
+            }
+            Command::Write {
+                ref address,
+                ref bytes,
+            } => {
+                let address = self.address(address)?;
+                for (offset, byte) in bytes.iter().enumerate() {
+                    let value = self.byte(byte)?;
+                    let byte_address = address.wrapping_add(offset as Address);
+                    self.machine.memory.data[byte_address as usize] = value;
+                }
+            }
+            Command::Memory {
+                ref address,
+                ref length,
+            } => {
+                let address = self.address(address)?;
+                let length = match *length {
+                    Some(ref length) => self.machine.evaluate(length),
+                    None => DEFAULT_DUMP_LENGTH,
+                };
+                self.print_memory(address, length as usize);
+            }
+            Command::Disassemble { ref address, count } => {
+                let address = match *address {
+                    Some(ref address) => self.address(address)?,
+                    None => self
+                        .machine
+                        .cpu
+                        .pc
+                        .saturating_sub(DISASSEMBLY_CONTEXT * cpu::INSTRUCTION_BYTES),
+                };
+                self.print_disassembly(address, count.unwrap_or(DEFAULT_DISASSEMBLY_COUNT));
+            }
+            Command::Stack => self.print_stack(),
+            Command::Screen => self.print_screen(),
+            Command::Quit => return Ok(Flow::Quit),
+        }
+
+        Ok(Flow::Continue)
+    }
+
+    /// Run the machine within the given limit, ticking the timers at the
+    /// debugger's frame rate.
+    fn run(&mut self, limit: RunLimit) -> StopReason {
+        let limit = RunLimit {
+            cycles_per_frame: self.cycles_per_frame,
+            ..limit
+        };
+        self.machine.run_until(&limit)
+    }
+
+    /// Run the machine backwards through its history.
+    fn run_back(&mut self, limit: RunLimit) -> StopReason {
+        let limit = RunLimit {
+            cycles_per_frame: self.cycles_per_frame,
+            ..limit
+        };
+        self.machine.run_back(&limit)
+    }
+
+    /// Execute one instruction, running a called subroutine to completion.
+    fn next(&mut self) -> StopReason {
+        let pc = self.machine.cpu.pc;
+        let running = self.machine.execution_state == ExecutionState::Running;
+        match self.machine.memory.read_operation(pc) {
+            Some((Operation::Call(_), size)) if running => {
+                let sp = self.machine.cpu.sp;
+                self.run_to(pc.wrapping_add(size), sp)
+            }
+            _ => self.run(RunLimit::instructions(1)),
+        }
+    }
+
+    /// Run until the program counter reaches the address with the stack no
+    /// deeper than `sp`, so that recursive calls are run through.
+    fn run_to(&mut self, address: Address, sp: u8) -> StopReason {
+        let limit = RunLimit::instructions(DEFAULT_CONTINUE_BUDGET).until_pc(address);
+        loop {
+            match self.run(limit.clone()) {
+                StopReason::PcReached(_) if self.machine.cpu.sp > sp => continue,
+                reason => return reason,
+            }
+        }
+    }
+
+    fn breakpoint_mut(&mut self, id: u32) -> Result<&mut BreakpointEntry, String> {
+        self.machine
+            .breakpoint_mut(id)
+            .ok_or_else(|| format!("no breakpoint number {}", id))
+    }
+
+    /// Evaluate an expression that must produce an address.
+    fn address(&self, expression: &Expression) -> Result<Address, String> {
+        let value = self.machine.evaluate(expression);
+        if value as usize >= MEMORY_SIZE_BYTES {
+            return Err(format!("address {:#X} is outside of memory", value));
+        }
+        Ok(value as Address)
+    }
+
+    /// Evaluate an expression that must produce a byte.
+    fn byte(&self, expression: &Expression) -> Result<u8, String> {
+        let value = self.machine.evaluate(expression);
+        if value > 0xFF {
+            return Err(format!("value {:#X} does not fit in a byte", value));
+        }
+        Ok(value as u8)
+    }
+
+    /// Assign a value to a register, timer or memory byte.
+    fn set(&mut self, target: &Expression, value: u32) -> Result<(), String> {
+        let too_large = || format!("value {:#X} is too large for '{}'", value, target);
+        let byte = if value <= 0xFF {
+            Some(value as u8)
+        } else {
+            None
+        };
+        let word = if value <= 0xFFFF {
+            Some(value as Address)
+        } else {
+            None
+        };
+
+        match *target {
+            Expression::Variable(variable) => match variable {
+                Variable::V(vx) => self.machine.cpu.v[vx as usize] = byte.ok_or_else(too_large)?,
+                Variable::I => self.machine.cpu.i = word.ok_or_else(too_large)?,
+                Variable::Pc => self.machine.cpu.pc = word.ok_or_else(too_large)?,
+                Variable::Sp => {
+                    let sp = byte
+                        .filter(|&sp| sp as usize <= cpu::MAX_NUM_STACK_ITEMS)
+                        .ok_or_else(too_large)?;
+                    self.machine.cpu.sp = sp;
+                }
+                Variable::Delay => self.machine.delay_timer.value = byte.ok_or_else(too_large)?,
+                Variable::Sound => {
+                    *self.machine.sound.timer.write().unwrap() = byte.ok_or_else(too_large)?
+                }
+            },
+            Expression::Memory(ref address) => {
+                let address = self.address(address)?;
+                self.machine.memory.data[address as usize] = byte.ok_or_else(too_large)?;
+            }
+            _ => return Err(format!("cannot assign to '{}'", target)),
+        }
+
+        Ok(())
+    }
+
+    /// Print any tracepoint messages, describe why the machine stopped, then
+    /// show the next instruction. Running out of the given budget is only
+    /// reported if one is given.
+    fn report_stop(&mut self, reason: StopReason, budget: Option<u64>) {
+        for message in self.machine.take_log_messages() {
+            println!("{}", message);
+        }
+
+        match reason {
+            StopReason::Exit => println!("Program exited"),
+            StopReason::Error(ref error) => println!("Error: {}", error_message(error)),
+            StopReason::BreakpointHit(hit) => println!("{}", describe_hit(hit)),
+            StopReason::WaitingForKey => {
+                println!("Waiting for a key, use 'key' to press one");
+            }
+            StopReason::BudgetExhausted => {
+                if let Some(budget) = budget {
+                    println!("Stopped after {} instructions", budget);
+                }
+            }
+            StopReason::HistoryExhausted => println!("Reached the start of the history"),
+            StopReason::PcReached(_)
+            | StopReason::MemoryWritten(_)
+            | StopReason::DisplayChanged => {}
+        }
+
+        if self.machine.machine_on {
+            let pc = self.machine.cpu.pc;
+            self.print_disassembly(pc, 1);
+        }
+    }
+
+    fn print_breakpoints(&self) {
+        if self.machine.breakpoints().is_empty() {
+            println!("No breakpoints");
+            return;
+        }
+
+        for &(id, ref entry) in self.machine.breakpoints().iter() {
+            let description = match entry.breakpoint {
+                Breakpoint::Pc(address) if entry.is_tracepoint() => {
+                    format!("trace at {:03X}", address)
+                }
+                ref breakpoint => describe_breakpoint(breakpoint),
+            };
+            let mut line = format!("{:<3} {:<28} hits {}", id, description, entry.hits());
+            if let Some(ref condition) = entry.condition {
+                line.push_str(&format!(" if {}", condition));
+            }
+            match entry.hit_condition {
+                HitCondition::Always => {}
+                HitCondition::Equal(count) => line.push_str(&format!(" on hit {}", count)),
+                HitCondition::AtLeast(count) => line.push_str(&format!(" from hit {}", count)),
+                HitCondition::Multiple(count) => line.push_str(&format!(" every {} hits", count)),
+            }
+            if let Some(ref message) = entry.log_message {
+                line.push_str(&format!(" log \"{}\"", message));
+            }
+            println!("{}", line);
+        }
+    }
+
+    fn print_registers(&self) {
+        let cpu = &self.machine.cpu;
+        for (row, values) in cpu.v.chunks(8).enumerate() {
+            let registers: Vec<String> = values
+                .iter()
+                .enumerate()
+                .map(|(column, value)| format!("V{:X} {:02X}", row * 8 + column, value))
+                .collect();
+            println!("{}", registers.join("  "));
+        }
+        println!(
+            "I  {:04X}  PC {:04X}  SP {:X}  DT {:02X}  ST {:02X}  cycles {}",
+            cpu.i,
+            cpu.pc,
+            cpu.sp,
+            self.machine.delay_timer.value,
+            *self.machine.sound.timer.read().unwrap(),
+            self.machine.cycles
+        );
+        if let ExecutionState::WaitingForKey(vx) | ExecutionState::WaitingForRelease(vx, _) =
+            self.machine.execution_state
+        {
+            println!("Waiting for a key into V{:X}", vx);
+        }
+    }
+
+    fn print_memory(&self, address: Address, length: usize) {
+        let end = (address as usize + length).min(MEMORY_SIZE_BYTES);
+        let data = &self.machine.memory.data[address as usize..end];
+
+        for (line, bytes) in data.chunks(BYTES_PER_DUMP_LINE).enumerate() {
+            let hex: Vec<String> = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+            let text: String = bytes
+                .iter()
+                .map(|&byte| {
+                    if byte.is_ascii_graphic() || byte == b' ' {
+                        byte as char
+                    } else {
+                        '.'
+                    }
+                })
+                .collect();
+            println!(
+                "{:04X}  {:<width$}  {}",
+                address as usize + line * BYTES_PER_DUMP_LINE,
+                hex.join(" "),
+                text,
+                width = BYTES_PER_DUMP_LINE * 3 - 1
+            );
+        }
+    }
+
+    /// Print `count` instructions starting at the address, marking the PC
+    /// with `=>` and breakpoints with `*`.
+    fn print_disassembly(&self, address: Address, count: u64) {
+        let memory = &self.machine.memory;
+        let mut address = address as usize;
+
+        for _ in 0..count {
+            if address + 1 >= MEMORY_SIZE_BYTES {
+                break;
+            }
+
+            let current = address as Address;
+            let marker = if current == self.machine.cpu.pc {
+                "=>"
+            } else {
+                "  "
+            };
+            let breakpoint = self
+                .machine
+                .breakpoints()
+                .iter()
+                .any(|(_, entry)| entry.breakpoint == Breakpoint::Pc(current));
+            let instruction = memory.read_instruction(current).unwrap_or(0);
+
+            let (words, text, size) = match memory.read_operation(current) {
+                Some((operation, size)) if size == cpu::LONG_INSTRUCTION_BYTES => (
+                    format!(
+                        "{:04X} {:04X}",
+                        instruction,
+                        memory
+                            .read_instruction(current + cpu::INSTRUCTION_BYTES)
+                            .unwrap_or(0)
+                    ),
+                    operation_text(&operation),
+                    size,
+                ),
+                Some((operation, size)) => (
+                    format!("{:04X}", instruction),
+                    operation_text(&operation),
+                    size,
+                ),
+                None => (
+                    format!("{:04X}", instruction),
+                    String::from("UNKNOWN"),
+                    cpu::INSTRUCTION_BYTES,
+                ),
+            };
+
+            println!(
+                "{} {} {:03X}  {:<9}  {}",
+                marker,
+                if breakpoint { "*" } else { " " },
+                current,
+                words,
+                text
+            );
+            address += size as usize;
+        }
+    }
+
+    fn print_stack(&self) {
+        let cpu = &self.machine.cpu;
+        println!("#0  {:03X}", cpu.pc);
+        for (depth, address) in cpu.stack[..cpu.sp as usize].iter().rev().enumerate() {
+            println!("#{:<2} {:03X}", depth + 1, address);
+        }
+    }
+
+    fn print_screen(&self) {
+        let display = &self.machine.display;
+        let border = format!("+{}+", "-".repeat(display.width()));
+
+        println!("{}", border);
+        for y in 0..display.height() {
+            let row: String = (0..display.width())
+                .map(|x| SCREEN_CHARS[display.pixel(x, y) as usize])
+                .collect();
+            println!("|{}|", row);
+        }
+        println!("{}", border);
+    }
+}
+
+/// Returns true for stop reasons that do not interrupt a sequence of steps.
+fn is_quiet_stop(reason: &StopReason) -> bool {
+    matches!(
+        *reason,
+        StopReason::PcReached(_) | StopReason::BudgetExhausted
+    )
+}
+
+fn describe_register(register: WatchedRegister) -> String {
+    match register {
+        WatchedRegister::V(vx) => format!("V{:X}", vx),
+        WatchedRegister::I => String::from("I"),
+        WatchedRegister::Sp => String::from("SP"),
+    }
+}
+
+fn describe_breakpoint(breakpoint: &Breakpoint) -> String {
+    match *breakpoint {
+        Breakpoint::Pc(address) => format!("break at {:03X}", address),
+        Breakpoint::Memory { start, end, access } if start == end => {
+            format!("watch {:03X} {:?}", start, access)
+        }
+        Breakpoint::Memory { start, end, access } => {
+            format!("watch {:03X}-{:03X} {:?}", start, end, access)
+        }
+        Breakpoint::Register(register) => format!("watch {}", describe_register(register)),
+        Breakpoint::Operation(info) => format!("break on {}", info.name),
+    }
+}
+
+fn describe_hit(hit: BreakpointHit) -> String {
+    match hit.reason {
+        HitReason::Pc(address) => format!("Breakpoint {} at {:03X}", hit.id, address),
+        HitReason::Operation(operation) => {
+            format!("Breakpoint {} on {}", hit.id, operation_text(&operation))
+        }
+        HitReason::MemoryRead(address) => {
+            format!("Watchpoint {}: read of {:03X}", hit.id, address)
+        }
+        HitReason::MemoryWrite(address) => {
+            format!("Watchpoint {}: write to {:03X}", hit.id, address)
+        }
+        HitReason::RegisterChanged { register, old, new } => format!(
+            "Watchpoint {}: {} changed from {:X} to {:X}",
+            hit.id,
+            describe_register(register),
+            old,
+            new
+        ),
+    }
+}
+
+/// Write an operation in Octo syntax, or by name if Octo has no mnemonic.
+fn operation_text(operation: &Operation) -> String {
+    isa::format(operation, Syntax::Octo).unwrap_or_else(|| format!("{:?}", operation))
+}
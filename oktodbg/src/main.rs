@@ -0,0 +1,181 @@
+extern crate clap;
+extern crate okto;
+
+mod command;
+mod debugger;
+
+use std::io::{self, BufRead, Write};
+
+use clap::{App, Arg};
+
+use okto::gdb;
+use okto::history;
+use okto::machine::Machine;
+use okto::memory;
+use okto::quirks::Quirks;
+use okto::random::XorShiftRandom;
+
+use command::Command;
+use debugger::{Debugger, Flow};
+
+/// Read commands from standard input until the user quits or input ends.
+fn run_repl(debugger: &mut Debugger) -> io::Result<()> {
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    let mut last_command = None;
+
+    loop {
+        print!("(oktodbg) ");
+        io::stdout().flush()?;
+
+        let line = match lines.next() {
+            Some(line) => line?,
+            None => return Ok(()),
+        };
+
+        let command = match Command::parse(&line) {
+            Ok(Some(command)) => command,
+            Ok(None) => match last_command.clone() {
+                Some(command) => command,
+                None => continue,
+            },
+            Err(message) => {
+                println!("error: {}", message);
+                continue;
+            }
+        };
+
+        match debugger.execute(&command) {
+            Ok(Flow::Quit) => return Ok(()),
+            Ok(Flow::Continue) => {}
+            Err(message) => println!("error: {}", message),
+        }
+        last_command = Some(command);
+    }
+}
+
+#[cfg(unix)]
+fn serve_unix(machine: &mut Machine, path: &str, cycles_per_frame: u32) -> io::Result<()> {
+    gdb::serve_unix(machine, path, cycles_per_frame)
+}
+
+#[cfg(not(unix))]
+fn serve_unix(_: &mut Machine, _: &str, _: u32) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "Unix sockets are not supported on this platform",
+    ))
+}
+
+fn main() -> io::Result<()> {
+    let matches = App::new("oktodbg")
+        .version("1.0")
+        .author("Eric Scrivner <eric.t.scrivner@gmail.com>")
+        .about("Debug Chip8 ROMs from the command line")
+        .arg(
+            Arg::with_name("ROMFILE")
+                .help("Path to the Chip8 ROM file.")
+                .required(true)
+                .index(1),
+        )
+        .arg(
+            Arg::with_name("quirks")
+                .short("q")
+                .long("quirks")
+                .value_name("PROFILE")
+                .help("interpreter behaviour to emulate for ambiguous opcodes")
+                .possible_values(&okto::quirks::PROFILE_NAMES)
+                .default_value("okto")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("seed")
+                .short("s")
+                .long("seed")
+                .value_name("SEED")
+                .help("seed for the random number generator, for reproducible runs")
+                .validator(|value| {
+                    value
+                        .parse::<u32>()
+                        .map(|_| ())
+                        .map_err(|_| String::from("seed must be an unsigned 32-bit integer"))
+                })
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("cycles")
+                .short("c")
+                .long("cycles-per-frame")
+                .value_name("CYCLES")
+                .help("number of instructions executed per 60 Hz frame")
+                .validator(|value| {
+                    value
+                        .parse::<u32>()
+                        .map(|_| ())
+                        .map_err(|_| String::from("cycles must be an unsigned 32-bit integer"))
+                })
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("gdb")
+                .long("gdb")
+                .value_name("ADDRESS")
+                .help("serve a GDB remote debugger on a TCP address such as localhost:1234")
+                .conflicts_with("gdb-socket")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("gdb-socket")
+                .long("gdb-socket")
+                .value_name("PATH")
+                .help("serve a GDB remote debugger on a Unix socket")
+                .takes_value(true),
+        )
+        .get_matches();
+
+    let rom_path = matches.value_of("ROMFILE").unwrap();
+    let rom_data = okto::read_rom_file(rom_path)?;
+
+    if rom_data.len() > memory::MAX_ROM_SIZE_BYTES {
+        println!("File is too large to be a valid Chip8 ROM.");
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "ROM too large"));
+    }
+
+    let quirks = Quirks::from_profile_name(matches.value_of("quirks").unwrap()).unwrap();
+    let mut machine = Machine::with_quirks(quirks);
+    if let Some(seed) = matches.value_of("seed") {
+        machine.rng = Box::new(XorShiftRandom::new(seed.parse().unwrap()));
+    }
+    machine
+        .memory
+        .load(&rom_data, okto::cpu::DEFAULT_PC_ADDRESS, rom_data.len())
+        .unwrap();
+    machine.start_history(history::DEFAULT_CAPACITY);
+
+    let cycles_per_frame = matches
+        .value_of("cycles")
+        .map_or(okto::machine::DEFAULT_CYCLES_PER_FRAME, |value| {
+            value.parse().unwrap()
+        });
+
+    if let Some(address) = matches.value_of("gdb") {
+        println!("Waiting for a GDB connection on {}", address);
+        return gdb::serve_tcp(&mut machine, address, cycles_per_frame);
+    }
+    if let Some(path) = matches.value_of("gdb-socket") {
+        println!("Waiting for a GDB connection on {}", path);
+        return serve_unix(&mut machine, path, cycles_per_frame);
+    }
+
+    let mut debugger = Debugger::new(machine, cycles_per_frame);
+
+    println!(
+        "Loaded {} ({} bytes), type 'help' for commands",
+        rom_path,
+        rom_data.len()
+    );
+    run_repl(&mut debugger)?;
+    println!();
+
+    Ok(())
+}
@@ -6,28 +6,63 @@ use std::io;
 use clap::{App, Arg};
 
 use okto::cpu;
+use okto::disassembler::{Classification, Disassembly};
+use okto::isa::{self, Syntax};
 use okto::memory;
 use okto::read_rom_file;
 use okto::OktoResult;
 
-/// Display the disassembly of the given ROM file. An offset can be provided so
-/// that, for example, a ROM with an initial data section can be correctly
-/// disassembled.
-fn print_disassembly(rom_data: &Vec<u8>, offset: usize) -> OktoResult<()> {
-    let mut memory = memory::Memory::new();
+/// Most bytes written on a single `db` line.
+const BYTES_PER_DATA_LINE: usize = 8;
 
-    memory.load(rom_data, cpu::DEFAULT_PC_ADDRESS, rom_data.len())?;
+/// Display the disassembly of the given ROM file, listing every byte outside
+/// of the code on `db` lines. Operations are written in the given syntax
+/// where it has a mnemonic for them, and by name otherwise.
+fn print_disassembly(
+    rom_data: &[u8],
+    disassembly: &Disassembly,
+    syntax: Option<Syntax>,
+) -> OktoResult<()> {
+    let start = cpu::DEFAULT_PC_ADDRESS as usize;
+    let end_address = start + rom_data.len();
 
-    let num_instructions = (rom_data.len() - offset) / 2;
-    for addr in 0..num_instructions {
-        let next_address =
-            cpu::DEFAULT_PC_ADDRESS + offset as cpu::Address + (2 * addr) as cpu::Address;
-        if let Some(instruction) = memory.read_instruction(next_address) {
-            if let Some(operation) = cpu::Operation::from_instruction(&instruction) {
-                println!("{:03X} {:04X} {:?}", next_address, instruction, operation);
+    let mut next_address = start;
+    while next_address < end_address {
+        let address = next_address as cpu::Address;
+        if let Some((operation, size)) = disassembly.operation(address) {
+            let instruction = u16::from(rom_data[next_address - start]) << 8
+                | u16::from(rom_data[next_address - start + 1]);
+            let text = match syntax.and_then(|syntax| isa::format(&operation, syntax)) {
+                Some(text) => text,
+                None => format!("{:?}", operation),
+            };
+            if disassembly.is_unresolved(address) {
+                println!("{:03X} {:04X} {} ; unresolved", address, instruction, text);
             } else {
-                println!("{:03X} {:04X} UNKNOWN", next_address, instruction);
+                println!("{:03X} {:04X} {}", address, instruction, text);
             }
+            next_address += size as usize;
+            continue;
+        }
+
+        // Group bytes of the same kind up to the next instruction
+        let classification = disassembly.classification(address);
+        let mut bytes = Vec::new();
+        while next_address < end_address
+            && bytes.len() < BYTES_PER_DATA_LINE
+            && disassembly.classification(next_address as cpu::Address) == classification
+            && disassembly
+                .operation(next_address as cpu::Address)
+                .is_none()
+        {
+            bytes.push(format!("0x{:02X}", rom_data[next_address - start]));
+            next_address += 1;
+        }
+        match classification {
+            Classification::Unknown => {
+                println!("{:03X}      db {} ; unknown", address, bytes.join(" "))
+            }
+            _ => println!("{:03X}      db {}", address, bytes.join(" ")),
         }
     }
 
@@ -50,7 +85,26 @@ fn main() -> io::Result<()> {
                 .short("o")
                 .long("offset")
                 .value_name("NUMBYTES")
-                .help("number of bytes in ROM at which to start disassembly")
+                .help("number of bytes in ROM at which the code starts")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("syntax")
+                .short("s")
+                .long("syntax")
+                .value_name("SYNTAX")
+                .help("write instructions in octo or cowgod assembly syntax")
+                .possible_values(&["octo", "cowgod"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("format")
+                .short("f")
+                .long("format")
+                .value_name("FORMAT")
+                .help("write a text listing, octo source that assembles back to the ROM, or json records")
+                .possible_values(&["text", "octo", "json"])
+                .default_value("text")
                 .takes_value(true),
         )
         .get_matches();
@@ -61,6 +115,7 @@ fn main() -> io::Result<()> {
         .unwrap_or("0")
         .parse::<usize>()
         .unwrap();
+    let syntax = matches.value_of("syntax").and_then(Syntax::from_name);
     let rom_data = read_rom_file(rom_path)?;
 
     if rom_data.len() > memory::MAX_ROM_SIZE_BYTES {
@@ -68,7 +123,15 @@ fn main() -> io::Result<()> {
         return Err(io::Error::new(io::ErrorKind::InvalidData, "ROM too large"));
     }
 
-    print_disassembly(&rom_data, offset).unwrap();
+    // Code is found by following the control flow from the start of the ROM,
+    // or from the given offset into it
+    let entry = (cpu::DEFAULT_PC_ADDRESS as usize + offset) as cpu::Address;
+    let disassembly = Disassembly::new(&rom_data, entry);
+    match matches.value_of("format").unwrap() {
+        "octo" => print!("{}", disassembly.to_octo()),
+        "json" => print!("{}", disassembly.to_json()),
+        _ => print_disassembly(&rom_data, &disassembly, syntax).unwrap(),
+    }
 
     Ok(())
 }